@@ -0,0 +1,57 @@
+//! Timing for the framebuffer fill and blit paths on a 4K-sized buffer.
+//!
+//! Run with `cargo bench --bench buffer`.
+
+use std::{
+    hint::black_box,
+    time::{Duration, Instant}
+};
+
+use mflm::{buffer::Buffer, color::Color};
+
+const SCREEN: (u32, u32) = (3840, 2160);
+const ITERATIONS: u32 = 20;
+
+fn time(name: &str, mut f: impl FnMut()) {
+    // Warm up page mappings before timing.
+    f();
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let per_iter: Duration = start.elapsed() / ITERATIONS;
+    println!("{name:<24} {per_iter:>12.3?}");
+}
+
+fn main() {
+    // Backed by u32s so the byte view is aligned like an mmap'd framebuffer.
+    let mut pixels = vec![0u32; (SCREEN.0 * SCREEN.1) as usize];
+    let frame: &mut [u8] = unsafe {
+        std::slice::from_raw_parts_mut(
+            pixels.as_mut_ptr() as *mut u8,
+            pixels.len() * 4
+        )
+    };
+    let color = Color::from_rgba_u8(0x12, 0x34, 0x56, 0xFF);
+
+    time("memset (full screen)", || {
+        let mut buf = Buffer::new(frame, SCREEN);
+        buf.memset(black_box(&color));
+    });
+
+    time("memset (form rect)", || {
+        let mut buf = Buffer::new(frame, SCREEN);
+        let mut form = buf.subdimensions((1664, 912, 512, 336)).unwrap();
+        form.memset(black_box(&color));
+    });
+
+    // The per-pixel copy `draw_text` does for a rendered text row.
+    let row = vec![0xFF10_2030u32; 512 * 72];
+    time("blit (512x72 row)", || {
+        let mut buf = Buffer::new(frame, SCREEN);
+        let mut dst = buf.subdimensions((1664, 912, 512, 72)).unwrap();
+        for (y, line) in row.chunks_exact(512).enumerate() {
+            dst.put_row_argb8888((0, y as u32), black_box(line)).unwrap();
+        }
+    });
+}
@@ -0,0 +1,76 @@
+//! What a keystroke in a form row costs to draw on a 1080p frame: the
+//! row's `Buffer`, the whole row through pango as drawn today, and a
+//! single glyph cell as an append-only redraw would draw it.
+//!
+//! Run with `cargo bench --bench row`.
+
+use std::{
+    hint::black_box,
+    time::{Duration, Instant}
+};
+
+use mflm::{buffer::Buffer, color::Color, font::Font};
+
+const SCREEN: (u32, u32) = (1920, 1080);
+/// The password row of the default form, centered on the screen.
+const ROW: (u32, u32, u32, u32) = (704, 520, 512, 48);
+const ITERATIONS: u32 = 200;
+
+fn time(name: &str, mut f: impl FnMut()) {
+    // Warm up page mappings and pango's caches before timing.
+    f();
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let per_iter: Duration = start.elapsed() / ITERATIONS;
+    println!("{name:<28} {per_iter:>12.3?}");
+}
+
+fn main() {
+    let mut pixels = vec![0u32; (SCREEN.0 * SCREEN.1) as usize];
+    let frame: &mut [u8] = unsafe {
+        std::slice::from_raw_parts_mut(
+            pixels.as_mut_ptr() as *mut u8,
+            pixels.len() * 4
+        )
+    };
+    let bg = Color::from_rgba_u8(0x20, 0x20, 0x30, 0xFF);
+    let fg = Color::from_rgba_u8(0xFA, 0xFA, 0xFA, 0xFF);
+    let mut font = Font::new("Sans", 32.0);
+    let stars = "*".repeat(20);
+
+    time("row buffer", || {
+        let mut buf = Buffer::new(frame, SCREEN);
+        black_box(buf.subdimensions(black_box(ROW)).unwrap().get_bounds());
+    });
+
+    time("whole row (20 chars)", || {
+        let mut buf = Buffer::new(frame, SCREEN);
+        let mut row = buf.subdimensions(ROW).unwrap();
+        font.auto_draw_text_aligned(
+            &mut row,
+            &bg,
+            &fg,
+            black_box(&stars),
+            pango::Alignment::Left,
+            ROW.3
+        )
+        .unwrap();
+    });
+
+    time("one glyph cell", || {
+        let mut buf = Buffer::new(frame, SCREEN);
+        let (x, y, _, h) = ROW;
+        let mut cell = buf.subdimensions((x + 200, y, 24, h)).unwrap();
+        font.auto_draw_text_aligned(
+            &mut cell,
+            &bg,
+            &fg,
+            black_box("*"),
+            pango::Alignment::Left,
+            h
+        )
+        .unwrap();
+    });
+}
@@ -0,0 +1,10 @@
+//! Color values from the config, as `Color::from_hex` reads them.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mflm::color::Color;
+
+fuzz_target!(|s: &str| {
+    let _ = Color::from_hex(s);
+});
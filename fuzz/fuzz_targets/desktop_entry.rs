@@ -0,0 +1,14 @@
+//! Session desktop entries, parsed as the greeter's session discovery
+//! parses them once read.
+
+#![no_main]
+
+use std::path::Path;
+
+use libfuzzer_sys::fuzz_target;
+use mflm::entry::{Entry, SessionKind};
+
+fuzz_target!(|data: &str| {
+    let path = Path::new("/usr/share/wayland-sessions/fuzz.desktop");
+    let _ = Entry::parse(path, data, SessionKind::Wayland, Some("de_DE"));
+});
@@ -0,0 +1,17 @@
+//! Terminal input, a byte at a time as the greeter reads it, with the
+//! escape timeout firing at the end as it would on a quiet terminal.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mflm::decode::Decoder;
+
+fuzz_target!(|bytes: &[u8]| {
+    let mut decoder = Decoder::default();
+    for &b in bytes {
+        let _ = decoder.push(b);
+    }
+    if decoder.timeout().is_some() {
+        let _ = decoder.time_out();
+    }
+});
@@ -0,0 +1,46 @@
+use std::{fs, io};
+
+const PASSWD_PATH: &str = "/etc/passwd";
+
+// Defaults of UID_MIN/UID_MAX in login.defs.
+const UID_MIN: u32 = 1000;
+const UID_MAX: u32 = 60000;
+
+/// Names of human login accounts from /etc/passwd: regular UID range and a
+/// usable shell. Purely local; never goes through NSS.
+pub fn local_usernames() -> io::Result<Vec<String>> {
+    let data = fs::read_to_string(PASSWD_PATH)?;
+    Ok(parse_passwd(&data))
+}
+
+/// The lowercase spelling of `typed`, e.g. "alice" for "Alice", if that's
+/// a local account and `typed` isn't.
+pub fn case_suggestion(typed: &str) -> Option<String> {
+    let lower = typed.to_lowercase();
+    if lower == typed {
+        return None;
+    }
+    let names = local_usernames().ok()?;
+    let exists = |name: &str| names.iter().any(|n| n == name);
+    (exists(&lower) && !exists(typed)).then_some(lower)
+}
+
+fn parse_passwd(data: &str) -> Vec<String> {
+    data.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            if fields.len() < 7 {
+                return None;
+            }
+            let uid: u32 = fields[2].parse().ok()?;
+            let shell = fields[6];
+            let login_shell = !shell.ends_with("/nologin")
+                && !shell.ends_with("/false")
+                && !shell.is_empty();
+            ((UID_MIN..UID_MAX).contains(&uid) && login_shell)
+                .then(|| fields[0].to_string())
+        })
+        .filter(|name| !name.is_empty())
+        .collect()
+}
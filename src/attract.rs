@@ -0,0 +1,90 @@
+//! `ui.attract_mode`: a slow animation for showroom machines left alone,
+//! against burn-in. Any key puts the form back the way it was.
+
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+use crate::color::Color;
+
+/// How long each step of the animation lasts.
+const STEP: Duration = Duration::from_secs(4);
+
+/// How far the greeting drifts up and down from its place.
+const DRIFT_PX: u32 = 4;
+
+/// Parses `ui.attract_palette`, leaving out colors that don't parse.
+pub fn palette(hexes: &[String]) -> Vec<Color> {
+    hexes
+        .iter()
+        .filter_map(|hex| match Color::from_hex(hex) {
+            Ok(color) => Some(color),
+            Err(e) => {
+                warn!("Ignoring ui.attract_palette color {hex:?}: {e}");
+                None
+            }
+        })
+        .collect()
+}
+
+pub(crate) struct Attract {
+    after: Duration,
+    palette: Vec<Color>,
+    last_input: Instant,
+    /// Steps since the animation started; None while the form is static.
+    step: Option<u32>
+}
+
+impl Attract {
+    pub fn new(after: Duration, palette: Vec<Color>) -> Self {
+        Self {
+            after,
+            palette,
+            last_input: Instant::now(),
+            step: None
+        }
+    }
+
+    /// A key was pressed. Returns true if the animation was running and
+    /// the form needs drawing as it normally is.
+    pub fn touch(&mut self) -> bool {
+        self.last_input = Instant::now();
+        self.step.take().is_some()
+    }
+
+    /// Moves the animation on to where it should be at `now`. Returns true
+    /// if that changed what's on screen.
+    pub fn advance(&mut self, now: Instant) -> bool {
+        let idle = now.saturating_duration_since(self.last_input);
+        let step = idle
+            .checked_sub(self.after)
+            .map(|running| (running.as_secs() / STEP.as_secs()) as u32);
+        let changed = step != self.step;
+        self.step = step;
+        changed
+    }
+
+    /// The underline color for this step, while running.
+    pub fn underline_color(&self) -> Option<Color> {
+        let step = self.step? as usize;
+        self.palette.get(step % self.palette.len().max(1)).copied()
+    }
+
+    /// How far to move the greeting down, negative for up. Goes through
+    /// 0, `DRIFT_PX`, 0, -`DRIFT_PX` a pixel a step.
+    pub fn drift(&self) -> i32 {
+        let step = match self.step {
+            Some(step) => step,
+            None => return 0
+        };
+        let d = DRIFT_PX as i32;
+        let phase = (step % (4 * DRIFT_PX)) as i32;
+        if phase <= d {
+            phase
+        } else if phase <= 3 * d {
+            2 * d - phase
+        } else {
+            phase - 4 * d
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use std::{
+    fs,
+    path::{Path, PathBuf}
+};
+
+const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatteryStatus {
+    pub capacity: u8,
+    pub charging: bool
+}
+
+/// The first `BAT*` entry under /sys/class/power_supply, if any.
+pub fn find() -> Option<PathBuf> {
+    let mut batteries: Vec<PathBuf> = fs::read_dir(POWER_SUPPLY_DIR)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter(|e| e.file_name().to_string_lossy().starts_with("BAT"))
+        .map(|e| e.path())
+        .collect();
+    batteries.sort();
+    batteries.into_iter().next()
+}
+
+/// Reads `capacity` and `status` from a power supply directory.
+pub fn read(path: &Path) -> Option<BatteryStatus> {
+    let capacity = fs::read_to_string(path.join("capacity")).ok()?;
+    let capacity: u8 = capacity.trim().parse().ok()?;
+    let status = fs::read_to_string(path.join("status")).unwrap_or_default();
+    let charging = matches!(status.trim(), "Charging" | "Full");
+    Some(BatteryStatus {
+        capacity: capacity.min(100),
+        charging
+    })
+}
+
+impl crate::LoginManager<'_> {
+    /// Re-reads the battery and redraws the indicator if it changed.
+    pub(crate) fn update_battery(&mut self) -> Result<(), crate::Error> {
+        let path = match &self.battery {
+            Some(path) => path,
+            None => return Ok(())
+        };
+        let status = read(path);
+        if status == self.battery_status {
+            return Ok(());
+        }
+        self.battery_status = status;
+        self.draw_battery()
+    }
+}
@@ -1,23 +1,78 @@
+//! Drawing into 32-bit framebuffer memory: XRGB8888 pixels in native
+//! byte order, row after row with no padding.
+
+use std::{cell::Cell, io};
+
+use cairo::{Format, ImageSurface};
 use thiserror::Error;
 
 use crate::color::Color;
 
+/// A position or size, x then y, in pixels.
 pub type Vect = (u32, u32);
+/// x, y, width and height, in pixels.
 pub type Rect = (u32, u32, u32, u32);
 
+thread_local! {
+    /// Pixels written by any `Buffer` on this thread since the last
+    /// `take_pixels_written`.
+    static PIXELS: Cell<u64> = const { Cell::new(0) };
+}
+
+fn touched(n: u64) {
+    PIXELS.with(|pixels| pixels.set(pixels.get() + n));
+}
+
+/// How many pixels buffers on this thread have written since the last
+/// call, for counting the cost of a frame.
+pub fn take_pixels_written() -> u64 {
+    PIXELS.with(|pixels| pixels.replace(0))
+}
+
+/// Why a drawing call failed.
 #[derive(Debug, Error)]
 #[non_exhaustive]
 pub enum BufferError {
+    /// `Buffer::subdimensions` was asked for more than the bounds hold.
     #[error("cannot create subdimensions larger than buffer: {subdimensions:?} > {bounds:?}")]
-    SubdimensionsTooLarge { subdimensions: Rect, bounds: Rect },
+    SubdimensionsTooLarge {
+        /// What was asked for.
+        subdimensions: Rect,
+        /// The bounds it had to fit in.
+        bounds: Rect
+    },
+    /// `Buffer::offset` was given a point outside the bounds.
     #[error("cannot create offset outside buffer: {offset:?} > {bounds:?}")]
-    OffsetOutOfBounds { offset: Vect, bounds: Rect },
+    OffsetOutOfBounds {
+        /// What was asked for.
+        offset: Vect,
+        /// The bounds it had to be in.
+        bounds: Rect
+    },
+    /// A pixel was outside the buffer's bounds.
     #[error("put({pos:?}) is not within subdimensions of buffer ({subdim:?})")]
-    PixelOutOfSubdimBounds { pos: Vect, subdim: Rect },
+    PixelOutOfSubdimBounds {
+        /// Where, relative to the bounds.
+        pos: Vect,
+        /// The bounds.
+        subdim: Rect
+    },
+    /// A pixel was outside the frame.
     #[error("put({pos:?}) is not within dimensions of buffer ({dim:?})")]
-    PixelOutOfBounds { pos: Vect, dim: Vect }
+    PixelOutOfBounds {
+        /// Where, relative to the bounds.
+        pos: Vect,
+        /// The frame's size.
+        dim: Vect
+    },
+    /// `Buffer::write_png` couldn't encode or write the image.
+    #[error("unable to write PNG: {0}")]
+    Png(#[source] cairo::IoError)
 }
 
+/// A view of framebuffer memory, or any other slice of XRGB8888 pixels,
+/// that draws within its bounds: the whole frame, or part of it after
+/// `subdimensions` or `offset`.
 pub struct Buffer<'a> {
     buf: &'a mut [u8],
     dimensions: Vect,
@@ -25,6 +80,7 @@ pub struct Buffer<'a> {
 }
 
 impl<'a> Buffer<'a> {
+    /// A buffer over all of `buf`, a frame `dimensions` pixels in size.
     pub fn new(buf: &'a mut [u8], dimensions: Vect) -> Self {
         Self {
             buf,
@@ -33,6 +89,7 @@ impl<'a> Buffer<'a> {
         }
     }
 
+    /// The part of the frame this buffer draws in.
     pub fn get_bounds(&self) -> Rect {
         if let Some(subdim) = self.subdimensions {
             subdim
@@ -41,6 +98,8 @@ impl<'a> Buffer<'a> {
         }
     }
 
+    /// A buffer for `subdimensions`, relative to this one's bounds and
+    /// within them.
     pub fn subdimensions(
         &mut self,
         subdimensions: Rect
@@ -67,6 +126,8 @@ impl<'a> Buffer<'a> {
         })
     }
 
+    /// A buffer for everything from `offset` to the bottom right corner
+    /// of this one's bounds.
     pub fn offset(&mut self, offset: Vect) -> Result<Buffer<'_>, BufferError> {
         let bounds = self.get_bounds();
         if offset.0 > bounds.2 || offset.1 > bounds.3 {
@@ -85,28 +146,159 @@ impl<'a> Buffer<'a> {
         })
     }
 
+    /// Fills the whole buffer with `c`, blended over what's there unless
+    /// it's opaque.
     pub fn memset(&mut self, c: &Color) {
-        if let Some(subdim) = self.subdimensions {
-            unsafe {
-                let ptr = self.buf.as_mut_ptr();
-                for y in subdim.1..(subdim.1 + subdim.3) {
-                    for x in subdim.0..(subdim.0 + subdim.2) {
-                        *((ptr as *mut u32)
-                            .offset((x + y * self.dimensions.0) as isize)) =
-                            c.as_argb8888();
-                    }
+        if !c.is_opaque() {
+            let (_, _, w, h) = self.get_bounds();
+            self.fill_rect_blend((0, 0, w, h), c);
+            return;
+        }
+        let (x, y, w, h) = self.get_bounds();
+        touched(w as u64 * h as u64);
+        let (x, y, w, h) = (x as usize, y as usize, w as usize, h as usize);
+        let stride = self.dimensions.0 as usize;
+        let argb = c.as_argb8888();
+
+        // The framebuffer mapping is page aligned, so this is the whole
+        // frame in practice; fall back to bytes if it somehow isn't.
+        let (prefix, pixels, _) = unsafe { self.buf.align_to_mut::<u32>() };
+        if prefix.is_empty() {
+            for row in y..y + h {
+                let start = row * stride + x;
+                if let Some(line) = pixels.get_mut(start..start + w) {
+                    line.fill(argb);
                 }
             }
         } else {
-            unsafe {
-                let ptr = self.buf.as_mut_ptr();
-                for p in 0..(self.dimensions.0 * self.dimensions.1) {
-                    *((ptr as *mut u32).offset(p as isize)) = c.as_argb8888();
+            let bytes = argb.to_ne_bytes();
+            for row in y..y + h {
+                let start = (row * stride + x) * 4;
+                if let Some(line) = self.buf.get_mut(start..start + w * 4) {
+                    for px in line.chunks_exact_mut(4) {
+                        px.copy_from_slice(&bytes);
+                    }
                 }
             }
         }
     }
 
+    /// Clips `rect`, in local coordinates, to the buffer. `None` if
+    /// nothing of it is visible.
+    fn clip(&self, rect: Rect) -> Option<Rect> {
+        let (_, _, w, h) = self.get_bounds();
+        if rect.0 >= w || rect.1 >= h || rect.2 == 0 || rect.3 == 0 {
+            return None;
+        }
+        Some((rect.0, rect.1, rect.2.min(w - rect.0), rect.3.min(h - rect.1)))
+    }
+
+    /// Fills `rect`, clipped to the buffer, blending unless `c` is opaque.
+    pub fn fill_rect(&mut self, rect: Rect, c: &Color) {
+        if let Some(rect) = self.clip(rect) {
+            if let Ok(mut area) = self.subdimensions(rect) {
+                area.memset(c);
+            }
+        }
+    }
+
+    /// Blends `c` over `rect` using its opacity, clipped to the buffer.
+    pub fn fill_rect_blend(&mut self, rect: Rect, c: &Color) {
+        let (x, y, w, h) = match self.clip(rect) {
+            Some(rect) => rect,
+            None => return
+        };
+        touched(w as u64 * h as u64);
+        let bounds = self.get_bounds();
+        let stride = self.dimensions.0 as usize;
+        let [alpha, r, g, b] = c.as_argb8888().to_be_bytes();
+        let alpha = alpha as u32;
+        let mix = |src: u8, dst: u8| {
+            ((src as u32 * alpha + dst as u32 * (255 - alpha) + 127) / 255) as u8
+        };
+
+        for row in (y + bounds.1)..(y + bounds.1 + h) {
+            let start = (row as usize * stride + (x + bounds.0) as usize) * 4;
+            let line = match self.buf.get_mut(start..start + w as usize * 4) {
+                Some(line) => line,
+                None => return
+            };
+            for px in line.chunks_exact_mut(4) {
+                let dst = u32::from_ne_bytes([px[0], px[1], px[2], px[3]]);
+                let [da, dr, dg, db] = dst.to_be_bytes();
+                let out = [da, mix(r, dr), mix(g, dg), mix(b, db)];
+                px.copy_from_slice(&u32::from_be_bytes(out).to_ne_bytes());
+            }
+        }
+    }
+
+    /// A horizontal line `len` pixels long starting at `pos`, clipped to
+    /// the buffer.
+    pub fn hline(&mut self, pos: Vect, len: u32, c: &Color) {
+        self.fill_rect((pos.0, pos.1, len, 1), c);
+    }
+
+    /// A vertical line `len` pixels long starting at `pos`, clipped to the
+    /// buffer.
+    pub fn vline(&mut self, pos: Vect, len: u32, c: &Color) {
+        self.fill_rect((pos.0, pos.1, 1, len), c);
+    }
+
+    /// Outlines `rect` with lines `thickness` pixels wide, drawn inside it.
+    pub fn stroke_rect(&mut self, rect: Rect, thickness: u32, c: &Color) {
+        let (x, y, w, h) = rect;
+        let t = thickness.min(w / 2).min(h / 2).max(1);
+        self.fill_rect((x, y, w, t), c);
+        self.fill_rect((x, y + h.saturating_sub(t), w, t), c);
+        self.fill_rect((x, y + t, t, h.saturating_sub(2 * t)), c);
+        self.fill_rect(
+            (x + w.saturating_sub(t), y + t, t, h.saturating_sub(2 * t)),
+            c
+        );
+    }
+
+    /// Fills the pixels `x0..=x1` of row `y`, which may reach off the
+    /// buffer on any side.
+    fn span(&mut self, y: i64, x0: i64, x1: i64, c: &Color) {
+        if y < 0 || x1 < 0 || x1 < x0 {
+            return;
+        }
+        let x0 = x0.max(0);
+        self.fill_rect((x0 as u32, y as u32, (x1 - x0 + 1) as u32, 1), c);
+    }
+
+    /// Fills the circle of `radius` around `center`, clipped to the buffer.
+    pub fn fill_circle(&mut self, center: Vect, radius: u32, c: &Color) {
+        self.stroke_circle(center, radius, radius, c);
+    }
+
+    /// Outlines the circle of `radius` around `center` with a ring
+    /// `thickness` pixels wide, drawn inside it.
+    pub fn stroke_circle(
+        &mut self,
+        center: Vect,
+        radius: u32,
+        thickness: u32,
+        c: &Color
+    ) {
+        let (cx, cy) = (center.0 as i64, center.1 as i64);
+        let r = radius as i64;
+        let inner = radius.saturating_sub(thickness) as i64;
+        // Half the width of a circle's row `dy` away from its center.
+        let half = |r: i64, dy: i64| ((r * r - dy * dy) as f64).sqrt() as i64;
+        for dy in -r..=r {
+            let outer = half(r, dy);
+            if dy.abs() < inner {
+                let inner = half(inner, dy);
+                self.span(cy + dy, cx - outer, cx - inner - 1, c);
+                self.span(cy + dy, cx + inner + 1, cx + outer, c);
+            } else {
+                self.span(cy + dy, cx - outer, cx + outer, c);
+            }
+        }
+    }
+
+    /// Sets the pixel at `pos`, blending unless `c` is opaque.
     pub fn put(&mut self, pos: Vect, c: &Color) -> Result<(), BufferError> {
         let true_pos = if let Some(subdim) = self.subdimensions {
             if pos.0 >= subdim.2 || pos.1 >= subdim.3 {
@@ -125,7 +317,12 @@ impl<'a> Buffer<'a> {
             }
             pos
         };
+        if !c.is_opaque() {
+            self.fill_rect_blend((pos.0, pos.1, 1, 1), c);
+            return Ok(());
+        }
 
+        touched(1);
         unsafe {
             let ptr = self.buf.as_mut_ptr().offset(
                 4 * (true_pos.0 + (true_pos.1 * self.dimensions.0)) as isize
@@ -136,36 +333,250 @@ impl<'a> Buffer<'a> {
         Ok(())
     }
 
-    pub fn put_argb8888(
+    /// Copies a row of ARGB8888 pixels to `pos`, clipped to the buffer.
+    pub fn put_row_argb8888(
         &mut self,
         pos: Vect,
-        argb8888: u32
+        row: &[u32]
     ) -> Result<(), BufferError> {
-        let true_pos = if let Some(subdim) = self.subdimensions {
-            if pos.0 >= subdim.2 || pos.1 >= subdim.3 {
-                return Err(BufferError::PixelOutOfSubdimBounds {
-                    pos,
-                    subdim
-                });
-            }
-            (pos.0 + subdim.0, pos.1 + subdim.1)
-        } else {
-            if pos.0 >= self.dimensions.0 || pos.1 >= self.dimensions.1 {
-                return Err(BufferError::PixelOutOfBounds {
-                    pos,
-                    dim: self.dimensions
-                });
-            }
-            pos
-        };
+        let dst = self.row_mut(pos, row.len())?;
+        for (px, argb) in dst.chunks_exact_mut(4).zip(row) {
+            px.copy_from_slice(&argb.to_ne_bytes());
+        }
 
-        unsafe {
-            let ptr = self.buf.as_mut_ptr().offset(
-                4 * (true_pos.0 + (true_pos.1 * self.dimensions.0)) as isize
-            );
-            *(ptr as *mut u32) = argb8888;
-        };
+        Ok(())
+    }
+
+    /// Composites a row of premultiplied ARGB8888 pixels, as cairo draws
+    /// them, over what's at `pos`, clipped to the buffer.
+    pub fn blend_row_argb8888(
+        &mut self,
+        pos: Vect,
+        row: &[u32]
+    ) -> Result<(), BufferError> {
+        let dst = self.row_mut(pos, row.len())?;
+        for (px, argb) in dst.chunks_exact_mut(4).zip(row) {
+            let [alpha, r, g, b] = argb.to_be_bytes();
+            let keep = 255 - alpha as u32;
+            let mix = |src: u8, dst: u8| {
+                (src as u32 + (dst as u32 * keep + 127) / 255).min(255) as u8
+            };
+            let dst = u32::from_ne_bytes([px[0], px[1], px[2], px[3]]);
+            let [da, dr, dg, db] = dst.to_be_bytes();
+            let out = [da, mix(r, dr), mix(g, dg), mix(b, db)];
+            px.copy_from_slice(&u32::from_be_bytes(out).to_ne_bytes());
+        }
 
         Ok(())
     }
+
+    /// The bytes of up to `len` pixels from `pos`, clipped to the buffer.
+    fn row_mut(
+        &mut self,
+        pos: Vect,
+        len: usize
+    ) -> Result<&mut [u8], BufferError> {
+        let bounds = self.get_bounds();
+        if pos.0 >= bounds.2 || pos.1 >= bounds.3 {
+            return Err(BufferError::PixelOutOfSubdimBounds {
+                pos,
+                subdim: bounds
+            });
+        }
+
+        let len = len.min((bounds.2 - pos.0) as usize);
+        touched(len as u64);
+        let start = ((pos.1 + bounds.1) * self.dimensions.0 + pos.0 + bounds.0)
+            as usize
+            * 4;
+        let dim = self.dimensions;
+        self.buf
+            .get_mut(start..start + len * 4)
+            .ok_or(BufferError::PixelOutOfBounds { pos, dim })
+    }
+
+    /// Copies the pixels of `rect`, clipped to the buffer, row by row.
+    /// Returns the clipped rect with them, to put back later with
+    /// `put_row_argb8888`.
+    pub fn read_rect(&self, rect: Rect) -> Option<(Rect, Vec<u32>)> {
+        let (x, y, w, h) = self.clip(rect)?;
+        let bounds = self.get_bounds();
+        let stride = self.dimensions.0 as usize;
+        let mut pixels = Vec::with_capacity((w * h) as usize);
+        for row in (y + bounds.1)..(y + bounds.1 + h) {
+            let start = (row as usize * stride + (x + bounds.0) as usize) * 4;
+            let line = self.buf.get(start..start + w as usize * 4)?;
+            pixels.extend(
+                line.chunks_exact(4)
+                    .map(|px| u32::from_ne_bytes([px[0], px[1], px[2], px[3]]))
+            );
+        }
+        Some(((x, y, w, h), pixels))
+    }
+
+    /// Writes what's within the bounds out as a PNG, for screenshots and
+    /// splash images.
+    pub fn write_png(
+        &self,
+        out: &mut impl io::Write
+    ) -> Result<(), BufferError> {
+        let png_error = |e: cairo::Error| BufferError::Png(e.into());
+        let (_, _, w, h) = self.get_bounds();
+        let stride = Format::Rgb24.stride_for_width(w).map_err(png_error)?;
+        let mut data = vec![0; stride as usize * h as usize];
+        if let Some((_, pixels)) = self.read_rect((0, 0, w, h)) {
+            let rows = data.chunks_exact_mut(stride as usize);
+            for (row, line) in rows.zip(pixels.chunks_exact(w as usize)) {
+                for (px, argb) in row.chunks_exact_mut(4).zip(line) {
+                    px.copy_from_slice(&argb.to_ne_bytes());
+                }
+            }
+        }
+        let surface = ImageSurface::create_for_data(
+            data,
+            Format::Rgb24,
+            w as i32,
+            h as i32,
+            stride
+        )
+        .map_err(png_error)?;
+        surface.write_to_png(out).map_err(BufferError::Png)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const W: u32 = 6;
+    const H: u32 = 4;
+
+    fn red() -> Color {
+        Color::from_rgba_u8(255, 0, 0, 255)
+    }
+
+    /// A blank `W`x`H` frame.
+    fn blank() -> Vec<u8> {
+        vec![0; (W * H * 4) as usize]
+    }
+
+    /// The frame as rows of `#` for red pixels and `.` for the rest.
+    fn coverage(frame: &[u8]) -> Vec<String> {
+        let red = red().as_argb8888().to_ne_bytes();
+        frame
+            .chunks_exact(W as usize * 4)
+            .map(|row| {
+                row.chunks_exact(4)
+                    .map(|px| if px == red { '#' } else { '.' })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fill_rect_covers_exactly_the_rect() {
+        let mut frame = blank();
+        Buffer::new(&mut frame, (W, H)).fill_rect((1, 1, 3, 2), &red());
+        assert_eq!(coverage(&frame), ["......", ".###..", ".###..", "......"]);
+    }
+
+    #[test]
+    fn fill_rect_clips_at_the_edges() {
+        let mut frame = blank();
+        let mut buf = Buffer::new(&mut frame, (W, H));
+        buf.fill_rect((4, 2, 10, 10), &red());
+        buf.fill_rect((0, 0, 1, u32::MAX), &red());
+        assert_eq!(coverage(&frame), ["#.....", "#.....", "#...##", "#...##"]);
+    }
+
+    #[test]
+    fn fill_rect_off_the_buffer_draws_nothing() {
+        let mut frame = blank();
+        let mut buf = Buffer::new(&mut frame, (W, H));
+        buf.fill_rect((W, 0, 2, 2), &red());
+        buf.fill_rect((0, H, 2, 2), &red());
+        buf.fill_rect((1, 1, 0, 2), &red());
+        buf.fill_rect((1, 1, 2, 0), &red());
+        assert!(frame.iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn fill_rect_is_local_to_subdimensions() {
+        let mut frame = blank();
+        let mut buf = Buffer::new(&mut frame, (W, H));
+        let mut area = buf.subdimensions((2, 1, 3, 2)).unwrap();
+        area.fill_rect((1, 1, 5, 5), &red());
+        assert_eq!(coverage(&frame), ["......", "......", "...##.", "......"]);
+    }
+
+    #[test]
+    fn stroke_rect_draws_inside_the_rect() {
+        let mut frame = blank();
+        Buffer::new(&mut frame, (W, H)).stroke_rect((0, 0, 5, 4), 1, &red());
+        assert_eq!(coverage(&frame), ["#####.", "#...#.", "#...#.", "#####."]);
+
+        // Too thick to leave a hole: it's filled, but not past the rect.
+        let mut thick = blank();
+        Buffer::new(&mut thick, (W, H)).stroke_rect((1, 0, 4, 4), 9, &red());
+        assert_eq!(coverage(&thick), [".####.", ".####.", ".####.", ".####."]);
+    }
+
+    #[test]
+    fn stroke_rect_clips_at_the_edges() {
+        let mut frame = blank();
+        Buffer::new(&mut frame, (W, H)).stroke_rect((3, 1, 5, 5), 1, &red());
+        assert_eq!(coverage(&frame), ["......", "...###", "...#..", "...#.."]);
+    }
+
+    #[test]
+    fn lines_cover_exactly_their_pixels() {
+        let mut frame = blank();
+        let mut buf = Buffer::new(&mut frame, (W, H));
+        buf.hline((1, 1), 4, &red());
+        buf.vline((0, 0), 3, &red());
+        assert_eq!(coverage(&frame), ["#.....", "#####.", "#.....", "......"]);
+    }
+
+    #[test]
+    fn lines_clip_at_the_edges() {
+        let mut frame = blank();
+        let mut buf = Buffer::new(&mut frame, (W, H));
+        buf.hline((3, 3), 10, &red());
+        buf.vline((5, 1), u32::MAX, &red());
+        // Off the buffer, or no length at all.
+        buf.hline((0, H), 3, &red());
+        buf.vline((W, 0), 3, &red());
+        buf.hline((0, 0), 0, &red());
+        buf.vline((0, 0), 0, &red());
+        assert_eq!(coverage(&frame), ["......", ".....#", ".....#", "...###"]);
+    }
+
+    #[test]
+    fn lines_are_local_to_subdimensions() {
+        let mut frame = blank();
+        let mut buf = Buffer::new(&mut frame, (W, H));
+        let mut area = buf.subdimensions((1, 1, 3, 2)).unwrap();
+        area.hline((1, 0), 5, &red());
+        area.vline((0, 1), 5, &red());
+        assert_eq!(coverage(&frame), ["......", "..##..", ".#....", "......"]);
+    }
+
+    #[test]
+    fn fill_rect_blend_mixes_and_clips() {
+        let mut frame = blank();
+        let mut buf = Buffer::new(&mut frame, (W, H));
+        buf.fill_rect((0, 0, W, H), &Color::from_rgba_u8(0, 0, 200, 255));
+        let half_red = Color::from_rgba_u8(255, 0, 0, 0).with_opacity(0.5);
+        buf.fill_rect_blend((4, 3, 5, 5), &half_red);
+
+        let pixel = |frame: &[u8], x: u32, y: u32| {
+            let i = ((y * W + x) * 4) as usize;
+            let px = [frame[i], frame[i + 1], frame[i + 2], frame[i + 3]];
+            u32::from_ne_bytes(px).to_be_bytes()
+        };
+        assert_eq!(pixel(&frame, 4, 3), [255, 127, 0, 100]);
+        assert_eq!(pixel(&frame, 5, 3), [255, 127, 0, 100]);
+        assert_eq!(pixel(&frame, 3, 3), [255, 0, 0, 200]);
+        assert_eq!(pixel(&frame, 4, 2), [255, 0, 0, 200]);
+    }
 }
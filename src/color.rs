@@ -1,4 +1,7 @@
-#[derive(Debug, Clone, Copy, Default)]
+//! Colors, as the config writes them and the framebuffer takes them.
+
+/// A straight (not premultiplied) RGBA color, each channel 0 to 1.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
 pub struct Color {
     red: f32,
     green: f32,
@@ -6,19 +9,26 @@ pub struct Color {
     opacity: f32
 }
 
+/// Why `Color::from_hex` rejected a string.
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
 pub enum ParseColorError {
+    /// Not 6 or 8 digits, once any "#" is taken off.
     #[error(
         "invalid hex color length ({len}); expected 6 (RRGGBB) or 8 (AARRGGBB)"
     )]
-    InvalidLength { len: usize },
+    InvalidLength {
+        /// How many digits there were.
+        len: usize
+    },
 
+    /// Something other than hex digits; holds the whole string.
     #[error("invalid hex color: {0}")]
     InvalidHex(String)
 }
 
 impl Color {
+    /// A color from 0-255 channels.
     pub fn from_rgba_u8(r: u8, g: u8, b: u8, a: u8) -> Self {
         Self {
             red: (r as f32) / 255.0,
@@ -30,37 +40,79 @@ impl Color {
 
     /// Parses "#RRGGBB", "RRGGBB", "#AARRGGBB", or "AARRGGBB".
     pub fn from_hex(s: &str) -> Result<Self, ParseColorError> {
-        let hex = s.trim().trim_start_matches('#');
+        let hex = s.trim().trim_start_matches('#').as_bytes();
+        // Checked first, so anything non-ASCII is reported as not hex
+        // rather than by its length in bytes.
+        if !hex.iter().all(u8::is_ascii_hexdigit) {
+            return Err(ParseColorError::InvalidHex(s.to_string()));
+        }
+        let byte = |i: usize| hex_digit(hex[i]) << 4 | hex_digit(hex[i + 1]);
         match hex.len() {
-            6 => {
-                let r = u8::from_str_radix(&hex[0..2], 16)
-                    .map_err(|_| ParseColorError::InvalidHex(s.to_string()))?;
-                let g = u8::from_str_radix(&hex[2..4], 16)
-                    .map_err(|_| ParseColorError::InvalidHex(s.to_string()))?;
-                let b = u8::from_str_radix(&hex[4..6], 16)
-                    .map_err(|_| ParseColorError::InvalidHex(s.to_string()))?;
-                Ok(Self::from_rgba_u8(r, g, b, 0xFF))
-            }
-            8 => {
-                let a = u8::from_str_radix(&hex[0..2], 16)
-                    .map_err(|_| ParseColorError::InvalidHex(s.to_string()))?;
-                let r = u8::from_str_radix(&hex[2..4], 16)
-                    .map_err(|_| ParseColorError::InvalidHex(s.to_string()))?;
-                let g = u8::from_str_radix(&hex[4..6], 16)
-                    .map_err(|_| ParseColorError::InvalidHex(s.to_string()))?;
-                let b = u8::from_str_radix(&hex[6..8], 16)
-                    .map_err(|_| ParseColorError::InvalidHex(s.to_string()))?;
-                Ok(Self::from_rgba_u8(r, g, b, a))
-            }
+            6 => Ok(Self::from_rgba_u8(byte(0), byte(2), byte(4), 0xFF)),
+            8 => Ok(Self::from_rgba_u8(byte(2), byte(4), byte(6), byte(0))),
             len => Err(ParseColorError::InvalidLength { len })
         }
     }
 
+    /// Moves `t` of the way toward `other`: 0 is `self`, 1 is `other`.
+    pub fn blend(&self, other: &Color, t: f32) -> Self {
+        let t = t.clamp(0.0, 1.0);
+        let mix = |a: f32, b: f32| a + (b - a) * t;
+        Self {
+            red: mix(self.red, other.red),
+            green: mix(self.green, other.green),
+            blue: mix(self.blue, other.blue),
+            opacity: mix(self.opacity, other.opacity)
+        }
+    }
+
+    /// The same color, `opacity` opaque.
+    pub fn with_opacity(&self, opacity: f32) -> Self {
+        Self {
+            opacity: opacity.clamp(0.0, 1.0),
+            ..*self
+        }
+    }
+
+    /// The alpha channel, 0 to 1.
+    pub fn opacity(&self) -> f32 {
+        self.opacity
+    }
+
+    /// Whether drawing it just replaces what's underneath.
+    pub fn is_opaque(&self) -> bool {
+        self.as_argb8888() >> 24 == 0xFF
+    }
+
+    /// What drawing it over `under` looks like. Opaque if `under` is.
+    pub fn over(&self, under: &Color) -> Self {
+        under.blend(&self.with_opacity(1.0), self.opacity)
+    }
+
+    /// Channel-wise product, as if lit by `light`. Keeps the opacity.
+    pub fn multiply(&self, light: &Color) -> Self {
+        Self {
+            red: self.red * light.red,
+            green: self.green * light.green,
+            blue: self.blue * light.blue,
+            opacity: self.opacity
+        }
+    }
+
+    /// "#AARRGGBB", as `from_hex` reads it.
+    pub fn as_hex(&self) -> String {
+        let argb = [self.opacity, self.red, self.green, self.blue];
+        let [a, r, g, b] = argb.map(|x| (x * 255.0).round() as u8);
+        format!("#{a:02x}{r:02x}{g:02x}{b:02x}")
+    }
+
+    /// As a framebuffer pixel: alpha in the top byte, blue in the bottom.
     pub fn as_argb8888(&self) -> u32 {
         let argb = [self.opacity, self.red, self.green, self.blue];
         u32::from_be_bytes(argb.map(|x| (x * 255.0) as u8))
     }
 
+    /// Red, green, blue and alpha, as cairo takes them.
     pub fn as_rgba_f32(&self) -> (f64, f64, f64, f64) {
         (
             self.red as f64,
@@ -70,3 +122,68 @@ impl Color {
         )
     }
 }
+
+/// The value of an ASCII hex digit; `from_hex` has checked it is one.
+fn hex_digit(b: u8) -> u8 {
+    match b {
+        b'0'..=b'9' => b - b'0',
+        b'a'..=b'f' => b - b'a' + 10,
+        _ => b - b'A' + 10
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid_hex(s: &str) -> String {
+        match Color::from_hex(s) {
+            Err(ParseColorError::InvalidHex(text)) => text,
+            other => panic!("{:?} parsed as {:?}", s, other)
+        }
+    }
+
+    #[test]
+    fn hex_colors_parse() {
+        let red = Color::from_rgba_u8(0xFF, 0, 0, 0xFF);
+        for hex in ["#FF0000", "ff0000", " #ff0000 ", "#ffff0000", "FFff0000"] {
+            assert_eq!(Color::from_hex(hex).unwrap(), red, "{:?}", hex);
+        }
+        let clear = Color::from_hex("#80123456").unwrap();
+        assert_eq!(clear, Color::from_rgba_u8(0x12, 0x34, 0x56, 0x80));
+        assert_eq!(clear.as_hex(), "#80123456");
+    }
+
+    #[test]
+    fn wrong_lengths_are_reported() {
+        for (hex, len) in [("", 0), ("#", 0), ("#fff", 3), ("#fffffff", 7)] {
+            match Color::from_hex(hex) {
+                Err(ParseColorError::InvalidLength { len: found }) => {
+                    assert_eq!(found, len, "{:?}", hex)
+                }
+                other => panic!("{:?} parsed as {:?}", hex, other)
+            }
+        }
+        assert_eq!(invalid_hex("#ggffff"), "#ggffff");
+    }
+
+    #[test]
+    fn non_ascii_is_not_hex() {
+        // Six or eight bytes long, so these used to get past the length
+        // check and split a character.
+        let cases = [
+            "ffffé",
+            "#ff€f",
+            "😀😀",
+            "#ffff😀",
+            "ffffffé",
+            // Not six or eight bytes, but not hex either.
+            "é€",
+            "#１２３４５６",
+            "ff\u{0301}0000"
+        ];
+        for hex in cases {
+            assert_eq!(invalid_hex(hex), hex);
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! `config.watch`: reloads the colors when a config file changes, so a
+//! theme can be tweaked over SSH without sending SIGHUP each time. The
+//! watcher thread only asks for the reload; the greeter loop does it on
+//! its next tick, as it does for the signal.
+
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant}
+};
+
+use log::{info, warn};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor};
+
+/// At most one reload this often, however many writes an editor makes.
+const DEBOUNCE: Duration = Duration::from_secs(1);
+
+/// Starts watching `files`. Watching their directories rather than the
+/// files themselves catches editors that save by renaming over them, and
+/// files that don't exist yet. Failures only cost the watching.
+pub fn start(files: &[PathBuf]) {
+    let inotify = match Inotify::init(InitFlags::IN_CLOEXEC) {
+        Ok(inotify) => inotify,
+        Err(e) => {
+            warn!("Unable to watch the config files: {e}");
+            return;
+        }
+    };
+    let flags = AddWatchFlags::IN_CLOSE_WRITE
+        | AddWatchFlags::IN_MOVED_TO
+        | AddWatchFlags::IN_CREATE
+        | AddWatchFlags::IN_DELETE;
+
+    let mut watched: HashMap<WatchDescriptor, Vec<OsString>> = HashMap::new();
+    for file in files {
+        let (dir, name) = match (file.parent(), file.file_name()) {
+            (Some(dir), Some(name)) => (dir, name),
+            _ => continue
+        };
+        if !dir.is_dir() {
+            continue;
+        }
+        match inotify.add_watch(dir, flags) {
+            Ok(wd) => watched.entry(wd).or_default().push(name.to_owned()),
+            Err(e) => warn!("Unable to watch {}: {e}", dir.display())
+        }
+    }
+    if watched.is_empty() {
+        return;
+    }
+    info!("Watching {} config directories for changes", watched.len());
+
+    let spawned = thread::Builder::new()
+        .name("config-watch".to_string())
+        .spawn(move || watch(&inotify, &watched));
+    if let Err(e) = spawned {
+        warn!("Unable to start watching the config files: {e}");
+    }
+}
+
+fn watch(
+    inotify: &Inotify,
+    watched: &HashMap<WatchDescriptor, Vec<OsString>>
+) {
+    loop {
+        let events = match inotify.read_events() {
+            Ok(events) => events,
+            Err(e) => {
+                warn!("Stopped watching the config files: {e}");
+                return;
+            }
+        };
+        let changed = events.iter().any(|event| {
+            let names = watched.get(&event.wd);
+            match (names, &event.name) {
+                (Some(names), Some(name)) => names.contains(name),
+                _ => false
+            }
+        });
+        if changed {
+            crate::signals::request_reload();
+            // Writes in the meantime queue up and come back as one batch.
+            thread::sleep(DEBOUNCE);
+        }
+    }
+}
+
+impl crate::LoginManager<'_> {
+    /// Loads the config files again and switches to their colors. Other
+    /// settings need a restart. Returns true if the colors changed and
+    /// everything needs drawing again.
+    pub(crate) fn reload_config(&mut self) -> bool {
+        let colors = crate::settings::Settings::load()
+            .map_err(|e| e.to_string())
+            .and_then(|s| s.resolve_colors().map_err(|e| e.to_string()));
+        let mut colors = match colors {
+            Ok(colors) => colors,
+            Err(e) => {
+                warn!("Not reloading the config: {e}");
+                self.config_warning = Some((
+                    format!("config error: {e} - keeping the old colors"),
+                    Instant::now() + crate::CONFIG_WARNING_TIME
+                ));
+                return true;
+            }
+        };
+        // There's nothing under the background to blend it with.
+        colors.background = colors.background.with_opacity(1.0);
+        if colors == self.base_colors {
+            info!("Reloaded the config; the colors are the same");
+            return false;
+        }
+        info!("Reloaded the config with new colors");
+        self.base_colors = colors;
+        self.colors = match self.night_window {
+            Some(window) if self.night_active => {
+                colors.warmed(window.strength)
+            }
+            _ => colors
+        };
+        true
+    }
+}
@@ -0,0 +1,308 @@
+use std::{
+    fs,
+    io::{self, Stdout, Write},
+    os::unix::io::RawFd,
+    panic,
+    sync::OnceLock,
+    thread,
+    time::Duration
+};
+
+use log::{debug, info, warn};
+use nix::sys::termios::{self, SetArg, Termios};
+use termion::raw::{IntoRawMode, RawTerminal};
+use thiserror::Error;
+
+use crate::{ioctls, signals};
+
+// Hide the cursor, and have terminals that support it mark pastes so
+// they can't be mistaken for typing.
+const ENTER_SEQUENCES: &[u8] = b"\x1b[?25l\x1b[?2004h";
+const LEAVE_SEQUENCES: &[u8] = b"\x1b[?2004l\x1b[?25h";
+// PIT input frequency, KIOCSOUND takes a divisor of this.
+const PIT_TICK_RATE: u32 = 1_193_180;
+
+const PRINTK_PATH: &str = "/proc/sys/kernel/printk";
+
+/// What to put back if the greeter dies without dropping its
+/// `ConsoleGuard`. Set once, then only read, so a signal handler can use
+/// it.
+static SAVED: OnceLock<Restore> = OnceLock::new();
+/// Only emergencies reach the console at this level.
+const QUIET_LOGLEVEL: nix::libc::c_int = 1;
+
+/// Starts a tone at `freq_hz` on the console speaker, or stops it when 0.
+pub fn kiocsound(fd: RawFd, freq_hz: u32) -> io::Result<()> {
+    let count = if freq_hz == 0 {
+        0
+    } else {
+        PIT_TICK_RATE / freq_hz.clamp(20, PIT_TICK_RATE)
+    };
+    ioctls::kiocsound(fd, count as nix::libc::c_int)
+}
+
+/// The current `console_loglevel`: the first of the four numbers in
+/// /proc/sys/kernel/printk.
+fn parse_console_loglevel(printk: &str) -> Option<nix::libc::c_int> {
+    printk.split_whitespace().next()?.parse().ok()
+}
+
+/// Stops the kernel printing its log over the form. Returns the level to
+/// put back, or None if it was left alone.
+fn quiet_console() -> Option<nix::libc::c_int> {
+    let printk = match fs::read_to_string(PRINTK_PATH) {
+        Ok(printk) => printk,
+        Err(e) => {
+            warn!("Unable to read {PRINTK_PATH}: {e}");
+            return None;
+        }
+    };
+    let level = match parse_console_loglevel(&printk) {
+        Some(level) => level,
+        None => {
+            warn!("Unable to parse {PRINTK_PATH}: {printk:?}");
+            return None;
+        }
+    };
+    if level <= QUIET_LOGLEVEL {
+        return None;
+    }
+    match ioctls::set_console_loglevel(QUIET_LOGLEVEL) {
+        Ok(()) => {
+            info!("Lowered console_loglevel from {level} to {QUIET_LOGLEVEL}");
+            Some(level)
+        }
+        Err(e) => {
+            warn!("Unable to quiet kernel console messages: {e}");
+            None
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum ConsoleError {
+    #[error("unable to read terminal attributes: {0}")]
+    Termios(#[source] nix::Error),
+
+    #[error("unable to query console mode: {0}")]
+    GetMode(#[source] io::Error),
+
+    #[error("unable to enter raw mode: {0}")]
+    RawMode(#[source] io::Error),
+
+    #[error("unable to enter graphics mode: {0}")]
+    Graphics(#[source] io::Error)
+}
+
+/// Puts the VT into raw graphics mode with the text cursor hidden, and puts
+/// back exactly what was there before (termios, KD mode, cursor, kernel
+/// console loglevel) on drop. Drop also runs when a panic unwinds through
+/// `main`.
+pub struct ConsoleGuard {
+    termios: Termios,
+    kd_mode: nix::libc::c_int,
+    console_loglevel: Option<nix::libc::c_int>,
+    raw: Option<RawTerminal<Stdout>>
+}
+
+impl ConsoleGuard {
+    /// With `quiet`, also keeps kernel messages off the screen.
+    pub fn enter(quiet: bool) -> Result<Self, ConsoleError> {
+        let fd = nix::libc::STDIN_FILENO;
+        let termios = termios::tcgetattr(fd).map_err(ConsoleError::Termios)?;
+        let kd_mode = ioctls::kd_get_mode(fd).map_err(ConsoleError::GetMode)?;
+
+        let raw = io::stdout().into_raw_mode().map_err(ConsoleError::RawMode)?;
+        let mut guard = Self {
+            termios,
+            kd_mode,
+            console_loglevel: None,
+            raw: Some(raw)
+        };
+        if quiet {
+            guard.console_loglevel = quiet_console();
+        }
+
+        ioctls::kd_set_mode(fd, ioctls::KD_GRAPHICS)
+            .map_err(ConsoleError::Graphics)?;
+        if let Some(raw) = guard.raw.as_mut() {
+            let _ = raw.write_all(ENTER_SEQUENCES).and_then(|_| raw.flush());
+        }
+
+        Ok(guard)
+    }
+
+    /// The console state to go back to, for the watchdog.
+    pub fn restore(&self) -> Restore {
+        Restore {
+            termios: self.termios.clone().into(),
+            kd_mode: self.kd_mode,
+            console_loglevel: self.console_loglevel
+        }
+    }
+
+    /// Also puts the console back when the greeter dies without dropping
+    /// the guard: on a panic in the main thread, before the message is
+    /// printed, and on SIGTERM or SIGINT.
+    pub fn restore_on_exit(&self) {
+        if SAVED.set(self.restore()).is_err() {
+            return;
+        }
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if thread::current().name() == Some("main") {
+                restore_saved();
+            }
+            hook(info);
+        }));
+        signals::on_terminate(restore_saved);
+    }
+}
+
+/// Puts back what `restore_on_exit` saved, if anything. Safe to call from
+/// a signal handler.
+fn restore_saved() {
+    if let Some(restore) = SAVED.get() {
+        restore.apply();
+    }
+}
+
+/// What `ConsoleGuard` puts back, for another thread or a signal handler
+/// to apply: raw syscalls on fds that are always open, and no locks or
+/// allocation, so it works whatever the main thread holds.
+#[derive(Clone)]
+pub struct Restore {
+    termios: nix::libc::termios,
+    kd_mode: nix::libc::c_int,
+    console_loglevel: Option<nix::libc::c_int>
+}
+
+impl Restore {
+    pub fn apply(&self) {
+        let fd = nix::libc::STDIN_FILENO;
+        let _ = ioctls::kd_set_mode(fd, self.kd_mode);
+        let termios = Termios::from(self.termios);
+        let _ = termios::tcsetattr(fd, SetArg::TCSANOW, &termios);
+        let _ = nix::unistd::write(nix::libc::STDOUT_FILENO, LEAVE_SEQUENCES);
+        if let Some(level) = self.console_loglevel {
+            let _ = ioctls::set_console_loglevel(level);
+        }
+    }
+}
+
+impl Drop for ConsoleGuard {
+    fn drop(&mut self) {
+        let fd = nix::libc::STDIN_FILENO;
+        if let Err(e) = ioctls::kd_set_mode(fd, self.kd_mode) {
+            warn!("Unable to restore console mode: {e}");
+        }
+
+        // Leaves raw mode; then make sure we end up exactly where we
+        // started, whatever termion thinks that was.
+        drop(self.raw.take());
+        if let Err(e) = termios::tcsetattr(fd, SetArg::TCSADRAIN, &self.termios)
+        {
+            warn!("Unable to restore terminal attributes: {e}");
+        }
+
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(LEAVE_SEQUENCES).and_then(|_| stdout.flush());
+
+        if let Some(level) = self.console_loglevel {
+            if let Err(e) = ioctls::set_console_loglevel(level) {
+                warn!("Unable to restore console_loglevel {level}: {e}");
+            }
+        }
+    }
+}
+
+impl crate::LoginManager<'_> {
+    /// Beeps if `ui.bell` is set. Never fails the greeter.
+    pub(crate) fn beep(&mut self) {
+        if !self.bell {
+            return;
+        }
+
+        if self.bell_frequency_hz == 0 || self.bell_unsupported {
+            let mut stdout = std::io::stdout();
+            let _ = stdout.write_all(b"\x07").and_then(|_| stdout.flush());
+            return;
+        }
+
+        let fd = nix::libc::STDOUT_FILENO;
+        if let Err(e) = kiocsound(fd, self.bell_frequency_hz) {
+            debug!("KIOCSOUND not supported on this console, using BEL: {e}");
+            self.bell_unsupported = true;
+            self.beep();
+            return;
+        }
+
+        let duration = Duration::from_millis(self.bell_duration_ms as u64);
+        thread::spawn(move || {
+            thread::sleep(duration);
+            let _ = kiocsound(fd, 0);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn console_loglevel_is_the_first_printk_number() {
+        let cases = [
+            ("4\t4\t1\t7\n", Some(4)),
+            ("7 4 1 7", Some(7)),
+            ("  15   4 1 7\n", Some(15)),
+            ("1", Some(1)),
+            ("", None),
+            ("\n", None),
+            ("x 4 1 7", None),
+            ("4.5 4 1 7", None),
+            ("99999999999 4 1 7", None)
+        ];
+        for (printk, level) in cases {
+            assert_eq!(parse_console_loglevel(printk), level, "{:?}", printk);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "vt-tests"))]
+mod vt_tests {
+    use std::{mem, slice};
+
+    use super::*;
+
+    /// The terminal attributes of stdin, as bytes. Read into zeroed
+    /// memory so padding compares equal too.
+    fn termios_bytes() -> Vec<u8> {
+        let mut raw: nix::libc::termios = unsafe { mem::zeroed() };
+        let fd = nix::libc::STDIN_FILENO;
+        let res = unsafe { nix::libc::tcgetattr(fd, &mut raw) };
+        assert_eq!(res, 0, "stdin must be a VT");
+        let size = mem::size_of::<nix::libc::termios>();
+        let ptr = &raw as *const nix::libc::termios as *const u8;
+        unsafe { slice::from_raw_parts(ptr, size) }.to_vec()
+    }
+
+    #[test]
+    fn termios_is_restored_byte_for_byte() {
+        let before = termios_bytes();
+        let guard = ConsoleGuard::enter(false).unwrap();
+        assert_ne!(termios_bytes(), before, "raw mode changes termios");
+        drop(guard);
+        assert_eq!(termios_bytes(), before);
+    }
+
+    #[test]
+    fn restore_puts_back_termios_byte_for_byte() {
+        let before = termios_bytes();
+        let guard = ConsoleGuard::enter(false).unwrap();
+        // As the signal path and the watchdog do, without the drop.
+        guard.restore().apply();
+        assert_eq!(termios_bytes(), before);
+        drop(guard);
+    }
+}
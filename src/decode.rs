@@ -0,0 +1,537 @@
+//! Turns bytes from the terminal into keypresses and pastes.
+
+use std::time::Duration;
+
+use crate::key::Key;
+
+/// How long the rest of an escape sequence may take to arrive, possibly
+/// in a later read, before what came so far is given up on. A lone ESC
+/// after this long is the Escape key.
+pub const ESC_TIMEOUT: Duration = Duration::from_millis(50);
+/// How long a bracketed paste may go without a byte before it's taken as
+/// finished. A terminal sends a paste in one go; a serial line's noise
+/// can send a start marker with no end marker ever coming, which would
+/// otherwise swallow every key typed after it.
+pub const PASTE_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// Pasted bytes kept; the rest of a huge paste is read and dropped.
+const PASTE_CAP: usize = 4096;
+const PASTE_END: &[u8] = b"\x1b[201~";
+/// Most CSI parameter bytes kept; longer sequences are read to the end
+/// and dropped as `Key::Unknown`.
+const CSI_CAP: usize = 16;
+
+/// What a read from the terminal produced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// A keypress.
+    Key(Key),
+    /// A bracketed paste, with control characters dropped.
+    Paste(String),
+    /// A character started repeating too fast and is being dropped. Only
+    /// sent the first time.
+    RateLimited,
+    /// A character kept repeating too fast; input is ignored for a moment.
+    StuckKey
+}
+
+/// Where `Decoder` is in an escape sequence or character.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum EscState {
+    Idle,
+    /// After ESC.
+    Esc,
+    /// After ESC [, with the parameter and intermediate bytes so far.
+    Csi(Vec<u8>),
+    /// After ESC [ [, the Linux console's F1-F5.
+    LinuxF,
+    /// After ESC O.
+    Ss3,
+    /// In an OSC, DCS, SOS, PM or APC string; true just after an ESC.
+    Str(bool),
+    /// In a bracketed paste, with how much of the end marker matched.
+    Paste(Vec<u8>, usize),
+    /// Partway through a UTF-8 character of `len` bytes.
+    Utf8([u8; 4], usize, usize)
+}
+
+/// Decodes events a byte at a time: UTF-8, the escape sequences the
+/// Linux console and common terminals send, and bracketed pastes. Any
+/// other escape sequence is consumed whole and reported as
+/// `Key::Unknown`, so none of it leaks into a field. A sequence split
+/// across reads decodes the same as one read whole.
+#[derive(Debug)]
+pub struct Decoder {
+    state: EscState
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self {
+            state: EscState::Idle
+        }
+    }
+}
+
+impl Decoder {
+    /// Feeds in the next byte. Returns the event it completed, if any.
+    pub fn push(&mut self, b: u8) -> Option<Event> {
+        let state = std::mem::replace(&mut self.state, EscState::Idle);
+        let key = match state {
+            EscState::Idle => self.start(b)?,
+            EscState::Esc => match b {
+                b'[' => return self.then(EscState::Csi(Vec::new())),
+                b'O' => return self.then(EscState::Ss3),
+                // OSC, DCS, SOS, PM and APC all run to a string
+                // terminator.
+                b']' | b'P' | b'X' | b'^' | b'_' => {
+                    return self.then(EscState::Str(false))
+                }
+                // Escape pressed twice.
+                0x1B => {
+                    self.state = EscState::Esc;
+                    Key::Escape
+                }
+                _ => Key::Unknown
+            },
+            EscState::Csi(params) if params.is_empty() && b == b'[' => {
+                return self.then(EscState::LinuxF)
+            }
+            // Parameter and intermediate bytes, then one final byte.
+            EscState::Csi(mut params) if (0x20..=0x3F).contains(&b) => {
+                if params.len() <= CSI_CAP {
+                    params.push(b);
+                }
+                return self.then(EscState::Csi(params));
+            }
+            EscState::Csi(params) => match csi_key(&params, b) {
+                Some(key) => key,
+                None => return self.then(EscState::Paste(Vec::new(), 0))
+            },
+            EscState::LinuxF => match b {
+                c @ b'A'..=b'E' => Key::F(c - b'A' + 1),
+                _ => Key::Unknown
+            },
+            EscState::Ss3 => match b {
+                c @ b'P'..=b'S' => Key::F(c - b'P' + 1),
+                b'H' => Key::Home,
+                b'F' => Key::End,
+                _ => Key::Unknown
+            },
+            // BEL or ESC \ (ST) ends it; the byte after ESC is skipped.
+            EscState::Str(true) => Key::Unknown,
+            EscState::Str(false) => match b {
+                0x07 => Key::Unknown,
+                _ => return self.then(EscState::Str(b == 0x1B))
+            },
+            EscState::Paste(bytes, matched) => {
+                return self.paste(bytes, matched, b)
+            }
+            EscState::Utf8(mut bytes, len, got) => {
+                bytes[got] = b;
+                if got + 1 < len {
+                    return self.then(EscState::Utf8(bytes, len, got + 1));
+                }
+                utf8_key(&bytes[..len])
+            }
+        };
+        Some(Event::Key(key))
+    }
+
+    /// A byte with nothing in progress.
+    fn start(&mut self, b: u8) -> Option<Key> {
+        let key = match b {
+            b'\r' => Key::Enter,
+            b'\t' => Key::Tab,
+            0x7F => Key::Backspace,
+            0x1B => return self.then(EscState::Esc),
+            0x01..=0x1A => Key::Ctrl((b'a' + b - 1) as char),
+            0x00..=0x1F => Key::Unknown,
+            0xC0..=0xDF => return self.then(EscState::Utf8([b, 0, 0, 0], 2, 1)),
+            0xE0..=0xEF => return self.then(EscState::Utf8([b, 0, 0, 0], 3, 1)),
+            0xF0..=0xF7 => return self.then(EscState::Utf8([b, 0, 0, 0], 4, 1)),
+            0x80..=0xFF => Key::Unknown,
+            _ => Key::Char(b as char)
+        };
+        Some(key)
+    }
+
+    /// Moves on to `state`, with no event yet.
+    fn then<T>(&mut self, state: EscState) -> Option<T> {
+        self.state = state;
+        None
+    }
+
+    /// A byte of a bracketed paste, which runs through ESC [ 201 ~.
+    fn paste(
+        &mut self,
+        mut bytes: Vec<u8>,
+        matched: usize,
+        b: u8
+    ) -> Option<Event> {
+        let keep = |bytes: &mut Vec<u8>, b: &[u8]| {
+            let room = PASTE_CAP.saturating_sub(bytes.len());
+            bytes.extend_from_slice(&b[..b.len().min(room)]);
+        };
+        let matched = if b == PASTE_END[matched] {
+            matched + 1
+        } else {
+            // Not the end marker after all; what was held back is
+            // content.
+            keep(&mut bytes, &PASTE_END[..matched]);
+            if b == PASTE_END[0] {
+                1
+            } else {
+                keep(&mut bytes, &[b]);
+                0
+            }
+        };
+        if matched < PASTE_END.len() {
+            return self.then(EscState::Paste(bytes, matched));
+        }
+        Some(Event::Paste(paste_text(&bytes)))
+    }
+
+    /// How long the rest of the sequence in progress may take to arrive
+    /// before `time_out` should be called, if one is in progress.
+    pub fn timeout(&self) -> Option<Duration> {
+        match self.state {
+            EscState::Idle => None,
+            EscState::Paste(..) => Some(PASTE_TIMEOUT),
+            _ => Some(ESC_TIMEOUT)
+        }
+    }
+
+    /// Gives up on the sequence in progress: a lone ESC is the Escape key,
+    /// a paste with no end marker is what was pasted so far, and anything
+    /// else is `Key::Unknown`.
+    pub fn time_out(&mut self) -> Event {
+        let key = match std::mem::replace(&mut self.state, EscState::Idle) {
+            EscState::Esc => Key::Escape,
+            EscState::Paste(bytes, _) if !bytes.is_empty() => {
+                return Event::Paste(paste_text(&bytes))
+            }
+            _ => Key::Unknown
+        };
+        Event::Key(key)
+    }
+}
+
+/// A paste's bytes as text, with control characters and invalid UTF-8
+/// dropped.
+fn paste_text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes)
+        .chars()
+        .filter(|&c| !c.is_control() && c != char::REPLACEMENT_CHARACTER)
+        .collect()
+}
+
+/// The key a CSI sequence with `params` and the final byte `b` stands
+/// for, or `None` if it starts a bracketed paste.
+///
+/// Ctrl+Enter only comes as the CSI u sequence `ESC[13;5u` or xterm's
+/// modifyOtherKeys `ESC[27;5;13~`. The Linux console sends neither, and
+/// there Ctrl+Enter is plain Enter.
+fn csi_key(params: &[u8], b: u8) -> Option<Key> {
+    if params.len() > CSI_CAP {
+        return Some(Key::Unknown);
+    }
+    match (params, b) {
+        (b"13;5", b'u') | (b"27;5;13", b'~') => return Some(Key::CtrlEnter),
+        _ => ()
+    }
+    let number = std::str::from_utf8(params)
+        .ok()
+        .and_then(|p| p.parse::<u32>().ok());
+
+    let key = match (params.is_empty(), b) {
+        (true, b'A') => Key::Up,
+        (true, b'B') => Key::Down,
+        (true, b'C') => Key::Right,
+        (true, b'D') => Key::Left,
+        (true, b'H') => Key::Home,
+        (true, b'F') => Key::End,
+        (false, b'~') => match number {
+            Some(200) => return None,
+            Some(1 | 7) => Key::Home,
+            Some(3) => Key::Delete,
+            Some(4 | 8) => Key::End,
+            Some(n @ 11..=15) => Key::F((n - 10) as u8),
+            Some(n @ 17..=21) => Key::F((n - 11) as u8),
+            Some(n @ 23..=24) => Key::F((n - 12) as u8),
+            _ => Key::Unknown
+        },
+        _ => Key::Unknown
+    };
+    Some(key)
+}
+
+fn utf8_key(bytes: &[u8]) -> Key {
+    // C1 controls are no more welcome in a field than C0 ones.
+    std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|s| s.chars().next())
+        .filter(|c| !c.is_control())
+        .map_or(Key::Unknown, Key::Char)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every sequence the decoder knows, with the key it stands for.
+    const SEQUENCES: &[(&[u8], Key)] = &[
+        (b"\x1b[A", Key::Up),
+        (b"\x1b[B", Key::Down),
+        (b"\x1b[C", Key::Right),
+        (b"\x1b[D", Key::Left),
+        (b"\x1b[H", Key::Home),
+        (b"\x1b[F", Key::End),
+        (b"\x1b[1~", Key::Home),
+        (b"\x1b[7~", Key::Home),
+        (b"\x1b[3~", Key::Delete),
+        (b"\x1b[4~", Key::End),
+        (b"\x1b[8~", Key::End),
+        (b"\x1b[13;5u", Key::CtrlEnter),
+        (b"\x1b[27;5;13~", Key::CtrlEnter),
+        (b"\x1b[11~", Key::F(1)),
+        (b"\x1b[15~", Key::F(5)),
+        (b"\x1b[17~", Key::F(6)),
+        (b"\x1b[21~", Key::F(10)),
+        (b"\x1b[23~", Key::F(11)),
+        (b"\x1b[24~", Key::F(12)),
+        (b"\x1b[[A", Key::F(1)),
+        (b"\x1b[[E", Key::F(5)),
+        (b"\x1bOP", Key::F(1)),
+        (b"\x1bOS", Key::F(4)),
+        (b"\x1bOH", Key::Home),
+        (b"\x1bOF", Key::End),
+        ("é".as_bytes(), Key::Char('é')),
+        ("€".as_bytes(), Key::Char('€')),
+        ("🦀".as_bytes(), Key::Char('🦀'))
+    ];
+
+    /// Feeds `chunks` as separate reads, each arriving before the
+    /// timeout, and collects the events.
+    fn decode(chunks: &[&[u8]]) -> Vec<Event> {
+        let mut decoder = Decoder::default();
+        chunks
+            .iter()
+            .flat_map(|chunk| chunk.iter())
+            .filter_map(|&b| decoder.push(b))
+            .collect()
+    }
+
+    #[test]
+    fn every_sequence_split_at_every_boundary() {
+        for &(bytes, key) in SEQUENCES {
+            let whole = decode(&[bytes]);
+            assert_eq!(whole, [Event::Key(key)], "{bytes:?}");
+            for at in 1..bytes.len() {
+                let (head, tail) = bytes.split_at(at);
+                let mut decoder = Decoder::default();
+                for &b in head {
+                    assert_eq!(decoder.push(b), None, "{bytes:?} at {at}");
+                }
+                // The reader waits for the rest rather than giving up.
+                assert!(decoder.timeout().is_some(), "{:?} at {}", bytes, at);
+                let events: Vec<_> =
+                    tail.iter().filter_map(|&b| decoder.push(b)).collect();
+                assert_eq!(events, whole, "{bytes:?} split at {at}");
+                assert_eq!(decoder.timeout(), None);
+            }
+        }
+    }
+
+    #[test]
+    fn sequences_back_to_back() {
+        let mut bytes = Vec::new();
+        let mut keys = Vec::new();
+        for &(seq, key) in SEQUENCES {
+            bytes.extend_from_slice(seq);
+            keys.push(Event::Key(key));
+        }
+        for at in 0..=bytes.len() {
+            let (head, tail) = bytes.split_at(at);
+            assert_eq!(decode(&[head, tail]), keys, "split at {at}");
+        }
+    }
+
+    #[test]
+    fn lone_esc_times_out_as_escape() {
+        let mut decoder = Decoder::default();
+        assert_eq!(decoder.push(0x1B), None);
+        assert_eq!(decoder.timeout(), Some(ESC_TIMEOUT));
+        assert_eq!(decoder.time_out(), Event::Key(Key::Escape));
+        assert_eq!(decoder.timeout(), None);
+        assert_eq!(decoder.push(b'A'), Some(Event::Key(Key::Char('A'))));
+    }
+
+    #[test]
+    fn partial_sequence_times_out_without_leaking() {
+        for partial in [&b"\x1b["[..], b"\x1b[1", b"\x1b[[", b"\x1bO", b"\xe2"]
+        {
+            let mut decoder = Decoder::default();
+            for &b in partial {
+                assert_eq!(decoder.push(b), None);
+            }
+            assert_eq!(decoder.time_out(), Event::Key(Key::Unknown));
+            assert_eq!(decoder.push(b'x'), Some(Event::Key(Key::Char('x'))));
+        }
+    }
+
+    #[test]
+    fn long_csi_is_capped_and_consumed() {
+        let mut bytes = b"\x1b[".to_vec();
+        bytes.extend(std::iter::repeat_n(b'1', 10_000));
+        bytes.extend_from_slice(b"~x");
+        assert_eq!(
+            decode(&[&bytes]),
+            [Event::Key(Key::Unknown), Event::Key(Key::Char('x'))]
+        );
+    }
+
+    #[test]
+    fn unterminated_paste_times_out() {
+        let mut decoder = Decoder::default();
+        for &b in b"\x1b[200~ab" {
+            assert_eq!(decoder.push(b), None);
+        }
+        assert_eq!(decoder.timeout(), Some(PASTE_TIMEOUT));
+        assert_eq!(decoder.time_out(), Event::Paste("ab".to_string()));
+        // Keys typed afterwards are keys again.
+        assert_eq!(decoder.push(b'\r'), Some(Event::Key(Key::Enter)));
+        assert_eq!(decoder.push(0x1B), None);
+        assert_eq!(decoder.time_out(), Event::Key(Key::Escape));
+    }
+
+    #[test]
+    fn empty_unterminated_paste_times_out_as_unknown() {
+        let mut decoder = Decoder::default();
+        for &b in b"\x1b[200~" {
+            assert_eq!(decoder.push(b), None);
+        }
+        assert_eq!(decoder.time_out(), Event::Key(Key::Unknown));
+    }
+
+    #[test]
+    fn paste_is_one_event_without_controls() {
+        assert_eq!(
+            decode(&[b"x\x1b[200~a\tb\r\nc\x7f\x1b[201~y"]),
+            [
+                Event::Key(Key::Char('x')),
+                Event::Paste("abc".to_string()),
+                Event::Key(Key::Char('y'))
+            ]
+        );
+    }
+
+    #[test]
+    fn paste_keeps_a_partial_end_marker_as_content() {
+        assert_eq!(
+            decode(&[b"\x1b[200~a\x1b[20b\x1b\x1b[201~"]),
+            [Event::Paste("a[20b".to_string())]
+        );
+    }
+
+    #[test]
+    fn paste_keeps_escape_sequences_out_of_keys() {
+        // Arrows inside a paste are content, not navigation.
+        assert_eq!(
+            decode(&[b"\x1b[200~\x1b[A\x1b[B\x1b[201~"]),
+            [Event::Paste("[A[B".to_string())]
+        );
+    }
+
+    #[test]
+    fn huge_paste_is_capped() {
+        let mut bytes = b"\x1b[200~".to_vec();
+        bytes.extend(std::iter::repeat_n(b'a', PASTE_CAP * 3));
+        bytes.extend_from_slice(PASTE_END);
+        assert_eq!(decode(&[&bytes]), [Event::Paste("a".repeat(PASTE_CAP))]);
+    }
+
+    #[test]
+    fn other_sequences_are_swallowed_whole() {
+        for seq in [
+            &b"\x1b]0;title\x07"[..],
+            b"\x1b]0;title\x1b\\",
+            b"\x1bPq#0;2;0;0;0\x1b\\",
+            b"\x1b_apc\x07",
+            b"\x1b[?1049h",
+            b"\x1b[38;5;196m",
+            b"\x1b[1;5A",
+            b"\x1b[99~",
+            b"\x1bx"
+        ] {
+            let mut bytes = seq.to_vec();
+            bytes.push(b'z');
+            assert_eq!(
+                decode(&[&bytes]),
+                [Event::Key(Key::Unknown), Event::Key(Key::Char('z'))],
+                "{:?}",
+                seq
+            );
+        }
+    }
+
+    #[test]
+    fn controls_and_bad_utf8() {
+        assert_eq!(
+            decode(&[b"\r\t\x7f\x01\x00\x80\xc2\x85\xff"]),
+            [
+                Event::Key(Key::Enter),
+                Event::Key(Key::Tab),
+                Event::Key(Key::Backspace),
+                Event::Key(Key::Ctrl('a')),
+                Event::Key(Key::Unknown),
+                Event::Key(Key::Unknown),
+                Event::Key(Key::Unknown),
+                Event::Key(Key::Unknown)
+            ]
+        );
+    }
+
+    /// Pseudo-random bytes, weighted towards the ones that start or end
+    /// sequences.
+    fn noise(seed: u64, len: usize) -> Vec<u8> {
+        const INTERESTING: &[u8] = b"\x1b[O]P~0123;\x07\\\r\xc3\xe2\xf0\x80";
+        let mut state = seed.max(1);
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                if state & 3 == 0 {
+                    (state >> 8) as u8
+                } else {
+                    INTERESTING[(state >> 8) as usize % INTERESTING.len()]
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn noise_never_puts_controls_in_a_field() {
+        for seed in 0..200 {
+            let bytes = noise(seed, 2000);
+            let mut decoder = Decoder::default();
+            let mut events: Vec<_> =
+                bytes.iter().filter_map(|&b| decoder.push(b)).collect();
+            if decoder.timeout().is_some() {
+                events.push(decoder.time_out());
+            }
+            assert_eq!(decoder.timeout(), None);
+            for event in events {
+                match event {
+                    Event::Key(Key::Char(c)) => assert!(!c.is_control()),
+                    Event::Paste(text) => {
+                        assert!(text.len() <= PASTE_CAP);
+                        assert!(!text.chars().any(char::is_control));
+                    }
+                    _ => ()
+                }
+            }
+        }
+    }
+}
@@ -1,405 +1,377 @@
-use crate::{
-    buffer::{Buffer, BufferError},
-    color::Color
+use std::{
+    fmt,
+    sync::atomic::{AtomicU8, Ordering}
 };
 
-use cairo::{Context, Format, ImageSurface};
-use pangocairo::functions as pangocairo;
-use thiserror::Error;
-
-#[derive(Error, Debug)]
-#[non_exhaustive]
-pub enum DrawError {
-    #[error("buffer error: {0}")]
-    Buffer(#[from] BufferError),
-
-    #[error("cairo/pango error: {0}")]
-    Render(String)
-}
-
-pub struct Font {
-    desc: pango::FontDescription,
-    size_px: f32
-}
-
-impl Font {
-    pub fn new(desc: &str, size_px: f32) -> Font {
-        let mut font_desc = pango::FontDescription::from_string(desc);
-        // Treat the configured string as a Pango font description, but keep
-        // size controlled by the caller to preserve existing layout.
-        font_desc.set_absolute_size((size_px as f64) * (pango::SCALE as f64));
-        Font {
-            desc: font_desc,
-            size_px
-        }
-    }
-
-    fn render_to_surface_aligned(
-        &self,
-        bg: &Color,
-        fg: &Color,
-        text: &str,
-        width_px: i32,
-        alignment: pango::Alignment
-    ) -> Result<(ImageSurface, i32, i32), DrawError> {
-        let width_px = width_px.max(1);
-
-        let tmp = ImageSurface::create(Format::ARgb32, 1, 1).map_err(|e| {
-            DrawError::Render(format!("failed to create cairo surface: {e:?}"))
-        })?;
-        let tmp_ctx = Context::new(&tmp).map_err(|e| {
-            DrawError::Render(format!("failed to create cairo context: {e:?}"))
-        })?;
-
-        let layout = pangocairo::create_layout(&tmp_ctx);
-        layout.set_font_description(Some(&self.desc));
-        layout.set_text(text);
-        layout.set_width(width_px * pango::SCALE);
-        layout.set_alignment(alignment);
-        let (_w, mut h) = layout.pixel_size();
-        h = h.max(1);
-
-        let surface = ImageSurface::create(Format::ARgb32, width_px, h)
-            .map_err(|e| {
-                DrawError::Render(format!(
-                    "failed to create cairo surface: {e:?}"
-                ))
-            })?;
-        let ctx = Context::new(&surface).map_err(|e| {
-            DrawError::Render(format!("failed to create cairo context: {e:?}"))
-        })?;
-
-        let (br, bgc, bb, ba) = bg.as_rgba_f32();
-        ctx.set_source_rgba(br, bgc, bb, ba);
-        ctx.paint().map_err(|e| {
-            DrawError::Render(format!("failed to paint background: {e:?}"))
-        })?;
-
-        let layout = pangocairo::create_layout(&ctx);
-        layout.set_font_description(Some(&self.desc));
-        layout.set_text(text);
-        layout.set_width(width_px * pango::SCALE);
-        layout.set_alignment(alignment);
-
-        let (fr, fgc, fb, fa) = fg.as_rgba_f32();
-        ctx.set_source_rgba(fr, fgc, fb, fa);
-        pangocairo::show_layout(&ctx, &layout);
-
-        Ok((surface, width_px, h))
-    }
-
-    pub fn auto_draw_text_aligned(
-        &mut self,
-        buf: &mut Buffer<'_>,
-        bg: &Color,
-        c: &Color,
-        s: &str,
-        alignment: pango::Alignment
-    ) -> Result<(u32, u32), DrawError> {
-        let bounds = buf.get_bounds();
-        let width_px = bounds.2 as i32;
-
-        let (mut surface, w, h) =
-            self.render_to_surface_aligned(bg, c, s, width_px, alignment)?;
-        surface.flush();
-
-        let stride = surface.stride() as usize;
-        let data = surface.data().map_err(|e| {
-            DrawError::Render(format!(
-                "failed to access cairo surface data: {e:?}"
-            ))
-        })?;
+use nix::libc;
 
-        let bounds = buf.get_bounds();
-        let max_w = (w as u32).min(bounds.2);
-        let max_h = (h as u32).min(bounds.3);
+use crate::{
+    buffer::Buffer,
+    color::Color,
+    ioctls::{FbActivate, FbDevice}
+};
 
-        for y in 0..max_h {
-            for x in 0..max_w {
-                let off = (y as usize * stride) + (x as usize * 4);
-                if off + 3 >= data.len() {
+/// 8x16 glyphs for printable ASCII (0x20..0x7F), one byte per row, MSB on
+/// the left; capitals sit on row 11 and descenders reach row 14. Used where
+/// pango isn't ready yet, or has failed.
+#[rustfmt::skip]
+const BITMAP_FONT: [[u8; 16]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x00, 0x00, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x08, 0x00, 0x08, 0x18, 0x00, 0x00, 0x00, 0x00], // '!'
+    [0x00, 0x00, 0x34, 0x34, 0x34, 0x24, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '"'
+    [0x00, 0x00, 0x1A, 0x12, 0x12, 0x7F, 0x34, 0x24, 0xFE, 0x6C, 0x68, 0x48, 0x00, 0x00, 0x00, 0x00], // '#'
+    [0x00, 0x00, 0x08, 0x3E, 0x6A, 0x68, 0x68, 0x3C, 0x0E, 0x0B, 0x4A, 0x3C, 0x08, 0x08, 0x00, 0x00], // '$'
+    [0x00, 0x00, 0x20, 0xF0, 0x98, 0xD0, 0x66, 0x38, 0x4F, 0x09, 0x09, 0x0E, 0x00, 0x00, 0x00, 0x00], // '%'
+    [0x00, 0x00, 0x3C, 0x20, 0x20, 0x30, 0x30, 0x59, 0xCD, 0xC7, 0x46, 0x3F, 0x00, 0x00, 0x00, 0x00], // '&'
+    [0x00, 0x00, 0x18, 0x18, 0x18, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '\''
+    [0x00, 0x00, 0x0C, 0x08, 0x18, 0x18, 0x10, 0x10, 0x10, 0x18, 0x18, 0x08, 0x08, 0x04, 0x00, 0x00], // '('
+    [0x00, 0x00, 0x10, 0x10, 0x18, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x18, 0x10, 0x10, 0x00, 0x00], // ')'
+    [0x00, 0x00, 0x08, 0x6A, 0x1C, 0x3C, 0x4A, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '*'
+    [0x00, 0x00, 0x00, 0x00, 0x08, 0x18, 0x18, 0x7F, 0x18, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00], // '+'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x18, 0x18, 0x10, 0x00, 0x00], // ','
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x3C, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '-'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00], // '.'
+    [0x00, 0x00, 0x02, 0x06, 0x04, 0x0C, 0x08, 0x18, 0x10, 0x30, 0x20, 0x60, 0x40, 0x00, 0x00, 0x00], // '/'
+    [0x00, 0x00, 0x3C, 0x66, 0x62, 0x42, 0x5A, 0x5A, 0x42, 0x62, 0x66, 0x3C, 0x00, 0x00, 0x00, 0x00], // '0'
+    [0x00, 0x00, 0x38, 0x28, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x3E, 0x00, 0x00, 0x00, 0x00], // '1'
+    [0x00, 0x00, 0x7C, 0x46, 0x06, 0x06, 0x04, 0x0C, 0x18, 0x30, 0x60, 0x7E, 0x00, 0x00, 0x00, 0x00], // '2'
+    [0x00, 0x00, 0x7C, 0x06, 0x02, 0x06, 0x1C, 0x06, 0x02, 0x02, 0x46, 0x7C, 0x00, 0x00, 0x00, 0x00], // '3'
+    [0x00, 0x00, 0x0C, 0x0C, 0x14, 0x34, 0x24, 0x44, 0x7E, 0x7E, 0x04, 0x04, 0x00, 0x00, 0x00, 0x00], // '4'
+    [0x00, 0x00, 0x7E, 0x60, 0x60, 0x78, 0x7C, 0x06, 0x02, 0x02, 0x06, 0x7C, 0x00, 0x00, 0x00, 0x00], // '5'
+    [0x00, 0x00, 0x1E, 0x20, 0x60, 0x48, 0x7E, 0x62, 0x62, 0x62, 0x66, 0x3C, 0x00, 0x00, 0x00, 0x00], // '6'
+    [0x00, 0x00, 0x7E, 0x06, 0x06, 0x04, 0x0C, 0x08, 0x08, 0x18, 0x10, 0x30, 0x00, 0x00, 0x00, 0x00], // '7'
+    [0x00, 0x00, 0x3C, 0x66, 0x62, 0x66, 0x3C, 0x66, 0x42, 0x42, 0x66, 0x3C, 0x00, 0x00, 0x00, 0x00], // '8'
+    [0x00, 0x00, 0x3C, 0x66, 0x42, 0x42, 0x66, 0x7E, 0x1A, 0x02, 0x06, 0x3C, 0x00, 0x00, 0x00, 0x00], // '9'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x18, 0x00, 0x00, 0x00, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00], // ':'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x18, 0x00, 0x00, 0x00, 0x18, 0x18, 0x18, 0x10, 0x00, 0x00], // ';'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0F, 0x38, 0x60, 0x70, 0x0E, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00], // '<'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x7F, 0x00, 0x7E, 0x7F, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '='
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x70, 0x1C, 0x07, 0x0E, 0x78, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00], // '>'
+    [0x00, 0x00, 0x3C, 0x06, 0x06, 0x06, 0x0C, 0x18, 0x18, 0x00, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00], // '?'
+    [0x00, 0x00, 0x00, 0x3E, 0x63, 0x45, 0xDF, 0x91, 0x91, 0x93, 0xCF, 0x40, 0x20, 0x1E, 0x00, 0x00], // '@'
+    [0x00, 0x00, 0x18, 0x1C, 0x3C, 0x34, 0x24, 0x26, 0x7E, 0x66, 0x43, 0xC3, 0x00, 0x00, 0x00, 0x00], // 'A'
+    [0x00, 0x00, 0x7C, 0x66, 0x62, 0x66, 0x7C, 0x66, 0x63, 0x63, 0x66, 0x7C, 0x00, 0x00, 0x00, 0x00], // 'B'
+    [0x00, 0x00, 0x1E, 0x30, 0x60, 0x60, 0x40, 0x40, 0x60, 0x60, 0x30, 0x1E, 0x00, 0x00, 0x00, 0x00], // 'C'
+    [0x00, 0x00, 0x78, 0x4E, 0x46, 0x42, 0x42, 0x42, 0x42, 0x46, 0x4E, 0x78, 0x00, 0x00, 0x00, 0x00], // 'D'
+    [0x00, 0x00, 0x7E, 0x60, 0x60, 0x60, 0x7E, 0x60, 0x60, 0x60, 0x60, 0x7E, 0x00, 0x00, 0x00, 0x00], // 'E'
+    [0x00, 0x00, 0x7F, 0x60, 0x60, 0x60, 0x7E, 0x60, 0x60, 0x60, 0x60, 0x60, 0x00, 0x00, 0x00, 0x00], // 'F'
+    [0x00, 0x00, 0x3E, 0x22, 0x60, 0x40, 0x40, 0x47, 0x43, 0x63, 0x63, 0x3E, 0x00, 0x00, 0x00, 0x00], // 'G'
+    [0x00, 0x00, 0x42, 0x42, 0x42, 0x42, 0x7E, 0x62, 0x42, 0x42, 0x42, 0x42, 0x00, 0x00, 0x00, 0x00], // 'H'
+    [0x00, 0x00, 0x7E, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x7E, 0x00, 0x00, 0x00, 0x00], // 'I'
+    [0x00, 0x00, 0x3E, 0x06, 0x06, 0x06, 0x06, 0x06, 0x06, 0x04, 0x44, 0x7C, 0x00, 0x00, 0x00, 0x00], // 'J'
+    [0x00, 0x00, 0x43, 0x46, 0x4C, 0x58, 0x78, 0x68, 0x4C, 0x46, 0x42, 0x43, 0x00, 0x00, 0x00, 0x00], // 'K'
+    [0x00, 0x00, 0x60, 0x60, 0x60, 0x60, 0x60, 0x60, 0x60, 0x60, 0x60, 0x7F, 0x00, 0x00, 0x00, 0x00], // 'L'
+    [0x00, 0x00, 0xE3, 0xE7, 0xE7, 0xD7, 0xDB, 0xDB, 0xC3, 0xC3, 0xC3, 0xC3, 0x00, 0x00, 0x00, 0x00], // 'M'
+    [0x00, 0x00, 0x62, 0x62, 0x72, 0x52, 0x5A, 0x4A, 0x4A, 0x4E, 0x46, 0x46, 0x00, 0x00, 0x00, 0x00], // 'N'
+    [0x00, 0x00, 0x3C, 0x66, 0x62, 0x42, 0x43, 0x43, 0x42, 0x62, 0x66, 0x3C, 0x00, 0x00, 0x00, 0x00], // 'O'
+    [0x00, 0x00, 0x7C, 0x66, 0x63, 0x63, 0x66, 0x7C, 0x60, 0x60, 0x60, 0x60, 0x00, 0x00, 0x00, 0x00], // 'P'
+    [0x00, 0x00, 0x3C, 0x66, 0x62, 0x42, 0x43, 0x43, 0x42, 0x62, 0x66, 0x3C, 0x04, 0x02, 0x00, 0x00], // 'Q'
+    [0x00, 0x00, 0x7C, 0x46, 0x42, 0x42, 0x6E, 0x7C, 0x46, 0x42, 0x43, 0x43, 0x00, 0x00, 0x00, 0x00], // 'R'
+    [0x00, 0x00, 0x3E, 0x62, 0x40, 0x60, 0x3C, 0x0E, 0x02, 0x02, 0x46, 0x7C, 0x00, 0x00, 0x00, 0x00], // 'S'
+    [0x00, 0x00, 0xFF, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00], // 'T'
+    [0x00, 0x00, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x66, 0x3C, 0x00, 0x00, 0x00, 0x00], // 'U'
+    [0x00, 0x00, 0xC3, 0x43, 0x62, 0x66, 0x26, 0x24, 0x34, 0x3C, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00], // 'V'
+    [0x00, 0x00, 0x81, 0xC1, 0xC1, 0xDB, 0x5B, 0x5F, 0x76, 0x66, 0x66, 0x66, 0x00, 0x00, 0x00, 0x00], // 'W'
+    [0x00, 0x00, 0x43, 0x66, 0x34, 0x1C, 0x18, 0x1C, 0x34, 0x26, 0x62, 0xC3, 0x00, 0x00, 0x00, 0x00], // 'X'
+    [0x00, 0x00, 0xC3, 0x62, 0x26, 0x34, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00], // 'Y'
+    [0x00, 0x00, 0x7F, 0x02, 0x06, 0x0C, 0x08, 0x18, 0x10, 0x20, 0x60, 0x7F, 0x00, 0x00, 0x00, 0x00], // 'Z'
+    [0x00, 0x0C, 0x1C, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x18, 0x1C, 0x00, 0x00], // '['
+    [0x00, 0x00, 0x40, 0x60, 0x20, 0x30, 0x10, 0x18, 0x08, 0x0C, 0x04, 0x06, 0x02, 0x00, 0x00, 0x00], // '\\'
+    [0x00, 0x18, 0x18, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x38, 0x00, 0x00], // ']'
+    [0x00, 0x00, 0x18, 0x3C, 0x66, 0x42, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '^'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0x00], // '_'
+    [0x00, 0x30, 0x10, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '`'
+    [0x00, 0x00, 0x00, 0x00, 0x38, 0x7E, 0x02, 0x3E, 0x62, 0x42, 0x66, 0x3E, 0x00, 0x00, 0x00, 0x00], // 'a'
+    [0x00, 0x00, 0x60, 0x60, 0x6C, 0x7E, 0x62, 0x62, 0x63, 0x62, 0x66, 0x7C, 0x00, 0x00, 0x00, 0x00], // 'b'
+    [0x00, 0x00, 0x00, 0x00, 0x0C, 0x3E, 0x20, 0x60, 0x60, 0x60, 0x30, 0x1E, 0x00, 0x00, 0x00, 0x00], // 'c'
+    [0x00, 0x02, 0x02, 0x02, 0x1A, 0x7E, 0x66, 0x42, 0x42, 0x42, 0x66, 0x3E, 0x00, 0x00, 0x00, 0x00], // 'd'
+    [0x00, 0x00, 0x00, 0x00, 0x1C, 0x36, 0x62, 0x7F, 0x7E, 0x40, 0x60, 0x3E, 0x00, 0x00, 0x00, 0x00], // 'e'
+    [0x00, 0x06, 0x0E, 0x18, 0x3E, 0x3E, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00], // 'f'
+    [0x00, 0x00, 0x00, 0x00, 0x1A, 0x7E, 0x66, 0x42, 0x42, 0x46, 0x66, 0x3E, 0x02, 0x06, 0x3C, 0x00], // 'g'
+    [0x00, 0x00, 0x60, 0x60, 0x6C, 0x7E, 0x62, 0x62, 0x62, 0x62, 0x62, 0x62, 0x00, 0x00, 0x00, 0x00], // 'h'
+    [0x00, 0x00, 0x08, 0x00, 0x38, 0x38, 0x08, 0x08, 0x08, 0x08, 0x08, 0x7E, 0x00, 0x00, 0x00, 0x00], // 'i'
+    [0x00, 0x08, 0x08, 0x00, 0x38, 0x38, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x18, 0x70, 0x00], // 'j'
+    [0x00, 0x00, 0x60, 0x60, 0x62, 0x66, 0x6C, 0x78, 0x7C, 0x64, 0x66, 0x63, 0x00, 0x00, 0x00, 0x00], // 'k'
+    [0x00, 0x70, 0x70, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x18, 0x0E, 0x00, 0x00, 0x00, 0x00], // 'l'
+    [0x00, 0x00, 0x00, 0x00, 0x76, 0x7E, 0x5B, 0x5B, 0x4B, 0x4B, 0x4B, 0x4B, 0x00, 0x00, 0x00, 0x00], // 'm'
+    [0x00, 0x00, 0x00, 0x00, 0x0C, 0x7E, 0x62, 0x62, 0x62, 0x62, 0x62, 0x62, 0x00, 0x00, 0x00, 0x00], // 'n'
+    [0x00, 0x00, 0x00, 0x00, 0x18, 0x3E, 0x62, 0x42, 0x42, 0x42, 0x66, 0x3C, 0x00, 0x00, 0x00, 0x00], // 'o'
+    [0x00, 0x00, 0x00, 0x00, 0x1C, 0x7E, 0x62, 0x62, 0x63, 0x62, 0x66, 0x7C, 0x60, 0x60, 0x60, 0x00], // 'p'
+    [0x00, 0x00, 0x00, 0x00, 0x1A, 0x3E, 0x66, 0x42, 0x42, 0x42, 0x66, 0x3E, 0x02, 0x02, 0x02, 0x00], // 'q'
+    [0x00, 0x00, 0x00, 0x00, 0x06, 0x3F, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x00, 0x00, 0x00, 0x00], // 'r'
+    [0x00, 0x00, 0x00, 0x00, 0x1C, 0x36, 0x60, 0x30, 0x1C, 0x06, 0x06, 0x7C, 0x00, 0x00, 0x00, 0x00], // 's'
+    [0x00, 0x00, 0x10, 0x10, 0x7E, 0x7E, 0x10, 0x10, 0x10, 0x10, 0x18, 0x0E, 0x00, 0x00, 0x00, 0x00], // 't'
+    [0x00, 0x00, 0x00, 0x00, 0x02, 0x62, 0x62, 0x62, 0x62, 0x62, 0x66, 0x3E, 0x00, 0x00, 0x00, 0x00], // 'u'
+    [0x00, 0x00, 0x00, 0x00, 0x40, 0x42, 0x62, 0x26, 0x24, 0x3C, 0x1C, 0x18, 0x00, 0x00, 0x00, 0x00], // 'v'
+    [0x00, 0x00, 0x00, 0x00, 0x81, 0xC1, 0xC1, 0x5B, 0x5A, 0x76, 0x66, 0x26, 0x00, 0x00, 0x00, 0x00], // 'w'
+    [0x00, 0x00, 0x00, 0x00, 0x42, 0x66, 0x34, 0x18, 0x18, 0x3C, 0x26, 0x42, 0x00, 0x00, 0x00, 0x00], // 'x'
+    [0x00, 0x00, 0x00, 0x00, 0x40, 0x42, 0x62, 0x26, 0x34, 0x1C, 0x1C, 0x18, 0x18, 0x10, 0x60, 0x00], // 'y'
+    [0x00, 0x00, 0x00, 0x00, 0x3E, 0x3E, 0x04, 0x0C, 0x18, 0x30, 0x20, 0x7E, 0x00, 0x00, 0x00, 0x00], // 'z'
+    [0x00, 0x00, 0x0E, 0x08, 0x18, 0x18, 0x18, 0x30, 0x30, 0x18, 0x18, 0x18, 0x08, 0x0E, 0x00, 0x00], // '{'
+    [0x00, 0x00, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x00], // '|'
+    [0x00, 0x00, 0x30, 0x18, 0x18, 0x18, 0x18, 0x0E, 0x0C, 0x18, 0x18, 0x18, 0x18, 0x30, 0x00, 0x00], // '}'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x7F, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '~'
+];
+
+/// Draws `text` centered in `buf` with the built-in bitmap font, each
+/// pixel scaled up to `scale`x`scale`. Anything that isn't printable ASCII
+/// is drawn as '?', and text that doesn't fit is cut off.
+pub fn draw_bitmap_text(
+    buf: &mut Buffer<'_>,
+    fg: &Color,
+    text: &str,
+    scale: u32
+) {
+    let scale = scale.max(1);
+    let (_, _, w, h) = buf.get_bounds();
+    let (glyph_w, glyph_h) = (8 * scale, 16 * scale);
+    let max_chars = (w / glyph_w) as usize;
+    let chars: Vec<char> = text.chars().take(max_chars).collect();
+    let x0 = (w - chars.len() as u32 * glyph_w) / 2;
+    let y0 = h.saturating_sub(glyph_h) / 2;
+
+    for (i, c) in chars.iter().enumerate() {
+        let c = if (' '..='~').contains(c) { *c } else { '?' };
+        let glyph = &BITMAP_FONT[c as usize - 0x20];
+        let gx = x0 + i as u32 * glyph_w;
+        for (row, bits) in (0u32..).zip(glyph) {
+            for col in 0..8 {
+                if bits & (0x80 >> col) == 0 {
                     continue;
                 }
-                let b = data[off];
-                let g = data[off + 1];
-                let r = data[off + 2];
-                let a = data[off + 3];
-                let argb = u32::from_be_bytes([a, r, g, b]);
-                buf.put_argb8888((x, y), argb)?;
+                let x = gx + col * scale;
+                let y = y0 + row * scale;
+                buf.fill_rect((x, y, scale, scale), fg);
             }
         }
-
-        Ok((w as u32, self.size_px.max(h as f32) as u32))
-    }
-
-    pub fn auto_draw_text_centered(
-        &mut self,
-        buf: &mut Buffer<'_>,
-        bg: &Color,
-        c: &Color,
-        s: &str
-    ) -> Result<(u32, u32), DrawError> {
-        self.auto_draw_text_aligned(buf, bg, c, s, pango::Alignment::Center)
     }
 }
 
-impl crate::LoginManager<'_> {
-    pub(crate) fn refresh(&mut self) {
-        if self.should_refresh {
-            self.should_refresh = false;
-            let mut screeninfo = self.var_screen_info.clone();
-            screeninfo.activate |=
-                crate::FB_ACTIVATE_NOW | crate::FB_ACTIVATE_FORCE;
-            if let Err(e) = framebuffer::Framebuffer::put_var_screeninfo(
-                self.device,
-                &screeninfo
-            ) {
-                log::error!("Failed to refresh framebuffer: {e}");
-            }
+/// Fills the screen and shows `text` in the bitmap font, sized to the
+/// screen, with an optional `heading` near the top.
+pub fn draw_splash(
+    buf: &mut Buffer<'_>,
+    bg: &Color,
+    fg: &Color,
+    heading: Option<&str>,
+    text: &str
+) {
+    buf.memset(bg);
+    let (_, _, w, h) = buf.get_bounds();
+    let scale = (h / 270).max(1);
+    if let Some(heading) = heading {
+        if let Ok(mut top) = buf.subdimensions((0, h / 8, w, 16 * scale)) {
+            draw_bitmap_text(&mut top, fg, heading, scale);
         }
     }
+    draw_bitmap_text(buf, fg, text, scale);
+}
 
-    pub(crate) fn clear(&mut self) {
-        let mut buf = crate::buffer::Buffer::new(self.buf, self.screen_size);
-        buf.memset(&self.colors.background);
-        self.should_refresh = true;
-    }
+/// Ways of making the driver show what's been drawn, in the order they're
+/// tried. Once one fails for a reason other than the driver being busy,
+/// the next one is used for the rest of the run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Refresh {
+    /// FBIOPUT_VSCREENINFO with FB_ACTIVATE_FORCE.
+    Force,
+    /// FBIOPUT_VSCREENINFO with just FB_ACTIVATE_NOW.
+    Now,
+    /// FBIOPAN_DISPLAY at the current offsets.
+    Pan,
+    /// Nothing; the writes have to show by themselves.
+    None
+}
 
-    fn draw_underline(
-        row: &mut crate::buffer::Buffer<'_>,
-        row_w: u32,
-        row_h: u32,
-        color: &Color
-    ) {
-        let thickness = 4u32.min(row_h.max(1));
-        let underline_w = (row_w).max(16).min(row_w);
-        let start_x = (row_w.saturating_sub(underline_w)) / 2;
-        let start_y = row_h.saturating_sub(thickness);
+impl Refresh {
+    const ALL: [Self; 4] = [Self::Force, Self::Now, Self::Pan, Self::None];
 
-        for y in start_y..row_h {
-            for x in start_x..start_x.saturating_add(underline_w) {
-                let _ = row.put((x, y), color);
-            }
-        }
+    fn next(self) -> Self {
+        Self::ALL[(self as usize + 1).min(Self::ALL.len() - 1)]
     }
 
-    pub(crate) fn draw_bg(
-        &mut self,
-        box_color: &Color
-    ) -> Result<(), crate::Error> {
-        let layout = self.form_layout();
-        let mut buf = crate::buffer::Buffer::new(self.buf, self.screen_size);
-        let bg = self.colors.background;
-        let fg = self.colors.foreground;
-
-        let form_fill =
-            if box_color.as_argb8888() == self.colors.neutral.as_argb8888() {
-                bg
-            } else {
-                *box_color
-            };
-
-        {
-            let mut form = buf.subdimensions((
-                layout.x,
-                layout.y,
-                layout.w,
-                layout.total_h
-            ))?;
-            form.memset(&form_fill);
-        }
-
-        let hostname = hostname::get()?.to_string_lossy().into_owned();
-
-        self.heading_font.auto_draw_text_centered(
-            &mut buf.offset((0, self.heading_offset_y_px))?,
-            &bg,
-            &fg,
-            &format!("Welcome to {hostname}")
-        )?;
-
-        // Underlines (username/password). Selected field uses selected color.
-        if let Some(y_username) = layout.username_y {
-            let mut row = buf.subdimensions((
-                layout.x,
-                y_username,
-                layout.w,
-                layout.row_h
-            ))?;
-            let c = if self.mode == crate::Mode::EditingUsername {
-                self.colors.selected
-            } else {
-                self.colors.neutral
-            };
-            Self::draw_underline(&mut row, layout.w, layout.row_h, &c);
-        }
-
-        {
-            let mut row = buf.subdimensions((
-                layout.x,
-                layout.password_y,
-                layout.w,
-                layout.row_h
-            ))?;
-            let c = if self.mode == crate::Mode::EditingPassword {
-                self.colors.selected
-            } else {
-                self.colors.neutral
-            };
-            Self::draw_underline(&mut row, layout.w, layout.row_h, &c);
+    fn refresh(
+        self,
+        device: &impl FbDevice,
+        var: &framebuffer::VarScreeninfo
+    ) -> std::io::Result<()> {
+        match self {
+            Self::Force => {
+                device.activate(var, FbActivate::NOW | FbActivate::FORCE)
+            }
+            Self::Now => device.activate(var, FbActivate::NOW),
+            Self::Pan => device.pan(var),
+            Self::None => Ok(())
         }
-
-        self.should_refresh = true;
-
-        Ok(())
     }
+}
 
-    pub(crate) fn draw_target(&mut self) -> Result<(), crate::Error> {
-        let layout = self.form_layout();
-        let y = match layout.session_y {
-            Some(y) => y,
-            None => return Ok(())
-        };
+impl fmt::Display for Refresh {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Force => "forced activate",
+            Self::Now => "activate",
+            Self::Pan => "pan",
+            Self::None => "no ioctl"
+        })
+    }
+}
 
-        let mut buf = crate::buffer::Buffer::new(self.buf, self.screen_size);
-        let mut buf =
-            buf.subdimensions((layout.x, y, layout.w, layout.row_h))?;
-        let bg = self.colors.background;
-        buf.memset(&bg);
+/// The `Refresh` in use, as its index in `Refresh::ALL`.
+static REFRESH: AtomicU8 = AtomicU8::new(0);
 
-        let fg = if self.mode == crate::Mode::SelectingSession {
-            self.colors.selected
-        } else {
-            self.colors.foreground
-        };
+/// Tells the framebuffer driver to show what's been drawn.
+pub fn refresh_screen(
+    device: &std::fs::File,
+    var_screen_info: &framebuffer::VarScreeninfo
+) {
+    refresh_with(device, var_screen_info, &REFRESH);
+}
 
-        let session_name = &self.targets[self.target_index].name;
-        let text = match (
-            self.session_left_arrow.as_str(),
-            self.session_right_arrow.as_str()
-        ) {
-            ("", "") => session_name.to_string(),
-            (l, "") => format!("{l}  {session_name}"),
-            ("", r) => format!("{session_name}  {r}"),
-            (l, r) => format!("{l}  {session_name}  {r}")
+/// `refresh_screen`, starting from the way `current` holds and moving it
+/// on for good when a way doesn't work with this driver.
+fn refresh_with(
+    device: &impl FbDevice,
+    var_screen_info: &framebuffer::VarScreeninfo,
+    current: &AtomicU8
+) {
+    let mut refresh = Refresh::ALL[current.load(Ordering::Relaxed) as usize];
+    loop {
+        let e = match refresh.refresh(device, var_screen_info) {
+            Ok(()) => return,
+            Err(e) => e
         };
+        // The driver was busy; what it shows may be half updated, so draw
+        // everything again at the next tick.
+        let busy = [libc::EBUSY, libc::EAGAIN, libc::EINTR];
+        if e.raw_os_error().is_some_and(|errno| busy.contains(&errno)) {
+            log::error!("Failed to refresh framebuffer: {e}");
+            crate::signals::request_redraw();
+            return;
+        }
+        let next = refresh.next();
+        log::warn!(
+            "Refreshing the framebuffer by {refresh} failed ({e}); using \
+             {next} from now on"
+        );
+        current.store(next as u8, Ordering::Relaxed);
+        refresh = next;
+    }
+}
 
-        self.main_font
-            .auto_draw_text_centered(&mut buf, &bg, &fg, &text)?;
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, io};
 
-        self.should_refresh = true;
+    use super::*;
 
-        Ok(())
+    /// Records the ioctls a refresh makes, failing the ones named in
+    /// `errors` with the errno given.
+    struct FakeDevice {
+        calls: RefCell<Vec<&'static str>>,
+        errors: Vec<(&'static str, i32)>
     }
 
-    pub(crate) fn draw_username(
-        &mut self,
-        username: &str,
-        redraw: bool
-    ) -> Result<(), crate::Error> {
-        let layout = self.form_layout();
-        let y = match layout.username_y {
-            Some(y) => y,
-            None => return Ok(())
-        };
-
-        let mut buf = crate::buffer::Buffer::new(self.buf, self.screen_size);
-        let mut buf =
-            buf.subdimensions((layout.x, y, layout.w, layout.row_h))?;
-        let bg = self.colors.background;
-        if redraw {
-            buf.memset(&bg);
+    impl FakeDevice {
+        fn new(errors: &[(&'static str, i32)]) -> Self {
+            Self {
+                calls: RefCell::new(Vec::new()),
+                errors: errors.to_vec()
+            }
         }
 
-        let fg = if self.mode == crate::Mode::EditingUsername {
-            self.colors.selected
-        } else {
-            self.colors.foreground
-        };
-
-        let align = match self.text_align {
-            crate::settings::TextAlign::Left => pango::Alignment::Left,
-            crate::settings::TextAlign::Center => pango::Alignment::Center,
-            crate::settings::TextAlign::Right => pango::Alignment::Right
-        };
-
-        let margin = self.input_margin_px.min(layout.w / 2);
-        if margin > 0 {
-            let inner_w = layout.w.saturating_sub(margin * 2);
-            let mut inner = buf.subdimensions((margin, 0, inner_w, layout.row_h))?;
-            self.main_font
-                .auto_draw_text_aligned(&mut inner, &bg, &fg, username, align)?;
-        } else {
-            self.main_font
-                .auto_draw_text_aligned(&mut buf, &bg, &fg, username, align)?;
+        fn call(&self, name: &'static str) -> io::Result<()> {
+            self.calls.borrow_mut().push(name);
+            match self.errors.iter().find(|(n, _)| *n == name) {
+                Some((_, errno)) => Err(io::Error::from_raw_os_error(*errno)),
+                None => Ok(())
+            }
         }
 
-        let border = if self.mode == crate::Mode::EditingUsername {
-            self.colors.selected
-        } else {
-            self.colors.neutral
-        };
-        Self::draw_underline(&mut buf, layout.w, layout.row_h, &border);
-
-        self.should_refresh = true;
-
-        Ok(())
+        fn take_calls(&self) -> Vec<&'static str> {
+            self.calls.take()
+        }
     }
 
-    pub(crate) fn draw_password(
-        &mut self,
-        password: &str,
-        redraw: bool
-    ) -> Result<(), crate::Error> {
-        let layout = self.form_layout();
-        let y = layout.password_y;
-
-        let mut buf = crate::buffer::Buffer::new(self.buf, self.screen_size);
-        let mut buf =
-            buf.subdimensions((layout.x, y, layout.w, layout.row_h))?;
-        let bg = self.colors.background;
-        if redraw {
-            buf.memset(&bg);
+    impl FbDevice for FakeDevice {
+        fn activate(
+            &self,
+            _: &framebuffer::VarScreeninfo,
+            flags: FbActivate
+        ) -> io::Result<()> {
+            if flags == FbActivate::NOW | FbActivate::FORCE {
+                self.call("force")
+            } else {
+                self.call("now")
+            }
         }
 
-        let mut stars = String::new();
-        for _ in 0..password.len() {
-            stars.push_str(&self.password_char);
+        fn pan(&self, _: &framebuffer::VarScreeninfo) -> io::Result<()> {
+            self.call("pan")
         }
+    }
 
-        let fg = if self.mode == crate::Mode::EditingPassword {
-            self.colors.selected
-        } else {
-            self.colors.foreground
-        };
+    fn var() -> framebuffer::VarScreeninfo {
+        // Plain integers all the way down.
+        unsafe { std::mem::zeroed() }
+    }
 
-        let align = match self.text_align {
-            crate::settings::TextAlign::Left => pango::Alignment::Left,
-            crate::settings::TextAlign::Center => pango::Alignment::Center,
-            crate::settings::TextAlign::Right => pango::Alignment::Right
-        };
+    #[test]
+    fn forced_activate_comes_first() {
+        let device = FakeDevice::new(&[]);
+        let current = AtomicU8::new(0);
+        refresh_with(&device, &var(), &current);
+        assert_eq!(device.take_calls(), ["force"]);
+        assert_eq!(current.load(Ordering::Relaxed), Refresh::Force as u8);
+    }
 
-        let margin = self.input_margin_px.min(layout.w / 2);
-        if margin > 0 {
-            let inner_w = layout.w.saturating_sub(margin * 2);
-            let mut inner = buf.subdimensions((margin, 0, inner_w, layout.row_h))?;
-            self.main_font
-                .auto_draw_text_aligned(&mut inner, &bg, &fg, &stars, align)?;
-        } else {
-            self.main_font
-                .auto_draw_text_aligned(&mut buf, &bg, &fg, &stars, align)?;
-        }
+    #[test]
+    fn ways_the_driver_lacks_are_dropped_for_good() {
+        let device =
+            FakeDevice::new(&[("force", libc::EINVAL), ("now", libc::ENOTTY)]);
+        let current = AtomicU8::new(0);
+        refresh_with(&device, &var(), &current);
+        assert_eq!(device.take_calls(), ["force", "now", "pan"]);
+        assert_eq!(current.load(Ordering::Relaxed), Refresh::Pan as u8);
+
+        refresh_with(&device, &var(), &current);
+        assert_eq!(device.take_calls(), ["pan"]);
+    }
 
-        // Bottom border under password input.
-        let border = if self.mode == crate::Mode::EditingPassword {
-            self.colors.selected
-        } else {
-            self.colors.neutral
-        };
-        Self::draw_underline(&mut buf, layout.w, layout.row_h, &border);
+    #[test]
+    fn busy_driver_keeps_the_way_and_asks_for_a_redraw() {
+        let device = FakeDevice::new(&[("force", libc::EBUSY)]);
+        let current = AtomicU8::new(0);
+        crate::signals::take_redraw();
+        refresh_with(&device, &var(), &current);
+        assert_eq!(device.take_calls(), ["force"]);
+        assert_eq!(current.load(Ordering::Relaxed), Refresh::Force as u8);
+        assert!(crate::signals::take_redraw());
+    }
 
-        self.should_refresh = true;
+    #[test]
+    fn nothing_working_ends_with_no_ioctl() {
+        let einval = libc::EINVAL;
+        let device = FakeDevice::new(&[
+            ("force", einval),
+            ("now", einval),
+            ("pan", einval)
+        ]);
+        let current = AtomicU8::new(0);
+        refresh_with(&device, &var(), &current);
+        assert_eq!(device.take_calls(), ["force", "now", "pan"]);
+        assert_eq!(current.load(Ordering::Relaxed), Refresh::None as u8);
+
+        refresh_with(&device, &var(), &current);
+        assert!(device.take_calls().is_empty());
+    }
 
-        Ok(())
+    #[test]
+    fn every_glyph_but_space_has_ink() {
+        for (c, glyph) in (' '..='~').zip(&BITMAP_FONT) {
+            let inked = glyph.iter().any(|&row| row != 0);
+            assert_eq!(inked, c != ' ', "glyph for {:?}", c);
+        }
+        let underscore = &BITMAP_FONT[usize::from(b'_' - 0x20)];
+        assert_eq!(underscore[13..15], [0xFF, 0xFF]);
     }
 }
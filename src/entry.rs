@@ -0,0 +1,301 @@
+//! Session desktop entries, as found in /usr/share/wayland-sessions and
+//! /usr/share/xsessions: read with limits on their size, and parsed into
+//! the name, comment, icon and command line a session is shown and
+//! started with.
+
+use std::{
+    fmt, fs,
+    io::Read,
+    path::{Path, PathBuf}
+};
+
+use freedesktop_desktop_entry::DesktopEntry;
+use log::warn;
+
+/// Desktop entries bigger than this are skipped, not read into memory.
+pub const MAX_ENTRY_BYTES: u64 = 64 * 1024;
+
+/// The longest `Exec=` line taken, in bytes.
+pub const MAX_EXEC_BYTES: usize = 4096;
+/// The most arguments `Exec=` may split into.
+pub const MAX_EXEC_ARGS: usize = 256;
+
+/// Which directory a session came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionKind {
+    /// /usr/share/wayland-sessions.
+    Wayland,
+    /// /usr/share/xsessions.
+    X11
+}
+
+impl SessionKind {
+    /// The tag shown after the name in the session row.
+    pub fn tag(self) -> &'static str {
+        match self {
+            SessionKind::Wayland => "wayland",
+            SessionKind::X11 => "x11"
+        }
+    }
+}
+
+/// Why a desktop entry didn't become a session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SkipReason {
+    /// It couldn't be opened or read, or isn't UTF-8.
+    Unreadable(String),
+    /// Not a valid desktop entry.
+    Invalid(String),
+    /// `Hidden=true`, which means deleted.
+    Hidden,
+    /// There's no `Exec=`.
+    NoExec,
+    /// `Exec=` isn't a valid command line.
+    BadExec(String),
+    /// Over `MAX_ENTRY_BYTES`.
+    TooLarge,
+    /// Over `MAX_EXEC_BYTES` or `MAX_EXEC_ARGS`.
+    ExecTooLong
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::Unreadable(e) => write!(f, "unreadable: {e}"),
+            SkipReason::Invalid(e) => write!(f, "parse error: {e}"),
+            SkipReason::Hidden => write!(f, "Hidden"),
+            SkipReason::NoExec => write!(f, "no Exec"),
+            SkipReason::BadExec(e) => write!(f, "bad Exec: {e}"),
+            SkipReason::TooLarge => {
+                write!(f, "over {} KiB", MAX_ENTRY_BYTES / 1024)
+            }
+            SkipReason::ExecTooLong => write!(f, "Exec too long")
+        }
+    }
+}
+
+/// Reads the desktop entry at `path`, unless it's over `MAX_ENTRY_BYTES`.
+pub fn read_entry(path: &Path) -> Result<String, SkipReason> {
+    let unreadable = |e: std::io::Error| SkipReason::Unreadable(e.to_string());
+    let file = fs::File::open(path).map_err(unreadable)?;
+    // A byte past the limit tells a file at it from one over it.
+    let mut data = String::new();
+    file.take(MAX_ENTRY_BYTES + 1)
+        .read_to_string(&mut data)
+        .map_err(unreadable)?;
+    if data.len() as u64 > MAX_ENTRY_BYTES {
+        warn!("Skipping oversized desktop entry {}", path.display());
+        return Err(SkipReason::TooLarge);
+    }
+    Ok(data)
+}
+
+/// The locale to pick localized desktop entry keys with, e.g. "de_DE".
+fn desktop_locale() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|v| !v.is_empty())
+        .map(|v| v.split(['.', '@']).next().unwrap_or_default().to_string())
+        .filter(|v| v != "C" && v != "POSIX" && !v.is_empty())
+}
+
+/// What a session desktop entry says about the session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// `Name=`, or the file name without `.desktop` if that's blank.
+    pub name: String,
+    /// The file it was read from.
+    pub path: PathBuf,
+    /// `Comment=` in the current locale, if it isn't blank.
+    pub comment: Option<String>,
+    /// `Icon=` as written, if it isn't blank.
+    pub icon: Option<String>,
+    /// `Exec=`, split into words.
+    pub exec: Vec<String>,
+    /// Which directory it came from.
+    pub kind: SessionKind
+}
+
+impl Entry {
+    /// Reads and parses the desktop entry at `path`, with `Comment=` in
+    /// the locale from the environment.
+    pub fn load(path: &Path, kind: SessionKind) -> Result<Self, SkipReason> {
+        let data = read_entry(path)?;
+        Self::parse(path, &data, kind, desktop_locale().as_deref())
+    }
+
+    /// Parses `data`, the contents of the desktop entry at `path`, picking
+    /// `Comment=` for `locale` such as "de_DE".
+    pub fn parse(
+        path: &Path,
+        data: &str,
+        kind: SessionKind,
+        locale: Option<&str>
+    ) -> Result<Self, SkipReason> {
+        let entry = DesktopEntry::decode(path, data)
+            .map_err(|e| SkipReason::Invalid(e.to_string()))?;
+        if entry.desktop_entry("Hidden") == Some("true") {
+            return Err(SkipReason::Hidden);
+        }
+        let cmdline = entry.exec().ok_or(SkipReason::NoExec)?;
+        if cmdline.len() > MAX_EXEC_BYTES {
+            warn!("Skipping {path:?}: Exec is {} bytes", cmdline.len());
+            return Err(SkipReason::ExecTooLong);
+        }
+        let exec = shell_words::split(cmdline)
+            .map_err(|e| SkipReason::BadExec(e.to_string()))?;
+        if exec.len() > MAX_EXEC_ARGS {
+            warn!("Skipping {path:?}: Exec has {} arguments", exec.len());
+            return Err(SkipReason::ExecTooLong);
+        }
+
+        let comment = entry
+            .comment(locale)
+            .map(|c| c.trim().to_string())
+            .filter(|c| !c.is_empty());
+        let icon = entry
+            .icon()
+            .map(|i| i.trim().to_string())
+            .filter(|i| !i.is_empty());
+        let name = entry
+            .name(None)
+            .map(|n| n.trim().to_string())
+            .filter(|n| !n.is_empty())
+            .or_else(|| {
+                path.file_stem()
+                    .map(|stem| stem.to_string_lossy().trim().to_string())
+                    .filter(|stem| !stem.is_empty())
+            })
+            .unwrap_or_else(|| entry.appid.to_string());
+
+        Ok(Self {
+            name,
+            path: path.to_path_buf(),
+            comment,
+            icon,
+            exec,
+            kind
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    fn desktop(exec: &str) -> String {
+        format!("[Desktop Entry]\nName=Sway\nType=Application\n{exec}\n")
+    }
+
+    fn parse(data: &str) -> Result<Entry, SkipReason> {
+        let path = Path::new("/usr/share/wayland-sessions/sway.desktop");
+        Entry::parse(path, data, SessionKind::Wayland, None)
+    }
+
+    fn exec(cmdline: &str) -> Result<Vec<String>, SkipReason> {
+        parse(&desktop(&format!("Exec={cmdline}"))).map(|entry| entry.exec)
+    }
+
+    #[test]
+    fn entries_up_to_the_limit_are_read() {
+        let dir = env::temp_dir()
+            .join(format!("mflm-entry-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let limit = MAX_ENTRY_BYTES as usize;
+
+        let at_limit = dir.join("at-limit.desktop");
+        fs::write(&at_limit, "#".repeat(limit)).unwrap();
+        assert_eq!(read_entry(&at_limit).map(|data| data.len()), Ok(limit));
+
+        let over = dir.join("over.desktop");
+        fs::write(&over, "#".repeat(limit + 1)).unwrap();
+        assert_eq!(read_entry(&over), Err(SkipReason::TooLarge));
+
+        let binary = dir.join("binary.desktop");
+        fs::write(&binary, b"[Desktop Entry]\nName=\xff\n").unwrap();
+        assert!(matches!(
+            read_entry(&binary),
+            Err(SkipReason::Unreadable(_))
+        ));
+        assert!(matches!(
+            read_entry(&dir.join("missing.desktop")),
+            Err(SkipReason::Unreadable(_))
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn exec_lines_up_to_the_limits_are_taken() {
+        let longest = "a".repeat(MAX_EXEC_BYTES);
+        assert_eq!(exec(&longest), Ok(vec![longest.clone()]));
+        let most = vec!["a"; MAX_EXEC_ARGS].join(" ");
+        assert_eq!(exec(&most).map(|args| args.len()), Ok(MAX_EXEC_ARGS));
+    }
+
+    #[test]
+    fn exec_lines_over_the_limits_are_skipped() {
+        let too_long = "a".repeat(MAX_EXEC_BYTES + 1);
+        assert_eq!(exec(&too_long), Err(SkipReason::ExecTooLong));
+        let too_many = vec!["a"; MAX_EXEC_ARGS + 1].join(" ");
+        assert_eq!(exec(&too_many), Err(SkipReason::ExecTooLong));
+        // Bytes, not characters.
+        let wide = "é".repeat(MAX_EXEC_BYTES / 2 + 1);
+        assert_eq!(exec(&wide), Err(SkipReason::ExecTooLong));
+    }
+
+    #[test]
+    fn exec_is_split_like_a_shell_would() {
+        assert_eq!(
+            exec("sh -c \"exec sway --unsupported-gpu\""),
+            Ok(vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                "exec sway --unsupported-gpu".to_string()
+            ])
+        );
+        assert!(matches!(exec("sway \"oops"), Err(SkipReason::BadExec(_))));
+    }
+
+    #[test]
+    fn hidden_and_commandless_entries_are_skipped() {
+        assert_eq!(parse(&desktop("Hidden=true")), Err(SkipReason::Hidden));
+        assert_eq!(parse(&desktop("")), Err(SkipReason::NoExec));
+    }
+
+    #[test]
+    fn names_fall_back_to_the_file_name() {
+        let entry = parse("[Desktop Entry]\nName= \nExec=sway\n").unwrap();
+        assert_eq!(entry.name, "sway");
+        assert_eq!(entry.comment, None);
+        assert_eq!(entry.icon, None);
+    }
+
+    #[test]
+    fn comments_follow_the_locale() {
+        let data = "[Desktop Entry]\nName=Sway\nExec=sway\n\
+                    Comment=A tiling compositor\n\
+                    Comment[de]=Ein kachelnder Compositor\n";
+        let path = Path::new("sway.desktop");
+        let comment = |locale| {
+            Entry::parse(path, data, SessionKind::Wayland, locale)
+                .unwrap()
+                .comment
+        };
+        assert_eq!(comment(None).as_deref(), Some("A tiling compositor"));
+        assert_eq!(
+            comment(Some("de_DE")).as_deref(),
+            Some("Ein kachelnder Compositor")
+        );
+    }
+
+    #[test]
+    fn skip_reasons_read_well_in_the_log() {
+        assert_eq!(SkipReason::TooLarge.to_string(), "over 64 KiB");
+        assert_eq!(SkipReason::ExecTooLong.to_string(), "Exec too long");
+        assert_eq!(SkipReason::NoExec.to_string(), "no Exec");
+    }
+}
@@ -0,0 +1,70 @@
+//! Why mflm exited, as its exit status, so greetd setups and supervisors
+//! can tell a login from a crash without reading the log.
+
+use std::process;
+
+use log::info;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    /// A session was started.
+    LoggedIn = 0,
+    /// A `--replay` script didn't load, or an expectation in it failed.
+    Replay = 1,
+    /// The user quit, or stdin closed. With nobody left to type, an
+    /// end of input counts as quitting; so does the end of a replay.
+    Quit = 2,
+    /// The greeter never came up: the framebuffer, the console, greetd or
+    /// the session list.
+    Startup = 3,
+    /// Drawing failed for good, or the watchdog found the loop stuck.
+    Fatal = 4
+}
+
+impl Exit {
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+
+    fn reason(self) -> &'static str {
+        match self {
+            Self::LoggedIn => "session started",
+            Self::Replay => "replay failed",
+            Self::Quit => "quit",
+            Self::Startup => "startup failed",
+            Self::Fatal => "fatal error"
+        }
+    }
+
+    /// Logs the status and exits with it. Nothing is dropped on the way
+    /// out, so anything that restores state must be gone by now.
+    pub fn exit(self) -> ! {
+        info!("Exiting with status {} ({})", self.code(), self.reason());
+        process::exit(self.code())
+    }
+
+    /// Exits with the status straight away: no log line, no atexit
+    /// handlers and no flushing, so it can't wait on a lock that a stuck
+    /// thread holds.
+    pub fn exit_now(self) -> ! {
+        unsafe { nix::libc::_exit(self.code()) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_are_the_documented_ones() {
+        let codes = [
+            Exit::LoggedIn,
+            Exit::Replay,
+            Exit::Quit,
+            Exit::Startup,
+            Exit::Fatal
+        ]
+        .map(Exit::code);
+        assert_eq!(codes, [0, 1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,542 @@
+//! Text rendering with pango, into a `Buffer`.
+
+use std::{
+    os::raw::{c_int, c_void},
+    path::Path
+};
+
+use cairo::{Context, Format, ImageSurface};
+use log::{error, info};
+use pangocairo::functions as pangocairo;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    buffer::{Buffer, BufferError},
+    color::Color
+};
+
+/// Where `TextValign::Baseline` puts the baseline, as a fraction of the
+/// row's height from the top.
+const BASELINE_AT: f32 = 0.7;
+
+/// Why drawing text failed.
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum DrawError {
+    /// The text didn't fit where it was drawn.
+    #[error("buffer error: {0}")]
+    Buffer(#[from] BufferError),
+
+    /// Cairo couldn't make a surface to render the text on.
+    #[error("failed to create cairo surface: {0}")]
+    SurfaceCreate(#[source] cairo::Error),
+
+    /// Cairo couldn't make a context for the surface.
+    #[error("failed to create cairo context: {0}")]
+    ContextCreate(#[source] cairo::Error),
+
+    /// Filling in the background under the text failed.
+    #[error("failed to paint background: {0}")]
+    Paint(#[source] cairo::Error),
+
+    /// The rendered pixels couldn't be read back.
+    #[error("failed to access cairo surface data: {0}")]
+    SurfaceData(#[source] cairo::BorrowError)
+}
+
+/// Where text sits in the height of a row; see `Font::offset_in_row`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextValign {
+    /// At the top of the row.
+    Top,
+    /// The line's full height centered in the row.
+    Center,
+    /// The baseline at the same height in every row, whatever the font.
+    Baseline
+}
+
+impl DrawError {
+    /// False for failures that drawing again may not hit, like the
+    /// surface's data being borrowed elsewhere for a moment.
+    pub fn is_fatal(&self) -> bool {
+        !matches!(self, Self::SurfaceData(cairo::BorrowError::NonExclusive))
+    }
+}
+
+/// A context on a 1x1 surface, for laying out text without drawing it.
+fn scratch_context() -> Result<Context, DrawError> {
+    let tmp = ImageSurface::create(Format::ARgb32, 1, 1)
+        .map_err(DrawError::SurfaceCreate)?;
+    Context::new(&tmp).map_err(DrawError::ContextCreate)
+}
+
+/// A pango font description at a size in pixels, for drawing text into
+/// a `Buffer`.
+pub struct Font {
+    desc: pango::FontDescription,
+    size_px: f32
+}
+
+impl Font {
+    /// The font for a pango description such as "DejaVu Sans Bold", at
+    /// `size_px` whatever size the description gives.
+    pub fn new(desc: &str, size_px: f32) -> Font {
+        let mut font_desc = pango::FontDescription::from_string(desc);
+        // Treat the configured string as a Pango font description, but keep
+        // size controlled by the caller to preserve existing layout.
+        font_desc.set_absolute_size((size_px as f64) * (pango::SCALE as f64));
+        Font {
+            desc: font_desc,
+            size_px
+        }
+    }
+
+    /// Whether every glyph in `text` is covered by this font (or one of its
+    /// fallbacks), i.e. nothing would render as a tofu box.
+    pub fn can_render(&self, text: &str) -> bool {
+        let ctx = ImageSurface::create(Format::ARgb32, 1, 1)
+            .and_then(|surface| Context::new(&surface));
+        let ctx = match ctx {
+            Ok(ctx) => ctx,
+            // Can't tell; don't second-guess the configuration.
+            Err(_) => return true
+        };
+
+        let layout = pangocairo::create_layout(&ctx);
+        layout.set_font_description(Some(&self.desc));
+        layout.set_text(text);
+        layout.unknown_glyphs_count() == 0
+    }
+
+    /// Height of one line of text in this font.
+    pub fn line_height(&self) -> u32 {
+        let ctx = ImageSurface::create(Format::ARgb32, 1, 1)
+            .and_then(|surface| Context::new(&surface));
+        let ctx = match ctx {
+            Ok(ctx) => ctx,
+            Err(_) => return (self.size_px * 1.2) as u32
+        };
+
+        let layout = pangocairo::create_layout(&ctx);
+        layout.set_font_description(Some(&self.desc));
+        layout.set_text("Ag");
+        layout.pixel_size().1.max(1) as u32
+    }
+
+    /// Distance from the top of a line of text to its baseline.
+    pub fn baseline(&self) -> u32 {
+        let ctx = ImageSurface::create(Format::ARgb32, 1, 1)
+            .and_then(|surface| Context::new(&surface));
+        let ctx = match ctx {
+            Ok(ctx) => ctx,
+            Err(_) => return self.size_px as u32
+        };
+
+        let layout = pangocairo::create_layout(&ctx);
+        layout.set_font_description(Some(&self.desc));
+        layout.set_text("Ag");
+        (layout.baseline() / pango::SCALE).max(0) as u32
+    }
+
+    /// How far down a row `row_h` high a line of text in this font starts
+    /// for `valign`. A line that doesn't fit starts at the top and is cut
+    /// off at the bottom.
+    pub fn offset_in_row(&self, row_h: u32, valign: TextValign) -> u32 {
+        let y = match valign {
+            TextValign::Top => 0,
+            TextValign::Center => row_h.saturating_sub(self.line_height()) / 2,
+            TextValign::Baseline => ((row_h as f32 * BASELINE_AT) as u32)
+                .saturating_sub(self.baseline())
+        };
+        y.min(row_h.saturating_sub(1))
+    }
+
+    /// Where `text` starts and how wide it is, laid out the same way
+    /// `auto_draw_text_aligned` would in `width_px`.
+    pub fn text_span(
+        &self,
+        text: &str,
+        width_px: u32,
+        alignment: pango::Alignment
+    ) -> Result<(i32, i32), DrawError> {
+        let ctx = scratch_context()?;
+
+        let layout = self.layout(
+            &ctx,
+            text,
+            width_px.max(1) as i32,
+            alignment,
+            pango::EllipsizeMode::None
+        );
+        let (_, logical) = layout.pixel_extents();
+        Ok((logical.x(), logical.width()))
+    }
+
+    /// Position and height of a text cursor before byte `index` of `text`,
+    /// laid out the same way `auto_draw_text_aligned` would.
+    pub fn caret_rect(
+        &self,
+        text: &str,
+        index: usize,
+        width_px: u32,
+        alignment: pango::Alignment
+    ) -> Result<(i32, i32, i32), DrawError> {
+        let ctx = scratch_context()?;
+
+        let layout = self.layout(
+            &ctx,
+            text,
+            width_px.max(1) as i32,
+            alignment,
+            pango::EllipsizeMode::None
+        );
+
+        // index_to_pos follows bidi reordering, so this is right for RTL
+        // text too.
+        let pos = layout.index_to_pos(index.min(text.len()) as i32);
+        let mut height = pos.height() / pango::SCALE;
+        if height <= 0 {
+            height = self.size_px as i32;
+        }
+        Ok((pos.x() / pango::SCALE, pos.y() / pango::SCALE, height))
+    }
+
+    /// A one-paragraph layout of `text`. The base direction follows the
+    /// text, and pango swaps left/right alignment for RTL paragraphs, so
+    /// `Left` effectively means "start".
+    fn layout(
+        &self,
+        ctx: &Context,
+        text: &str,
+        width_px: i32,
+        alignment: pango::Alignment,
+        ellipsize: pango::EllipsizeMode
+    ) -> pango::Layout {
+        let layout = pangocairo::create_layout(ctx);
+        layout.set_font_description(Some(&self.desc));
+        layout.set_auto_dir(true);
+        layout.set_text(text);
+        layout.set_width(width_px * pango::SCALE);
+        layout.set_alignment(alignment);
+        layout.set_ellipsize(ellipsize);
+        layout
+    }
+
+    fn render_to_surface_aligned(
+        &self,
+        bg: &Color,
+        fg: &Color,
+        text: &str,
+        width_px: i32,
+        alignment: pango::Alignment,
+        ellipsize: pango::EllipsizeMode
+    ) -> Result<(ImageSurface, i32, i32), DrawError> {
+        let width_px = width_px.max(1);
+
+        let tmp_ctx = scratch_context()?;
+
+        let layout =
+            self.layout(&tmp_ctx, text, width_px, alignment, ellipsize);
+        let (_w, mut h) = layout.pixel_size();
+        h = h.max(1);
+
+        let surface = ImageSurface::create(Format::ARgb32, width_px, h)
+            .map_err(DrawError::SurfaceCreate)?;
+        let ctx = Context::new(&surface).map_err(DrawError::ContextCreate)?;
+
+        let (br, bgc, bb, ba) = bg.as_rgba_f32();
+        ctx.set_source_rgba(br, bgc, bb, ba);
+        ctx.paint().map_err(DrawError::Paint)?;
+
+        let layout = self.layout(&ctx, text, width_px, alignment, ellipsize);
+
+        let (fr, fgc, fb, fa) = fg.as_rgba_f32();
+        ctx.set_source_rgba(fr, fgc, fb, fa);
+        pangocairo::show_layout(&ctx, &layout);
+
+        Ok((surface, width_px, h))
+    }
+
+    /// Draws `s` wrapped to the buffer width, cut off below `max_height`
+    /// or the bottom of the buffer, whichever comes first.
+    pub fn auto_draw_text_aligned(
+        &mut self,
+        buf: &mut Buffer<'_>,
+        bg: &Color,
+        c: &Color,
+        s: &str,
+        alignment: pango::Alignment,
+        max_height: u32
+    ) -> Result<(u32, u32), DrawError> {
+        let (_, _, w, h) = buf.get_bounds();
+        let mut buf = buf.subdimensions((0, 0, w, h.min(max_height)))?;
+        self.draw_text(
+            &mut buf,
+            bg,
+            c,
+            s,
+            alignment,
+            pango::EllipsizeMode::None
+        )
+    }
+
+    /// Like `auto_draw_text_aligned`, but keeps the text on one line and
+    /// ellipsizes it at the end when it doesn't fit the buffer width.
+    pub fn auto_draw_text_ellipsized(
+        &mut self,
+        buf: &mut Buffer<'_>,
+        bg: &Color,
+        c: &Color,
+        s: &str,
+        alignment: pango::Alignment
+    ) -> Result<(u32, u32), DrawError> {
+        self.draw_text(buf, bg, c, s, alignment, pango::EllipsizeMode::End)
+    }
+
+    fn draw_text(
+        &mut self,
+        buf: &mut Buffer<'_>,
+        bg: &Color,
+        c: &Color,
+        s: &str,
+        alignment: pango::Alignment,
+        ellipsize: pango::EllipsizeMode
+    ) -> Result<(u32, u32), DrawError> {
+        let bounds = buf.get_bounds();
+        let width_px = bounds.2 as i32;
+
+        let (mut surface, w, h) = self.render_to_surface_aligned(
+            bg, c, s, width_px, alignment, ellipsize
+        )?;
+        surface.flush();
+
+        let stride = surface.stride() as usize;
+        let data = surface.data().map_err(DrawError::SurfaceData)?;
+
+        let bounds = buf.get_bounds();
+        let max_w = (w as u32).min(bounds.2);
+        let max_h = (h as u32).min(bounds.3);
+
+        // A translucent background goes over what's already there.
+        let blend = !bg.is_opaque();
+        let mut row = Vec::with_capacity(max_w as usize);
+        for y in 0..max_h {
+            let off = y as usize * stride;
+            let src = match data.get(off..off + max_w as usize * 4) {
+                Some(src) => src,
+                None => break
+            };
+            // Cairo's ARGB32 is BGRA in memory on little-endian machines.
+            row.clear();
+            row.extend(src.chunks_exact(4).map(|p| {
+                u32::from_be_bytes([p[3], p[2], p[1], p[0]])
+            }));
+            if blend {
+                buf.blend_row_argb8888((0, y), &row)?;
+            } else {
+                buf.put_row_argb8888((0, y), &row)?;
+            }
+        }
+
+        Ok((w as u32, self.size_px.max(h as f32) as u32))
+    }
+
+    /// Draws `s` centered, wrapped to the buffer width and cut off at its
+    /// bottom.
+    pub fn auto_draw_text_centered(
+        &mut self,
+        buf: &mut Buffer<'_>,
+        bg: &Color,
+        c: &Color,
+        s: &str
+    ) -> Result<(u32, u32), DrawError> {
+        let max_height = buf.get_bounds().3;
+        self.auto_draw_text_aligned(
+            buf,
+            bg,
+            c,
+            s,
+            pango::Alignment::Center,
+            max_height
+        )
+    }
+}
+
+/// Loads `desc` through fontconfig once, so the cost of building font
+/// caches on a cold boot is paid here rather than on the first real draw.
+pub fn warm_up_font(desc: &str) {
+    Font::new(desc, 16.0).can_render("A");
+}
+
+// fontconfig is already linked in through pango; these are the two calls
+// pango doesn't expose.
+#[allow(dead_code)]
+#[repr(C)]
+struct FcFontSet {
+    nfont: c_int,
+    sfont: c_int,
+    fonts: *mut *mut c_void
+}
+
+const FC_SET_SYSTEM: c_int = 0;
+
+#[link(name = "fontconfig")]
+extern "C" {
+    fn FcConfigGetFonts(config: *mut c_void, set: c_int) -> *mut FcFontSet;
+    #[cfg(feature = "embedded-font")]
+    fn FcConfigAppFontAddFile(
+        config: *mut c_void,
+        file: *const std::os::raw::c_char
+    ) -> c_int;
+}
+
+/// How many fonts fontconfig found installed.
+fn system_font_count() -> usize {
+    // A null config means the current one, loaded on first use.
+    let set = unsafe { FcConfigGetFonts(std::ptr::null_mut(), FC_SET_SYSTEM) };
+    if set.is_null() {
+        return 0;
+    }
+    unsafe { (*set).nfont.max(0) as usize }
+}
+
+#[cfg(feature = "embedded-font")]
+const EMBEDDED_FONT: &[u8] = include_bytes!("../assets/DejaVuSansMono.ttf");
+
+/// Writes the embedded font out and adds it to fontconfig's application
+/// fonts.
+#[cfg(feature = "embedded-font")]
+fn add_embedded_font(dir: &Path) -> Result<(), String> {
+    use std::{ffi::CString, fs, os::unix::ffi::OsStrExt};
+
+    let path = dir.join("DejaVuSansMono.ttf");
+    let current = fs::read(&path).ok();
+    if current.as_deref() != Some(EMBEDDED_FONT) {
+        fs::create_dir_all(dir)
+            .and_then(|_| fs::write(&path, EMBEDDED_FONT))
+            .map_err(|e| format!("unable to write {}: {e}", path.display()))?;
+    }
+
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| e.to_string())?;
+    let config = std::ptr::null_mut();
+    let added = unsafe { FcConfigAppFontAddFile(config, c_path.as_ptr()) };
+    if added == 0 {
+        return Err(format!("fontconfig rejected {}", path.display()));
+    }
+    log::warn!(
+        "No fonts installed; using the embedded font from {}",
+        path.display()
+    );
+    Ok(())
+}
+
+/// Makes sure pango has at least one font to render with, writing the
+/// embedded font to `font_dir` if there are none. Must run before
+/// anything else touches pango, which caches what fontconfig has.
+pub fn ensure_fonts(font_dir: &Path) {
+    let count = system_font_count();
+    if count > 0 {
+        info!("fontconfig found {count} fonts");
+        return;
+    }
+
+    #[cfg(feature = "embedded-font")]
+    if let Err(e) = add_embedded_font(font_dir) {
+        error!("No fonts installed, and unable to add the embedded font: {e}");
+    }
+    #[cfg(not(feature = "embedded-font"))]
+    {
+        let _ = font_dir;
+        error!("No fonts installed, so text will not render");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROW_H: u32 = 72;
+
+    fn font() -> Font {
+        Font::new("DejaVu Sans", 32.0)
+    }
+
+    #[test]
+    fn top_is_the_top_of_the_row() {
+        assert_eq!(font().offset_in_row(ROW_H, TextValign::Top), 0);
+    }
+
+    #[test]
+    fn center_leaves_as_much_room_above_as_below() {
+        let font = font();
+        let line_h = font.line_height();
+        let y = font.offset_in_row(ROW_H, TextValign::Center);
+        assert!(y > 0);
+        let below = ROW_H - y - line_h;
+        assert!(below == y || below == y + 1, "{} {}", y, below);
+    }
+
+    #[test]
+    fn baseline_goes_most_of_the_way_down() {
+        let font = font();
+        let y = font.offset_in_row(ROW_H, TextValign::Baseline);
+        // 0.7 of 72 is 50.4.
+        assert_eq!(y + font.baseline(), 50);
+    }
+
+    #[test]
+    fn text_too_tall_for_the_row_starts_at_the_top() {
+        let font = font();
+        assert!(font.line_height() > 10);
+        let valigns =
+            [TextValign::Top, TextValign::Center, TextValign::Baseline];
+        for valign in valigns {
+            assert_eq!(font.offset_in_row(10, valign), 0, "{:?}", valign);
+            assert_eq!(font.offset_in_row(0, valign), 0, "{:?}", valign);
+        }
+    }
+
+    /// The lowest pixel row `font` inks drawing "H" in a row aligned to
+    /// `valign`.
+    fn bottom_of_h(font: &mut Font, valign: TextValign) -> usize {
+        let (w, h) = (64, ROW_H);
+        let mut pixels = vec![0; (w * h * 4) as usize];
+        let mut buf = Buffer::new(&mut pixels, (w, h));
+        let bg = Color::from_rgba_u8(0, 0, 0, 255);
+        let fg = Color::from_rgba_u8(255, 255, 255, 255);
+        buf.memset(&bg);
+        let y = font.offset_in_row(h, valign);
+        let mut row = buf.subdimensions((0, y, w, h - y)).unwrap();
+        font.auto_draw_text_ellipsized(
+            &mut row,
+            &bg,
+            &fg,
+            "H",
+            pango::Alignment::Left
+        )
+        .unwrap();
+        // Mostly covered, past any antialiasing.
+        pixels
+            .chunks_exact(w as usize * 4)
+            .rposition(|row| row.chunks_exact(4).any(|px| px[0] > 128))
+            .unwrap()
+    }
+
+    #[test]
+    fn baselines_line_up_across_fonts() {
+        let mut sans = Font::new("DejaVu Sans", 32.0);
+        let mut serif = Font::new("DejaVu Serif Bold", 20.0);
+        assert_eq!(
+            bottom_of_h(&mut sans, TextValign::Baseline),
+            bottom_of_h(&mut serif, TextValign::Baseline)
+        );
+        // Which they don't do centered.
+        assert_ne!(
+            bottom_of_h(&mut sans, TextValign::Center),
+            bottom_of_h(&mut serif, TextValign::Center)
+        );
+    }
+}
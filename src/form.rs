@@ -0,0 +1,698 @@
+//! The login form as a state machine: the username and password fields,
+//! which row has focus, and what submitting does. It draws nothing, so the
+//! framebuffer greeter and the `display.fallback_text` prompts both drive
+//! it, and it can be tested on its own.
+
+use std::time::{Duration, Instant};
+
+use crate::{
+    field::TextField,
+    keys::{Action, Key},
+    settings::{self, SubmitKey},
+    Mode
+};
+
+/// How long the first Enter counts with login.submit_key = "double-enter".
+pub const DOUBLE_ENTER_WINDOW: Duration = Duration::from_secs(2);
+
+/// `login.username`, trimmed, unless it's unset or blank.
+pub fn forced_username(login: &settings::Login) -> Option<String> {
+    login
+        .username
+        .as_deref()
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+}
+
+/// The `[login]` settings the form follows.
+#[derive(Debug, Clone)]
+pub(crate) struct Rules {
+    /// The rows that take focus, top to bottom. Always has the password
+    /// row.
+    pub rows: Vec<Mode>,
+    pub forced_username: Option<String>,
+    pub trim_username: bool,
+    pub clear_username_on_failure: bool,
+    pub allow_empty_password: bool,
+    pub submit_key: SubmitKey,
+    pub numeric_only: bool,
+    pub password_cap: usize,
+    pub retry_delay: Duration
+}
+
+impl Rules {
+    /// The rules for `login` with `rows` on screen. With a single field
+    /// there's nothing to submit by mistake, so plain Enter submits.
+    pub fn new(
+        login: &settings::Login,
+        rows: Vec<Mode>,
+        single_field: bool
+    ) -> Self {
+        Self {
+            rows,
+            forced_username: forced_username(login),
+            trim_username: login.trim_username,
+            clear_username_on_failure: login.clear_username_on_failure,
+            allow_empty_password: login.allow_empty_password,
+            submit_key: if single_field {
+                SubmitKey::Enter
+            } else {
+                login.submit_key
+            },
+            numeric_only: login.numeric_only,
+            password_cap: match login.max_password_len {
+                0 => crate::PASSWORD_CAP,
+                len => len.min(crate::PASSWORD_CAP)
+            },
+            retry_delay: Duration::from_millis(login.retry_delay_ms)
+        }
+    }
+}
+
+/// What typing a character did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Typed {
+    Inserted,
+    /// Focus is on the session row.
+    Ignored,
+    /// The field is full.
+    Full,
+    /// Not a digit, with `login.numeric_only`.
+    NotDigit
+}
+
+/// Why `login.submit_key` held back a submit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Hold {
+    /// Plain Enter with "ctrl-enter".
+    NeedCtrlEnter,
+    /// The first Enter with "double-enter".
+    NeedSecondEnter
+}
+
+/// What a submit did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Submit {
+    /// Moved focus, or stayed put; nothing to send yet.
+    Moved,
+    /// Within `login.retry_delay_ms` of a failure.
+    TooSoon,
+    /// The username has control characters in it; focus is back on it.
+    ControlChars,
+    /// Ready to log in with `credentials`.
+    Ready
+}
+
+#[derive(Debug)]
+pub(crate) struct Form {
+    pub username: TextField,
+    pub password: TextField,
+    pub mode: Mode,
+    /// The username was put there rather than typed, and typing replaces
+    /// it.
+    pub username_prefilled: bool,
+    rules: Rules,
+    /// When the first Enter came with "double-enter".
+    submit_armed: Option<Instant>,
+    /// No login before this, after a failure.
+    retry_at: Option<Instant>
+}
+
+impl Form {
+    /// A form with the forced username filled in, if there is one. With
+    /// `select_username` typing replaces it.
+    pub fn new(rules: Rules, select_username: bool) -> Self {
+        let username = rules
+            .forced_username
+            .clone()
+            .map(TextField::from_text)
+            .unwrap_or_else(|| TextField::with_capacity(crate::USERNAME_CAP));
+        let mode = if rules.rows.contains(&Mode::EditingUsername) {
+            Mode::EditingUsername
+        } else {
+            Mode::EditingPassword
+        };
+        let username_prefilled = select_username
+            && rules.forced_username.is_some()
+            && mode == Mode::EditingUsername;
+        Self {
+            username,
+            password: TextField::with_capacity(crate::PASSWORD_CAP),
+            mode,
+            username_prefilled,
+            rules,
+            submit_armed: None,
+            retry_at: None
+        }
+    }
+
+    pub fn rules(&self) -> &Rules {
+        &self.rules
+    }
+
+    pub fn shows(&self, row: Mode) -> bool {
+        self.rules.rows.contains(&row)
+    }
+
+    /// Changes the rows on screen. Focus on a row that went moves to the
+    /// password row.
+    pub fn set_rows(&mut self, rows: Vec<Mode>) {
+        self.rules.rows = rows;
+        if !self.shows(self.mode) {
+            self.mode = Mode::EditingPassword;
+        }
+    }
+
+    /// The row `step` rows down from `from` (up if negative) in the
+    /// order on screen, wrapping around, and whether it wrapped.
+    pub fn step(&self, from: Mode, step: isize) -> (Mode, bool) {
+        let rows = &self.rules.rows;
+        let len = rows.len() as isize;
+        let pos = match rows.iter().position(|&mode| mode == from) {
+            Some(pos) => pos as isize,
+            None => return (rows[0], false)
+        };
+        let to = pos + step;
+        (rows[to.rem_euclid(len) as usize], !(0..len).contains(&to))
+    }
+
+    /// Moves focus `step` rows, wrapping around. Returns whether it
+    /// wrapped.
+    pub fn move_focus(&mut self, step: isize) -> bool {
+        let (mode, wrapped) = self.step(self.mode, step);
+        self.mode = mode;
+        wrapped
+    }
+
+    /// The username as it will be sent to greetd.
+    pub fn typed_username(&self) -> &str {
+        if self.rules.trim_username {
+            self.username.as_str().trim()
+        } else {
+            self.username.as_str()
+        }
+    }
+
+    /// Whether Enter on the username row can log in straight away: the
+    /// password was typed first, and now the username is there too.
+    pub fn submit_ready(&self) -> bool {
+        self.rules.submit_key == SubmitKey::Enter
+            && !self.password.is_empty()
+            && !self.typed_username().is_empty()
+    }
+
+    /// The text field with focus, if it's one.
+    pub fn field_mut(&mut self) -> Option<&mut TextField> {
+        match self.mode {
+            Mode::SelectingSession => None,
+            Mode::EditingUsername => Some(&mut self.username),
+            Mode::EditingPassword => Some(&mut self.password)
+        }
+    }
+
+    /// Notes a key about to be handled. Editing a username that was put
+    /// there means typing a new one, so the first edit clears it.
+    pub fn before_key(&mut self, key: Key, action: Option<Action>, paste: bool) {
+        if !self.username_prefilled || self.mode != Mode::EditingUsername {
+            return;
+        }
+        let edits = matches!(
+            action,
+            Some(Action::ClearField)
+                | Some(Action::DeleteWord)
+                | Some(Action::CompleteNext)
+                | Some(Action::CompletePrev)
+        ) || (action.is_none()
+            && matches!(key, Key::Char(_) | Key::Backspace | Key::Delete))
+            || paste;
+        if edits {
+            self.username.clear();
+            self.username_prefilled = false;
+        } else if action == Some(Action::Submit) {
+            self.username_prefilled = false;
+        }
+    }
+
+    /// Types `c` into the field with focus.
+    pub fn type_char(&mut self, c: char) -> Typed {
+        let (field, cap) = match self.mode {
+            Mode::SelectingSession => return Typed::Ignored,
+            Mode::EditingUsername => (&mut self.username, crate::USERNAME_CAP),
+            Mode::EditingPassword => {
+                if self.rules.numeric_only && !c.is_ascii_digit() {
+                    return Typed::NotDigit;
+                }
+                (&mut self.password, self.rules.password_cap)
+            }
+        };
+        if field.len() >= cap {
+            return Typed::Full;
+        }
+        field.insert(c);
+        Typed::Inserted
+    }
+
+    /// Types a paste into the field with focus, as text, never as keys.
+    /// Stops at the first character refused, and returns why.
+    pub fn paste(&mut self, text: &str) -> Typed {
+        for c in text.chars() {
+            match self.type_char(c) {
+                Typed::Inserted => (),
+                refused => return refused
+            }
+        }
+        Typed::Inserted
+    }
+
+    /// Deletes the word before the cursor. Word boundaries in a password
+    /// are meaningless, and deleting by word would leak them, so there it
+    /// clears the field.
+    pub fn delete_word(&mut self) {
+        match self.mode {
+            Mode::SelectingSession => (),
+            Mode::EditingUsername => self.username.delete_word(),
+            Mode::EditingPassword => self.password.clear()
+        }
+    }
+
+    /// Applies `login.submit_key` to the action `key` maps to, in the
+    /// password row. Some other action, or the submit itself, passes.
+    /// Ctrl+Enter submits wherever Enter does.
+    pub fn gate(
+        &mut self,
+        key: Key,
+        action: Option<Action>,
+        now: Instant
+    ) -> Result<Option<Action>, Hold> {
+        let action = match (key, action) {
+            (Key::CtrlEnter, None) => Some(Action::Submit),
+            _ => action
+        };
+        if self.mode != Mode::EditingPassword {
+            self.submit_armed = None;
+            return Ok(action);
+        }
+        match self.rules.submit_key {
+            SubmitKey::Enter => Ok(action),
+            SubmitKey::CtrlEnter => match (key, action) {
+                (Key::CtrlEnter, _) => Ok(action),
+                (_, Some(Action::Submit)) => Err(Hold::NeedCtrlEnter),
+                _ => Ok(action)
+            },
+            SubmitKey::DoubleEnter => match action {
+                Some(Action::Submit)
+                    if self.submit_armed.take().is_some_and(|at| {
+                        now.duration_since(at) < DOUBLE_ENTER_WINDOW
+                    }) =>
+                {
+                    Ok(action)
+                }
+                Some(Action::Submit) => {
+                    self.submit_armed = Some(now);
+                    Err(Hold::NeedSecondEnter)
+                }
+                _ => {
+                    self.submit_armed = None;
+                    Ok(action)
+                }
+            }
+        }
+    }
+
+    /// When the first Enter of a double Enter came, if it still counts.
+    pub fn armed_at(&self) -> Option<Instant> {
+        self.submit_armed
+    }
+
+    /// When the first Enter of a double Enter stops counting, if one came.
+    pub fn armed_until(&self) -> Option<Instant> {
+        self.submit_armed.map(|at| at + DOUBLE_ENTER_WINDOW)
+    }
+
+    pub fn disarm(&mut self) {
+        self.submit_armed = None;
+    }
+
+    /// Submits the row with focus: moves on from the session and username
+    /// rows, and checks the form is ready to log in from the password
+    /// row.
+    pub fn submit(&mut self, now: Instant) -> Submit {
+        match self.mode {
+            // On to the next row, or the password row if the session row
+            // is last.
+            Mode::SelectingSession => {
+                let (next, wrapped) = self.step(self.mode, 1);
+                self.mode = if wrapped { Mode::EditingPassword } else { next };
+                Submit::Moved
+            }
+            Mode::EditingUsername => {
+                if self.submit_ready() {
+                    self.mode = Mode::EditingPassword;
+                    return self.submit(now);
+                }
+                if self.typed_username().is_empty() {
+                    self.username.clear();
+                } else {
+                    self.mode = Mode::EditingPassword;
+                }
+                Submit::Moved
+            }
+            Mode::EditingPassword => {
+                let shows_username = self.shows(Mode::EditingUsername);
+                if self.retry_at.is_some_and(|at| now < at) {
+                    Submit::TooSoon
+                } else if self.password.is_empty()
+                    && !self.rules.allow_empty_password
+                {
+                    if shows_username {
+                        self.username.clear();
+                        self.mode = Mode::EditingUsername;
+                    }
+                    Submit::Moved
+                } else if shows_username && self.typed_username().is_empty() {
+                    // The password stays, for Enter on the username row
+                    // to log in with.
+                    self.username.clear();
+                    self.mode = Mode::EditingUsername;
+                    Submit::Moved
+                } else if shows_username
+                    && self.typed_username().chars().any(char::is_control)
+                {
+                    self.mode = Mode::EditingUsername;
+                    Submit::ControlChars
+                } else {
+                    Submit::Ready
+                }
+            }
+        }
+    }
+
+    /// The username and password to log in with, after `Submit::Ready`.
+    /// Takes the password out of the form.
+    pub fn credentials(&mut self) -> (String, String) {
+        let username = if self.shows(Mode::EditingUsername) {
+            self.typed_username().to_string()
+        } else {
+            self.rules
+                .forced_username
+                .clone()
+                .unwrap_or_else(|| self.username.as_str().to_string())
+        };
+        self.username_prefilled = false;
+        (username, self.password.take())
+    }
+
+    /// Resets the form after a login attempt. After a failure, focus goes
+    /// back to the first field, and no login is tried again for
+    /// `login.retry_delay_ms`.
+    pub fn finish_attempt(&mut self, failed: bool, now: Instant) {
+        let shows_username = self.shows(Mode::EditingUsername);
+        if !shows_username {
+            if let Some(name) = &self.rules.forced_username {
+                self.username = TextField::from_text(name.clone());
+            }
+        } else if self.rules.clear_username_on_failure {
+            self.username = TextField::with_capacity(crate::USERNAME_CAP);
+        }
+        self.password = TextField::with_capacity(crate::PASSWORD_CAP);
+        if !failed {
+            return;
+        }
+        self.retry_at = Some(now + self.rules.retry_delay);
+        self.mode = if shows_username {
+            Mode::EditingUsername
+        } else {
+            Mode::EditingPassword
+        };
+        // Keep what was typed on screen so a wrong username is easy to
+        // spot.
+        self.username_prefilled = shows_username && !self.username.is_empty();
+    }
+
+    /// How long until a login may be tried again.
+    pub fn retry_in(&self, now: Instant) -> Duration {
+        self.retry_at
+            .map_or(Duration::ZERO, |at| at.saturating_duration_since(now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn form(submit_key: SubmitKey) -> Form {
+        let login = settings::Login {
+            submit_key,
+            ..Default::default()
+        };
+        let rows = crate::form_layout::DEFAULT_ROW_ORDER.to_vec();
+        Form::new(Rules::new(&login, rows, false), false)
+    }
+
+    fn submit() -> Option<Action> {
+        Some(Action::Submit)
+    }
+
+    #[test]
+    fn enter_submits_straight_away() {
+        let mut f = form(SubmitKey::Enter);
+        f.mode = Mode::EditingPassword;
+        let now = Instant::now();
+        assert_eq!(f.gate(Key::Enter, submit(), now), Ok(submit()));
+        assert_eq!(f.gate(Key::CtrlEnter, None, now), Ok(submit()));
+        assert_eq!(f.armed_at(), None);
+    }
+
+    #[test]
+    fn ctrl_enter_needs_ctrl_enter() {
+        let mut f = form(SubmitKey::CtrlEnter);
+        f.mode = Mode::EditingPassword;
+        let now = Instant::now();
+        assert_eq!(f.gate(Key::Enter, submit(), now), Err(Hold::NeedCtrlEnter));
+        // A bare LF, as a serial console sends, isn't Ctrl+Enter.
+        assert_eq!(f.gate(Key::Ctrl('j'), None, now), Ok(None));
+        assert_eq!(f.gate(Key::CtrlEnter, None, now), Ok(submit()));
+    }
+
+    #[test]
+    fn submit_key_only_guards_the_password_row() {
+        for submit_key in [SubmitKey::CtrlEnter, SubmitKey::DoubleEnter] {
+            let mut f = form(submit_key);
+            let now = Instant::now();
+            assert_eq!(f.gate(Key::Enter, submit(), now), Ok(submit()));
+        }
+    }
+
+    #[test]
+    fn double_enter_within_the_window_submits() {
+        let mut f = form(SubmitKey::DoubleEnter);
+        f.mode = Mode::EditingPassword;
+        let first = Instant::now();
+        assert_eq!(
+            f.gate(Key::Enter, submit(), first),
+            Err(Hold::NeedSecondEnter)
+        );
+        assert_eq!(f.armed_at(), Some(first));
+        assert_eq!(f.armed_until(), Some(first + DOUBLE_ENTER_WINDOW));
+
+        let second = first + DOUBLE_ENTER_WINDOW - Duration::from_millis(1);
+        assert_eq!(f.gate(Key::Enter, submit(), second), Ok(submit()));
+        assert_eq!(f.armed_at(), None);
+    }
+
+    #[test]
+    fn double_enter_times_out() {
+        let mut f = form(SubmitKey::DoubleEnter);
+        f.mode = Mode::EditingPassword;
+        let first = Instant::now();
+        let late = first + DOUBLE_ENTER_WINDOW;
+        assert_eq!(
+            f.gate(Key::Enter, submit(), first),
+            Err(Hold::NeedSecondEnter)
+        );
+        // Too late to count; it starts a new window instead.
+        assert_eq!(
+            f.gate(Key::Enter, submit(), late),
+            Err(Hold::NeedSecondEnter)
+        );
+        assert_eq!(f.armed_at(), Some(late));
+        assert_eq!(f.gate(Key::Enter, submit(), late), Ok(submit()));
+    }
+
+    #[test]
+    fn double_enter_is_disarmed_by_other_keys() {
+        let mut f = form(SubmitKey::DoubleEnter);
+        f.mode = Mode::EditingPassword;
+        let now = Instant::now();
+        let _ = f.gate(Key::Enter, submit(), now);
+        assert_eq!(f.gate(Key::Char('a'), None, now), Ok(None));
+        assert_eq!(f.armed_at(), None);
+        assert_eq!(
+            f.gate(Key::Enter, submit(), now),
+            Err(Hold::NeedSecondEnter)
+        );
+
+        // Leaving the password row disarms it too.
+        f.move_focus(-1);
+        let _ = f.gate(Key::Tab, Some(Action::NextField), now);
+        assert_eq!(f.armed_at(), None);
+    }
+
+    #[test]
+    fn typing_during_a_slow_failed_login_lands_in_the_new_password() {
+        let mut f = form(SubmitKey::Enter);
+        let now = Instant::now();
+        f.paste("alice");
+        f.submit(now);
+        f.paste("wrong");
+        assert_eq!(f.submit(now), Submit::Ready);
+        assert_eq!(
+            f.credentials(),
+            ("alice".to_string(), "wrong".to_string())
+        );
+
+        // The greeter queues what's typed meanwhile, and replays it into
+        // the password row once the failure is in.
+        f.finish_attempt(true, now);
+        assert_eq!(f.username.as_str(), "alice");
+        assert!(f.password.is_empty());
+        f.mode = Mode::EditingPassword;
+        assert_eq!(f.paste("right"), Typed::Inserted);
+        assert_eq!(f.password.as_str(), "right");
+        assert_eq!(f.submit(now), Submit::TooSoon);
+        assert_eq!(f.submit(now + f.rules().retry_delay), Submit::Ready);
+    }
+
+    #[test]
+    fn username_of_only_spaces_is_empty() {
+        let mut f = form(SubmitKey::Enter);
+        let now = Instant::now();
+        f.paste("   ");
+        assert_eq!(f.submit(now), Submit::Moved);
+        assert_eq!(f.mode, Mode::EditingUsername);
+        assert!(f.username.is_empty());
+
+        // From the password row it bounces back, keeping the password.
+        f.paste("  ");
+        f.mode = Mode::EditingPassword;
+        f.paste("secret");
+        assert_eq!(f.submit(now), Submit::Moved);
+        assert_eq!(f.mode, Mode::EditingUsername);
+        assert!(f.username.is_empty());
+        assert_eq!(f.password.as_str(), "secret");
+    }
+
+    #[test]
+    fn username_is_trimmed() {
+        let mut f = form(SubmitKey::Enter);
+        let now = Instant::now();
+        f.paste(" bob\t");
+        f.submit(now);
+        assert_eq!(f.mode, Mode::EditingPassword);
+        f.paste("pw");
+        assert_eq!(f.submit(now), Submit::Ready);
+        assert_eq!(f.credentials().0, "bob");
+    }
+
+    #[test]
+    fn username_is_kept_as_typed_without_trimming() {
+        let login = settings::Login {
+            trim_username: false,
+            ..Default::default()
+        };
+        let rows = crate::form_layout::DEFAULT_ROW_ORDER.to_vec();
+        let mut f = Form::new(Rules::new(&login, rows, false), false);
+        let now = Instant::now();
+        f.paste("bob ");
+        f.submit(now);
+        f.paste("pw");
+        assert_eq!(f.submit(now), Submit::Ready);
+        assert_eq!(f.credentials().0, "bob ");
+    }
+
+    #[test]
+    fn username_with_control_characters_is_refused() {
+        let mut f = form(SubmitKey::Enter);
+        let now = Instant::now();
+        f.username = TextField::from_text("bo\u{7}b".to_string());
+        f.mode = Mode::EditingPassword;
+        f.paste("pw");
+        assert_eq!(f.submit(now), Submit::ControlChars);
+        assert_eq!(f.mode, Mode::EditingUsername);
+    }
+
+    #[test]
+    fn empty_password_submits_only_when_allowed() {
+        let now = Instant::now();
+        let mut f = form(SubmitKey::Enter);
+        f.paste("alice");
+        f.submit(now);
+        assert_eq!(f.submit(now), Submit::Moved);
+        assert_eq!(f.mode, Mode::EditingUsername);
+
+        let login = settings::Login {
+            allow_empty_password: true,
+            ..Default::default()
+        };
+        let rows = crate::form_layout::DEFAULT_ROW_ORDER.to_vec();
+        let mut f = Form::new(Rules::new(&login, rows, false), false);
+        f.paste("alice");
+        f.submit(now);
+        assert_eq!(f.submit(now), Submit::Ready);
+        assert_eq!(f.credentials(), ("alice".to_string(), String::new()));
+    }
+
+    #[test]
+    fn ctrl_w_takes_a_run_of_spaces_with_the_word() {
+        let mut f = form(SubmitKey::Enter);
+        f.paste("alice \t  bob  ");
+        f.delete_word();
+        assert_eq!(f.username.as_str(), "alice \t  ");
+        f.delete_word();
+        assert_eq!(f.username.as_str(), "");
+        assert_eq!(f.username.cursor(), 0);
+    }
+
+    #[test]
+    fn ctrl_w_deletes_back_from_the_cursor() {
+        let mut f = form(SubmitKey::Enter);
+        f.paste("one two three");
+        for _ in 0.." three".len() {
+            f.username.move_left();
+        }
+        f.delete_word();
+        assert_eq!(f.username.as_str(), "one  three");
+        assert_eq!(f.username.cursor(), "one ".len());
+
+        // From inside a word, only the part before the cursor goes.
+        f.username.move_end();
+        f.username.move_left();
+        f.username.move_left();
+        f.delete_word();
+        assert_eq!(f.username.as_str(), "one  ee");
+        assert_eq!(f.username.cursor(), "one  ".len());
+    }
+
+    #[test]
+    fn ctrl_w_clears_the_whole_password() {
+        let mut f = form(SubmitKey::Enter);
+        f.mode = Mode::EditingPassword;
+        f.paste("correct horse battery");
+        for _ in 0.." battery".len() {
+            f.password.move_left();
+        }
+        f.delete_word();
+        assert!(f.password.is_empty());
+        assert_eq!(f.password.cursor(), 0);
+    }
+
+    #[test]
+    fn ctrl_w_leaves_the_session_row_alone() {
+        let mut f = form(SubmitKey::Enter);
+        f.paste("alice");
+        f.mode = Mode::SelectingSession;
+        f.delete_word();
+        assert_eq!(f.username.as_str(), "alice");
+    }
+}
@@ -0,0 +1,418 @@
+use thiserror::Error;
+
+use crate::{
+    layout::{place, Edge, FormLayout},
+    Mode
+};
+
+const FOOTER_MARGIN_PX: u32 = 8;
+const NARROW_MARGIN_PX: u32 = 16;
+const BADGE_GAP_PX: u32 = 8;
+const GREETING_MARGIN_PX: u32 = 32;
+
+/// The form's rows from top to bottom, as `ui.row_order` has them by
+/// default.
+pub(crate) const DEFAULT_ROW_ORDER: [Mode; 3] =
+    [Mode::SelectingSession, Mode::EditingUsername, Mode::EditingPassword];
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum RowOrderError {
+    #[error("unknown row {0:?} in ui.row_order")]
+    Unknown(String),
+
+    #[error("row {0:?} is in ui.row_order more than once")]
+    Repeated(String),
+
+    #[error("ui.row_order must include password")]
+    NoPassword
+}
+
+/// Parses `ui.row_order` into the rows it lists, top to bottom.
+pub(crate) fn parse_row_order(
+    names: &[String]
+) -> Result<Vec<Mode>, RowOrderError> {
+    let mut rows = Vec::new();
+    for name in names {
+        let row = crate::replay::parse_mode(name.trim())
+            .ok_or_else(|| RowOrderError::Unknown(name.clone()))?;
+        if rows.contains(&row) {
+            return Err(RowOrderError::Repeated(name.clone()));
+        }
+        rows.push(row);
+    }
+    if !rows.contains(&Mode::EditingPassword) {
+        return Err(RowOrderError::NoPassword);
+    }
+    Ok(rows)
+}
+
+impl crate::LoginManager<'_> {
+    /// The current layout. Cached; call `relayout` after changing anything
+    /// it depends on.
+    pub(crate) fn form_layout(&self) -> FormLayout {
+        self.layout
+    }
+
+    pub(crate) fn relayout(&mut self) {
+        self.layout = self.compute_form_layout();
+    }
+
+    fn compute_form_layout(&self) -> FormLayout {
+        let row_h = self.row_h;
+        let gap_below_session_px = self.gap_below_session_px;
+        let gap_below_username_px = self.gap_below_username_px;
+
+        let show_session = self.show_target_row();
+        let show_username = self.show_username_row();
+        let show_comment = show_session && self.show_session_comment;
+        let comment_h = if show_comment { row_h / 2 } else { 0 };
+
+        let visible = self.visible_rows();
+        let rows = visible.len() as u32;
+        let gap_below = |row: Mode| match row {
+            Mode::SelectingSession => gap_below_session_px,
+            Mode::EditingUsername => gap_below_username_px,
+            Mode::EditingPassword => 0
+        };
+        // Only between rows, not under the last one.
+        let gaps_h = visible[..visible.len() - 1]
+            .iter()
+            .fold(0u32, |sum, &row| sum.saturating_add(gap_below(row)));
+
+        let label_h = if self.narrow_layout { row_h / 2 } else { 0 };
+
+        let user_list_rows = match self.user_list {
+            Some(_) if show_username => {
+                self.user_list_rows.min(self.username_candidates.len()) as u32
+            }
+            _ => 0
+        };
+
+        let total_h = rows * (row_h + label_h)
+            + user_list_rows * row_h
+            + comment_h
+            + gaps_h;
+
+        // Beside `[panel]`, if there is one.
+        let (area_x, area_w) = self.content_area();
+        let w = if self.narrow_layout {
+            // Full-width column.
+            area_w.saturating_sub(NARROW_MARGIN_PX * 2).max(1)
+        } else {
+            let margin_x = 32;
+            let max_w = area_w.saturating_sub(margin_x * 2).max(1);
+            self.dimensions.0.min(max_w).max(1)
+        };
+
+        let screen_h = self.screen_size.1;
+        let (greeting_h, greeting_v) = self.greeting_anchor.edges();
+        let heading_y = match greeting_v {
+            Edge::End => screen_h.saturating_sub(
+                self.heading_font.line_height() + self.heading_offset_y_px
+            ),
+            _ => self.heading_offset_y_px
+        }
+        .min(screen_h.saturating_sub(1));
+        // A centered form stays below a greeting at the top, if it fits;
+        // without one it has the whole height.
+        let below_greeting = if self.show_greeting && greeting_v != Edge::End {
+            heading_y + self.heading_font.line_height()
+        } else {
+            0
+        };
+
+        // The message line hangs below the form; keep it on screen too.
+        let block_h = total_h + row_h / 2 + row_h;
+        let (h_edge, v_edge) = self.anchor.edges();
+        let x = area_x + place(h_edge, area_w, w, self.offset.0);
+        let y = match v_edge {
+            Edge::Center => place(v_edge, screen_h, total_h, self.offset.1)
+                .max(below_greeting)
+                .min(screen_h.saturating_sub(block_h)),
+            _ => place(v_edge, screen_h, block_h, self.offset.1)
+        };
+
+        let mut cur_y = y;
+        // Takes `h` off the top.
+        let take = |cur_y: &mut u32, h: u32| {
+            let out = *cur_y;
+            *cur_y = cur_y.saturating_add(h);
+            out
+        };
+        // Takes a label row off the top if this layout has them.
+        let label = |cur_y: &mut u32| {
+            (label_h > 0).then(|| take(cur_y, label_h))
+        };
+
+        let mut session_label_y = None;
+        let mut session_y = None;
+        let mut comment_y = None;
+        let mut username_label_y = None;
+        let mut username_y = None;
+        let mut user_list_y = None;
+        let mut password_label_y = None;
+        let mut password_y = 0;
+        for (i, &row) in visible.iter().enumerate() {
+            match row {
+                Mode::SelectingSession => {
+                    session_label_y = label(&mut cur_y);
+                    session_y = Some(take(&mut cur_y, row_h));
+                    if show_comment {
+                        comment_y = Some(take(&mut cur_y, comment_h));
+                    }
+                }
+                Mode::EditingUsername => {
+                    username_label_y = label(&mut cur_y);
+                    username_y = Some(take(&mut cur_y, row_h));
+                    if user_list_rows > 0 {
+                        let list_h = user_list_rows * row_h;
+                        user_list_y = Some(take(&mut cur_y, list_h));
+                    }
+                }
+                Mode::EditingPassword => {
+                    password_label_y = label(&mut cur_y);
+                    password_y = take(&mut cur_y, row_h);
+                }
+            }
+            if i + 1 < visible.len() {
+                cur_y = cur_y.saturating_add(gap_below(row));
+            }
+        }
+        let message_y = cur_y.saturating_add(row_h / 2);
+
+        let footer_h = row_h / 2;
+        let footer_y = self
+            .screen_size
+            .1
+            .checked_sub(footer_h + FOOTER_MARGIN_PX)
+            .filter(|&y| y >= message_y.saturating_add(row_h));
+
+        let attempts_h = row_h / 2;
+        let attempts_y = Some(message_y.saturating_add(row_h)).filter(|&y| {
+            y.saturating_add(attempts_h)
+                <= footer_y.unwrap_or(self.screen_size.1)
+        });
+
+        let badge_h = row_h / 2;
+        let badge_y = y.checked_sub(badge_h + BADGE_GAP_PX);
+
+        let heading_margin = if greeting_h == Edge::Center {
+            0
+        } else {
+            GREETING_MARGIN_PX.min(area_w / 4)
+        };
+        // The greeting's column, narrowed to ui.content_max_width_px and
+        // placed the way the greeting is.
+        let column_w = area_w.saturating_sub(heading_margin * 2).max(1);
+        let heading_w = self
+            .content_max_width_px
+            .map_or(column_w, |max_w| max_w.clamp(1, column_w));
+        let heading = (
+            area_x + heading_margin + place(greeting_h, column_w, heading_w, 0),
+            heading_y,
+            heading_w,
+            screen_h - heading_y
+        );
+
+        FormLayout {
+            x,
+            y,
+            w,
+            row_h,
+            total_h,
+            session_y,
+            comment_y,
+            comment_h,
+            username_y,
+            user_list_y,
+            user_list_rows,
+            password_y,
+            message_y,
+            footer_y,
+            footer_h,
+            badge_y,
+            badge_h,
+            session_label_y,
+            username_label_y,
+            password_label_y,
+            label_h,
+            heading,
+            attempts_y,
+            attempts_h,
+            input_margin: self.input_margin.resolve(w)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{framebuffer, login_manager};
+
+    /// The form's layout on a `w`x`h` screen with `config`.
+    fn layout(w: u32, h: u32, config: &str) -> (FormLayout, bool) {
+        let mut fb = framebuffer(w, h);
+        let lm = login_manager(&mut fb, config);
+        (lm.form_layout(), lm.narrow_layout)
+    }
+
+    #[test]
+    fn wide_profile_centers_a_box() {
+        let (layout, narrow) = layout(1920, 1080, "");
+        assert!(!narrow);
+        assert_eq!(
+            layout,
+            FormLayout {
+                x: 704,
+                y: 384,
+                w: 512,
+                row_h: 72,
+                total_h: 312,
+                session_y: Some(384),
+                comment_y: None,
+                comment_h: 0,
+                username_y: Some(520),
+                user_list_y: None,
+                user_list_rows: 0,
+                password_y: 624,
+                message_y: 732,
+                footer_y: Some(1036),
+                footer_h: 36,
+                badge_y: Some(340),
+                badge_h: 36,
+                session_label_y: None,
+                username_label_y: None,
+                password_label_y: None,
+                label_h: 0,
+                heading: (0, 24, 1920, 1056),
+                attempts_y: Some(804),
+                attempts_h: 36,
+                input_margin: 15
+            }
+        );
+    }
+
+    #[test]
+    fn narrow_profile_puts_labels_over_a_full_width_column() {
+        let (layout, narrow) = layout(480, 800, "ui.layout = \"narrow\"");
+        assert!(narrow);
+        assert_eq!(
+            layout,
+            FormLayout {
+                x: 16,
+                y: 190,
+                w: 448,
+                row_h: 72,
+                total_h: 420,
+                session_y: Some(226),
+                comment_y: None,
+                comment_h: 0,
+                username_y: Some(398),
+                user_list_y: None,
+                user_list_rows: 0,
+                password_y: 538,
+                message_y: 646,
+                footer_y: Some(756),
+                footer_h: 36,
+                badge_y: Some(146),
+                badge_h: 36,
+                session_label_y: Some(190),
+                username_label_y: Some(362),
+                password_label_y: Some(502),
+                label_h: 36,
+                heading: (0, 24, 480, 776),
+                attempts_y: Some(718),
+                attempts_h: 36,
+                input_margin: 15
+            }
+        );
+    }
+
+    #[test]
+    fn single_field_leaves_only_the_password_row() {
+        let config = "[ui]\nsingle_field = true\n\
+                      [login]\nusername = \"alice\"\ntarget = \"session 0\"";
+        let (layout, _) = layout(1920, 1080, config);
+        assert_eq!(
+            layout,
+            FormLayout {
+                x: 704,
+                y: 504,
+                w: 512,
+                row_h: 72,
+                total_h: 72,
+                session_y: None,
+                comment_y: None,
+                comment_h: 0,
+                username_y: None,
+                user_list_y: None,
+                user_list_rows: 0,
+                password_y: 504,
+                message_y: 612,
+                footer_y: Some(1036),
+                footer_h: 36,
+                badge_y: Some(460),
+                badge_h: 36,
+                session_label_y: None,
+                username_label_y: None,
+                password_label_y: None,
+                label_h: 0,
+                heading: (0, 24, 1920, 1056),
+                attempts_y: Some(684),
+                attempts_h: 36,
+                input_margin: 15
+            }
+        );
+    }
+
+    #[test]
+    fn auto_profile_goes_narrow_below_640px() {
+        let auto = "ui.layout = \"auto\"";
+        assert!(layout(639, 800, auto).1);
+        assert!(!layout(640, 800, auto).1);
+        assert!(!layout(1920, 1080, auto).1);
+        // The same as asking for it.
+        assert_eq!(
+            layout(480, 800, auto),
+            layout(480, 800, "ui.layout = \"narrow\"")
+        );
+        assert_eq!(layout(1920, 1080, auto), layout(1920, 1080, ""));
+    }
+
+    #[test]
+    fn profiles_given_override_the_screen_width() {
+        let (wide, narrow) = layout(480, 800, "ui.layout = \"wide\"");
+        assert!(!narrow);
+        assert_eq!((wide.x, wide.w, wide.label_h), (32, 416, 0));
+        let (narrow, is_narrow) =
+            layout(1920, 1080, "ui.layout = \"narrow\"");
+        assert!(is_narrow);
+        assert_eq!((narrow.x, narrow.w, narrow.label_h), (16, 1888, 36));
+    }
+
+    #[test]
+    fn row_order_is_checked() {
+        let names = |names: &[&str]| {
+            let names: Vec<String> =
+                names.iter().map(|name| name.to_string()).collect();
+            parse_row_order(&names)
+        };
+        assert_eq!(
+            names(&["password", " username "]).unwrap(),
+            [Mode::EditingPassword, Mode::EditingUsername]
+        );
+        assert!(matches!(
+            names(&["password", "pin"]),
+            Err(RowOrderError::Unknown(name)) if name == "pin"
+        ));
+        assert!(matches!(
+            names(&["password", "password"]),
+            Err(RowOrderError::Repeated(_))
+        ));
+        assert!(matches!(
+            names(&["session", "username"]),
+            Err(RowOrderError::NoPassword)
+        ));
+    }
+}
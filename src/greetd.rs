@@ -1,16 +1,70 @@
-use std::{env, error::Error, os::unix::net::UnixStream};
+use std::{
+    env,
+    error::Error,
+    io::{self, Read},
+    os::unix::{io::AsRawFd, net::UnixStream},
+    time::Duration
+};
 
-use greetd_ipc::{codec::SyncCodec, AuthMessageType, Request, Response};
+use greetd_ipc::{
+    codec::{Error as CodecError, SyncCodec},
+    AuthMessageType, ErrorType, Request, Response
+};
+use log::{info, warn};
+use nix::poll::{poll, PollFd, PollFlags};
 use thiserror::Error as ThisError;
 
+use crate::metrics::Stopwatch;
+
+/// The password `--no-greetd` accepts, for any username.
+const DRY_RUN_PASSWORD: &str = "test";
+
+/// How often `login` reports that it's still waiting on PAM.
+const WAITING_EVERY: Duration = Duration::from_millis(100);
+
+/// What `login` tells the greeter while PAM works.
+pub enum Progress<'a> {
+    /// Something PAM wants shown, such as "Please touch the security
+    /// key". `error` for PAM error messages.
+    Message { text: &'a str, error: bool },
+    /// Still waiting for PAM; time to turn a spinner.
+    Waiting
+}
+
 pub struct GreetD {
-    pub stream: UnixStream
+    pub stream: UnixStream,
+    socket: String,
+    /// An IPC call failed part way, so the stream may hold an unread
+    /// response. Reconnect before the next login.
+    poisoned: bool,
+    /// A CreateSession went out and the session hasn't been started or
+    /// cancelled yet.
+    session_open: bool,
+    /// Treat greetd hanging up after StartSession as a failure rather than
+    /// as the session starting.
+    strict_start_ack: bool
+}
+
+/// Reads through to the stream, remembering the kind of the last error,
+/// which greetd_ipc only passes on as text.
+struct KindOfError<'a> {
+    stream: &'a mut UnixStream,
+    kind: Option<io::ErrorKind>
+}
+
+impl Read for KindOfError<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf).inspect_err(|e| self.kind = Some(e.kind()))
+    }
 }
 
 #[derive(ThisError, Debug)]
 #[non_exhaustive]
 pub enum GreetDError {
-    #[error("GREETD_SOCK environment variable must be defined: {0}")]
+    #[error(
+        "GREETD_SOCK environment variable must be defined (run with \
+         --no-greetd to try the greeter without greetd): {0}"
+    )]
     MissingSocketEnv(#[from] env::VarError),
 
     #[error("failed to connect to greetd socket at {path:?}: {source}")]
@@ -23,8 +77,11 @@ pub enum GreetDError {
     #[error("greetd IPC error: {0}")]
     Ipc(#[source] Box<dyn Error + Send + Sync>),
 
-    #[error("authentication failed: {0}")]
-    AuthFailed(String)
+    #[error("authentication failed: {description}")]
+    AuthFailed { description: String },
+
+    #[error("greetd error: {description}")]
+    Daemon { description: String }
 }
 
 impl GreetDError {
@@ -34,81 +91,665 @@ impl GreetDError {
     {
         Self::Ipc(Box::new(err))
     }
+
+    fn auth_failed(description: &str) -> Self {
+        Self::AuthFailed {
+            description: description.to_string()
+        }
+    }
+
+    /// Maps a `Response::Error` payload onto the matching variant.
+    fn from_response(error_type: ErrorType, description: String) -> Self {
+        match error_type {
+            ErrorType::AuthError => Self::AuthFailed { description },
+            ErrorType::Error => Self::Daemon { description }
+        }
+    }
+}
+
+/// Where logins go: greetd, or a stand-in for trying the greeter out.
+pub enum Backend {
+    GreetD(GreetD),
+    /// `--no-greetd`: any username logs in with the password "test", and
+    /// nothing is started.
+    DryRun
+}
+
+/// Whether to run without greetd although `--no-greetd` wasn't given:
+/// `MFLM_DRY_RUN=1` with no `GREETD_SOCK`.
+pub fn dry_run_from_env() -> bool {
+    env::var_os("GREETD_SOCK").is_none()
+        && env::var_os("MFLM_DRY_RUN").is_some_and(|v| v == "1")
+}
+
+impl Backend {
+    /// Connects to greetd, unless this is a dry run.
+    pub fn new(
+        dry_run: bool,
+        strict_start_ack: bool
+    ) -> Result<Self, GreetDError> {
+        if dry_run {
+            warn!(
+                "Dry run: not connecting to greetd; any username logs in \
+                 with the password {DRY_RUN_PASSWORD:?}"
+            );
+            return Ok(Self::DryRun);
+        }
+        GreetD::new(strict_start_ack).map(Self::GreetD)
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        matches!(self, Self::DryRun)
+    }
+
+    pub fn login(
+        &mut self,
+        username: String,
+        password: String,
+        cmd: &[String],
+        timing: &mut Stopwatch,
+        progress: &mut dyn FnMut(Progress<'_>)
+    ) -> Result<(), GreetDError> {
+        match self {
+            Self::GreetD(greetd) => {
+                greetd.login(username, password, cmd, timing, progress)
+            }
+            Self::DryRun => {
+                timing.lap("auth");
+                if password != DRY_RUN_PASSWORD {
+                    return Err(GreetDError::auth_failed(
+                        "wrong username or password"
+                    ));
+                }
+                info!("Dry run: would start {cmd:?}");
+                Ok(())
+            }
+        }
+    }
+
+    pub fn cancel(&mut self) -> Result<(), GreetDError> {
+        match self {
+            Self::GreetD(greetd) => greetd.cancel(),
+            Self::DryRun => Ok(())
+        }
+    }
 }
 
 impl GreetD {
-    pub fn new() -> Result<Self, GreetDError> {
-        let socket = env::var("GREETD_SOCK")?;
-        match UnixStream::connect(&socket) {
-            Ok(stream) => Ok(GreetD { stream }),
-            Err(source) => Err(GreetDError::Connect {
-                path: socket,
-                source
-            })
+    pub fn new(strict_start_ack: bool) -> Result<Self, GreetDError> {
+        Self::connect_to(env::var("GREETD_SOCK")?, strict_start_ack)
+    }
+
+    /// Connects to greetd at `socket` rather than `$GREETD_SOCK`.
+    pub(crate) fn connect_to(
+        socket: String,
+        strict_start_ack: bool
+    ) -> Result<Self, GreetDError> {
+        let stream = Self::connect(&socket)?;
+        Ok(GreetD {
+            stream,
+            socket,
+            poisoned: false,
+            session_open: false,
+            strict_start_ack
+        })
+    }
+
+    fn connect(socket: &str) -> Result<UnixStream, GreetDError> {
+        UnixStream::connect(socket).map_err(|source| GreetDError::Connect {
+            path: socket.to_string(),
+            source
+        })
+    }
+
+    fn send(&mut self, request: Request) -> Result<(), GreetDError> {
+        let res = request.write_to(&mut self.stream);
+        self.poisoned |= res.is_err();
+        res.map_err(GreetDError::ipc)
+    }
+
+    fn receive(&mut self) -> Result<Response, GreetDError> {
+        let res = Response::read_from(&mut self.stream);
+        self.poisoned |= res.is_err();
+        res.map_err(GreetDError::ipc)
+    }
+
+    /// Like `receive`, but reports `Progress::Waiting` every
+    /// `WAITING_EVERY` until the answer starts to arrive.
+    fn receive_waiting(
+        &mut self,
+        progress: &mut dyn FnMut(Progress<'_>)
+    ) -> Result<Response, GreetDError> {
+        let timeout_ms = WAITING_EVERY.as_millis() as i32;
+        loop {
+            let mut fds =
+                [PollFd::new(self.stream.as_raw_fd(), PollFlags::POLLIN)];
+            match poll(&mut fds, timeout_ms) {
+                Ok(0) | Err(nix::errno::Errno::EINTR) => {
+                    progress(Progress::Waiting)
+                }
+                // Readable, hung up or failed; the read says which.
+                _ => return self.receive()
+            }
         }
     }
 
+    /// Reads the answer to StartSession. Some greetd versions close the
+    /// socket as the session starts instead of answering; unless
+    /// `strict_start_ack` is set, that counts as started.
+    fn receive_start(&mut self) -> Result<Response, GreetDError> {
+        let mut reader = KindOfError {
+            stream: &mut self.stream,
+            kind: None
+        };
+        let res = Response::read_from(&mut reader);
+        let hung_up = match &res {
+            Err(CodecError::Eof) => true,
+            Err(_) => matches!(
+                reader.kind,
+                Some(io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset)
+            ),
+            Ok(_) => false
+        };
+        if hung_up && !self.strict_start_ack {
+            warn!(
+                "greetd hung up instead of answering StartSession; assuming \
+                 the session started (login.strict_start_ack = true treats \
+                 this as a failure)"
+            );
+            self.poisoned = true;
+            return Ok(Response::Success);
+        }
+        self.poisoned |= res.is_err();
+        res.map_err(GreetDError::ipc)
+    }
+
+    /// Logs `username` in and starts `cmd`. Answers PAM's secret prompt
+    /// with `password` and passes anything it only wants shown on to
+    /// `progress`, so a flow like pam_u2f's "touch the security key" works
+    /// without a password at all.
+    ///
+    /// A failed attempt's session is cancelled before returning, so every
+    /// attempt gets a fresh CreateSession and PAM conversation, and a
+    /// retry can't answer what's left of the last one. If greetd doesn't
+    /// acknowledge the cancel, the next attempt reconnects instead.
     pub fn login(
         &mut self,
         username: String,
         password: String,
-        cmd: Vec<String>
+        cmd: &[String],
+        timing: &mut Stopwatch,
+        progress: &mut dyn FnMut(Progress<'_>)
     ) -> Result<(), GreetDError> {
-        Request::CreateSession { username }
-            .write_to(&mut self.stream)
-            .map_err(GreetDError::ipc)?;
+        let res = self.attempt(username, password, cmd, timing, progress);
+        if res.is_err() && self.session_open {
+            if let Err(e) = self.cancel() {
+                warn!("greetd didn't acknowledge the cancel: {e}");
+                self.poisoned = true;
+            }
+        }
+        res
+    }
 
-        Request::PostAuthMessageResponse {
-            response: Some(password)
+    fn attempt(
+        &mut self,
+        username: String,
+        password: String,
+        cmd: &[String],
+        timing: &mut Stopwatch,
+        progress: &mut dyn FnMut(Progress<'_>)
+    ) -> Result<(), GreetDError> {
+        if self.session_open {
+            // greetd refuses a second CreateSession while one is open.
+            if let Err(e) = self.cancel() {
+                warn!("Failed to cancel previous greetd session: {e}");
+            }
         }
-        .write_to(&mut self.stream)
-        .map_err(GreetDError::ipc)?;
-
-        let response =
-            Response::read_from(&mut self.stream).map_err(GreetDError::ipc)?;
-        match response {
-            Response::AuthMessage {
-                auth_message: _,
-                auth_message_type
-            } => match auth_message_type {
-                AuthMessageType::Secret => {
-                    Request::StartSession { cmd }
-                        .write_to(&mut self.stream)
-                        .map_err(GreetDError::ipc)?;
-                    let resp = Response::read_from(&mut self.stream)
-                        .map_err(GreetDError::ipc)?;
-                    match resp {
-                        Response::Success => Ok(()),
-                        Response::Error { .. }
-                        | Response::AuthMessage { .. } => {
-                            Err(GreetDError::AuthFailed(
-                                "wrong username or password".to_string()
-                            ))
-                        }
+        if self.poisoned {
+            info!("Reconnecting to greetd after an IPC failure");
+            self.stream = Self::connect(&self.socket)?;
+            self.poisoned = false;
+            self.session_open = false;
+        }
+
+        self.send(Request::CreateSession { username })?;
+        self.session_open = true;
+        timing.lap("create");
+
+        // PAM's side of the conversation, until it's done with us.
+        let mut password = Some(password);
+        loop {
+            match self.receive_waiting(progress)? {
+                Response::Success => break,
+                Response::Error {
+                    error_type,
+                    description
+                } => {
+                    return Err(GreetDError::from_response(
+                        error_type,
+                        description
+                    ))
+                }
+                Response::AuthMessage {
+                    auth_message,
+                    auth_message_type
+                } => match auth_message_type {
+                    AuthMessageType::Secret => {
+                        // A second ask means the first answer was wrong.
+                        let response = password.take().ok_or_else(|| {
+                            GreetDError::auth_failed(
+                                "wrong username or password"
+                            )
+                        })?;
+                        self.send(Request::PostAuthMessageResponse {
+                            response: Some(response)
+                        })?;
+                    }
+                    AuthMessageType::Visible => {
+                        return Err(GreetDError::auth_failed("wrong username"));
+                    }
+                    AuthMessageType::Info | AuthMessageType::Error => {
+                        progress(Progress::Message {
+                            text: &auth_message,
+                            error: matches!(
+                                auth_message_type,
+                                AuthMessageType::Error
+                            )
+                        });
+                        self.send(Request::PostAuthMessageResponse {
+                            response: None
+                        })?;
                     }
                 }
-                _ => Err(GreetDError::AuthFailed("wrong username".to_string()))
-            },
+            }
+        }
+        timing.lap("auth");
+
+        self.send(Request::StartSession { cmd: cmd.to_vec() })?;
+        let resp = self.receive_start()?;
+        timing.lap("start");
+        match resp {
             Response::Success => {
-                Request::StartSession { cmd }
-                    .write_to(&mut self.stream)
-                    .map_err(GreetDError::ipc)?;
-                let _ = Response::read_from(&mut self.stream)
-                    .map_err(GreetDError::ipc)?;
+                self.session_open = false;
                 Ok(())
             }
-            _ => Err(GreetDError::AuthFailed(
-                "unknown greetd response".to_string()
-            ))
+            Response::Error {
+                error_type,
+                description
+            } => Err(GreetDError::from_response(error_type, description)),
+            Response::AuthMessage { .. } => {
+                Err(GreetDError::auth_failed("wrong username or password"))
+            }
         }
     }
 
+    /// Cancels the open session, if any.
     pub fn cancel(&mut self) -> Result<(), GreetDError> {
-        Request::CancelSession
-            .write_to(&mut self.stream)
-            .map_err(GreetDError::ipc)?;
-        let _ =
-            Response::read_from(&mut self.stream).map_err(GreetDError::ipc)?;
-        Ok(())
+        if !self.session_open || self.poisoned {
+            // With a poisoned stream, greetd drops the session along with
+            // the connection when we reconnect.
+            return Ok(());
+        }
+        self.send(Request::CancelSession)?;
+        // Whatever the answer, greetd won't consider it ours any more.
+        self.session_open = false;
+        match self.receive()? {
+            Response::Success => Ok(()),
+            Response::Error {
+                error_type,
+                description
+            } => Err(GreetDError::from_response(error_type, description)),
+            Response::AuthMessage { .. } => {
+                // Not an answer to a cancel; we've lost track of the
+                // conversation.
+                self.poisoned = true;
+                Err(GreetDError::Daemon {
+                    description: "unexpected auth message on cancel".to_string()
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, os::unix::net::UnixListener, thread};
+
+    use super::*;
+
+    /// What the fake greetd does with the next request.
+    enum Reply {
+        Answer(Response),
+        /// Closes the connection without answering.
+        HangUp
+    }
+
+    fn secret() -> Reply {
+        Reply::Answer(Response::AuthMessage {
+            auth_message: "Password:".to_string(),
+            auth_message_type: AuthMessageType::Secret
+        })
+    }
+
+    fn success() -> Reply {
+        Reply::Answer(Response::Success)
+    }
+
+    fn error(error_type: ErrorType, description: &str) -> Reply {
+        Reply::Answer(Response::Error {
+            error_type,
+            description: description.to_string()
+        })
+    }
+
+    /// A greetd listening on a fresh socket, answering each request with
+    /// the next reply in `script`, across reconnects. Joining it gives the
+    /// requests it got, with "connect" for each connection.
+    fn fake_greetd(
+        name: &str,
+        script: Vec<Reply>
+    ) -> (String, thread::JoinHandle<Vec<String>>) {
+        let path = env::temp_dir()
+            .join(format!("mflm-{}-{name}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let socket = path.to_str().unwrap().to_string();
+        let greetd = thread::spawn(move || {
+            let mut script = VecDeque::from(script);
+            let mut requests = Vec::new();
+            while let Ok((mut stream, _)) = listener.accept() {
+                requests.push("connect".to_string());
+                while let Ok(request) = Request::read_from(&mut stream) {
+                    requests.push(format!("{request:?}"));
+                    match script.pop_front() {
+                        Some(Reply::Answer(response)) => {
+                            response.write_to(&mut stream).unwrap()
+                        }
+                        Some(Reply::HangUp) | None => break
+                    }
+                }
+                if script.is_empty() {
+                    break;
+                }
+            }
+            let _ = std::fs::remove_file(&path);
+            requests
+        });
+        (socket, greetd)
+    }
+
+    fn connect(socket: &str, strict_start_ack: bool) -> GreetD {
+        GreetD::connect_to(socket.to_string(), strict_start_ack).unwrap()
+    }
+
+    /// Logs in as "alice" to start "sway". Also gives the messages PAM
+    /// had shown.
+    fn log_in(
+        greetd: &mut GreetD,
+        password: &str
+    ) -> (Result<(), GreetDError>, Vec<String>) {
+        let mut messages = Vec::new();
+        let res = greetd.login(
+            "alice".to_string(),
+            password.to_string(),
+            &["sway".to_string()],
+            &mut Stopwatch::new(),
+            &mut |progress| {
+                if let Progress::Message { text, .. } = progress {
+                    messages.push(text.to_string());
+                }
+            }
+        );
+        (res, messages)
+    }
+
+    const CREATE: &str = r#"CreateSession { username: "alice" }"#;
+    const START: &str = r#"StartSession { cmd: ["sway"] }"#;
+    const CANCEL: &str = "CancelSession";
+
+    fn answer(password: &str) -> String {
+        format!("PostAuthMessageResponse {{ response: Some({password:?}) }}")
+    }
+
+    #[test]
+    fn hanging_up_after_start_counts_as_started() {
+        let (socket, greetd) =
+            fake_greetd("start-eof", vec![secret(), success(), Reply::HangUp]);
+        let mut client = connect(&socket, false);
+        assert!(log_in(&mut client, "pw").0.is_ok());
+        // The stream is gone; the next login would reconnect.
+        assert!(client.poisoned);
+        assert!(!client.session_open);
+        drop(client);
+        assert_eq!(
+            greetd.join().unwrap(),
+            [
+                "connect",
+                CREATE,
+                &answer("pw"),
+                START
+            ]
+        );
+    }
+
+    #[test]
+    fn hanging_up_after_start_fails_when_strict() {
+        let (socket, greetd) = fake_greetd(
+            "start-eof-strict",
+            vec![secret(), success(), Reply::HangUp]
+        );
+        let mut client = connect(&socket, true);
+        let res = log_in(&mut client, "pw").0;
+        assert!(matches!(res, Err(GreetDError::Ipc(_))), "{:?}", res);
+        drop(client);
+        assert_eq!(greetd.join().unwrap().last().unwrap(), START);
+    }
+
+    #[test]
+    fn cancel_passes_on_greetd_errors() {
+        let (socket, greetd) = fake_greetd(
+            "cancel-error",
+            vec![error(ErrorType::Error, "no session to cancel")]
+        );
+        let mut client = connect(&socket, false);
+        client.session_open = true;
+        let res = client.cancel();
+        assert!(
+            matches!(
+                &res,
+                Err(GreetDError::Daemon { description })
+                    if description == "no session to cancel"
+            ),
+            "{:?}",
+            res
+        );
+        // The answer was read, so the stream is still in step.
+        assert!(!client.poisoned);
+        assert!(!client.session_open);
+        // Nothing left to cancel; nothing is sent.
+        assert!(client.cancel().is_ok());
+        drop(client);
+        assert_eq!(greetd.join().unwrap(), ["connect", CANCEL]);
+    }
+
+    #[test]
+    fn cancel_answered_out_of_step_poisons() {
+        let (socket, greetd) = fake_greetd("cancel-confused", vec![secret()]);
+        let mut client = connect(&socket, false);
+        client.session_open = true;
+        assert!(client.cancel().is_err());
+        assert!(client.poisoned);
+        drop(client);
+        greetd.join().unwrap();
+    }
+
+    #[test]
+    fn failed_cancel_after_a_failure_reconnects() {
+        let (socket, greetd) = fake_greetd(
+            "cancel-after-failure",
+            vec![
+                secret(),
+                error(ErrorType::AuthError, "bad password"),
+                Reply::HangUp,
+                secret(),
+                success(),
+                success()
+            ]
+        );
+        let mut client = connect(&socket, false);
+        let res = log_in(&mut client, "wrong").0;
+        // The login's own failure wins over the cancel's.
+        assert!(
+            matches!(
+                &res,
+                Err(GreetDError::AuthFailed { description })
+                    if description == "bad password"
+            ),
+            "{:?}",
+            res
+        );
+        assert!(client.poisoned);
+        assert!(log_in(&mut client, "right").0.is_ok());
+        drop(client);
+        assert_eq!(
+            greetd.join().unwrap(),
+            [
+                "connect",
+                CREATE,
+                &answer("wrong"),
+                CANCEL,
+                "connect",
+                CREATE,
+                &answer("right"),
+                START
+            ]
+        );
+    }
+
+    #[test]
+    fn back_to_back_failures_each_get_a_fresh_session() {
+        let (socket, greetd) = fake_greetd(
+            "back-to-back",
+            vec![
+                // Asks for a username, so the session is left waiting.
+                Reply::Answer(Response::AuthMessage {
+                    auth_message: "login:".to_string(),
+                    auth_message_type: AuthMessageType::Visible
+                }),
+                success(),
+                secret(),
+                error(ErrorType::AuthError, "bad password"),
+                success(),
+                secret(),
+                success(),
+                success()
+            ]
+        );
+        let mut client = connect(&socket, false);
+        assert!(log_in(&mut client, "pw").0.is_err());
+        assert!(!client.session_open);
+        assert!(log_in(&mut client, "wrong").0.is_err());
+        assert!(log_in(&mut client, "right").0.is_ok());
+        drop(client);
+        assert_eq!(
+            greetd.join().unwrap(),
+            [
+                "connect",
+                CREATE,
+                CANCEL,
+                CREATE,
+                &answer("wrong"),
+                CANCEL,
+                CREATE,
+                &answer("right"),
+                START
+            ]
+        );
+    }
+
+    #[test]
+    fn session_left_open_is_cancelled_first() {
+        let (socket, greetd) = fake_greetd(
+            "left-open",
+            vec![success(), secret(), success(), success()]
+        );
+        let mut client = connect(&socket, false);
+        client.session_open = true;
+        assert!(log_in(&mut client, "pw").0.is_ok());
+        drop(client);
+        assert_eq!(
+            greetd.join().unwrap(),
+            ["connect", CANCEL, CREATE, &answer("pw"), START]
+        );
+    }
+
+    #[test]
+    fn two_failed_attempts_request_sequence() {
+        let (socket, greetd) = fake_greetd(
+            "two-failures",
+            vec![
+                secret(),
+                error(ErrorType::AuthError, "bad password"),
+                success(),
+                // Asking again means the answer was wrong; it isn't
+                // answered with the same password.
+                secret(),
+                secret(),
+                success()
+            ]
+        );
+        let mut client = connect(&socket, false);
+        for password in ["first", "second"] {
+            let res = log_in(&mut client, password).0;
+            assert!(
+                matches!(res, Err(GreetDError::AuthFailed { .. })),
+                "{:?}",
+                res
+            );
+            assert!(!client.session_open);
+            assert!(!client.poisoned);
+        }
+        drop(client);
+        assert_eq!(
+            greetd.join().unwrap(),
+            [
+                "connect",
+                CREATE,
+                &answer("first"),
+                CANCEL,
+                CREATE,
+                &answer("second"),
+                CANCEL
+            ]
+        );
+    }
+
+    #[test]
+    fn touch_key_flow_needs_no_password() {
+        let (socket, greetd) = fake_greetd(
+            "touch-key",
+            vec![
+                Reply::Answer(Response::AuthMessage {
+                    auth_message: "Please touch the security key".to_string(),
+                    auth_message_type: AuthMessageType::Info
+                }),
+                success(),
+                success()
+            ]
+        );
+        let mut client = connect(&socket, false);
+        let (res, messages) = log_in(&mut client, "");
+        assert!(res.is_ok(), "{:?}", res);
+        assert_eq!(messages, ["Please touch the security key"]);
+        drop(client);
+        assert_eq!(
+            greetd.join().unwrap(),
+            [
+                "connect",
+                CREATE,
+                "PostAuthMessageResponse { response: None }",
+                START
+            ]
+        );
     }
 }
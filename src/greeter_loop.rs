@@ -1,323 +1,1204 @@
-use std::io::Read;
+use std::{
+    process::{Command, Stdio},
+    sync::Arc,
+    time::{Duration, Instant}
+};
 
 use log::{error, info, warn};
 
+use crate::{
+    accounts,
+    exit::Exit,
+    field::TextField,
+    form::{Hold, Submit, Typed},
+    greetd::{GreetDError, Progress},
+    input::Event,
+    keys::{Action, Key},
+    metrics::Stopwatch,
+    replay::{Replay, ReplayError}
+};
+
+/// How long each spinner frame shows while a session starts.
+const SPINNER_FRAME: Duration = Duration::from_millis(100);
+
 impl crate::LoginManager<'_> {
-    fn mode_allowed(&self, mode: crate::Mode) -> bool {
-        match mode {
-            crate::Mode::SelectingSession => self.show_target_row(),
-            crate::Mode::EditingUsername => self.show_username_row(),
-            crate::Mode::EditingPassword => true
+    /// Whether `key` submits the form, however login.submit_key is set.
+    fn is_submit(&self, key: Key) -> bool {
+        matches!(key, Key::Enter | Key::CtrlEnter)
+            || self.keys.action(key) == Some(Action::Submit)
+    }
+
+    /// The rows on screen, top to bottom. Never empty, since the
+    /// password row always shows.
+    pub(crate) fn visible_rows(&self) -> Vec<crate::Mode> {
+        self.form.rules().rows.clone()
+    }
+
+    /// Moves focus `step` rows, beeping on wrapping around with
+    /// `ui.bell_on_wrap`.
+    fn move_focus(&mut self, step: isize) {
+        if self.form.move_focus(step) && self.bell_on_wrap {
+            self.beep();
         }
     }
 
-    fn next_allowed_mode(&self, from: crate::Mode) -> crate::Mode {
-        let mut cur = from;
-        for _ in 0..3 {
-            cur = match cur {
-                crate::Mode::SelectingSession => crate::Mode::EditingUsername,
-                crate::Mode::EditingUsername => crate::Mode::EditingPassword,
-                crate::Mode::EditingPassword => crate::Mode::SelectingSession
-            };
-            if self.mode_allowed(cur) {
-                return cur;
-            }
+    /// Beeps or flashes for a character the form refused.
+    fn refused(&mut self, typed: Typed) {
+        match typed {
+            Typed::Inserted | Typed::Ignored => (),
+            Typed::Full => self.beep(),
+            Typed::NotDigit => self.flash_password()
         }
-        from
     }
 
-    fn prev_allowed_mode(&self, from: crate::Mode) -> crate::Mode {
-        let mut cur = from;
-        for _ in 0..3 {
-            cur = match cur {
-                crate::Mode::SelectingSession => crate::Mode::EditingPassword,
-                crate::Mode::EditingUsername => crate::Mode::SelectingSession,
-                crate::Mode::EditingPassword => crate::Mode::EditingUsername
-            };
-            if self.mode_allowed(cur) {
-                return cur;
-            }
+    /// Index of the next (or previous) completion candidate starting with
+    /// `prefix`, cycling from `current`.
+    fn complete_username(
+        &self,
+        prefix: &str,
+        current: Option<usize>,
+        forward: bool
+    ) -> Option<usize> {
+        let matches: Vec<usize> = self
+            .username_candidates
+            .iter()
+            .enumerate()
+            .filter(|(_, name)| name.starts_with(prefix))
+            .map(|(i, _)| i)
+            .collect();
+        if matches.is_empty() {
+            return None;
         }
-        from
+
+        let pos = current.and_then(|c| matches.iter().position(|&i| i == c));
+        let next = match (pos, forward) {
+            (Some(p), true) => (p + 1) % matches.len(),
+            (Some(p), false) => (p + matches.len() - 1) % matches.len(),
+            (None, true) => 0,
+            (None, false) => matches.len() - 1
+        };
+        Some(matches[next])
     }
 
-    fn goto_next_mode(&mut self) {
-        self.mode = self.next_allowed_mode(self.mode);
+    /// Refuses a password key: beeps, and flashes the underline in the
+    /// error color.
+    fn flash_password(&mut self) {
+        self.beep();
+        self.password_flash = Some(Instant::now() + crate::ERROR_FLASH_TIME);
+        self.drawn_password = None;
     }
 
-    fn goto_prev_mode(&mut self) {
-        self.mode = self.prev_allowed_mode(self.mode);
+    /// Shows `session` starting for `handoff_delay`, if set, so the
+    /// session has time to take over the display before mflm exits.
+    fn hand_off(&mut self, session: &str) {
+        let delay = match self.handoff_delay {
+            Some(delay) => delay,
+            None => return
+        };
+        let text = format!("Starting {session}…");
+        let until = Instant::now() + delay;
+        let mut frame = 0;
+        loop {
+            if let Err(e) = self.draw_handoff(&text, frame) {
+                warn!("Unable to draw session startup: {e}");
+                return;
+            }
+            self.flush();
+            let left = until.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return;
+            }
+            std::thread::sleep(SPINNER_FRAME.min(left));
+            self.watchdog.beat();
+            frame += 1;
+        }
     }
 
-    pub(crate) fn greeter_loop(&mut self) {
-        let mut username = self
-            .forced_username
-            .clone()
-            .unwrap_or_else(|| String::with_capacity(crate::USERNAME_CAP));
-        let mut password = String::with_capacity(crate::PASSWORD_CAP);
-        let mut last_username_len = usize::MAX;
-        let mut last_password_len = password.len();
-        let mut last_target_index = self.target_index;
-        let mut last_mode = self.mode;
-        let mut had_failure = false;
+    fn wants_ticks(&self) -> bool {
+        self.battery.is_some()
+            || self.show_network
+            || self.require_network
+            || self.night_window.is_some()
+            || self.motd_file.is_some()
+            || self.config_warning.is_some()
+            || self.attract.is_some()
+            || self.log_stats_every.is_some()
+            || crate::signals::installed()
+    }
 
-        let stdin_handle = std::io::stdin();
-        let stdin_lock = stdin_handle.lock();
-        let mut stdin_bytes = stdin_lock.bytes();
+    /// Timed updates while waiting for input. Failures here are cosmetic.
+    /// Returns true if the whole screen needs drawing again.
+    fn tick(&mut self) -> bool {
+        let warning_expired = self
+            .config_warning
+            .take_if(|(_, until)| *until <= Instant::now())
+            .is_some();
+        if crate::signals::take_redraw() {
+            info!("Redrawing everything, as asked");
+            return true;
+        }
+        if crate::signals::take_reload() && self.reload_config() {
+            return true;
+        }
+        let attract_moved = self
+            .attract
+            .as_mut()
+            .is_some_and(|attract| attract.advance(Instant::now()));
+        if warning_expired
+            | attract_moved
+            | self.update_night_mode()
+            | self.update_motd()
+        {
+            // Everything is redrawn anyway.
+            return true;
+        }
+        self.log_stats();
+        if let Err(e) = self.update_battery() {
+            warn!("Unable to draw battery status: {e}");
+        }
+        if let Err(e) = self.update_network() {
+            warn!("Unable to draw network status: {e}");
+        }
+        false
+    }
 
-        let mut read_byte =
-            || -> Option<u8> { stdin_bytes.next().and_then(Result::ok) };
+    /// Shows what PAM has to say during a login, and turns the spinner
+    /// next to it while it works.
+    fn show_progress(&mut self, progress: Progress<'_>, frame: &mut usize) {
+        match progress {
+            Progress::Message { text, error } => {
+                info!("PAM says: {text}");
+                let color = if error {
+                    self.colors.error
+                } else {
+                    self.colors.neutral
+                };
+                self.set_message(text, color);
+                if let Err(e) = self.draw_message() {
+                    warn!("Unable to draw message: {e}");
+                }
+                self.flush();
+            }
+            Progress::Waiting => {
+                *frame += 1;
+                if let Err(e) = self.draw_message_spinner(*frame) {
+                    warn!("Unable to draw spinner: {e}");
+                }
+                self.refresh();
+            }
+        }
+    }
 
-        if self.show_target_row() {
-            if let Err(e) = self.draw_target() {
-                error!("Fatal: unable to draw target session: {e}");
-                return;
+    /// Runs `f` with the greetd backend lent out of `self`, so that it can
+    /// draw while greetd works. The backend goes back afterwards, even if
+    /// `f` panics; meanwhile `self` has a dry run in its place.
+    fn with_greetd<T>(
+        &mut self,
+        f: impl FnOnce(&mut crate::greetd::Backend, &mut Self) -> T
+    ) -> T {
+        struct Lent<'a, 'b> {
+            lm: &'a mut crate::LoginManager<'b>,
+            greetd: crate::greetd::Backend
+        }
+        impl Drop for Lent<'_, '_> {
+            fn drop(&mut self) {
+                std::mem::swap(&mut self.lm.greetd, &mut self.greetd);
             }
         }
 
-        if self.show_username_row() {
-            if let Err(e) = self.draw_username(&username, true) {
-                error!("Fatal: unable to draw username prompt: {e}");
-                return;
+        let greetd =
+            std::mem::replace(&mut self.greetd, crate::greetd::Backend::DryRun);
+        let mut lent = Lent { lm: self, greetd };
+        let Lent { lm, greetd } = &mut lent;
+        f(greetd, lm)
+    }
+
+    /// Logs the drawing statistics if `logging.stats` wants them by now.
+    fn log_stats(&mut self) {
+        let every = match self.log_stats_every {
+            Some(every) => every,
+            None => return
+        };
+        if self.stats_logged_at.elapsed() >= every {
+            self.stats_logged_at = Instant::now();
+            info!("Draw stats: {}", self.stats);
+        }
+    }
+
+    /// Opens the username list with `highlight` selected, or closes it.
+    /// The form changes height either way, so this clears the screen for
+    /// a full redraw.
+    fn set_user_list(&mut self, highlight: Option<usize>) {
+        self.user_list = highlight.map(|_| (0, 0));
+        if let Some(highlight) = highlight {
+            self.scroll_user_list(highlight);
+        }
+        self.relayout();
+        self.clear();
+    }
+
+    /// Moves the list highlight, scrolling to keep it in view.
+    fn scroll_user_list(&mut self, highlight: usize) {
+        let rows = self.user_list_rows.max(1);
+        if let Some((current, first)) = &mut self.user_list {
+            *current = highlight;
+            if highlight < *first {
+                *first = highlight;
+            } else if highlight >= *first + rows {
+                *first = highlight + 1 - rows;
             }
-            last_username_len = username.len();
+        }
+    }
+
+    /// Runs the greeter until a login succeeds, the user quits or drawing
+    /// fails, and returns which. With a replay script, returns how the
+    /// script went instead if it failed.
+    pub(crate) fn greeter_loop(
+        &mut self,
+        replay: Option<Replay>
+    ) -> Result<Exit, ReplayError> {
+        let mut input =
+            crate::input::Input::new(replay, self.max_repeat_per_sec);
+        let exit = self.run_greeter(&mut input);
+        info!("Refreshed the screen {} times", self.refreshes);
+        if exit == Exit::Fatal {
+            self.show_fatal("mflm: unable to draw", exit);
+        }
+        input.finish().map(|()| exit)
+    }
+
+    fn run_greeter(&mut self, input: &mut crate::input::Input) -> Exit {
+        let mut last_target_index = self.target_index;
+        let mut last_mode = self.form.mode;
+        let mut full_redraw = false;
+        // Typed prefix and current candidate while cycling completions.
+        let mut completion: Option<(String, Option<usize>)> = None;
+        // The lowercase name offered after a failed login, with
+        // login.username_case_hint.
+        let mut case_hint: Option<String> = None;
+        // Auto-submit state: a failed auto-submit disarms it until a key
+        // other than a character is pressed, so a reader that keeps
+        // retyping a bad PIN can't loop.
+        let mut auto_submit_armed = true;
+        let mut submit_now = false;
+        let mut password_typed_at: Option<Instant> = None;
+
+        if self.tick() {
+            self.clear();
+            full_redraw = true;
+        }
+
+        if self.show_target_row()
+            && !self.try_draw("target session", Self::draw_target)
+        {
+            return Exit::Fatal;
+        }
+
+        if self.show_username_row()
+            && !self.try_draw("username prompt", |lm| lm.draw_username(true))
+        {
+            return Exit::Fatal;
         }
 
         loop {
-            if self.show_username_row() && username.len() != last_username_len {
-                if let Err(e) = self.draw_username(
-                    &username,
-                    username.len() < last_username_len
-                ) {
-                    error!("Fatal: unable to draw username prompt: {e}");
-                    return;
-                }
-                last_username_len = username.len();
+            self.watchdog.beat();
+            self.report_status();
+
+            // A device typing is drawn once it's done, not per character.
+            let bursting = input.burst_continues();
+
+            // Both rows compare against what they last drew, and return
+            // early if nothing changed.
+            if !bursting
+                && self.show_username_row()
+                && !self.try_draw("username prompt", |lm| {
+                    lm.draw_username(false)
+                })
+            {
+                return Exit::Fatal;
             }
-            if password.len() != last_password_len {
-                if let Err(e) = self.draw_password(
-                    &password,
-                    password.len() < last_password_len
-                ) {
-                    error!("Fatal: unable to draw password prompt: {e}");
-                    return;
-                }
-                last_password_len = password.len();
+            if !bursting
+                && !self.try_draw("password prompt", |lm| {
+                    lm.draw_password(false)
+                })
+            {
+                return Exit::Fatal;
             }
             if self.show_target_row() && last_target_index != self.target_index {
-                if let Err(e) = self.draw_target() {
-                    error!("Fatal: unable to draw target session: {e}");
-                    return;
+                if !self.try_draw("target session", Self::draw_target) {
+                    return Exit::Fatal;
                 }
                 last_target_index = self.target_index;
             }
-            if last_mode != self.mode {
-                let bg = self.colors.neutral;
-                if let Err(e) = self.draw_bg(&bg) {
-                    error!("Fatal: unable to draw background: {e}");
-                    return;
-                }
-                if self.show_target_row() {
-                    if let Err(e) = self.draw_target() {
-                        error!("Fatal: unable to draw target session: {e}");
-                        return;
-                    }
+            if full_redraw {
+                if !self.try_draw("form", Self::draw_form) {
+                    return Exit::Fatal;
                 }
-                if self.show_username_row() {
-                    if let Err(e) = self.draw_username(&username, true) {
-                        error!("Fatal: unable to draw username prompt: {e}");
-                        return;
-                    }
+                last_mode = self.form.mode;
+                full_redraw = false;
+            } else if last_mode != self.form.mode {
+                // Only the rows losing and gaining focus change, and the
+                // key hints; the text rows above noticed on their own.
+                let session = crate::Mode::SelectingSession;
+                if self.show_target_row()
+                    && (last_mode == session || self.form.mode == session)
+                    && !self.try_draw("target session", Self::draw_target)
+                {
+                    return Exit::Fatal;
                 }
-                if let Err(e) = self.draw_password(&password, true) {
-                    error!("Fatal: unable to draw password prompt: {e}");
-                    return;
+                if !self.try_draw("footer", Self::draw_footer) {
+                    return Exit::Fatal;
                 }
-                last_mode = self.mode;
+                last_mode = self.form.mode;
             }
 
-            if had_failure {
-                let bg = self.colors.neutral;
-                if let Err(e) = self.draw_bg(&bg) {
-                    error!("Fatal: unable to draw background: {e}");
-                    return;
+            if self.message_dirty {
+                if !self.try_draw("message", Self::draw_message) {
+                    return Exit::Fatal;
+                }
+                self.refresh();
+            }
+
+            let idle_deadline = match (self.auto_submit_idle, password_typed_at)
+            {
+                (Some(idle), Some(at))
+                    if auto_submit_armed
+                        && self.form.mode == crate::Mode::EditingPassword
+                        && !self.form.password.is_empty() =>
+                {
+                    Some(at + idle)
+                }
+                _ => None
+            };
+            let mut peek_deadline = match (self.peeking, self.password_peek) {
+                (Some((_, at)), Some(peek)) => Some(at + peek),
+                _ => None
+            };
+            let mut armed_deadline = self.form.armed_until();
+            let mut blink_deadline =
+                self.submit_blink(Instant::now()).map(|(_, next)| next);
+            let mut flash_deadline = self.password_flash;
+            let mut repaint = false;
+            while !submit_now {
+                let now = Instant::now();
+                if armed_deadline.is_some_and(|d| d <= now) {
+                    armed_deadline = None;
+                    self.form.disarm();
+                }
+                if blink_deadline.is_some_and(|d| d <= now) {
+                    blink_deadline =
+                        self.submit_blink(now).map(|(_, next)| next);
+                    if !self.try_draw("password prompt", |lm| {
+                        lm.draw_password(true)
+                    }) {
+                        return Exit::Fatal;
+                    }
+                    self.refresh();
+                    continue;
                 }
-                if self.show_target_row() {
-                    if let Err(e) = self.draw_target() {
-                        error!("Fatal: unable to draw target session: {e}");
-                        return;
+                if peek_deadline.is_some_and(|d| d <= now) {
+                    peek_deadline = None;
+                    self.peeking = None;
+                    if !self.try_draw("password prompt", |lm| {
+                        lm.draw_password(true)
+                    }) {
+                        return Exit::Fatal;
                     }
+                    self.refresh();
+                    continue;
                 }
-                if self.show_username_row() {
-                    if let Err(e) = self.draw_username(&username, true) {
-                        error!("Fatal: unable to draw username prompt: {e}");
-                        return;
+                if flash_deadline.is_some_and(|d| d <= now) {
+                    flash_deadline = None;
+                    self.password_flash = None;
+                    if !self.try_draw("password prompt", |lm| {
+                        lm.draw_password(true)
+                    }) {
+                        return Exit::Fatal;
                     }
+                    self.refresh();
+                    continue;
+                }
+                if idle_deadline.is_some_and(|d| d <= now) {
+                    info!("Password input idle; submitting");
+                    submit_now = true;
+                    break;
+                }
+                // A held back refresh must happen even if no more keys
+                // come, or the last one wouldn't show.
+                let refresh_deadline = self.pending_refresh();
+                if refresh_deadline.is_some_and(|d| d <= now) {
+                    self.refresh();
+                    continue;
+                }
+                let next = [
+                    idle_deadline,
+                    peek_deadline,
+                    armed_deadline,
+                    blink_deadline,
+                    flash_deadline,
+                    refresh_deadline
+                ]
+                .iter()
+                .flatten()
+                .min()
+                .copied();
+                let timeout = match next {
+                    Some(deadline) => (deadline - now).min(crate::TICK),
+                    None if self.wants_ticks() => crate::TICK,
+                    None => break
+                };
+                self.watchdog.idle();
+                let ready = input.wait(timeout);
+                self.watchdog.beat();
+                if ready {
+                    break;
                 }
-                if let Err(e) = self.draw_password(&password, true) {
-                    error!("Fatal: unable to draw password prompt: {e}");
-                    return;
+                if self.tick() {
+                    repaint = true;
+                    break;
                 }
-                had_failure = false;
+                self.refresh();
             }
 
-            let b = match read_byte() {
-                Some(b) => b,
-                None => {
-                    warn!("stdin closed; exiting greeter loop");
-                    return;
+            if repaint {
+                self.clear();
+                full_redraw = true;
+                continue;
+            }
+
+            let auto_submit = std::mem::take(&mut submit_now);
+            // Typed while logging in; the failure it follows stays up.
+            let typed_ahead = input.has_queued();
+            let mut pasted = None;
+            let key = if auto_submit {
+                Key::Unknown
+            } else {
+                let message = self.message.as_ref().map(|(t, _)| t.as_str());
+                input.observe(self.form.mode, message);
+                self.watchdog.idle();
+                let event = input.read_event();
+                self.watchdog.beat();
+                match event {
+                    Some(Event::Key(key)) => key,
+                    Some(Event::Paste(text)) => {
+                        pasted = Some(text);
+                        Key::Unknown
+                    }
+                    Some(Event::RateLimited) => {
+                        warn!("Keyboard input rate-limited");
+                        let color = self.colors.error;
+                        self.set_message("Keyboard input rate-limited", color);
+                        continue;
+                    }
+                    Some(Event::StuckKey) => {
+                        warn!("Stuck key; clearing the field and pausing input");
+                        if let Some(field) = self.form.field_mut() {
+                            field.clear();
+                        }
+                        let color = self.colors.error;
+                        self.set_message("Stuck key? Input paused", color);
+                        continue;
+                    }
+                    // Nothing more can be typed, so it's as good as a
+                    // quit; see `Exit::Quit`.
+                    None => {
+                        warn!("stdin closed; exiting greeter loop");
+                        return Exit::Quit;
+                    }
                 }
             };
 
-            match b as char {
-                '\x15' | '\x0B' => match self.mode {
-                    // ctrl-k/ctrl-u
-                    crate::Mode::SelectingSession => (),
-                    crate::Mode::EditingUsername => {
-                        username.clear();
+            if self.attract.as_mut().is_some_and(|attract| attract.touch()) {
+                // Back to the static form straight away.
+                self.clear();
+                full_redraw = true;
+            }
+
+            if !typed_ahead {
+                self.clear_message();
+            }
+
+            if self.form_state != crate::FormState::Idle && !typed_ahead {
+                // A failure shows until the next keypress, like its message.
+                self.form_state = crate::FormState::Idle;
+                full_redraw = true;
+            }
+
+            if self.config_warning.take().is_some() {
+                // Dismissed; the strip it was drawn in needs clearing.
+                self.clear();
+                full_redraw = true;
+            }
+
+            if self.peeking.take().is_some() {
+                // Mask the peeked character again before anything else.
+                self.drawn_password = None;
+            }
+
+            // The debug overlay is up over everything until dismissed;
+            // other keys would draw over it.
+            if self.showing_debug {
+                if self.keys.action(key) == Some(Action::DebugOverlay) {
+                    self.showing_debug = false;
+                    self.clear();
+                    full_redraw = true;
+                }
+                continue;
+            }
+
+            // An open username list takes the arrows, Enter and Esc; any
+            // other key closes it and then does what it normally does.
+
+            if let Some((highlight, _)) = self.user_list {
+                match key {
+                    Key::Up | Key::Down if pasted.is_none() => {
+                        let last = self.username_candidates.len() - 1;
+                        let next = if key == Key::Up {
+                            highlight.saturating_sub(1)
+                        } else {
+                            (highlight + 1).min(last)
+                        };
+                        self.scroll_user_list(next);
+                        if !self.try_draw("user list", Self::draw_user_list) {
+                            return Exit::Fatal;
+                        }
+                        continue;
                     }
-                    crate::Mode::EditingPassword => password.clear()
-                },
-                '\x03' | '\x04' => {
-                    // ctrl-c/ctrl-D
-                    username.clear();
-                    password.clear();
+                    Key::Enter | Key::Escape if pasted.is_none() => {
+                        if key == Key::Enter {
+                            self.form.username = TextField::from_text(
+                                self.username_candidates[highlight].clone()
+                            );
+                            self.form.username_prefilled = false;
+                        }
+                        self.set_user_list(None);
+                        full_redraw = true;
+                        continue;
+                    }
+                    _ => {
+                        self.set_user_list(None);
+                        full_redraw = true;
+                    }
+                }
+            }
+
+            let action = if auto_submit {
+                Some(Action::Submit)
+            } else {
+                if pasted.is_none() && !matches!(key, Key::Char(_)) {
+                    auto_submit_armed = true;
+                }
+                if self.form.mode == crate::Mode::EditingPassword {
+                    password_typed_at = Some(Instant::now());
+                }
+                self.keys.action(key)
+            };
+
+            // login.submit_key guards the submit that actually logs in.
+            let action = if auto_submit {
+                self.form.disarm();
+                action
+            } else {
+                let was_armed = self.form.armed_at().is_some();
+                let action = match self.form.gate(key, action, Instant::now()) {
+                    Ok(action) => action,
+                    Err(Hold::NeedCtrlEnter) => {
+                        let color = self.colors.neutral;
+                        self.set_message("Press Ctrl+Enter to log in", color);
+                        None
+                    }
+                    // The underline blinks until the window ends.
+                    Err(Hold::NeedSecondEnter) => None
+                };
+                if was_armed || self.form.armed_at().is_some() {
+                    self.drawn_password = None;
+                }
+                action
+            };
+            if !matches!(
+                action,
+                Some(Action::CompleteNext) | Some(Action::CompletePrev)
+            ) {
+                // Any other key accepts the completed text.
+                completion = None;
+            }
+            // Only the key right after the hint can take it.
+            if let Some(name) = case_hint.take() {
+                if action == Some(Action::NextField)
+                    && self.form.mode == crate::Mode::EditingUsername
+                {
+                    info!("Using the suggested lowercase username");
+                    self.form.username = TextField::from_text(name);
+                    self.form.username_prefilled = false;
+                }
+            }
+
+            self.form.before_key(key, action, pasted.is_some());
+
+            if let Some(text) = pasted {
+                // Inserted as text, never interpreted as keys.
+                let typed = self.form.paste(&text);
+                self.refused(typed);
+            }
+
+            match action {
+                Some(action @ Action::CompleteNext)
+                | Some(action @ Action::CompletePrev) => {
+                    if self.form.mode == crate::Mode::EditingUsername
+                        && !self.username_candidates.is_empty()
+                    {
+                        let (prefix, current) = completion.take().unwrap_or_else(
+                            || (self.form.username.as_str().to_string(), None)
+                        );
+                        let next = self.complete_username(
+                            &prefix,
+                            current,
+                            action == Action::CompleteNext
+                        );
+                        match next {
+                            Some(i) => {
+                                self.form.username = TextField::from_text(
+                                    self.username_candidates[i].clone()
+                                );
+                            }
+                            None => self.beep()
+                        }
+                        completion = Some((prefix, next));
+                    }
+                }
+                Some(Action::ClearField) => {
+                    if let Some(field) = self.form.field_mut() {
+                        field.clear();
+                    }
+                }
+                Some(Action::DeleteWord) => self.form.delete_word(),
+                Some(Action::Quit) => {
+                    self.form.username.clear();
+                    self.form.password.clear();
                     if let Err(e) = self.greetd.cancel() {
                         warn!("Failed to cancel greetd session: {e}");
                     }
-                    return;
+                    return Exit::Quit;
                 }
-                '\x7F' => match self.mode {
-                    // backspace
-                    crate::Mode::SelectingSession => (),
-                    crate::Mode::EditingUsername => {
-                        username.pop();
+                Some(Action::NextField) => self.move_focus(1),
+                Some(Action::PrevField) => self.move_focus(-1),
+                // In the text fields the session keys move the cursor.
+                Some(Action::NextSession) => match self.form.field_mut() {
+                    Some(field) => field.move_right(),
+                    None => {
+                        if self.show_target_row() {
+                            self.next_target();
+                        }
                     }
-                    crate::Mode::EditingPassword => {
-                        password.pop();
+                },
+                Some(Action::PrevSession) => match self.form.field_mut() {
+                    Some(field) => field.move_left(),
+                    None => {
+                        if self.show_target_row() {
+                            self.prev_target();
+                        }
                     }
                 },
-                '\t' => self.goto_next_mode(),
-                '\r' => match self.mode {
-                    crate::Mode::SelectingSession => {
-                        self.mode = if self.show_username_row() {
-                            crate::Mode::EditingUsername
-                        } else {
-                            crate::Mode::EditingPassword
-                        };
+                Some(Action::LineStart) => {
+                    if let Some(field) = self.form.field_mut() {
+                        field.move_home();
                     }
-                    crate::Mode::EditingUsername => {
-                        if !username.is_empty() {
-                            self.mode = crate::Mode::EditingPassword;
+                }
+                Some(Action::LineEnd) => {
+                    if let Some(field) = self.form.field_mut() {
+                        field.move_end();
+                    }
+                }
+                Some(Action::ToggleReveal) => {
+                    self.reveal_password = !self.reveal_password;
+                    // Same text, different rendering.
+                    self.drawn_password = None;
+                }
+                Some(Action::UserList) => {
+                    if self.form.mode == crate::Mode::EditingUsername
+                        && self.user_list_rows > 0
+                        && !self.username_candidates.is_empty()
+                    {
+                        // Start from the typed name if it's in the list.
+                        let highlight = self
+                            .username_candidates
+                            .iter()
+                            .position(|name| {
+                                name == self.form.username.as_str()
+                            })
+                            .unwrap_or(0);
+                        self.set_user_list(Some(highlight));
+                        full_redraw = true;
+                    } else {
+                        self.beep();
+                    }
+                }
+                Some(Action::DebugOverlay) => {
+                    if self.debug_overlay {
+                        self.showing_debug = true;
+                        self.stats.reset();
+                        if let Err(e) = self.draw_debug_overlay() {
+                            warn!("Unable to draw debug overlay: {e}");
                         }
                     }
-                    crate::Mode::EditingPassword => {
-                        if password.is_empty() {
-                            if self.show_username_row() {
-                                username.clear();
-                                self.mode = crate::Mode::EditingUsername;
+                }
+                Some(Action::Rescan) => {
+                    if self.rescan_targets() {
+                        // Rows may have moved; start from a blank screen.
+                        self.clear();
+                        full_redraw = true;
+                    }
+                }
+                Some(Action::Shutdown) => {
+                    let cmd = self.shutdown_command.clone();
+                    if !self.run_power_command("shutdown", &cmd) {
+                        full_redraw = true;
+                    }
+                }
+                Some(Action::Reboot) => {
+                    let cmd = self.reboot_command.clone();
+                    if !self.run_power_command("reboot", &cmd) {
+                        full_redraw = true;
+                    }
+                }
+                Some(Action::Submit) => {
+                    let mut timing = Stopwatch::new();
+                    let from_username =
+                        self.form.mode == crate::Mode::EditingUsername;
+                    match self.form.submit(Instant::now()) {
+                        Submit::Moved => (),
+                        Submit::TooSoon => {
+                            info!("Ignoring submit so soon after a failure");
+                        }
+                        Submit::ControlChars => {
+                            warn!("Rejecting username containing control characters");
+                            let color = self.colors.error;
+                            self.set_message(
+                                "Username contains control characters",
+                                color
+                            );
+                        }
+                        Submit::Ready
+                            if self.require_network
+                                && self.online != Some(true)
+                                && !crate::network::is_online() =>
+                        {
+                            info!("Not submitting while the network is down");
+                            let color = self.colors.error;
+                            self.set_message(
+                                "Waiting for the network to come up",
+                                color
+                            );
+                        }
+                        Submit::Ready => {
+                            if from_username {
+                                info!("Password typed first; submitting");
                             }
-                        } else {
+                            let (username_for_login, password_for_login) =
+                                self.form.credentials();
                             info!(
-                                "Attempting login via greetd (session_index={}, username_len={})",
+                                "Attempting login via greetd (session_index={}, {})",
                                 self.target_index,
-                                username.len()
+                                crate::log_safe::username(&username_for_login)
                             );
-
-                            let username_for_login = if self.show_username_row() {
-                                username.clone()
-                            } else {
-                                self.forced_username
-                                    .clone()
-                                    .unwrap_or_else(|| username.clone())
-                            };
-                            let password_for_login =
-                                std::mem::take(&mut password);
-                            let res = self.greetd.login(
-                                username_for_login,
-                                password_for_login,
-                                self.targets[self.target_index].exec.clone()
+                            crate::log_safe::trace_username(
+                                "Logging in as",
+                                &username_for_login
+                            );
+                            let attempted = username_for_login.clone();
+                            let target = self.current_target();
+                            info!(
+                                "Session {:?} will start with {:?} (Exec={:?})",
+                                target.name,
+                                target.effective_exec,
+                                target.exec
                             );
+                            let cmd = Arc::clone(&target.effective_exec);
+                            let session = target.name.clone();
 
-                            if self.show_username_row() {
-                                username =
-                                    String::with_capacity(crate::USERNAME_CAP);
-                            } else {
-                                username =
-                                    self.forced_username.clone().unwrap();
+                            // Show that something is happening while PAM
+                            // takes its time.
+                            self.form_state = crate::FormState::Authenticating;
+                            let color = self.colors.neutral;
+                            self.set_message("Logging in...", color);
+                            if let Err(e) = self.draw_form() {
+                                warn!("Unable to draw form: {e}");
                             }
-                            password =
-                                String::with_capacity(crate::PASSWORD_CAP);
+                            if let Err(e) = self.draw_message() {
+                                warn!("Unable to draw message: {e}");
+                            }
+                            self.flush();
+                            timing.lap("draw");
+
+                            // PAM may wait on the user, e.g. for a
+                            // security key touch. The form is drawn while it
+                            // does.
+                            self.watchdog.idle();
+                            let mut frame = 0;
+                            let res = self.with_greetd(|greetd, lm| {
+                                greetd.login(
+                                    username_for_login,
+                                    password_for_login,
+                                    &cmd,
+                                    &mut timing,
+                                    &mut |progress| {
+                                        lm.show_progress(progress, &mut frame)
+                                    }
+                                )
+                            });
+                            self.watchdog.beat();
+                            info!("login timing: {timing}");
+
+                            self.form
+                                .finish_attempt(res.is_err(), Instant::now());
                             match res {
                                 Ok(_) => {
                                     info!(
                                         "Login succeeded; exiting greeter loop"
                                     );
-
-                                    if self.blackout_on_success {
-                                        let mut buf = crate::buffer::Buffer::new(
-                                            self.buf,
-                                            self.screen_size
-                                        );
-                                        buf.memset(&crate::color::Color::from_rgba_u8(
-                                            0,
-                                            0,
-                                            0,
-                                            0xFF
-                                        ));
-                                        self.should_refresh = true;
-                                        self.refresh();
+                                    if let Some(state) = &mut self.state {
+                                        state.clear_failures();
                                     }
-                                    return;
+                                    self.status.send("auth=success");
+
+                                    self.wipe_screen();
+                                    self.hand_off(&session);
+                                    return Exit::LoggedIn;
                                 }
                                 Err(e) => {
                                     warn!("Login failed: {e}");
-                                    let bg = self.colors.error;
-                                    if let Err(e) = self.draw_bg(&bg) {
-                                        error!("Fatal: unable to draw background: {e}");
-                                        return;
+                                    if let (
+                                        Some(state),
+                                        GreetDError::AuthFailed { .. }
+                                    ) = (&mut self.state, &e)
+                                    {
+                                        state.record_failure();
                                     }
-                                    self.mode =
-                                        if self.show_username_row() {
-                                            crate::Mode::EditingUsername
-                                        } else {
-                                            crate::Mode::EditingPassword
-                                        };
-                                    if let Err(e) = self.greetd.cancel() {
-                                        warn!("Failed to cancel greetd session after login failure: {e}");
+                                    if auto_submit {
+                                        info!("Auto-submit disabled until a non-character key is pressed");
+                                        auto_submit_armed = false;
+                                    }
+                                    self.beep();
+                                    // Daemon errors aren't the user's fault;
+                                    // don't style them like a bad password.
+                                    let (color, text) = match &e {
+                                        GreetDError::Daemon { description } => (
+                                            self.colors.neutral,
+                                            format!("greetd error: {description}")
+                                        ),
+                                        GreetDError::AuthFailed { description }
+                                            if !description.trim().is_empty() =>
+                                        {
+                                            (self.colors.error, description.clone())
+                                        }
+                                        _ => (
+                                            self.colors.error,
+                                            "Login failed".to_string()
+                                        )
+                                    };
+                                    self.form_state = match &e {
+                                        GreetDError::Daemon { .. } => {
+                                            crate::FormState::Idle
+                                        }
+                                        _ => crate::FormState::Failed
+                                    };
+                                    self.status
+                                        .send(&format!("auth=failed: {text}"));
+                                    case_hint = match &e {
+                                        GreetDError::AuthFailed { .. }
+                                            if self.username_case_hint
+                                                && self.show_username_row() =>
+                                        {
+                                            accounts::case_suggestion(&attempted)
+                                        }
+                                        _ => None
+                                    };
+                                    let text = match &case_hint {
+                                        Some(name) => {
+                                            self.with_case_hint(&text, name)
+                                        }
+                                        None => text
+                                    };
+                                    self.set_message(&text, color);
+                                    full_redraw = true;
+
+                                    // Keys typed while PAM was busy are the
+                                    // next password; Enter among them
+                                    // would submit it half-typed.
+                                    let queued = input.queue_pending(|event| {
+                                        match event {
+                                            Event::Key(key) => {
+                                                !self.is_submit(*key)
+                                            }
+                                            Event::Paste(_) => true,
+                                            _ => false
+                                        }
+                                    });
+                                    if queued > 0 {
+                                        info!("Replaying {queued} events typed while logging in");
+                                        self.form.mode =
+                                            crate::Mode::EditingPassword;
                                     }
-                                    had_failure = true;
                                 }
                             }
                         }
                     }
-                },
-                // this is terrible
-                '\x1b' => if let Some(b'[') = read_byte() { match read_byte() {
-                    Some(b'A') => self.goto_prev_mode(),
-                    Some(b'B') => self.goto_next_mode(),
-                    Some(b'C') => if self.mode == crate::Mode::SelectingSession && self.show_target_row() {
-                        self.target_index = (self.target_index + 1)
-                            % self.targets.len()
-                    },
-                    Some(b'D') => if self.mode == crate::Mode::SelectingSession && self.show_target_row() {
-                            if self.target_index == 0 {
-                                self.target_index = self.targets.len();
-                            }
-                            self.target_index -= 1;
+                }
+                None => match key {
+                    Key::Backspace => {
+                        if let Some(field) = self.form.field_mut() {
+                            field.backspace();
                         }
-                    ,
-                    _ => () // shrug
-                } },
-                v => match self.mode {
-                    crate::Mode::SelectingSession => (),
-                    crate::Mode::EditingUsername => {
-                        username.push(v)
                     }
-                    crate::Mode::EditingPassword => password.push(v)
+                    Key::Delete => {
+                        if let Some(field) = self.form.field_mut() {
+                            field.delete();
+                        }
+                    }
+                    Key::Char(v) => match self.form.type_char(v) {
+                        Typed::Inserted
+                            if self.form.mode
+                                == crate::Mode::EditingPassword =>
+                        {
+                            let password = &self.form.password;
+                            if input.in_burst() {
+                                // Not typed by hand; nothing to check.
+                                self.peeking = None;
+                            } else if self.password_peek.is_some() {
+                                self.peeking = Some((
+                                    password.cursor_chars() - 1,
+                                    Instant::now()
+                                ));
+                            }
+                            if auto_submit_armed
+                                && self.auto_submit_length > 0
+                                && password.as_str().chars().count()
+                                    == self.auto_submit_length
+                            {
+                                info!("Password reached auto-submit length; submitting");
+                                submit_now = true;
+                            }
+                        }
+                        typed => self.refused(typed)
+                    },
+                    _ => ()
                 }
             }
             self.refresh();
         }
     }
+
+    /// Runs `draw`, once more if the error may be transient. False, with
+    /// the error logged, if it still failed.
+    fn try_draw(
+        &mut self,
+        what: &str,
+        mut draw: impl FnMut(&mut Self) -> Result<(), crate::Error>
+    ) -> bool {
+        let started = Instant::now();
+        let mut result = draw(self);
+        if let Err(e) = &result {
+            if !e.is_fatal() {
+                warn!("Unable to draw {what}, trying again: {e}");
+                result = draw(self);
+            }
+        }
+        self.stats.drew(started);
+        match result {
+            Ok(()) => true,
+            Err(e) => {
+                error!("Fatal: unable to draw {what}: {e}");
+                false
+            }
+        }
+    }
+
+    /// A failure message with the suggested lowercase username, naming
+    /// the key that takes it.
+    fn with_case_hint(&self, text: &str, name: &str) -> String {
+        let hint = format!("{text} - did you mean '{name}'?");
+        match self.keys.key_label(Action::NextField) {
+            Some(key) => format!("{hint} {key} uses it"),
+            None => hint
+        }
+    }
+
+    /// Runs a shutdown or reboot command, leaving the form up until it has
+    /// worked: the action may still be refused, e.g. by polkit. Returns
+    /// false if it failed, after clearing the screen to show why, so the
+    /// caller knows to redraw the form.
+    pub(crate) fn run_power_command(
+        &mut self,
+        name: &str,
+        cmdline: &str
+    ) -> bool {
+        info!("Running {name} command: {cmdline:?}");
+        let argv = match shell_words::split(cmdline) {
+            Ok(argv) if !argv.is_empty() => argv,
+            Ok(_) => {
+                warn!("No {name} command configured");
+                return true;
+            }
+            Err(e) => {
+                warn!("Unable to parse {name} command {cmdline:?}: {e}");
+                let color = self.colors.error;
+                self.set_message(&format!("Invalid {name} command"), color);
+                return true;
+            }
+        };
+
+        // Commands such as `systemctl poweroff` return once the action is
+        // under way, or with an error status if it was refused.
+        self.watchdog.idle();
+        let status = Command::new(&argv[0])
+            .args(&argv[1..])
+            .stdin(Stdio::null())
+            .status();
+        self.watchdog.beat();
+        let error = match status {
+            Ok(status) if status.success() => {
+                self.wipe_screen();
+                return true;
+            }
+            Ok(status) => status.to_string(),
+            Err(e) => e.to_string()
+        };
+        warn!("Unable to run {name} command {cmdline:?}: {error}");
+        let color = self.colors.error;
+        self.set_message(&format!("Unable to {name} ({error})"), color);
+        self.clear();
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        field::TextField,
+        testing::{framebuffer, login_manager, pixels, run},
+        Mode
+    };
+
+    #[test]
+    fn power_command_leaves_only_the_background() {
+        let cases = [
+            ("", Some(0xFF00_0000)),
+            ("ui.blackout_on_success = false", None)
+        ];
+        for (config, wipe) in cases {
+            let mut fb = framebuffer(800, 600);
+            let mut lm = login_manager(&mut fb, config);
+            let bg = lm.colors.background.as_argb8888();
+            let wipe = wipe.unwrap_or(bg);
+            lm.clear();
+            lm.draw_bg().unwrap();
+            assert!(pixels(&lm).iter().any(|&px| px != wipe));
+
+            assert!(lm.run_power_command("shutdown", "true"));
+            assert!(pixels(&lm).iter().all(|&px| px == wipe), "{:?}", config);
+        }
+    }
+
+    #[test]
+    fn failed_power_command_brings_the_form_back() {
+        for cmdline in ["false", "/nonexistent/poweroff"] {
+            let mut fb = framebuffer(800, 600);
+            let mut lm = login_manager(&mut fb, "");
+            lm.clear();
+            lm.draw_bg().unwrap();
+            let form = pixels(&lm);
+
+            assert!(!lm.run_power_command("reboot", cmdline));
+            let (message, _) = lm.message.clone().unwrap();
+            assert!(message.starts_with("Unable to reboot ("), "{}", message);
+            assert!(lm.drawn_username.is_none());
+            assert!(lm.drawn_password.is_none());
+            // The caller redraws everything; the message aside, the form
+            // is back as it was.
+            lm.message = None;
+            lm.draw_bg().unwrap();
+            assert_eq!(pixels(&lm), form, "{:?}", cmdline);
+        }
+    }
+
+    #[test]
+    fn failed_replay_comes_back_to_the_caller() {
+        let mut fb = framebuffer(1024, 768);
+        let mut lm = login_manager(&mut fb, "");
+        let script = "type alice\nexpect-mode password\nkey enter";
+        let replay = crate::replay::Replay::from_script(script).unwrap();
+        assert!(lm.greeter_loop(Some(replay)).is_err());
+        // Stopped at the failure.
+        assert_eq!(lm.form.mode, Mode::EditingUsername);
+    }
+
+    #[test]
+    fn greetd_is_put_back_after_a_panic() {
+        use std::{
+            env,
+            os::unix::net::UnixListener,
+            panic::{self, AssertUnwindSafe}
+        };
+
+        use crate::greetd::{Backend, GreetD};
+
+        let path = env::temp_dir()
+            .join(format!("mflm-{}-lent.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let _listener = UnixListener::bind(&path).unwrap();
+        let socket = path.to_str().unwrap().to_string();
+
+        let mut fb = framebuffer(1024, 768);
+        let mut lm = login_manager(&mut fb, "");
+        lm.greetd = Backend::GreetD(GreetD::connect_to(socket, false).unwrap());
+        let lent = panic::catch_unwind(AssertUnwindSafe(|| {
+            lm.with_greetd(|greetd, lm| {
+                assert!(!greetd.is_dry_run());
+                assert!(lm.greetd.is_dry_run());
+                panic!("PAM module crashed");
+            })
+        }));
+        assert!(lent.is_err());
+        assert!(!lm.greetd.is_dry_run());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn line_start_and_end_move_the_cursor_in_either_field() {
+        for (start, end) in [("ctrl+a", "ctrl+e"), ("home", "end")] {
+            for mode in [Mode::EditingUsername, Mode::EditingPassword] {
+                let mut fb = framebuffer(1024, 768);
+                let mut lm = login_manager(&mut fb, "");
+                lm.form.mode = mode;
+                let script = format!(
+                    "type bob\nkey {start}\ntype x\nkey {end}\ntype y"
+                );
+                run(&mut lm, &script);
+                assert_eq!(lm.form.mode, mode);
+                let field = lm.form.field_mut().unwrap();
+                assert_eq!(field.as_str(), "xboby", "{} {:?}", start, mode);
+                assert_eq!(field.cursor(), 5);
+            }
+        }
+    }
+
+    #[test]
+    fn line_start_and_end_do_nothing_on_the_session_row() {
+        let mut fb = framebuffer(1024, 768);
+        let mut lm = login_manager(&mut fb, "");
+        let mut alice = TextField::from_text("alice".to_string());
+        alice.move_left();
+        alice.move_left();
+        lm.form.username = alice.clone();
+        lm.form.password = alice.clone();
+        lm.form.mode = Mode::SelectingSession;
+        let target = lm.target_index;
+        run(&mut lm, "key ctrl+a\nkey ctrl+e\nkey home\nkey end");
+        assert_eq!(lm.form.mode, Mode::SelectingSession);
+        assert_eq!(lm.target_index, target);
+        for field in [&lm.form.username, &lm.form.password] {
+            assert_eq!(field.as_str(), "alice");
+            assert_eq!(field.cursor(), alice.cursor());
+        }
+    }
 }
@@ -0,0 +1,159 @@
+//! `ui.show_session_icons`: the desktop entry's Icon= beside the session
+//! name. Only PNGs, by absolute path or from the hicolor and pixmaps
+//! directories; the full icon theme spec is more than a greeter needs.
+
+use std::{
+    collections::HashMap,
+    fs,
+    io,
+    path::{Path, PathBuf}
+};
+
+use cairo::{Context, Format, ImageSurface};
+use log::{debug, warn};
+use thiserror::Error;
+
+use crate::buffer::{Buffer, BufferError, Vect};
+
+/// Where icons named without a path are looked for, in order.
+const ICON_DIRS: &[&str] =
+    &["/usr/share/icons/hicolor/48x48/apps", "/usr/share/pixmaps"];
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum IconError {
+    #[error("unable to open: {0}")]
+    Open(#[source] io::Error),
+
+    #[error("not a PNG cairo can read: {0}")]
+    Decode(#[source] cairo::IoError),
+
+    #[error("unable to scale: {0}")]
+    Scale(#[source] cairo::Error),
+
+    #[error("unable to read the scaled pixels: {0}")]
+    Data(#[source] cairo::BorrowError)
+}
+
+/// The PNG file for an Icon= value, if there is one.
+pub fn resolve(icon: &str) -> Option<PathBuf> {
+    let path = Path::new(icon);
+    if path.is_absolute() {
+        let png = path.extension().is_some_and(|ext| ext == "png");
+        return (png && path.is_file()).then(|| path.to_path_buf());
+    }
+    if icon.is_empty() || icon.contains('/') {
+        return None;
+    }
+    let file = if icon.ends_with(".png") {
+        icon.to_string()
+    } else {
+        format!("{icon}.png")
+    };
+    ICON_DIRS
+        .iter()
+        .map(|dir| Path::new(dir).join(&file))
+        .find(|path| path.is_file())
+}
+
+/// A square icon, scaled once, as rows of premultiplied ARGB8888.
+pub struct Icon {
+    size: u32,
+    pixels: Vec<u32>
+}
+
+impl Icon {
+    /// Loads the PNG at `path`, scaled to fit `size` pixels square and
+    /// centered in it.
+    pub fn load(path: &Path, size: u32) -> Result<Self, IconError> {
+        let mut file = fs::File::open(path).map_err(IconError::Open)?;
+        let image = ImageSurface::create_from_png(&mut file)
+            .map_err(IconError::Decode)?;
+        let side = size.max(1) as i32;
+        let mut surface = ImageSurface::create(Format::ARgb32, side, side)
+            .map_err(IconError::Scale)?;
+        {
+            let ctx = Context::new(&surface).map_err(IconError::Scale)?;
+            let (w, h) = (image.width().max(1), image.height().max(1));
+            let scale = side as f64 / w.max(h) as f64;
+            ctx.translate(
+                (side as f64 - w as f64 * scale) / 2.0,
+                (side as f64 - h as f64 * scale) / 2.0
+            );
+            ctx.scale(scale, scale);
+            ctx.set_source_surface(&image, 0.0, 0.0)
+                .map_err(IconError::Scale)?;
+            ctx.paint().map_err(IconError::Scale)?;
+        }
+        surface.flush();
+
+        let stride = surface.stride() as usize;
+        let data = surface.data().map_err(IconError::Data)?;
+        let mut pixels = Vec::with_capacity((side * side) as usize);
+        for y in 0..side as usize {
+            let row = &data[y * stride..y * stride + side as usize * 4];
+            // Cairo's ARGB32 is BGRA in memory on little-endian machines.
+            pixels.extend(row.chunks_exact(4).map(|p| {
+                u32::from_be_bytes([p[3], p[2], p[1], p[0]])
+            }));
+        }
+        Ok(Self {
+            size: side as u32,
+            pixels
+        })
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Composites the icon over what's at `pos`, clipped to the buffer.
+    pub fn draw(
+        &self,
+        buf: &mut Buffer<'_>,
+        pos: Vect
+    ) -> Result<(), BufferError> {
+        let (_, _, _, h) = buf.get_bounds();
+        let rows = self.pixels.chunks_exact(self.size as usize);
+        for (y, row) in (pos.1..h).zip(rows) {
+            buf.blend_row_argb8888((pos.0, y), row)?;
+        }
+        Ok(())
+    }
+}
+
+/// Icons by Icon= value; `None` for those that didn't load, so they're
+/// only tried once.
+pub type IconCache = HashMap<String, Option<Icon>>;
+
+impl crate::LoginManager<'_> {
+    /// Loads the icons of the sessions not loaded yet, sized to the
+    /// session row. Does nothing without `ui.show_session_icons`.
+    pub(crate) fn load_session_icons(&mut self) {
+        let size = self.form_layout().row_h * 3 / 4;
+        let cache = match &mut self.session_icons {
+            Some(cache) => cache,
+            None => return
+        };
+        for target in self.targets.iter() {
+            let name = match &target.icon {
+                Some(name) if !cache.contains_key(name) => name,
+                _ => continue
+            };
+            let icon = match resolve(name) {
+                Some(path) => match Icon::load(&path, size) {
+                    Ok(icon) => Some(icon),
+                    Err(e) => {
+                        warn!("Unable to load icon {}: {e}", path.display());
+                        None
+                    }
+                },
+                None => {
+                    debug!("No PNG icon found for {name:?}");
+                    None
+                }
+            };
+            cache.insert(name.clone(), icon);
+        }
+    }
+}
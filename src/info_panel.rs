@@ -0,0 +1,227 @@
+//! `[panel]`: a column of fixed information beside the form, such as
+//! opening hours and a QR code for the wifi on a kiosk. Drawn with the
+//! background; typing never touches it.
+
+use log::warn;
+
+use crate::{
+    buffer::Rect,
+    icons::Icon,
+    settings::{self, PanelSide},
+    WithContext
+};
+
+/// Space around the panel's contents.
+const MARGIN_PX: u32 = 32;
+
+pub(crate) struct InfoPanel {
+    /// The whole column, full height.
+    rect: Rect,
+    image: Option<Icon>,
+    lines: Vec<String>
+}
+
+impl InfoPanel {
+    /// The panel for `[panel]` on a `screen` sized screen, if it has one.
+    pub fn new(panel: &settings::Panel, screen: (u32, u32)) -> Option<Self> {
+        let (screen_w, screen_h) = screen;
+        let w = screen_w * panel.width_percent.clamp(10, 90) / 100;
+        let x = match panel.side {
+            PanelSide::None => return None,
+            PanelSide::Left => 0,
+            PanelSide::Right => screen_w - w
+        };
+
+        let side = w.saturating_sub(MARGIN_PX * 2).min(screen_h / 2);
+        let image = panel.image.as_deref().and_then(|path| {
+            Icon::load(path.as_ref(), side)
+                .map_err(|e| warn!("Unable to load panel.image {path}: {e}"))
+                .ok()
+        });
+        let lines = panel
+            .text
+            .as_deref()
+            .unwrap_or_default()
+            .lines()
+            .map(|line| line.trim_end().to_string())
+            .collect();
+        Some(Self {
+            rect: (x, 0, w, screen_h),
+            image,
+            lines
+        })
+    }
+
+    /// The part of the screen width left for everything else, as its
+    /// start and width.
+    pub fn remaining(&self, screen_w: u32) -> (u32, u32) {
+        let (x, _, w, _) = self.rect;
+        if x == 0 {
+            (w, screen_w - w)
+        } else {
+            (0, x)
+        }
+    }
+}
+
+impl crate::LoginManager<'_> {
+    /// Sets up `[panel]`, and lays the form out beside it.
+    pub(crate) fn set_info_panel(&mut self, panel: &settings::Panel) {
+        self.info_panel = InfoPanel::new(panel, self.screen_size);
+        self.relayout();
+    }
+
+    /// Where the form and the greeting go: the screen width, less the
+    /// panel's.
+    pub(crate) fn content_area(&self) -> (u32, u32) {
+        match &self.info_panel {
+            Some(panel) => panel.remaining(self.screen_size.0),
+            None => (0, self.screen_size.0)
+        }
+    }
+
+    /// Draws the panel's image and text, centered in it as a block. Part
+    /// of the background.
+    pub(crate) fn draw_info_panel(&mut self) -> Result<(), crate::Error> {
+        let panel = match &self.info_panel {
+            Some(panel) => panel,
+            None => return Ok(())
+        };
+        let (_, _, w, h) = panel.rect;
+        let line_h = self.main_font.line_height();
+        let image_h = panel.image.as_ref().map_or(0, |image| image.size());
+        let gap = if image_h > 0 && !panel.lines.is_empty() {
+            line_h
+        } else {
+            0
+        };
+        let block_h = image_h + gap + panel.lines.len() as u32 * line_h;
+        let mut y = h.saturating_sub(block_h) / 2;
+
+        let mut buf = crate::buffer::Buffer::new(self.buf, self.screen_size);
+        let mut buf = buf.subdimensions(panel.rect).with_context("panel")?;
+        if let Some(image) = &panel.image {
+            let image_x = w.saturating_sub(image.size()) / 2;
+            image.draw(&mut buf, (image_x, y)).with_context("panel")?;
+            y += image_h + gap;
+        }
+
+        let text_w = w.saturating_sub(MARGIN_PX * 2);
+        let bg = self.colors.background;
+        let fg = self.colors.foreground;
+        for line in &panel.lines {
+            if y + line_h > h || text_w == 0 {
+                break;
+            }
+            let mut row = buf
+                .subdimensions((MARGIN_PX, y, text_w, line_h))
+                .with_context("panel")?;
+            self.main_font
+                .auto_draw_text_ellipsized(
+                    &mut row,
+                    &bg,
+                    &fg,
+                    line,
+                    pango::Alignment::Center
+                )
+                .with_context("panel")?;
+            y += line_h;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{framebuffer, login_manager, pixels, run};
+
+    fn panel(side: PanelSide, width_percent: u32) -> settings::Panel {
+        settings::Panel {
+            side,
+            width_percent,
+            ..settings::Panel::default()
+        }
+    }
+
+    #[test]
+    fn panel_takes_its_side_of_the_screen() {
+        let screen = (1920, 1080);
+        assert!(InfoPanel::new(&panel(PanelSide::None, 25), screen).is_none());
+
+        let left = InfoPanel::new(&panel(PanelSide::Left, 25), screen).unwrap();
+        assert_eq!(left.rect, (0, 0, 480, 1080));
+        assert_eq!(left.remaining(1920), (480, 1440));
+
+        let right =
+            InfoPanel::new(&panel(PanelSide::Right, 25), screen).unwrap();
+        assert_eq!(right.rect, (1440, 0, 480, 1080));
+        assert_eq!(right.remaining(1920), (0, 1440));
+
+        // Never all or none of the screen.
+        let narrow = InfoPanel::new(&panel(PanelSide::Left, 0), screen);
+        assert_eq!(narrow.unwrap().rect, (0, 0, 192, 1080));
+        let wide = InfoPanel::new(&panel(PanelSide::Right, 100), screen);
+        assert_eq!(wide.unwrap().rect, (192, 0, 1728, 1080));
+    }
+
+    const TEXT: &str = "text = \"Open 9 to 5\\nAsk at the desk\"";
+
+    #[test]
+    fn form_is_centered_beside_the_panel() {
+        for (side, content, x) in
+            [("left", (480, 1440), 944), ("right", (0, 1440), 464)]
+        {
+            let config =
+                format!("[panel]\nside = \"{side}\"\nwidth_percent = 25");
+            let mut fb = framebuffer(1920, 1080);
+            let lm = login_manager(&mut fb, &config);
+            assert_eq!(lm.content_area(), content, "{}", side);
+            let layout = lm.form_layout();
+            assert_eq!((layout.x, layout.w), (x, 512), "{}", side);
+            assert_eq!(layout.x - content.0, 464, "{}", side);
+        }
+    }
+
+    /// The screen with a panel on `side`, as the greeter starts up.
+    fn screen_with_panel(side: &str) -> Vec<u32> {
+        let config = format!(
+            "[panel]\nside = \"{side}\"\nwidth_percent = 25\n{TEXT}"
+        );
+        let mut fb = framebuffer(1920, 1080);
+        let mut lm = login_manager(&mut fb, &config);
+        run(&mut lm, "");
+        pixels(&lm)
+    }
+
+    #[test]
+    fn panel_text_is_centered_in_the_panel() {
+        let mut fb = framebuffer(1920, 1080);
+        let lm = login_manager(&mut fb, "");
+        let bg = lm.colors.background.as_argb8888();
+        let line_h = lm.main_font.line_height();
+
+        let screen = screen_with_panel("left");
+        let inked = |y: u32| {
+            (0..480).any(|x| screen[(y * 1920 + x) as usize] != bg)
+        };
+        let top = (1080 - 2 * line_h) / 2;
+        assert!((top..top + line_h).any(inked));
+        assert!((top + line_h..top + 2 * line_h).any(inked));
+        assert!(!(0..top).any(inked));
+        assert!(!(top + 2 * line_h..1080).any(inked));
+    }
+
+    #[test]
+    fn right_panel_mirrors_the_left_one() {
+        // The panel and the rest of the screen trade places, and neither
+        // changes otherwise. The corner indicators above the greeting
+        // stay where they are.
+        let left = screen_with_panel("left").split_off(24 * 1920);
+        let right = screen_with_panel("right").split_off(24 * 1920);
+        for (left, right) in left.chunks(1920).zip(right.chunks(1920)) {
+            assert_eq!(left[..480], right[1440..]);
+            assert_eq!(left[480..], right[..1440]);
+        }
+    }
+}
@@ -0,0 +1,570 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant}
+};
+
+use log::error;
+use nix::{
+    errno::Errno,
+    poll::{poll, PollFd, PollFlags},
+    unistd
+};
+
+pub use crate::decode::Event;
+use crate::{decode::Decoder, keys::Key, replay::Replay};
+
+const STDIN_FD: i32 = nix::libc::STDIN_FILENO;
+/// How long a key has to repeat too fast before it counts as stuck.
+const STUCK_AFTER: Duration = Duration::from_secs(3);
+/// How long everything is dropped after a stuck key.
+const STUCK_PAUSE: Duration = Duration::from_secs(1);
+/// The window `input.max_repeat_per_sec` counts characters in.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+/// Characters at most this far apart, `BURST_CHARS` or more in a row, are
+/// typed by a password manager, a YubiKey static password or a keyboard
+/// wedge, not by hand: 5 in 60ms is beyond anyone's typing, while those
+/// devices send 100 or more a second.
+pub const BURST_GAP: Duration = Duration::from_millis(15);
+const BURST_CHARS: u32 = 5;
+/// An LF this soon after a CR is the rest of a CRLF line ending, as badge
+/// readers send, not a separate Ctrl+J.
+const CRLF_WINDOW: Duration = Duration::from_millis(50);
+/// Most events `queue_pending` keeps.
+const QUEUE_CAP: usize = 64;
+
+/// What `RepeatLimiter` made of a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Pass,
+    Drop,
+    /// Dropped, and the first key ever dropped.
+    Limited,
+    /// Dropped, and input is paused.
+    Stuck
+}
+
+/// Drops more than `max_per_sec` of the same character a second, as a
+/// stuck or broken key sends them. Also notices bursts of characters
+/// typed by a device, which it lets through.
+pub struct RepeatLimiter {
+    max_per_sec: u32,
+    /// The repeating character, when its current second started, and how
+    /// many arrived in it.
+    run: Option<(char, Instant, u32)>,
+    /// When the run first went over the limit.
+    limited_since: Option<Instant>,
+    paused_until: Option<Instant>,
+    warned: bool,
+    /// When the last character arrived, and how many in a row arrived
+    /// within `BURST_GAP` of the one before.
+    burst: Option<(Instant, u32)>
+}
+
+impl RepeatLimiter {
+    /// 0 lets everything through.
+    pub fn new(max_per_sec: u32) -> Self {
+        Self {
+            max_per_sec,
+            run: None,
+            limited_since: None,
+            paused_until: None,
+            warned: false,
+            burst: None
+        }
+    }
+
+    /// Whether the last characters came in a burst, as a device types
+    /// them. Stays true until something else is read.
+    pub fn in_burst(&self) -> bool {
+        self.burst.is_some_and(|(_, run)| run >= BURST_CHARS)
+    }
+
+    pub fn check(&mut self, key: Key, now: Instant) -> Verdict {
+        if self.paused_until.is_some_and(|until| now < until) {
+            return Verdict::Drop;
+        }
+        self.burst = match (key, self.burst) {
+            (Key::Char(_), Some((last, run)))
+                if now.duration_since(last) <= BURST_GAP =>
+            {
+                Some((now, run + 1))
+            }
+            (Key::Char(_), _) => Some((now, 1)),
+            _ => None
+        };
+        let c = match key {
+            Key::Char(c) if self.max_per_sec > 0 => c,
+            _ => {
+                self.run = None;
+                self.limited_since = None;
+                return Verdict::Pass;
+            }
+        };
+
+        let count = match &mut self.run {
+            Some((run, start, count)) if *run == c => {
+                if now.duration_since(*start) >= RATE_WINDOW {
+                    // A second under the limit ends the run.
+                    if *count <= self.max_per_sec {
+                        self.limited_since = None;
+                    }
+                    *start = now;
+                    *count = 0;
+                }
+                *count += 1;
+                *count
+            }
+            _ => {
+                self.run = Some((c, now, 1));
+                self.limited_since = None;
+                1
+            }
+        };
+        if count <= self.max_per_sec {
+            return Verdict::Pass;
+        }
+
+        let since = *self.limited_since.get_or_insert(now);
+        if now.duration_since(since) >= STUCK_AFTER {
+            self.paused_until = Some(now + STUCK_PAUSE);
+            self.run = None;
+            self.limited_since = None;
+            Verdict::Stuck
+        } else if !self.warned {
+            self.warned = true;
+            Verdict::Limited
+        } else {
+            Verdict::Drop
+        }
+    }
+}
+
+/// Buffered stdin that can also wait for input with a timeout, so the
+/// greeter loop can wake up for timed updates between keypresses. Reads
+/// the fd directly: std's stdin buffer would hide pending bytes from poll.
+pub struct Input {
+    /// Stdin, except in tests.
+    fd: i32,
+    buf: [u8; 256],
+    start: usize,
+    end: usize,
+    /// With `--replay`, events come from the script instead of stdin.
+    replay: Option<Replay>,
+    limiter: RepeatLimiter,
+    /// When the last Enter was read, to swallow the LF of a CRLF.
+    last_enter: Option<Instant>,
+    /// Events from `queue_pending`, handed out before anything else.
+    queue: VecDeque<Event>,
+    decoder: Decoder
+}
+
+impl Input {
+    pub fn new(replay: Option<Replay>, max_repeat_per_sec: u32) -> Self {
+        Self {
+            fd: STDIN_FD,
+            buf: [0; 256],
+            start: 0,
+            end: 0,
+            replay,
+            limiter: RepeatLimiter::new(max_repeat_per_sec),
+            last_enter: None,
+            queue: VecDeque::new(),
+            decoder: Decoder::default()
+        }
+    }
+
+    /// Tells a replay script what the greeter looks like now, for its
+    /// `expect-*` lines.
+    pub(crate) fn observe(&mut self, mode: crate::Mode, message: Option<&str>) {
+        if let Some(replay) = &mut self.replay {
+            replay.observe(mode, message);
+        }
+    }
+
+    /// The outcome of the replay script, if there is one.
+    pub fn finish(self) -> Result<(), crate::replay::ReplayError> {
+        self.replay.map_or(Ok(()), Replay::finish)
+    }
+
+    /// Reads one byte, blocking. Returns `None` once input is closed.
+    pub fn read_byte(&mut self) -> Option<u8> {
+        if self.start == self.end && !self.fill() {
+            return None;
+        }
+        let byte = self.buf[self.start];
+        self.start += 1;
+        Some(byte)
+    }
+
+    /// Refills the buffer with whatever stdin has, blocking until there's
+    /// something. Reads interrupted by a signal are retried, and if stdin
+    /// was left non-blocking, EAGAIN waits for input. Returns false at end
+    /// of input or, after logging it, on a read error.
+    fn fill(&mut self) -> bool {
+        loop {
+            let error = match unistd::read(self.fd, &mut self.buf) {
+                Ok(0) => return false,
+                Ok(n) => {
+                    self.start = 0;
+                    self.end = n;
+                    return true;
+                }
+                Err(Errno::EINTR) => continue,
+                Err(Errno::EAGAIN) => {
+                    let mut fds = [PollFd::new(self.fd, PollFlags::POLLIN)];
+                    match poll(&mut fds, -1) {
+                        Ok(_) | Err(Errno::EINTR) => continue,
+                        Err(e) => e
+                    }
+                }
+                Err(e) => e
+            };
+            error!("Unable to read stdin: {error}");
+            return false;
+        }
+    }
+
+    /// Waits up to `timeout` for a byte to be available. Spurious wakeups
+    /// (signals) count as a timeout.
+    pub fn wait(&mut self, timeout: Duration) -> bool {
+        if let Some(replay) = &mut self.replay {
+            return replay.wait(timeout);
+        }
+        !self.queue.is_empty() || self.stdin_ready(timeout)
+    }
+
+    fn stdin_ready(&self, timeout: Duration) -> bool {
+        if self.start < self.end {
+            return true;
+        }
+
+        let mut fds = [PollFd::new(self.fd, PollFlags::POLLIN)];
+        let timeout_ms = timeout.as_millis().min(i32::MAX as u128) as i32;
+        matches!(poll(&mut fds, timeout_ms), Ok(n) if n > 0)
+    }
+
+    /// Reads everything typed so far without blocking, and queues up to
+    /// `QUEUE_CAP` of the events `keep` accepts to be read next. For
+    /// keystrokes typed while the greeter was busy. Returns how many were
+    /// queued.
+    pub fn queue_pending(&mut self, keep: impl Fn(&Event) -> bool) -> usize {
+        // A script has nothing typed ahead; its steps are all "pending".
+        if self.replay.is_some() {
+            return 0;
+        }
+        let mut queued = 0;
+        while self.stdin_ready(Duration::ZERO) {
+            let event = match self.read_stdin_event() {
+                Some(event) => event,
+                None => break
+            };
+            if keep(&event) && self.queue.len() < QUEUE_CAP {
+                self.queue.push_back(event);
+                queued += 1;
+            }
+        }
+        queued
+    }
+
+    /// Whether the last characters read came in a burst, as a password
+    /// manager or badge reader types them.
+    pub fn in_burst(&self) -> bool {
+        self.limiter.in_burst()
+    }
+
+    /// Whether a burst is still going: more input follows within
+    /// `BURST_GAP`. Waits that long for it.
+    pub fn burst_continues(&mut self) -> bool {
+        self.in_burst() && self.wait(BURST_GAP)
+    }
+
+    /// Whether the next `read_event` comes from `queue_pending`.
+    pub fn has_queued(&self) -> bool {
+        !self.queue.is_empty()
+    }
+
+    /// Reads one keypress or paste, blocking. Returns `None` once input
+    /// is closed.
+    pub fn read_event(&mut self) -> Option<Event> {
+        if let Some(replay) = &mut self.replay {
+            return replay.next_event();
+        }
+        if let Some(event) = self.queue.pop_front() {
+            return Some(event);
+        }
+        self.read_stdin_event()
+    }
+
+    /// Reads and decodes bytes until they make an event. A sequence cut
+    /// short is given up on after the decoder's timeout, so a lone Esc
+    /// doesn't wait for the next key.
+    fn decode_stdin(&mut self) -> Option<Event> {
+        loop {
+            let expired = self
+                .decoder
+                .timeout()
+                .is_some_and(|timeout| !self.stdin_ready(timeout));
+            if expired {
+                return Some(self.decoder.time_out());
+            }
+            let byte = self.read_byte()?;
+            if let Some(event) = self.decoder.push(byte) {
+                return Some(event);
+            }
+        }
+    }
+
+    fn read_stdin_event(&mut self) -> Option<Event> {
+        loop {
+            let key = match self.decode_stdin()? {
+                Event::Key(key) => key,
+                event => return Some(event)
+            };
+            let now = Instant::now();
+            let crlf = self
+                .last_enter
+                .take()
+                .is_some_and(|at| now.duration_since(at) < CRLF_WINDOW);
+            match key {
+                Key::Enter => self.last_enter = Some(now),
+                Key::Ctrl('j') if crlf => continue,
+                _ => ()
+            }
+            match self.limiter.check(key, now) {
+                Verdict::Pass => return Some(Event::Key(key)),
+                Verdict::Drop => continue,
+                Verdict::Limited => return Some(Event::RateLimited),
+                Verdict::Stuck => return Some(Event::StuckKey)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Input reading `bytes` from a pipe, closed after them.
+    fn piped(bytes: &[u8]) -> Input {
+        let (read, write) = unistd::pipe().unwrap();
+        assert_eq!(unistd::write(write, bytes).unwrap(), bytes.len());
+        unistd::close(write).unwrap();
+        Input {
+            fd: read,
+            ..Input::new(None, 0)
+        }
+    }
+
+    /// What the last `fill` read.
+    fn filled(input: &Input) -> &[u8] {
+        &input.buf[input.start..input.end]
+    }
+
+    #[test]
+    fn fill_takes_what_there_is() {
+        let (read, write) = unistd::pipe().unwrap();
+        let mut input = Input {
+            fd: read,
+            ..Input::new(None, 0)
+        };
+        unistd::write(write, b"ab").unwrap();
+        assert!(input.fill());
+        assert_eq!(filled(&input), b"ab");
+
+        // More than fits comes a buffer at a time.
+        unistd::write(write, &[b'x'; 300]).unwrap();
+        assert!(input.fill());
+        assert_eq!(filled(&input), &[b'x'; 256][..]);
+        assert!(input.fill());
+        assert_eq!(filled(&input), &[b'x'; 44][..]);
+
+        unistd::close(write).unwrap();
+        assert!(!input.fill());
+    }
+
+    #[test]
+    fn fill_ends_with_input() {
+        assert!(!piped(b"").fill());
+        let mut input = piped(b"a");
+        assert!(input.fill());
+        assert!(!input.fill());
+    }
+
+    #[test]
+    fn fill_gives_up_on_a_read_error() {
+        let mut input = piped(b"a");
+        unistd::close(input.fd).unwrap();
+        assert!(!input.fill());
+    }
+
+    #[test]
+    fn fill_waits_on_non_blocking_stdin() {
+        use nix::fcntl::{fcntl, FcntlArg, OFlag};
+
+        let (read, write) = unistd::pipe().unwrap();
+        fcntl(read, FcntlArg::F_SETFL(OFlag::O_NONBLOCK)).unwrap();
+        let mut input = Input {
+            fd: read,
+            ..Input::new(None, 0)
+        };
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            unistd::write(write, b"late").unwrap();
+            write
+        });
+        assert!(input.fill());
+        assert_eq!(filled(&input), b"late");
+        unistd::close(writer.join().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn fill_retries_after_a_signal() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        use nix::sys::{
+            pthread::{pthread_kill, pthread_self},
+            signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal}
+        };
+
+        static CAUGHT: AtomicUsize = AtomicUsize::new(0);
+        extern "C" fn caught(_: nix::libc::c_int) {
+            CAUGHT.fetch_add(1, Ordering::Relaxed);
+        }
+        // Without SA_RESTART, so the blocked read fails with EINTR.
+        let action = SigAction::new(
+            SigHandler::Handler(caught),
+            SaFlags::empty(),
+            SigSet::empty()
+        );
+        unsafe { sigaction(Signal::SIGUSR2, &action) }.unwrap();
+
+        let (read, write) = unistd::pipe().unwrap();
+        let mut input = Input {
+            fd: read,
+            ..Input::new(None, 0)
+        };
+        let reader = pthread_self();
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            pthread_kill(reader, Signal::SIGUSR2).unwrap();
+            std::thread::sleep(Duration::from_millis(50));
+            unistd::write(write, b"after").unwrap();
+            write
+        });
+        assert!(input.fill());
+        assert_eq!(filled(&input), b"after");
+        assert_eq!(CAUGHT.load(Ordering::Relaxed), 1);
+        unistd::close(writer.join().unwrap()).unwrap();
+    }
+
+    fn not_submit(event: &Event) -> bool {
+        !matches!(event, Event::Key(Key::Enter))
+    }
+
+    #[test]
+    fn keys_typed_while_busy_are_queued_without_enter() {
+        let mut input = piped(b"pw\r1\x1b[200~x\x1b[201~");
+        assert_eq!(input.queue_pending(not_submit), 4);
+        assert!(input.has_queued());
+        let events: Vec<_> =
+            std::iter::from_fn(|| input.read_event()).collect();
+        assert_eq!(
+            format!("{events:?}"),
+            format!(
+                "{:?}",
+                [
+                    Event::Key(Key::Char('p')),
+                    Event::Key(Key::Char('w')),
+                    Event::Key(Key::Char('1')),
+                    Event::Paste("x".to_string())
+                ]
+            )
+        );
+        assert!(!input.has_queued());
+    }
+
+    #[test]
+    fn queue_is_bounded() {
+        let typed: Vec<u8> = (0..100).map(|i| b'a' + i % 26).collect();
+        let mut input = piped(&typed);
+        assert_eq!(input.queue_pending(not_submit), QUEUE_CAP);
+        let queued = std::iter::from_fn(|| input.read_event()).count();
+        assert_eq!(queued, QUEUE_CAP);
+    }
+
+    /// Types `text` into `limiter` a character every `gap` from `start`,
+    /// and returns whether it's in a burst after each one.
+    fn typed(
+        limiter: &mut RepeatLimiter,
+        text: &str,
+        start: Instant,
+        gap: Duration
+    ) -> Vec<bool> {
+        (0u32..)
+            .zip(text.chars())
+            .map(|(i, c)| {
+                let verdict = limiter.check(Key::Char(c), start + gap * i);
+                assert_eq!(verdict, Verdict::Pass, "{:?}", c);
+                limiter.in_burst()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn two_hundred_chars_a_second_is_a_burst() {
+        let gap = Duration::from_millis(5);
+        for max_per_sec in [0, 30] {
+            let mut limiter = RepeatLimiter::new(max_per_sec);
+            let bursts = typed(&mut limiter, "hunter22", Instant::now(), gap);
+            assert_eq!(
+                bursts,
+                [false, false, false, false, true, true, true, true]
+            );
+        }
+    }
+
+    #[test]
+    fn typing_by_hand_is_no_burst() {
+        for gap in [16, 60, 150] {
+            let mut limiter = RepeatLimiter::new(30);
+            let gap = Duration::from_millis(gap);
+            let bursts = typed(&mut limiter, "hunter22", Instant::now(), gap);
+            assert!(bursts.iter().all(|&burst| !burst), "{:?}", gap);
+        }
+    }
+
+    #[test]
+    fn burst_gap_is_inclusive() {
+        let mut limiter = RepeatLimiter::new(0);
+        let bursts = typed(&mut limiter, "abcde", Instant::now(), BURST_GAP);
+        assert_eq!(bursts.last(), Some(&true));
+    }
+
+    #[test]
+    fn a_pause_or_other_key_ends_the_burst() {
+        let gap = Duration::from_millis(5);
+        let mut limiter = RepeatLimiter::new(0);
+        let start = Instant::now();
+        typed(&mut limiter, "abcdef", start, gap);
+        assert!(limiter.in_burst());
+
+        // Enter, as a device sends after the password.
+        let later = start + gap * 6;
+        assert_eq!(limiter.check(Key::Enter, later), Verdict::Pass);
+        assert!(!limiter.in_burst());
+        let bursts = typed(&mut limiter, "abcde", later + gap, gap);
+        assert_eq!(bursts, [false, false, false, false, true]);
+
+        // A gap between characters starts counting again.
+        let after_pause = later + Duration::from_millis(500);
+        let bursts = typed(&mut limiter, "abcde", after_pause, gap);
+        assert_eq!(bursts, [false, false, false, false, true]);
+    }
+
+    #[test]
+    fn nothing_typed_queues_nothing() {
+        let mut input = piped(b"");
+        assert_eq!(input.queue_pending(not_submit), 0);
+        assert!(!input.has_queued());
+    }
+}
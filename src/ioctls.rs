@@ -0,0 +1,257 @@
+//! Safe wrappers for the console and framebuffer ioctls mflm uses. All the
+//! raw ioctl numbers and `unsafe` calls live here.
+
+use std::{
+    fs::File,
+    io,
+    ops::BitOr,
+    os::unix::io::{AsRawFd, RawFd}
+};
+
+use framebuffer::VarScreeninfo;
+use nix::libc::{c_char, c_int, c_short};
+
+// from linux/fb.h
+const FBIOPUT_VSCREENINFO: u32 = 0x4601;
+const FBIOPAN_DISPLAY: u32 = 0x4606;
+const FBIOBLANK: u32 = 0x4611;
+
+// from linux/kd.h
+const KIOCSOUND: u32 = 0x4B2F;
+const KDSETMODE: u32 = 0x4B3A;
+const KDGETMODE: u32 = 0x4B3B;
+const KDGKBLED: u32 = 0x4B64;
+
+// from linux/vt.h
+const VT_SETMODE: u32 = 0x5602;
+
+// from the syslog(2) man page
+const SYSLOG_ACTION_CONSOLE_LEVEL: c_int = 8;
+
+pub const KD_GRAPHICS: c_int = 0x01;
+
+nix::ioctl_write_ptr_bad!(
+    fbioput_vscreeninfo,
+    FBIOPUT_VSCREENINFO,
+    VarScreeninfo
+);
+nix::ioctl_write_ptr_bad!(fbiopan_display, FBIOPAN_DISPLAY, VarScreeninfo);
+nix::ioctl_write_int_bad!(fbioblank, FBIOBLANK);
+nix::ioctl_write_int_bad!(kiocsound_raw, KIOCSOUND);
+nix::ioctl_write_int_bad!(kdsetmode, KDSETMODE);
+nix::ioctl_read_bad!(kdgetmode, KDGETMODE, c_int);
+nix::ioctl_read_bad!(kdgkbled, KDGKBLED, c_char);
+nix::ioctl_write_ptr_bad!(vt_setmode, VT_SETMODE, VtMode);
+
+/// `activate` flags for `fb_activate`, combined with `|`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FbActivate(u32);
+
+impl FbActivate {
+    pub const NOW: Self = Self(0);
+    pub const FORCE: Self = Self(128);
+
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+}
+
+impl BitOr for FbActivate {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Blanking levels for `fb_blank`.
+// For blanking the screen when idle, which nothing does yet.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FbBlank {
+    Unblank = 0,
+    Normal = 1,
+    VsyncSuspend = 2,
+    HsyncSuspend = 3,
+    Powerdown = 4
+}
+
+/// `struct vt_mode`, for `vt_set_mode`.
+// For taking over VT switching, which nothing does yet.
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VtMode {
+    pub mode: c_char,
+    pub waitv: c_char,
+    pub relsig: c_short,
+    pub acqsig: c_short,
+    pub frsig: c_short
+}
+
+/// Keyboard LED flags from `kd_get_leds`.
+// For a Caps Lock warning, which nothing shows yet.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Leds(u8);
+
+#[allow(dead_code)]
+impl Leds {
+    pub const SCROLL_LOCK: u8 = 0x01;
+    pub const NUM_LOCK: u8 = 0x02;
+    pub const CAPS_LOCK: u8 = 0x04;
+
+    pub fn contains(self, led: u8) -> bool {
+        self.0 & led == led
+    }
+}
+
+/// Applies `var` with `flags` added to its `activate` field, which makes
+/// the driver show what's been drawn.
+pub fn fb_activate(
+    device: &File,
+    var: &VarScreeninfo,
+    flags: FbActivate
+) -> io::Result<()> {
+    let mut var = var.clone();
+    var.activate |= flags.bits();
+    unsafe { fbioput_vscreeninfo(device.as_raw_fd(), &var) }?;
+    Ok(())
+}
+
+/// Pans the display to `var`'s offsets, which some drivers need to show
+/// what's been drawn.
+pub fn fb_pan_display(device: &File, var: &VarScreeninfo) -> io::Result<()> {
+    unsafe { fbiopan_display(device.as_raw_fd(), var) }?;
+    Ok(())
+}
+
+/// The framebuffer ioctls a screen refresh can use, so the choice between
+/// them can run against something other than a real device.
+pub trait FbDevice {
+    fn activate(
+        &self,
+        var: &VarScreeninfo,
+        flags: FbActivate
+    ) -> io::Result<()>;
+    fn pan(&self, var: &VarScreeninfo) -> io::Result<()>;
+}
+
+impl FbDevice for File {
+    fn activate(
+        &self,
+        var: &VarScreeninfo,
+        flags: FbActivate
+    ) -> io::Result<()> {
+        fb_activate(self, var, flags)
+    }
+
+    fn pan(&self, var: &VarScreeninfo) -> io::Result<()> {
+        fb_pan_display(self, var)
+    }
+}
+
+/// Blanks or unblanks the screen, or powers it down.
+#[allow(dead_code)]
+pub fn fb_blank(device: &File, level: FbBlank) -> io::Result<()> {
+    unsafe { fbioblank(device.as_raw_fd(), level as c_int) }?;
+    Ok(())
+}
+
+/// Starts a tone with the PIT divisor `count`, or stops it when 0.
+pub fn kiocsound(fd: RawFd, count: c_int) -> io::Result<()> {
+    unsafe { kiocsound_raw(fd, count) }?;
+    Ok(())
+}
+
+pub fn kd_get_mode(fd: RawFd) -> io::Result<c_int> {
+    let mut mode = 0;
+    unsafe { kdgetmode(fd, &mut mode) }?;
+    Ok(mode)
+}
+
+pub fn kd_set_mode(fd: RawFd, mode: c_int) -> io::Result<()> {
+    unsafe { kdsetmode(fd, mode) }?;
+    Ok(())
+}
+
+/// Which keyboard LEDs are lit on the console `fd`.
+#[allow(dead_code)]
+pub fn kd_get_leds(fd: RawFd) -> io::Result<Leds> {
+    let mut leds = 0;
+    unsafe { kdgkbled(fd, &mut leds) }?;
+    Ok(Leds(leds as u8))
+}
+
+/// Sets how the console `fd` handles VT switches.
+#[allow(dead_code)]
+pub fn vt_set_mode(fd: RawFd, mode: &VtMode) -> io::Result<()> {
+    unsafe { vt_setmode(fd, mode) }?;
+    Ok(())
+}
+
+/// Sets the kernel's `console_loglevel`: only messages more urgent than
+/// `level` get printed on the console.
+pub fn set_console_loglevel(level: c_int) -> io::Result<()> {
+    let null = std::ptr::null_mut();
+    let res = unsafe {
+        nix::libc::klogctl(SYSLOG_ACTION_CONSOLE_LEVEL, null, level)
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use nix::libc;
+
+    use super::*;
+
+    #[test]
+    fn activate_flags_combine() {
+        assert_eq!((FbActivate::NOW | FbActivate::FORCE).bits(), 128);
+        assert_eq!(FbActivate::FORCE | FbActivate::NOW, FbActivate::FORCE);
+        assert_eq!((FbActivate::NOW | FbActivate::NOW).bits(), 0);
+    }
+
+    #[test]
+    fn types_match_the_kernel_headers() {
+        let levels = [
+            FbBlank::Unblank,
+            FbBlank::Normal,
+            FbBlank::VsyncSuspend,
+            FbBlank::HsyncSuspend,
+            FbBlank::Powerdown
+        ];
+        for (i, level) in levels.iter().enumerate() {
+            assert_eq!(*level as usize, i);
+        }
+        assert_eq!(std::mem::size_of::<VtMode>(), 8);
+    }
+
+    #[test]
+    fn leds_are_told_apart() {
+        let leds = Leds(Leds::CAPS_LOCK | Leds::NUM_LOCK);
+        assert!(leds.contains(Leds::CAPS_LOCK));
+        assert!(leds.contains(Leds::NUM_LOCK));
+        assert!(!leds.contains(Leds::SCROLL_LOCK));
+        assert!(!Leds(0).contains(Leds::CAPS_LOCK));
+    }
+
+    #[test]
+    fn errors_come_back_as_io_errors() {
+        // Not a console or a framebuffer, so every ioctl is refused.
+        let null = File::open("/dev/null").unwrap();
+        let fd = null.as_raw_fd();
+        let refused = |res: io::Result<()>| {
+            assert_eq!(res.unwrap_err().raw_os_error(), Some(libc::ENOTTY));
+        };
+        refused(fb_blank(&null, FbBlank::Unblank));
+        refused(kd_get_leds(fd).map(drop));
+        refused(vt_set_mode(fd, &VtMode::default()));
+        refused(kd_get_mode(fd).map(drop));
+        refused(kiocsound(fd, 0));
+    }
+}
@@ -0,0 +1,92 @@
+//! Keypresses as the input decoder reports them. The greeter's key
+//! bindings, which need its settings, are built on these.
+
+use std::fmt;
+
+/// A decoded keypress.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Key {
+    /// A printable character.
+    Char(char),
+    /// Ctrl plus a lowercase ASCII letter.
+    Ctrl(char),
+    /// Tab.
+    Tab,
+    /// Enter, or Return.
+    Enter,
+    /// Only from terminals that report modifiers on Enter, with CSI u or
+    /// xterm's modifyOtherKeys.
+    CtrlEnter,
+    /// Backspace.
+    Backspace,
+    /// Escape, on its own.
+    Escape,
+    /// The up arrow.
+    Up,
+    /// The down arrow.
+    Down,
+    /// The left arrow.
+    Left,
+    /// The right arrow.
+    Right,
+    /// Home.
+    Home,
+    /// End.
+    End,
+    /// Delete.
+    Delete,
+    /// A function key, F1 to F12.
+    F(u8),
+    /// An escape sequence we don't know about.
+    Unknown
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Key::Char(' ') => write!(f, "space"),
+            Key::Char(c) => write!(f, "{c}"),
+            Key::Ctrl(c) => write!(f, "ctrl+{c}"),
+            Key::Tab => write!(f, "tab"),
+            Key::Enter => write!(f, "enter"),
+            Key::CtrlEnter => write!(f, "ctrl+enter"),
+            Key::Backspace => write!(f, "backspace"),
+            Key::Escape => write!(f, "esc"),
+            Key::Up => write!(f, "up"),
+            Key::Down => write!(f, "down"),
+            Key::Left => write!(f, "left"),
+            Key::Right => write!(f, "right"),
+            Key::Home => write!(f, "home"),
+            Key::End => write!(f, "end"),
+            Key::Delete => write!(f, "delete"),
+            Key::F(n) => write!(f, "f{n}"),
+            Key::Unknown => write!(f, "unknown")
+        }
+    }
+}
+
+impl Key {
+    /// How the key is written in hints: "Ctrl+U", "F12", "←".
+    pub fn label(self) -> String {
+        let name = match self {
+            Key::Char(' ') => "Space",
+            Key::Char(c) => return c.to_string(),
+            Key::Ctrl(c) => return format!("Ctrl+{}", c.to_ascii_uppercase()),
+            Key::F(n) => return format!("F{n}"),
+            Key::Tab => "Tab",
+            Key::Enter => "Enter",
+            Key::CtrlEnter => "Ctrl+Enter",
+            Key::Backspace => "Backspace",
+            Key::Escape => "Esc",
+            Key::Up => "↑",
+            Key::Down => "↓",
+            Key::Left => "←",
+            Key::Right => "→",
+            Key::Home => "Home",
+            Key::End => "End",
+            Key::Delete => "Del",
+            Key::Unknown => "?"
+        };
+        name.to_string()
+    }
+}
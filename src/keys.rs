@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+pub use crate::key::Key;
+use crate::{
+    settings::{Keys, SubmitKey},
+    Mode
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    NextField,
+    PrevField,
+    NextSession,
+    PrevSession,
+    ClearField,
+    DeleteWord,
+    LineStart,
+    LineEnd,
+    CompleteNext,
+    CompletePrev,
+    Submit,
+    Quit,
+    Shutdown,
+    Reboot,
+    ToggleReveal,
+    Rescan,
+    UserList,
+    DebugOverlay
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum KeyParseError {
+    #[error("unknown key descriptor {0:?}")]
+    UnknownKey(String),
+
+    #[error("key {key:?} is bound to both {first:?} and {second:?}")]
+    Conflict {
+        key: String,
+        first: Action,
+        second: Action
+    }
+}
+
+/// Parses a key descriptor such as "ctrl+u", "tab", "f12" or "up".
+pub fn parse_descriptor(s: &str) -> Result<Key, KeyParseError> {
+    let desc = s.trim().to_lowercase();
+    let unknown = || KeyParseError::UnknownKey(s.to_string());
+
+    if let Some(rest) = desc.strip_prefix("ctrl+") {
+        if rest == "enter" || rest == "return" {
+            return Ok(Key::CtrlEnter);
+        }
+        let mut chars = rest.chars();
+        return match (chars.next(), chars.next()) {
+            // These are indistinguishable from their named keys on a tty.
+            (Some('i'), None) => Ok(Key::Tab),
+            (Some('m'), None) => Ok(Key::Enter),
+            (Some('['), None) => Ok(Key::Escape),
+            (Some(c), None) if c.is_ascii_lowercase() => Ok(Key::Ctrl(c)),
+            _ => Err(unknown())
+        };
+    }
+
+    let key = match desc.as_str() {
+        "tab" => Key::Tab,
+        "enter" | "return" => Key::Enter,
+        "backspace" => Key::Backspace,
+        "esc" | "escape" => Key::Escape,
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "delete" | "del" => Key::Delete,
+        "space" => Key::Char(' '),
+        _ => {
+            if let Some(n) = desc.strip_prefix('f') {
+                match n.parse::<u8>() {
+                    Ok(n @ 1..=12) => return Ok(Key::F(n)),
+                    _ if n.is_empty() => return Ok(Key::Char('f')),
+                    _ => return Err(unknown())
+                }
+            }
+            let mut chars = desc.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if !c.is_control() => Key::Char(c),
+                _ => return Err(unknown())
+            }
+        }
+    };
+
+    Ok(key)
+}
+
+/// Lookup table from keys to actions, built from the `[keys]` section.
+pub struct KeyBindings {
+    map: HashMap<Key, Action>,
+    /// The first key listed for each action, for hints.
+    first: HashMap<Action, Key>
+}
+
+impl KeyBindings {
+    pub fn from_settings(keys: &Keys) -> Result<Self, KeyParseError> {
+        let mut map = HashMap::new();
+        let mut first = HashMap::new();
+        for (action, descriptors) in keys.entries() {
+            for descriptor in descriptors {
+                let key = parse_descriptor(descriptor)?;
+                first.entry(action).or_insert(key);
+                if let Some(first) = map.insert(key, action) {
+                    if first != action {
+                        return Err(KeyParseError::Conflict {
+                            key: descriptor.to_string(),
+                            first,
+                            second: action
+                        });
+                    }
+                }
+            }
+        }
+        Ok(Self { map, first })
+    }
+
+    pub fn action(&self, key: Key) -> Option<Action> {
+        self.map.get(&key).copied()
+    }
+
+    /// The label of the first key bound to `action`.
+    pub fn key_label(&self, action: Action) -> Option<String> {
+        self.first.get(&action).map(|key| key.label())
+    }
+
+    /// The footer hint for `mode`, naming the keys that are actually
+    /// bound. Actions without a key are left out.
+    pub fn hint_for(&self, mode: Mode, submit_key: SubmitKey) -> String {
+        let key = |action| self.key_label(action);
+        let submit = match submit_key {
+            SubmitKey::Enter => key(Action::Submit),
+            SubmitKey::CtrlEnter => Some("Ctrl+Enter".to_string()),
+            SubmitKey::DoubleEnter => key(Action::Submit).map(|k| k + " twice")
+        };
+        let prev = key(Action::PrevSession);
+        let sessions = match (prev, key(Action::NextSession)) {
+            (Some(prev), Some(next)) => Some(format!("{prev}/{next}")),
+            (prev, next) => prev.or(next)
+        };
+
+        let parts = match mode {
+            Mode::SelectingSession => vec![
+                (sessions, "change session"),
+                (key(Action::Submit), "next")
+            ],
+            Mode::EditingUsername => vec![
+                (key(Action::Submit), "next"),
+                (key(Action::NextField), "switch field"),
+                (key(Action::ClearField), "clear")
+            ],
+            Mode::EditingPassword => vec![
+                (submit, "log in"),
+                (key(Action::ClearField), "clear"),
+                (key(Action::Shutdown), "power off"),
+                (key(Action::Reboot), "reboot")
+            ]
+        };
+        parts
+            .into_iter()
+            .filter_map(|(key, what)| Some(format!("{} {what}", key?)))
+            .collect::<Vec<_>>()
+            .join(" · ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn descriptors() {
+        let cases = [
+            ("ctrl+u", Key::Ctrl('u')),
+            ("Ctrl+K", Key::Ctrl('k')),
+            (" tab ", Key::Tab),
+            ("ctrl+i", Key::Tab),
+            ("enter", Key::Enter),
+            ("return", Key::Enter),
+            ("ctrl+m", Key::Enter),
+            ("ctrl+enter", Key::CtrlEnter),
+            ("esc", Key::Escape),
+            ("ctrl+[", Key::Escape),
+            ("backspace", Key::Backspace),
+            ("up", Key::Up),
+            ("down", Key::Down),
+            ("left", Key::Left),
+            ("right", Key::Right),
+            ("home", Key::Home),
+            ("end", Key::End),
+            ("del", Key::Delete),
+            ("f1", Key::F(1)),
+            ("F12", Key::F(12)),
+            ("f", Key::Char('f')),
+            ("space", Key::Char(' ')),
+            ("q", Key::Char('q')),
+            ("é", Key::Char('é'))
+        ];
+        for (desc, key) in cases {
+            assert_eq!(parse_descriptor(desc).unwrap(), key, "{desc:?}");
+        }
+    }
+
+    #[test]
+    fn unknown_descriptors_are_named() {
+        for desc in ["", "ctrl+", "ctrl+1", "ctrl+uu", "f0", "f13", "hyper+x"] {
+            match parse_descriptor(desc) {
+                Err(KeyParseError::UnknownKey(s)) => assert_eq!(s, desc),
+                other => panic!("{:?}: {:?}", desc, other)
+            }
+        }
+    }
+
+    #[test]
+    fn display_parses_back() {
+        let keys = [
+            Key::Char('x'),
+            Key::Char(' '),
+            Key::Ctrl('w'),
+            Key::Tab,
+            Key::Enter,
+            Key::Backspace,
+            Key::Escape,
+            Key::Up,
+            Key::Down,
+            Key::Left,
+            Key::Right,
+            Key::Home,
+            Key::End,
+            Key::Delete,
+            Key::F(7)
+        ];
+        for key in keys {
+            assert_eq!(parse_descriptor(&key.to_string()).unwrap(), key);
+        }
+    }
+
+    #[test]
+    fn defaults_reproduce_the_old_bindings() {
+        let bindings = KeyBindings::from_settings(&Keys::default()).unwrap();
+        let expected = [
+            (Key::Tab, Action::NextField),
+            (Key::Down, Action::NextField),
+            (Key::Up, Action::PrevField),
+            (Key::Right, Action::NextSession),
+            (Key::Left, Action::PrevSession),
+            (Key::Ctrl('u'), Action::ClearField),
+            (Key::Enter, Action::Submit),
+            (Key::Ctrl('c'), Action::Quit),
+            (Key::Ctrl('d'), Action::Quit)
+        ];
+        for (key, action) in expected {
+            assert_eq!(bindings.action(key), Some(action), "{key}");
+        }
+        assert_eq!(bindings.action(Key::Char('a')), None);
+    }
+
+    #[test]
+    fn one_key_for_two_actions_is_a_conflict() {
+        let keys = Keys {
+            quit: crate::settings::KeyList::One("tab".to_string()),
+            ..Keys::default()
+        };
+        match KeyBindings::from_settings(&keys) {
+            Err(KeyParseError::Conflict { key, first, second }) => {
+                assert_eq!(key, "tab");
+                assert_eq!(first, Action::NextField);
+                assert_eq!(second, Action::Quit);
+            }
+            other => panic!("{:?}", other.err())
+        }
+    }
+}
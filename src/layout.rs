@@ -1,74 +1,238 @@
-#[derive(Clone, Copy, Debug)]
-pub(crate) struct FormLayout {
-    pub(crate) x: u32,
-    pub(crate) y: u32,
-    pub(crate) w: u32,
-    pub(crate) row_h: u32,
-    pub(crate) total_h: u32,
-    pub(crate) session_y: Option<u32>,
-    pub(crate) username_y: Option<u32>,
-    pub(crate) password_y: u32
+//! Where things go on screen: an `Anchor` and an offset place a block,
+//! a `Margin` keeps field text in from the edges, and `FormLayout` is
+//! where the greeter put each part of its form.
+//!
+//! Placing a 200px wide block 16px in from the right edge of a 1024px
+//! wide screen:
+//!
+//! ```
+//! use mflm::layout::{place, Anchor};
+//!
+//! let (h_edge, _) = Anchor::TopRight.edges();
+//! assert_eq!(place(h_edge, 1024, 200, 16), 808);
+//! ```
+
+use serde::{Deserialize, Serialize};
+
+use crate::buffer::Rect;
+
+/// Where on the screen something is placed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Anchor {
+    /// The middle of the screen.
+    Center,
+    /// The top left corner.
+    TopLeft,
+    /// The top right corner.
+    TopRight,
+    /// The bottom left corner.
+    BottomLeft,
+    /// The bottom right corner.
+    BottomRight
 }
 
-impl crate::LoginManager<'_> {
-    pub(crate) fn form_layout(&self) -> FormLayout {
-        let row_h = self.row_h;
-        let gap_below_session_px = self.gap_below_session_px;
-        let gap_below_username_px = self.gap_below_username_px;
+/// Where along one axis of the screen a block goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    /// Against the left or top edge.
+    Start,
+    /// In the middle.
+    Center,
+    /// Against the right or bottom edge.
+    End
+}
 
-        let show_session = self.show_target_row();
-        let show_username = self.show_username_row();
+impl Anchor {
+    /// The edges it puts a block against: horizontal, then vertical.
+    pub fn edges(self) -> (Edge, Edge) {
+        match self {
+            Anchor::Center => (Edge::Center, Edge::Center),
+            Anchor::TopLeft => (Edge::Start, Edge::Start),
+            Anchor::TopRight => (Edge::End, Edge::Start),
+            Anchor::BottomLeft => (Edge::Start, Edge::End),
+            Anchor::BottomRight => (Edge::End, Edge::End)
+        }
+    }
+}
 
-        let rows = (show_session as u32) + (show_username as u32) + 1;
+/// Start of a `size` long block on a `screen` long axis: `offset` in from
+/// the anchored edge, or shifted by `offset` from the middle. Clamped to
+/// keep the block on screen where it fits.
+pub fn place(edge: Edge, screen: u32, size: u32, offset: i32) -> u32 {
+    let max = screen.saturating_sub(size) as i64;
+    let pos = match edge {
+        Edge::Start => offset as i64,
+        Edge::Center => max / 2 + offset as i64,
+        Edge::End => max - offset as i64
+    };
+    pos.clamp(0, max) as u32
+}
 
-        let gaps_h = {
-            let mut sum = 0u32;
-            if show_session {
-                sum = sum.saturating_add(gap_below_session_px);
-            }
-            if show_username {
-                sum = sum.saturating_add(gap_below_username_px);
+/// Space between each side of a field and its text.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Margin {
+    /// This many pixels.
+    Px(u32),
+    /// This share of the form width, 0 to 1.
+    Fraction(f32)
+}
+
+impl Margin {
+    /// Parses pixels ("12") or a percentage ("8%"), or None if it's
+    /// neither.
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        match text.strip_suffix('%') {
+            Some(percent) => {
+                let percent: f32 = percent.trim().parse().ok()?;
+                (0.0..=100.0)
+                    .contains(&percent)
+                    .then(|| Self::Fraction(percent / 100.0))
             }
-            sum
+            None => text.parse().ok().map(Self::Px)
+        }
+    }
+
+    /// In pixels for a form `w` wide, at most half of it.
+    pub fn resolve(self, w: u32) -> u32 {
+        let px = match self {
+            Self::Px(px) => px,
+            Self::Fraction(share) => (w as f32 * share).round() as u32
         };
+        px.min(w / 2)
+    }
+}
 
-        let total_h = rows * row_h + gaps_h;
+/// Where each part of the login form is on screen. Positions are in
+/// pixels from the top left of the screen; rows that aren't shown are
+/// None.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FormLayout {
+    /// Left edge of the form.
+    pub x: u32,
+    /// Top of the form.
+    pub y: u32,
+    /// Width of the form.
+    pub w: u32,
+    /// Height of each field row.
+    pub row_h: u32,
+    /// Height of the form, from its top row to its bottom one.
+    pub total_h: u32,
+    /// Top of the session row.
+    pub session_y: Option<u32>,
+    /// Top of the session comment line below the session row.
+    pub comment_y: Option<u32>,
+    /// Height of the session comment line.
+    pub comment_h: u32,
+    /// Top of the username row.
+    pub username_y: Option<u32>,
+    /// Username list rows below the username row, while it's open.
+    pub user_list_y: Option<u32>,
+    /// How many names the username list shows.
+    pub user_list_rows: u32,
+    /// Top of the password row.
+    pub password_y: u32,
+    /// Top of the message line below the form.
+    pub message_y: u32,
+    /// Footer row at the bottom of the screen, if there's room below the
+    /// message line.
+    pub footer_y: Option<u32>,
+    /// Height of the footer row.
+    pub footer_h: u32,
+    /// Status badge row just above the form, if there's room.
+    pub badge_y: Option<u32>,
+    /// Height of the status badge row.
+    pub badge_h: u32,
+    /// Label above the session row; narrow layout only.
+    pub session_label_y: Option<u32>,
+    /// Label above the username row; narrow layout only.
+    pub username_label_y: Option<u32>,
+    /// Label above the password row; narrow layout only.
+    pub password_label_y: Option<u32>,
+    /// Height of each label row, 0 without them.
+    pub label_h: u32,
+    /// Area the greeting is drawn into, from its top edge down. The
+    /// message of the day and key hints keep to the same column.
+    pub heading: Rect,
+    /// Failed attempts line below the message, if there's room.
+    pub attempts_y: Option<u32>,
+    /// Height of the failed attempts line.
+    pub attempts_h: u32,
+    /// The input margin in pixels for this width.
+    pub input_margin: u32
+}
 
-        let margin_x = 32;
-        let max_w = self.screen_size.0.saturating_sub(margin_x * 2).max(1);
-        let w = self.dimensions.0.min(max_w).max(1);
+impl FormLayout {
+    /// Where field text goes within a row: inside the input margins on
+    /// both sides.
+    pub fn inner_field_rect(&self) -> Rect {
+        let margin = self.input_margin;
+        (margin, 0, self.w - margin * 2, self.row_h)
+    }
+}
 
-        let x = (self.screen_size.0.saturating_sub(w)) / 2;
-        let y = (self.screen_size.1.saturating_sub(total_h)) / 2;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        let mut cur_y = y;
-        let session_y = if show_session {
-            let out = cur_y;
-            cur_y = cur_y.saturating_add(row_h + gap_below_session_px);
-            Some(out)
-        } else {
-            None
-        };
+    #[test]
+    fn margins_parse() {
+        assert_eq!(Margin::parse("12"), Some(Margin::Px(12)));
+        assert_eq!(Margin::parse(" 0 "), Some(Margin::Px(0)));
+        assert_eq!(Margin::parse("8%"), Some(Margin::Fraction(0.08)));
+        assert_eq!(Margin::parse("12.5 %"), Some(Margin::Fraction(0.125)));
+        assert_eq!(Margin::parse("100%"), Some(Margin::Fraction(1.0)));
+        for bad in ["", "%", "-4", "-1%", "101%", "4px", "wide"] {
+            assert_eq!(Margin::parse(bad), None, "{:?}", bad);
+        }
+    }
 
-        let username_y = if show_username {
-            let out = cur_y;
-            cur_y = cur_y.saturating_add(row_h + gap_below_username_px);
-            Some(out)
-        } else {
-            None
-        };
+    #[test]
+    fn pixel_margins_keep_their_size() {
+        for w in [200, 400, 1000] {
+            assert_eq!(Margin::Px(16).resolve(w), 16, "{}", w);
+        }
+        // Never more than half the form.
+        assert_eq!(Margin::Px(300).resolve(400), 200);
+        assert_eq!(Margin::Px(5).resolve(7), 3);
+    }
 
-        let password_y = cur_y;
-
-        FormLayout {
-            x,
-            y,
-            w,
-            row_h,
-            total_h,
-            session_y,
-            username_y,
-            password_y
+    #[test]
+    fn fraction_margins_follow_the_form() {
+        let margin = Margin::parse("10%").unwrap();
+        let cases = [(200, 20), (400, 40), (1000, 100), (255, 26), (0, 0)];
+        for (w, px) in cases {
+            assert_eq!(margin.resolve(w), px, "{}", w);
         }
+        assert_eq!(Margin::Fraction(0.9).resolve(400), 200);
+    }
+
+    #[test]
+    fn field_text_sits_inside_the_margins() {
+        for (w, margin, inner) in [(400, 16, 368), (200, 100, 0), (50, 0, 50)]
+        {
+            let layout = FormLayout {
+                w,
+                row_h: 32,
+                input_margin: margin,
+                ..FormLayout::default()
+            };
+            assert_eq!(layout.inner_field_rect(), (margin, 0, inner, 32));
+        }
+    }
+
+    #[test]
+    fn blocks_are_placed_against_their_edge() {
+        assert_eq!(place(Edge::Start, 1000, 200, 10), 10);
+        assert_eq!(place(Edge::Center, 1000, 200, 0), 400);
+        assert_eq!(place(Edge::Center, 1000, 200, -50), 350);
+        assert_eq!(place(Edge::End, 1000, 200, 10), 790);
+        // Offsets never push a block off screen.
+        assert_eq!(place(Edge::Start, 1000, 200, -10), 0);
+        assert_eq!(place(Edge::End, 1000, 200, 900), 0);
+        assert_eq!(place(Edge::Center, 1000, 200, 900), 800);
+        // Nor does a block wider than the screen.
+        assert_eq!(place(Edge::End, 100, 200, 0), 0);
     }
 }
@@ -0,0 +1,39 @@
+//! mflm's drawing, as a library for tools that draw what the greeter
+//! does, such as boot splash images: a `Buffer` over 32-bit framebuffer
+//! memory, `Color`, text through pango with `Font`, and the `layout`
+//! types that place the form: `Anchor`, `Margin` and `FormLayout`. The
+//! greeter itself is the `mflm` binary, built on the same modules.
+//!
+//! The session desktop entry parser (`entry`) and the terminal input
+//! decoder (`decode`, `key`) are here too, so the fuzz targets under
+//! `fuzz/` run the same code the greeter does.
+//!
+//! Rendering text into memory and saving it as a PNG:
+//!
+//! ```
+//! use mflm::{buffer::Buffer, color::Color, font::Font};
+//!
+//! let (w, h) = (320, 80);
+//! let mut pixels = vec![0; (w * h * 4) as usize];
+//! let mut buf = Buffer::new(&mut pixels, (w, h));
+//! let bg = Color::from_hex("#202030")?;
+//! let fg = Color::from_hex("#fafafa")?;
+//! buf.memset(&bg);
+//! Font::new("Sans", 32.0).auto_draw_text_centered(&mut buf, &bg, &fg, "Hi")?;
+//!
+//! let mut png = Vec::new();
+//! buf.write_png(&mut png)?;
+//! assert!(png.starts_with(b"\x89PNG"));
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+#![deny(rust_2018_idioms)]
+#![deny(missing_docs)]
+
+pub mod buffer;
+pub mod color;
+pub mod decode;
+pub mod entry;
+pub mod font;
+pub mod key;
+pub mod layout;
@@ -0,0 +1,112 @@
+//! What may be logged about user input. Anything typed at the greeter or
+//! passed on its command line goes through here on its way to the log.
+//! Usernames show as a short hash, so lines about the same user can be
+//! matched up without naming them; building with `insecure-logs` also
+//! logs the real names at trace level. Passwords, and their lengths, are
+//! never logged.
+
+use std::fmt;
+
+/// A username as it may be logged, e.g. `user#5d4f0d1a`.
+pub struct Username<'a>(&'a str);
+
+pub fn username(name: &str) -> Username<'_> {
+    Username(name)
+}
+
+impl fmt::Display for Username<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "user#{:08x}", fnv1a(self.0.as_bytes()))
+    }
+}
+
+impl fmt::Debug for Username<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// 32-bit FNV-1a. Only for telling names apart in the log; a short list
+/// of likely names can be hashed to find a match, which is why the
+/// username itself stays out of it.
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, &b| {
+        (hash ^ b as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// Logs the real username at trace level, with the `insecure-logs`
+/// feature only.
+pub fn trace_username(what: &str, name: &str) {
+    #[cfg(feature = "insecure-logs")]
+    log::trace!("{what}: {name:?}");
+    #[cfg(not(feature = "insecure-logs"))]
+    let _ = (what, name);
+}
+
+/// Command line arguments as they may be logged: the names of options,
+/// without their values, which could be anything an admin passed.
+pub fn args(args: impl IntoIterator<Item = String>) -> Vec<String> {
+    args.into_iter()
+        .map(|arg| match arg.split_once('=') {
+            Some((name, _)) if arg.starts_with('-') => format!("{name}=…"),
+            _ if arg.starts_with('-') => arg,
+            _ => "…".to_string()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_is_fnv1a() {
+        assert_eq!(fnv1a(b""), 0x811c_9dc5);
+        assert_eq!(fnv1a(b"a"), 0xe40c_292c);
+        assert_eq!(fnv1a(b"foobar"), 0xbf9c_f968);
+    }
+
+    #[test]
+    fn usernames_are_logged_as_a_hash() {
+        assert_eq!(username("a").to_string(), "user#e40c292c");
+        assert_eq!(format!("{:?}", username("a")), "user#e40c292c");
+        assert_eq!(username("").to_string(), "user#811c9dc5");
+        let logged = format!("{:?}", Some(username("alice")));
+        assert!(!logged.contains("alice"), "{}", logged);
+    }
+
+    #[test]
+    fn same_name_same_hash() {
+        let logged = |name: &str| username(name).to_string();
+        assert_eq!(logged("alice"), logged("alice"));
+        assert_ne!(logged("alice"), logged("Alice"));
+        assert_ne!(logged("alice"), logged("alice "));
+    }
+
+    #[test]
+    fn option_values_are_left_out() {
+        let args = [
+            "--replay",
+            "/home/alice/script",
+            "--status-fd=3",
+            "--dump-config",
+            "-h",
+            "a=b",
+            "hunter2"
+        ];
+        assert_eq!(
+            super::args(args.iter().map(|arg| arg.to_string())),
+            [
+                "--replay",
+                "…",
+                "--status-fd=…",
+                "--dump-config",
+                "-h",
+                "…",
+                "…"
+            ]
+        );
+        assert!(super::args(Vec::new()).is_empty());
+    }
+}
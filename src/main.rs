@@ -1,94 +1,247 @@
 #![deny(rust_2018_idioms)]
 
-use std::{fs, fs::OpenOptions, io, path::Path};
+use std::{
+    fs,
+    fs::OpenOptions,
+    io,
+    panic,
+    path::{Path, PathBuf},
+    process,
+    sync::Arc,
+    thread
+};
 
 use chrono::Local;
-use framebuffer::{Framebuffer, KdMode, VarScreeninfo};
-use freedesktop_desktop_entry::DesktopEntry;
+use framebuffer::{Framebuffer, VarScreeninfo};
 use log::{debug, error, info, warn};
 use simplelog::{ConfigBuilder as LogConfigBuilder, LevelFilter, WriteLogger};
-use termion::raw::IntoRawMode;
 use thiserror::Error;
 
+use exit::Exit;
+use mflm::{buffer, color, decode, entry, font, key, layout};
+
 const USERNAME_CAP: usize = 64;
 const PASSWORD_CAP: usize = 64;
 
-// from linux/fb.h
-const FB_ACTIVATE_NOW: u32 = 0;
-const FB_ACTIVATE_FORCE: u32 = 128;
+/// Screens narrower than this get the narrow layout with `ui.layout = "auto"`.
+const NARROW_SCREEN_PX: u32 = 640;
+
+/// How long fatal errors stay on screen before we give the console back.
+const FATAL_DISPLAY: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// How often the greeter loop wakes up for timed updates while idle.
+const TICK: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// How long a config error stays on screen, unless a key is pressed first.
+const CONFIG_WARNING_TIME: std::time::Duration =
+    std::time::Duration::from_secs(10);
 
-mod buffer;
-mod color;
+/// How long the password underline flashes when a key is refused.
+const ERROR_FLASH_TIME: std::time::Duration =
+    std::time::Duration::from_millis(300);
+
+mod accounts;
+mod attract;
+mod battery;
+mod config_watch;
+mod console;
 mod draw;
+mod exit;
+mod field;
+mod form;
+mod form_layout;
 mod greetd;
+mod icons;
+mod info_panel;
 mod greeter_loop;
-mod layout;
+mod input;
+mod ioctls;
+mod keys;
+mod log_safe;
+mod metrics;
+mod motd;
+mod network;
+mod night;
+mod paths;
+mod qr;
+mod replay;
+mod sessions;
 mod settings;
+mod signals;
+mod state;
+mod stats;
+mod status;
+#[cfg(test)]
+mod testing;
+mod text_mode;
+mod watchdog;
+mod widgets;
 
-#[derive(PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Copy, Clone)]
 enum Mode {
     SelectingSession,
     EditingUsername,
     EditingPassword
 }
 
+/// What the form is doing, which decides its fill color.
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum FormState {
+    Idle,
+    Authenticating,
+    Failed
+}
+
 #[derive(Error, Debug)]
 #[non_exhaustive]
 enum Error {
     #[error("Error performing buffer operation: {0}")]
     Buffer(#[from] buffer::BufferError),
     #[error("Error performing draw operation: {0}")]
-    Draw(#[from] draw::DrawError),
+    Draw(#[from] font::DrawError),
     #[error("I/O error: {0}")]
-    Io(#[from] std::io::Error)
+    Io(#[from] std::io::Error),
+    #[error("{element}: {source}")]
+    Element {
+        element: &'static str,
+        #[source]
+        source: Box<Error>
+    }
+}
+
+impl Error {
+    /// False if the draw that failed is worth trying once more.
+    fn is_fatal(&self) -> bool {
+        match self {
+            Error::Draw(e) => e.is_fatal(),
+            Error::Element { source, .. } => source.is_fatal(),
+            Error::Buffer(_) | Error::Io(_) => true
+        }
+    }
+}
+
+/// Attaches the name of the UI element being drawn to an error, so a
+/// failure deep inside a draw call says where it happened.
+trait WithContext<T> {
+    fn with_context(self, element: &'static str) -> Result<T, Error>;
+}
+
+impl<T, E: Into<Error>> WithContext<T> for Result<T, E> {
+    fn with_context(self, element: &'static str) -> Result<T, Error> {
+        self.map_err(|e| Error::Element {
+            element,
+            source: Box::new(e.into())
+        })
+    }
 }
 
+#[derive(PartialEq)]
 struct Target {
     name: String,
-    exec: Vec<String>
+    /// The desktop entry it was loaded from.
+    path: PathBuf,
+    comment: Option<String>,
+    /// Icon= as written, for `ui.show_session_icons`.
+    icon: Option<String>,
+    /// The command line from the desktop entry.
+    exec: Arc<[String]>,
+    /// What is actually sent to greetd, after `[sessions]` processing.
+    /// Shared, so logging in doesn't copy it.
+    effective_exec: Arc<[String]>,
+    kind: sessions::SessionKind,
+    /// Show `kind` after the name. Never part of the name itself.
+    tagged: bool
 }
 
-impl Target {
-    fn load<P: AsRef<Path>>(path: P) -> Option<Self> {
-        let path = path.as_ref();
-        let data = match fs::read_to_string(path) {
-            Ok(data) => data,
-            Err(e) => {
-                debug!("Skipping target at {:?}: failed to read desktop entry: {e}", path);
-                return None;
-            }
-        };
+/// The session list. Never empty, so a wrapped index is always valid.
+#[derive(PartialEq)]
+struct Targets(Vec<Target>);
 
-        let entry = match DesktopEntry::decode(path, &data) {
-            Ok(entry) => entry,
-            Err(e) => {
-                debug!("Skipping target at {:?}: failed to parse desktop entry: {e}", path);
-                return None;
-            }
-        };
+impl Targets {
+    fn new(targets: Vec<Target>) -> Option<Self> {
+        if targets.is_empty() {
+            None
+        } else {
+            Some(Self(targets))
+        }
+    }
 
-        let cmdline = match entry.exec() {
-            Some(cmdline) => cmdline,
-            None => {
-                debug!("Skipping target at {:?}: missing Exec=", path);
-                return None;
-            }
-        };
+    fn len(&self) -> usize {
+        self.0.len()
+    }
 
-        let exec = match shell_words::split(cmdline) {
-            Ok(exec) => exec,
-            Err(e) => {
-                debug!(
-                    "Skipping target at {:?}: failed to parse Exec command line ({cmdline:?}): {e}",
-                    path
-                );
-                return None;
-            }
-        };
+    fn get(&self, index: usize) -> &Target {
+        &self.0[index % self.0.len()]
+    }
+
+    /// The index after `index`, wrapping around to the first.
+    fn next(&self, index: usize) -> usize {
+        (index % self.len() + 1) % self.len()
+    }
+
+    /// The index before `index`, wrapping around to the last.
+    fn prev(&self, index: usize) -> usize {
+        let len = self.len();
+        (index % len + len - 1) % len
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.0.iter().position(|t| t.name == name)
+    }
+
+    fn names(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(|t| t.name.as_str())
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Target> {
+        self.0.iter()
+    }
+}
+
+/// Applies `sessions.command_prefix` and `sessions.wrap_in_shell` to a
+/// desktop entry's command line.
+fn effective_exec(
+    exec: &[String],
+    prefix: &[String],
+    wrap_in_shell: bool
+) -> Vec<String> {
+    let cmd: Vec<String> = prefix.iter().chain(exec).cloned().collect();
+    if wrap_in_shell {
+        vec![
+            "/bin/sh".to_string(),
+            "-l".to_string(),
+            "-c".to_string(),
+            format!("exec {}", shell_words::join(&cmd))
+        ]
+    } else {
+        cmd
+    }
+}
 
-        let name = entry.name(None).unwrap_or(entry.appid.into()).into_owned();
+impl Target {
+    fn load<P: AsRef<Path>>(
+        path: P,
+        kind: sessions::SessionKind,
+        prefix: &[String],
+        wrap_in_shell: bool
+    ) -> Result<Self, sessions::SkipReason> {
+        let entry = entry::Entry::load(path.as_ref(), kind)?;
+        let effective_exec = effective_exec(&entry.exec, prefix, wrap_in_shell);
+        debug!(
+            "Loaded target {:?} from {:?}: exec={:?} effective_exec={effective_exec:?}",
+            entry.name, entry.path, entry.exec
+        );
 
-        Some(Self { name, exec })
+        Ok(Self {
+            name: entry.name,
+            path: entry.path,
+            comment: entry.comment,
+            icon: entry.icon,
+            exec: entry.exec.into(),
+            effective_exec: effective_exec.into(),
+            kind,
+            tagged: false
+        })
     }
 }
 
@@ -96,64 +249,176 @@ struct LoginManager<'a> {
     buf: &'a mut [u8],
     device: &'a fs::File,
 
-    heading_font: draw::Font,
-    main_font: draw::Font,
+    heading_font: font::Font,
+    main_font: font::Font,
+    /// Half-size main font for the session comment and the footer.
+    small_font: font::Font,
 
     colors: settings::ResolvedColors,
+    /// The configured palette; `colors` is this, warmed at night.
+    base_colors: settings::ResolvedColors,
+    night_window: Option<night::NightWindow>,
+    /// Only with ui.attract_mode.
+    attract: Option<attract::Attract>,
+    night_active: bool,
+    /// Config errors to show at the top of the screen, until when.
+    config_warning: Option<(String, std::time::Instant)>,
+    motd_file: Option<std::path::PathBuf>,
+    motd_max_lines: usize,
+    motd: Vec<String>,
+    motd_mtime: Option<std::time::SystemTime>,
+    hostname: String,
 
-    forced_username: Option<String>,
-    lock_target: bool,
+    /// `ui.hide_target`, or the session row isn't listed.
     hide_target: bool,
-    hide_username: bool,
+    /// Every row, top to bottom, including hidden ones.
+    row_order: Vec<Mode>,
+    labels: settings::Labels,
     gap_below_session_px: u32,
     gap_below_username_px: u32,
     row_h: u32,
     password_char: String,
+    password_style: settings::PasswordStyle,
     text_align: settings::TextAlign,
-    input_margin_px: u32,
+    text_valign: settings::TextValign,
+    input_margin: layout::Margin,
     heading_offset_y_px: u32,
+    show_greeting: bool,
+    form_opacity: f32,
+    unfocused_dim: f32,
+    underline_px: u32,
+    underline_width: widgets::UnderlineWidth,
+    underline_gap_px: Option<u32>,
+    /// The translucent form panel, once composited.
+    panel: Option<widgets::Panel>,
     blackout_on_success: bool,
     session_left_arrow: String,
     session_right_arrow: String,
+    bell: bool,
+    bell_frequency_hz: u32,
+    bell_duration_ms: u32,
+    bell_on_wrap: bool,
+    bell_unsupported: bool,
+    username_case_hint: bool,
+    username_candidates: Vec<String>,
+    user_list_rows: usize,
+    /// Highlighted and first visible candidate while the username list is
+    /// open.
+    user_list: Option<(usize, usize)>,
+    debug_overlay: bool,
+    /// The debug overlay is up.
+    showing_debug: bool,
+    show_session_comment: bool,
+    /// `[panel]`, beside the form.
+    info_panel: Option<info_panel::InfoPanel>,
+    /// Session icons by Icon= value, with `ui.show_session_icons`.
+    session_icons: Option<icons::IconCache>,
+    /// `ui.qr`, encoded.
+    qr: Option<qr::QrImage>,
+    /// A fixed footer; without one it shows hints for the focused field.
+    footer: Option<String>,
+    show_version: bool,
+    battery: Option<std::path::PathBuf>,
+    battery_status: Option<battery::BatteryStatus>,
+    battery_low_percent: u8,
+    battery_icon: String,
+    show_network: bool,
+    /// Failed attempt tracking; only with ui.show_failed_attempts.
+    state: Option<state::State>,
+    require_network: bool,
+    /// Last connectivity check result; `None` until checked.
+    online: Option<bool>,
+    network_checked: Option<std::time::Instant>,
+    auto_submit_length: usize,
+    auto_submit_idle: Option<std::time::Duration>,
+    /// `input.max_repeat_per_sec`.
+    max_repeat_per_sec: u32,
+    /// When the refused-key flash on the password underline ends.
+    password_flash: Option<std::time::Instant>,
+    handoff_delay: Option<std::time::Duration>,
+
+    keys: keys::KeyBindings,
+    shutdown_command: String,
+    reboot_command: String,
+    reveal_password: bool,
+    password_peek: Option<std::time::Duration>,
+    /// Index of the password character shown in clear, and when it was
+    /// typed.
+    peeking: Option<(usize, std::time::Instant)>,
+
+    message: Option<(String, color::Color)>,
+    message_dirty: bool,
 
     screen_size: (u32, u32),
     dimensions: (u32, u32),
-    mode: Mode,
-    greetd: greetd::GreetD,
-    targets: Vec<Target>,
+    layout: layout::FormLayout,
+    narrow_layout: bool,
+    anchor: settings::Anchor,
+    offset: (i32, i32),
+    greeting_anchor: settings::Anchor,
+    content_max_width_px: Option<u32>,
+    /// What the text rows last showed, to skip redundant renders. The
+    /// password is only remembered masked.
+    drawn_username: Option<widgets::DrawnText>,
+    drawn_password: Option<widgets::DrawnText>,
+    /// The fields, focus and submit rules.
+    form: form::Form,
+    form_state: FormState,
+    greetd: greetd::Backend,
+    targets: Targets,
     target_index: usize,
+    discovery: sessions::Discovery,
+    /// Desktop entries discovery left out, for the debug overlay.
+    skipped_targets: sessions::Skipped,
+    status: status::Status,
+    watchdog: watchdog::Watchdog,
+    /// `login.target`, kept to re-resolve it after a re-scan.
+    forced_target: Option<String>,
 
     var_screen_info: &'a VarScreeninfo,
-    should_refresh: bool
+    should_refresh: bool,
+    /// Shortest time between refreshes, from `ui.max_refresh_hz`.
+    refresh_interval: Option<std::time::Duration>,
+    last_refresh: Option<std::time::Instant>,
+    /// Refreshes done, for the log.
+    refreshes: u64,
+    stats: stats::DrawStats,
+    /// How often to log `stats`, with `logging.stats`, and when they
+    /// were last logged.
+    log_stats_every: Option<std::time::Duration>,
+    stats_logged_at: std::time::Instant
 }
 
 impl<'a> LoginManager<'a> {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         fb: &'a mut Framebuffer,
         screen_size: (u32, u32),
         dimensions: (u32, u32),
-        greetd: greetd::GreetD,
-        targets: Vec<Target>,
+        greetd: greetd::Backend,
+        targets: Targets,
+        discovery: sessions::Discovery,
         fonts: &settings::Fonts,
         colors: settings::ResolvedColors,
+        hostname: String,
+        keys: keys::KeyBindings,
         login: &settings::Login,
-        ui: &settings::Ui
+        ui: &settings::Ui,
+        power: &settings::Power,
+        input: &settings::Input
     ) -> Self {
-        let forced_username = login
-            .username
-            .as_deref()
-            .map(str::trim)
-            .filter(|s| !s.is_empty())
-            .map(|s| s.to_string());
+        let forced_username = form::forced_username(login);
 
-        let (target_index, forced_target_found) = match login
+        let forced_target = login
             .target
             .as_deref()
             .map(str::trim)
             .filter(|s| !s.is_empty())
+            .map(|s| s.to_string());
+
+        let (target_index, forced_target_found) = match forced_target.as_deref()
         {
-            Some(forced) => match targets.iter().position(|t| t.name == forced)
-            {
+            Some(forced) => match targets.position(forced) {
                 Some(i) => {
                     info!("Using configured target session as default: {forced:?}");
                     (i, true)
@@ -168,19 +433,33 @@ impl<'a> LoginManager<'a> {
             None => (0, false)
         };
 
-        let lock_target = forced_target_found && ui.hide_target;
+        // Validated in main, which warns about a bad order.
+        let mut row_order = form_layout::parse_row_order(&ui.row_order)
+            .unwrap_or_else(|_| form_layout::DEFAULT_ROW_ORDER.to_vec());
+        let listed = |row| row_order.contains(&row);
+        // A single field means no session or username rows.
+        let hide_target = ui.hide_target
+            || ui.single_field
+            || !listed(Mode::SelectingSession);
+        let hide_username = ui.hide_username
+            || ui.single_field
+            || !listed(Mode::EditingUsername);
+        // Rows that can't be hidden after all go at the bottom.
+        for row in &form_layout::DEFAULT_ROW_ORDER {
+            if !row_order.contains(row) {
+                row_order.push(*row);
+            }
+        }
+        // The session row only goes if a configured session was found.
+        let show_target = !(forced_target_found && hide_target);
+        let show_username = forced_username.is_none() || !hide_username;
+        let rows = form_rows(&row_order, show_target, show_username);
 
         if let Some(u) = forced_username.as_deref() {
-            info!("Forcing username from config (len={})", u.len());
-            debug!("Forced username: {u:?}");
+            info!("Forcing username {} from config", log_safe::username(u));
+            log_safe::trace_username("Forced username", u);
         }
 
-        let mode = if forced_username.is_some() && ui.hide_username {
-            Mode::EditingPassword
-        } else {
-            Mode::EditingUsername
-        };
-
         let password_char = ui.password_char.trim();
         let password_char = if password_char.is_empty() {
             "*".to_string()
@@ -188,71 +467,502 @@ impl<'a> LoginManager<'a> {
             password_char.to_string()
         };
 
-        let session_left_arrow = ui.session_left_arrow.trim().to_string();
-        let session_right_arrow = ui.session_right_arrow.trim().to_string();
+        let username_candidates =
+            if login.complete_usernames && forced_username.is_none() {
+                match accounts::local_usernames() {
+                    Ok(names) => {
+                        info!("Loaded {} username completion candidates", names.len());
+                        names
+                    }
+                    Err(e) => {
+                        warn!("Unable to read local accounts for completion: {e}");
+                        Vec::new()
+                    }
+                }
+            } else {
+                Vec::new()
+            };
+
+        let main_font = font::Font::new(&fonts.main, fonts.main_size_px);
+        let small_font = font::Font::new(&fonts.main, fonts.main_size_px * 0.5);
+
+        let battery = if ui.show_battery {
+            let found = battery::find();
+            if found.is_none() {
+                info!("No battery found; not showing the battery indicator");
+            }
+            found
+        } else {
+            None
+        };
+        let battery_icon = if small_font.can_render("🔋") {
+            "🔋".to_string()
+        } else {
+            "BAT".to_string()
+        };
+
+        let session_left_arrow =
+            checked_arrow(&main_font, ui.session_left_arrow.trim(), "<");
+        let session_right_arrow =
+            checked_arrow(&main_font, ui.session_right_arrow.trim(), ">");
 
-        Self {
+        let mut lm = Self {
             buf: &mut fb.frame,
             device: &fb.device,
-            heading_font: draw::Font::new(&fonts.heading, fonts.heading_size_px),
-            main_font: draw::Font::new(&fonts.main, fonts.main_size_px),
+            heading_font: font::Font::new(&fonts.heading, fonts.heading_size_px),
+            main_font,
+            small_font,
             colors,
-            forced_username,
-            lock_target,
-            hide_target: ui.hide_target,
-            hide_username: ui.hide_username,
+            base_colors: colors,
+            night_window: night::window(&ui.night_mode),
+            attract: ui.attract_mode.then(|| {
+                attract::Attract::new(
+                    std::time::Duration::from_secs(ui.attract_after_secs),
+                    attract::palette(&ui.attract_palette)
+                )
+            }),
+            night_active: false,
+            config_warning: None,
+            motd_file: ui
+                .motd_file
+                .as_deref()
+                .filter(|path| !path.trim().is_empty())
+                .map(std::path::PathBuf::from),
+            motd_max_lines: ui.motd_max_lines,
+            motd: Vec::new(),
+            motd_mtime: None,
+            hostname,
+            hide_target,
+            row_order,
+            labels: ui.labels.clone(),
             gap_below_session_px: ui.gap_below_session_px,
             gap_below_username_px: ui.gap_below_username_px,
             row_h: ui.row_h,
             password_char,
+            password_style: ui.password_style,
             text_align: ui.text_align,
-            input_margin_px: ui.input_margin_px,
+            text_valign: ui.text_valign,
+            input_margin: ui
+                .input_margin
+                .as_ref()
+                .and_then(settings::InputMargin::margin)
+                .unwrap_or(layout::Margin::Px(ui.input_margin_px)),
             heading_offset_y_px: ui.heading_offset_y_px,
+            show_greeting: ui.show_greeting,
+            form_opacity: ui.form_opacity.clamp(0.0, 1.0),
+            unfocused_dim: ui.unfocused_dim.clamp(0.0, 1.0),
+            underline_px: ui.underline_px,
+            underline_width: widgets::UnderlineWidth::parse(&ui.underline_width)
+                .unwrap_or(widgets::UnderlineWidth::Full),
+            underline_gap_px: ui.underline_gap_px,
+            panel: None,
             blackout_on_success: ui.blackout_on_success,
             session_left_arrow,
             session_right_arrow,
+            bell: ui.bell,
+            bell_frequency_hz: ui.bell_frequency_hz,
+            bell_duration_ms: ui.bell_duration_ms,
+            bell_on_wrap: ui.bell_on_wrap,
+            bell_unsupported: false,
+            username_case_hint: login.username_case_hint,
+            username_candidates,
+            user_list_rows: ui.user_list_rows,
+            user_list: None,
+            debug_overlay: ui.debug_overlay,
+            showing_debug: false,
+            show_session_comment: ui.show_session_comment,
+            info_panel: None,
+            session_icons: ui.show_session_icons.then(icons::IconCache::new),
+            qr: ui.qr.as_ref().and_then(qr::QrImage::new),
+            footer: ui.footer.as_deref().map(|f| f.trim().to_string()),
+            show_version: ui.show_version,
+            battery,
+            battery_status: None,
+            battery_low_percent: ui.battery_low_percent,
+            battery_icon,
+            show_network: ui.show_network,
+            state: ui.show_failed_attempts.then(|| {
+                let path = paths::state_file();
+                let seat = state::seat();
+                info!("Keeping state for {seat} in {}", path.display());
+                state::State::for_seat(&path, &seat, &state::boot_id())
+            }),
+            require_network: login.require_network,
+            online: None,
+            network_checked: None,
+            auto_submit_length: login.auto_submit_length,
+            auto_submit_idle: (login.auto_submit_idle_ms > 0).then(|| {
+                std::time::Duration::from_millis(login.auto_submit_idle_ms)
+            }),
+            password_flash: None,
+            max_repeat_per_sec: input.max_repeat_per_sec,
+            handoff_delay: (login.handoff_delay_ms > 0).then(|| {
+                std::time::Duration::from_millis(login.handoff_delay_ms)
+            }),
+            keys,
+            shutdown_command: power.shutdown_command.clone(),
+            reboot_command: power.reboot_command.clone(),
+            reveal_password: false,
+            password_peek: (ui.password_peek_ms > 0).then(|| {
+                std::time::Duration::from_millis(ui.password_peek_ms)
+            }),
+            peeking: None,
+            message: None,
+            message_dirty: false,
             screen_size,
             dimensions,
-            mode,
+            layout: layout::FormLayout::default(),
+            anchor: ui.anchor,
+            offset: (ui.offset_x_px, ui.offset_y_px),
+            greeting_anchor: ui.greeting_anchor,
+            content_max_width_px: ui.content_max_width_px,
+            narrow_layout: match ui.layout {
+                settings::LayoutProfile::Wide => false,
+                settings::LayoutProfile::Narrow => true,
+                settings::LayoutProfile::Auto => screen_size.0 < NARROW_SCREEN_PX
+            },
+            drawn_username: None,
+            drawn_password: None,
+            form: form::Form::new(
+                form::Rules::new(login, rows, ui.single_field),
+                login.select_username
+            ),
+            form_state: FormState::Idle,
             greetd,
             targets,
             target_index, // TODO: remember last user selection
+            discovery,
+            skipped_targets: Vec::new(),
+            status: status::Status::default(),
+            watchdog: watchdog::Watchdog::default(),
+            forced_target,
             var_screen_info: &fb.var_screen_info,
-            should_refresh: false
-        }
+            should_refresh: false,
+            refresh_interval: (ui.max_refresh_hz > 0).then(|| {
+                std::time::Duration::from_secs(1) / ui.max_refresh_hz
+            }),
+            last_refresh: None,
+            refreshes: 0,
+            stats: stats::DrawStats::default(),
+            log_stats_every: None,
+            stats_logged_at: std::time::Instant::now()
+        };
+        lm.relayout();
+        lm.update_night_mode();
+        lm
     }
 
     pub(crate) fn show_target_row(&self) -> bool {
-        if self.lock_target {
-            !self.hide_target
-        } else {
-            true
-        }
+        self.form.shows(Mode::SelectingSession)
+    }
+
+    pub(crate) fn current_target(&self) -> &Target {
+        self.targets.get(self.target_index)
+    }
+
+    /// Selects the next session, wrapping around.
+    pub(crate) fn next_target(&mut self) {
+        self.target_index = self.targets.next(self.target_index);
+    }
+
+    /// Selects the previous session, wrapping around.
+    pub(crate) fn prev_target(&mut self) {
+        self.target_index = self.targets.prev(self.target_index);
     }
 
     pub(crate) fn show_username_row(&self) -> bool {
-        if self.forced_username.is_some() {
-            !self.hide_username
-        } else {
-            true
+        self.form.shows(Mode::EditingUsername)
+    }
+
+    /// Shows `text` with the bitmap font on a blank screen for a few
+    /// seconds, for errors we can't recover from.
+    fn show_fatal(&mut self, text: &str, exit: Exit) {
+        show_fatal(
+            self.buf,
+            self.screen_size,
+            self.device,
+            self.var_screen_info,
+            &self.colors,
+            text,
+            exit
+        );
+    }
+
+    /// Shows a one-line message below the form until the next keypress.
+    pub(crate) fn set_message(&mut self, text: &str, color: color::Color) {
+        self.message = Some((text.to_string(), color));
+        self.message_dirty = true;
+    }
+
+    pub(crate) fn clear_message(&mut self) {
+        if self.message.take().is_some() {
+            self.message_dirty = true;
+        }
+    }
+}
+
+const FALLBACK_HOSTNAME: &str = "this machine";
+
+/// The machine name for the greeting: `ui.hostname` if set, otherwise the
+/// result of `lookup` shaped by `ui.hostname_style`. Never fails; a lookup
+/// error only costs us the name.
+fn display_hostname(
+    ui: &settings::Ui,
+    lookup: impl FnOnce() -> io::Result<std::ffi::OsString>
+) -> String {
+    if let Some(name) = ui.hostname.as_deref().map(str::trim) {
+        if !name.is_empty() {
+            return name.to_string();
+        }
+    }
+
+    let name = match lookup() {
+        Ok(name) => name.to_string_lossy().into_owned(),
+        Err(e) => {
+            warn!("Unable to look up hostname: {e}");
+            return FALLBACK_HOSTNAME.to_string();
+        }
+    };
+    let name = match ui.hostname_style {
+        settings::HostnameStyle::Short => {
+            name.split('.').next().unwrap_or_default().to_string()
+        }
+        settings::HostnameStyle::Fqdn => name
+    };
+    if name.is_empty() {
+        FALLBACK_HOSTNAME.to_string()
+    } else {
+        name
+    }
+}
+
+/// Shows `text` with the bitmap font on a blank screen for a few seconds,
+/// for errors we can't recover from, along with the status mflm is about
+/// to exit with. Needs nothing but the framebuffer.
+fn show_fatal(
+    frame: &mut [u8],
+    screen_size: (u32, u32),
+    device: &fs::File,
+    var_screen_info: &VarScreeninfo,
+    colors: &settings::ResolvedColors,
+    text: &str,
+    exit: Exit
+) {
+    draw::draw_splash(
+        &mut buffer::Buffer::new(frame, screen_size),
+        &colors.background,
+        &colors.error,
+        None,
+        &format!("{text} (exit {})", exit.code())
+    );
+    draw::refresh_screen(device, var_screen_info);
+    std::thread::sleep(FATAL_DISPLAY);
+}
+
+/// `display.fallback_text`: logs in with prompts on stdin and stdout
+/// once greetd and the session scan, started before the framebuffer
+/// turned out to be missing, are done.
+fn run_text_fallback(
+    connecting: thread::JoinHandle<
+        Result<greetd::Backend, greetd::GreetDError>
+    >,
+    scanning: thread::JoinHandle<(
+        sessions::Discovery,
+        (Vec<Target>, sessions::Skipped)
+    )>,
+    settings: &settings::Settings
+) -> Exit {
+    let joined = connecting.join();
+    let greetd = match joined.unwrap_or_else(|e| panic::resume_unwind(e)) {
+        Ok(g) => g,
+        Err(e) => {
+            error!("Unable to connect to greetd: {e}");
+            eprintln!("mflm: unable to connect to greetd: {e}");
+            return Exit::Startup;
+        }
+    };
+    let joined = scanning.join();
+    let (_, (targets, _)) = joined.unwrap_or_else(|e| panic::resume_unwind(e));
+    let targets = match Targets::new(targets) {
+        Some(targets) => targets,
+        None => {
+            error!("No session targets found; cannot continue");
+            eprintln!("mflm: no sessions found");
+            return Exit::Startup;
+        }
+    };
+    info!("Loaded {} session targets", targets.len());
+
+    let hostname = display_hostname(&settings.ui, hostname::get);
+    text_mode::run(greetd, targets, &settings.login, &hostname)
+}
+
+/// The rows of `row_order` that show, top to bottom. The password row
+/// always does.
+fn form_rows(
+    row_order: &[Mode],
+    show_target: bool,
+    show_username: bool
+) -> Vec<Mode> {
+    row_order
+        .iter()
+        .copied()
+        .filter(|&row| match row {
+            Mode::SelectingSession => show_target,
+            Mode::EditingUsername => show_username,
+            Mode::EditingPassword => true
+        })
+        .collect()
+}
+
+/// Returns `arrow` if `font` can render it, `fallback` otherwise.
+fn checked_arrow(font: &font::Font, arrow: &str, fallback: &str) -> String {
+    if arrow.is_empty() || font.can_render(arrow) {
+        arrow.to_string()
+    } else {
+        info!(
+            "Session arrow {arrow:?} is not renderable with fonts.main; using {fallback:?}"
+        );
+        fallback.to_string()
+    }
+}
+
+/// Command line options.
+struct Args {
+    /// Script to drive the greeter with instead of the keyboard.
+    replay: Option<PathBuf>,
+    /// Where to write a line per state change.
+    status_fd: Option<std::os::unix::io::RawFd>,
+    /// Print the configuration in effect and exit.
+    dump_config: bool,
+    /// Log in against a stand-in instead of greetd.
+    no_greetd: bool
+}
+
+/// Parses the command line, exiting on `--help` or bad options.
+fn parse_args() -> Args {
+    let mut opts = getopts::Options::new();
+    opts.optopt(
+        "",
+        "replay",
+        "drive the greeter from a script instead of the keyboard",
+        "FILE"
+    );
+    opts.optopt(
+        "",
+        "status-fd",
+        "write a line to file descriptor N on every state change",
+        "N"
+    );
+    opts.optflag(
+        "",
+        "dump-config",
+        "print the configuration in effect as TOML and exit"
+    );
+    opts.optflag(
+        "",
+        "no-greetd",
+        "try the greeter without greetd; any username logs in with the \
+         password \"test\""
+    );
+    opts.optflag("h", "help", "print this help and exit");
+
+    let usage = opts.usage("Usage: mflm [options]");
+    let matches = match opts.parse(std::env::args().skip(1)) {
+        Ok(matches) => matches,
+        Err(e) => {
+            eprintln!("mflm: {e}\n\n{usage}");
+            process::exit(Exit::Startup.code());
+        }
+    };
+    if matches.opt_present("help") {
+        print!("{usage}");
+        process::exit(0);
+    }
+
+    let status_fd = match matches.opt_get("status-fd") {
+        Ok(fd) => fd,
+        Err(e) => {
+            eprintln!("mflm: invalid --status-fd: {e}\n\n{usage}");
+            process::exit(Exit::Startup.code());
+        }
+    };
+
+    Args {
+        replay: matches.opt_str("replay").map(PathBuf::from),
+        status_fd,
+        dump_config: matches.opt_present("dump-config"),
+        no_greetd: matches.opt_present("no-greetd")
+    }
+}
+
+/// `--dump-config`: loads the settings the way the greeter does and
+/// prints them, without touching the framebuffer or greetd.
+fn dump_config() -> ! {
+    let dumped = settings::Settings::load()
+        .map_err(|e| e.to_string())
+        .and_then(|s| s.to_toml().map_err(|e| e.to_string()));
+    match dumped {
+        Ok(toml) => {
+            print!("{toml}");
+            process::exit(0);
+        }
+        Err(e) => {
+            eprintln!("mflm: unable to load config: {e}");
+            process::exit(Exit::Startup.code());
         }
     }
 }
 
 fn main() {
-    if let Err(e) = init_logging() {
+    run().exit();
+}
+
+/// Everything up to exiting, so that the console and the framebuffer are
+/// put back before the process goes.
+fn run() -> Exit {
+    let args = parse_args();
+    if args.dump_config {
+        dump_config();
+    }
+
+    let log_path = paths::log_dir().join("mflm.log");
+    if let Err(e) = init_logging(&log_path) {
         // If the log file can't be opened (permissions, missing /var, etc), we
         // can't reliably provide the requested file logging.
         eprintln!(
-            "Failed to initialize file logger (/var/log/mflm/mflm.log): {e}"
+            "Failed to initialize file logger ({}): {e}",
+            log_path.display()
         );
-        return;
+        return Exit::Startup;
     }
 
     info!("mflm starting at {}", Local::now().to_rfc3339());
-    debug!("argv: {:?}", std::env::args().collect::<Vec<_>>());
+    info!("Logging to {}", log_path.display());
+    debug!("args: {:?}", log_safe::args(std::env::args().skip(1)));
 
-    let settings = match settings::Settings::load() {
+    let replay = match args.replay.as_deref().map(replay::Replay::load) {
+        Some(Ok(replay)) => Some(replay),
+        Some(Err(e)) => {
+            error!("{e}");
+            eprintln!("mflm: {e}");
+            return Exit::Replay;
+        }
+        None => None
+    };
+    let status = match args.status_fd.map(status::Status::open) {
+        Some(Ok(status)) => status,
+        Some(Err(e)) => {
+            error!("Unable to use --status-fd: {e}");
+            eprintln!("mflm: unable to use --status-fd: {e}");
+            return Exit::Startup;
+        }
+        None => status::Status::default()
+    };
+
+    // Shown on screen for a while, so a broken config doesn't go unnoticed.
+    let mut config_warnings = Vec::new();
+    let mut settings = match settings::Settings::load() {
         Ok(s) => {
             info!("Loaded configuration successfully");
             debug!(
@@ -263,29 +973,114 @@ fn main() {
                 s.fonts.main_size_px
             );
             debug!(
-                "Configured login: target={:?} username={:?}",
+                "Configured login: target={:?} username={:?} select_username={} trim_username={} complete_usernames={} clear_username_on_failure={} username_case_hint={} require_network={} auto_submit_length={} auto_submit_idle_ms={} submit_key={:?} max_password_len={} numeric_only={} handoff_delay_ms={} strict_start_ack={} allow_empty_password={} retry_delay_ms={}",
                 s.login.target,
-                s.login.username
+                s.login.username.as_deref().map(log_safe::username),
+                s.login.select_username,
+                s.login.trim_username,
+                s.login.complete_usernames,
+                s.login.clear_username_on_failure,
+                s.login.username_case_hint,
+                s.login.require_network,
+                s.login.auto_submit_length,
+                s.login.auto_submit_idle_ms,
+                s.login.submit_key,
+                s.login.max_password_len,
+                s.login.numeric_only,
+                s.login.handoff_delay_ms,
+                s.login.strict_start_ack,
+                s.login.allow_empty_password,
+                s.login.retry_delay_ms
             );
             debug!(
-                "Configured ui: hide_target={} hide_username={} gap_below_session_px={} gap_below_username_px={} row_h={} password_char={:?} text_align={:?} input_margin_px={} heading_offset_y_px={} blackout_on_success={} form_width={} form_height={}",
+                "Configured ui: hide_target={} hide_username={} gap_below_session_px={} gap_below_username_px={} row_h={} password_char={:?} password_style={:?} password_peek_ms={} text_align={:?} text_valign={:?} input_margin_px={} input_margin={:?} heading_offset_y_px={} blackout_on_success={} form_width={} form_height={} bell={} bell_frequency_hz={} bell_duration_ms={} bell_on_wrap={} show_session_comment={} show_session_icons={} footer={:?} show_version={} hostname={:?} hostname_style={:?} show_battery={} battery_low_percent={} show_network={} show_failed_attempts={} layout={:?} anchor={:?} offset_x_px={} offset_y_px={} greeting_anchor={:?} content_max_width_px={:?} night_mode={:?} motd_file={:?} motd_max_lines={} user_list_rows={} max_refresh_hz={} debug_overlay={} labels={:?} single_field={} show_greeting={} form_opacity={} unfocused_dim={} underline_px={} underline_width={:?} underline_gap_px={:?} attract_mode={} attract_after_secs={} attract_palette={:?} row_order={:?} qr={:?}",
                 s.ui.hide_target,
                 s.ui.hide_username,
                 s.ui.gap_below_session_px,
                 s.ui.gap_below_username_px,
                 s.ui.row_h,
                 s.ui.password_char,
+                s.ui.password_style,
+                s.ui.password_peek_ms,
                 s.ui.text_align,
+                s.ui.text_valign,
                 s.ui.input_margin_px,
+                s.ui.input_margin,
                 s.ui.heading_offset_y_px,
                 s.ui.blackout_on_success,
                 s.ui.form_width,
-                s.ui.form_height
+                s.ui.form_height,
+                s.ui.bell,
+                s.ui.bell_frequency_hz,
+                s.ui.bell_duration_ms,
+                s.ui.bell_on_wrap,
+                s.ui.show_session_comment,
+                s.ui.show_session_icons,
+                s.ui.footer,
+                s.ui.show_version,
+                s.ui.hostname,
+                s.ui.hostname_style,
+                s.ui.show_battery,
+                s.ui.battery_low_percent,
+                s.ui.show_network,
+                s.ui.show_failed_attempts,
+                s.ui.layout,
+                s.ui.anchor,
+                s.ui.offset_x_px,
+                s.ui.offset_y_px,
+                s.ui.greeting_anchor,
+                s.ui.content_max_width_px,
+                s.ui.night_mode,
+                s.ui.motd_file,
+                s.ui.motd_max_lines,
+                s.ui.user_list_rows,
+                s.ui.max_refresh_hz,
+                s.ui.debug_overlay,
+                s.ui.labels,
+                s.ui.single_field,
+                s.ui.show_greeting,
+                s.ui.form_opacity,
+                s.ui.unfocused_dim,
+                s.ui.underline_px,
+                s.ui.underline_width,
+                s.ui.underline_gap_px,
+                s.ui.attract_mode,
+                s.ui.attract_after_secs,
+                s.ui.attract_palette,
+                s.ui.row_order,
+                s.ui.qr
+            );
+            debug!(
+                "Configured input: max_repeat_per_sec={}",
+                s.input.max_repeat_per_sec
+            );
+            debug!(
+                "Configured watchdog: timeout_secs={}",
+                s.watchdog.timeout_secs
+            );
+            debug!(
+                "Configured display: quiet_console={} fallback_text={}",
+                s.display.quiet_console,
+                s.display.fallback_text
+            );
+            debug!(
+                "Configured logging: stats={} stats_interval_secs={}",
+                s.logging.stats,
+                s.logging.stats_interval_secs
+            );
+            debug!("Configured config: watch={}", s.config.watch);
+            debug!(
+                "Configured panel: side={:?} width_percent={} image={:?} text={:?}",
+                s.panel.side,
+                s.panel.width_percent,
+                s.panel.image,
+                s.panel.text
             );
             s
         }
         Err(e) => {
             warn!("Failed to load config; using defaults: {e}");
+            config_warnings.push(format!("config error: {e} - using defaults"));
             let s = settings::Settings::default();
             debug!(
                 "Default fonts: heading={:?} ({}px), main={:?} ({}px)",
@@ -295,109 +1090,308 @@ fn main() {
                 s.fonts.main_size_px
             );
             debug!(
-                "Default login: target={:?} username={:?}",
+                "Default login: target={:?} username={:?} select_username={} trim_username={} complete_usernames={} clear_username_on_failure={} username_case_hint={} require_network={} auto_submit_length={} auto_submit_idle_ms={} submit_key={:?} max_password_len={} numeric_only={} handoff_delay_ms={} strict_start_ack={} allow_empty_password={} retry_delay_ms={}",
                 s.login.target,
-                s.login.username
+                s.login.username.as_deref().map(log_safe::username),
+                s.login.select_username,
+                s.login.trim_username,
+                s.login.complete_usernames,
+                s.login.clear_username_on_failure,
+                s.login.username_case_hint,
+                s.login.require_network,
+                s.login.auto_submit_length,
+                s.login.auto_submit_idle_ms,
+                s.login.submit_key,
+                s.login.max_password_len,
+                s.login.numeric_only,
+                s.login.handoff_delay_ms,
+                s.login.strict_start_ack,
+                s.login.allow_empty_password,
+                s.login.retry_delay_ms
             );
             debug!(
-                "Default ui: hide_target={} hide_username={} gap_below_session_px={} gap_below_username_px={} row_h={} password_char={:?} text_align={:?} input_margin_px={} heading_offset_y_px={} blackout_on_success={} form_width={} form_height={}",
+                "Default ui: hide_target={} hide_username={} gap_below_session_px={} gap_below_username_px={} row_h={} password_char={:?} password_style={:?} password_peek_ms={} text_align={:?} text_valign={:?} input_margin_px={} input_margin={:?} heading_offset_y_px={} blackout_on_success={} form_width={} form_height={} bell={} bell_frequency_hz={} bell_duration_ms={} bell_on_wrap={} show_session_comment={} show_session_icons={} footer={:?} show_version={} hostname={:?} hostname_style={:?} show_battery={} battery_low_percent={} show_network={} show_failed_attempts={} layout={:?} anchor={:?} offset_x_px={} offset_y_px={} greeting_anchor={:?} content_max_width_px={:?} night_mode={:?} motd_file={:?} motd_max_lines={} user_list_rows={} max_refresh_hz={} debug_overlay={} labels={:?} single_field={} show_greeting={} form_opacity={} unfocused_dim={} underline_px={} underline_width={:?} underline_gap_px={:?} attract_mode={} attract_after_secs={} attract_palette={:?} row_order={:?} qr={:?}",
                 s.ui.hide_target,
                 s.ui.hide_username,
                 s.ui.gap_below_session_px,
                 s.ui.gap_below_username_px,
                 s.ui.row_h,
                 s.ui.password_char,
+                s.ui.password_style,
+                s.ui.password_peek_ms,
                 s.ui.text_align,
+                s.ui.text_valign,
                 s.ui.input_margin_px,
+                s.ui.input_margin,
                 s.ui.heading_offset_y_px,
                 s.ui.blackout_on_success,
                 s.ui.form_width,
-                s.ui.form_height
+                s.ui.form_height,
+                s.ui.bell,
+                s.ui.bell_frequency_hz,
+                s.ui.bell_duration_ms,
+                s.ui.bell_on_wrap,
+                s.ui.show_session_comment,
+                s.ui.show_session_icons,
+                s.ui.footer,
+                s.ui.show_version,
+                s.ui.hostname,
+                s.ui.hostname_style,
+                s.ui.show_battery,
+                s.ui.battery_low_percent,
+                s.ui.show_network,
+                s.ui.show_failed_attempts,
+                s.ui.layout,
+                s.ui.anchor,
+                s.ui.offset_x_px,
+                s.ui.offset_y_px,
+                s.ui.greeting_anchor,
+                s.ui.content_max_width_px,
+                s.ui.night_mode,
+                s.ui.motd_file,
+                s.ui.motd_max_lines,
+                s.ui.user_list_rows,
+                s.ui.max_refresh_hz,
+                s.ui.debug_overlay,
+                s.ui.labels,
+                s.ui.single_field,
+                s.ui.show_greeting,
+                s.ui.form_opacity,
+                s.ui.unfocused_dim,
+                s.ui.underline_px,
+                s.ui.underline_width,
+                s.ui.underline_gap_px,
+                s.ui.attract_mode,
+                s.ui.attract_after_secs,
+                s.ui.attract_palette,
+                s.ui.row_order,
+                s.ui.qr
+            );
+            debug!(
+                "Default input: max_repeat_per_sec={}",
+                s.input.max_repeat_per_sec
+            );
+            debug!(
+                "Default watchdog: timeout_secs={}",
+                s.watchdog.timeout_secs
+            );
+            debug!(
+                "Default display: quiet_console={} fallback_text={}",
+                s.display.quiet_console,
+                s.display.fallback_text
+            );
+            debug!(
+                "Default logging: stats={} stats_interval_secs={}",
+                s.logging.stats,
+                s.logging.stats_interval_secs
+            );
+            debug!("Default config: watch={}", s.config.watch);
+            debug!(
+                "Default panel: side={:?} width_percent={} image={:?} text={:?}",
+                s.panel.side,
+                s.panel.width_percent,
+                s.panel.image,
+                s.panel.text
             );
             s
         }
     };
 
-    let colors = match settings.resolve_colors() {
+    let mut colors = match settings.resolve_colors() {
         Ok(c) => {
             debug!(
-                "Configured colors: fg={:?} bg={:?} neutral={:?} selected={:?} error={:?}",
+                "Configured colors: fg={:?} bg={:?} neutral={:?} selected={:?} error={:?} form_idle={:?} form_busy={:?} form_error={:?}",
                 settings.colors.foreground,
                 settings.colors.background,
                 settings.colors.neutral,
                 settings.colors.selected,
-                settings.colors.error
+                settings.colors.error,
+                settings.colors.form_idle,
+                settings.colors.form_busy,
+                settings.colors.form_error
             );
             c
         }
         Err(e) => {
             warn!("Invalid colors in config; using defaults: {e}");
+            config_warnings
+                .push(format!("config error: {e} - using default colors"));
             settings::Settings::default()
                 .resolve_colors()
                 .expect("default colors must be valid")
         }
     };
 
+    if !colors.background.is_opaque() {
+        // There's nothing under the background to blend it with.
+        warn!("colors.background has an alpha below ff; drawing it opaque");
+        colors.background = colors.background.with_opacity(1.0);
+    }
+
+    if let Err(e) = settings.check_single_field() {
+        warn!("{e}; ignoring it");
+        config_warnings.push(format!("config error: {e}"));
+    }
+
+    if widgets::UnderlineWidth::parse(&settings.ui.underline_width).is_none() {
+        warn!(
+            "Invalid ui.underline_width {:?}; using full",
+            settings.ui.underline_width
+        );
+        config_warnings.push(format!(
+            "config error: invalid ui.underline_width {:?}",
+            settings.ui.underline_width
+        ));
+    }
+
+    let input_margin = settings.ui.input_margin.as_ref();
+    if input_margin.is_some_and(|m| m.margin().is_none()) {
+        warn!(
+            "Invalid ui.input_margin {:?}; using ui.input_margin_px",
+            settings.ui.input_margin
+        );
+        config_warnings.push(format!(
+            "config error: invalid ui.input_margin {:?}",
+            settings.ui.input_margin
+        ));
+    }
+
+    if let Err(e) = form_layout::parse_row_order(&settings.ui.row_order) {
+        warn!("{e}; using the default order");
+        config_warnings.push(format!("config error: {e}"));
+    }
+
+    let keys = match keys::KeyBindings::from_settings(&settings.keys) {
+        Ok(k) => k,
+        Err(e) => {
+            warn!("Invalid key bindings in config; using defaults: {e}");
+            config_warnings.push(format!("config error: {e} - using default keys"));
+            keys::KeyBindings::from_settings(&settings::Keys::default())
+                .expect("default key bindings must be valid")
+        }
+    };
+
+    let mut startup = metrics::Stopwatch::new();
+
+    // Neither needs the screen; they run while the splash goes up and the
+    // fonts load.
+    let strict_start_ack = settings.login.strict_start_ack;
+    let dry_run = args.no_greetd || greetd::dry_run_from_env();
+    let connecting = thread::spawn(move || {
+        let mut timing = metrics::Stopwatch::new();
+        let greetd = greetd::Backend::new(dry_run, strict_start_ack);
+        timing.lap("connect");
+        info!("greetd timing: {timing}");
+        greetd
+    });
+    let discovery = sessions::Discovery::from_settings(&settings.sessions);
+    let scanning = thread::spawn(move || {
+        let mut timing = metrics::Stopwatch::new();
+        info!("Scanning session targets");
+        let found = discovery.discover();
+        timing.lap("scan");
+        info!("session scan timing: {timing}");
+        (discovery, found)
+    });
+
     let mut framebuffer = match Framebuffer::new("/dev/fb0") {
         Ok(fb) => fb,
+        Err(e) if settings.display.fallback_text => {
+            warn!("Unable to open /dev/fb0 ({e}); using text prompts");
+            return run_text_fallback(connecting, scanning, &settings);
+        }
         Err(e) => {
             error!("Unable to open framebuffer device /dev/fb0: {e}");
-            return;
+            return Exit::Startup;
         }
     };
 
     let w = framebuffer.var_screen_info.xres;
     let h = framebuffer.var_screen_info.yres;
 
-    let raw = match std::io::stdout().into_raw_mode() {
-        Ok(raw) => raw,
+    // Restores the console on every way out of main from here on.
+    let quiet_console = settings.display.quiet_console;
+    let console = match console::ConsoleGuard::enter(quiet_console) {
+        Ok(console) => console,
         Err(e) => {
-            error!("Unable to enter raw mode: {e}");
-            return;
+            error!("Unable to set up the console: {e}");
+            return Exit::Startup;
         }
     };
+    console.restore_on_exit();
 
-    if let Err(e) = Framebuffer::set_kd_mode(KdMode::Graphics) {
-        error!("Unable to enter graphics mode: {e}");
-        drop(raw);
-        return;
-    }
+    // Something on screen straight away, before anything that may block
+    // or fail.
+    let hostname = display_hostname(&settings.ui, hostname::get);
+    draw::draw_splash(
+        &mut buffer::Buffer::new(&mut framebuffer.frame, (w, h)),
+        &colors.background,
+        &colors.neutral,
+        Some(&format!("Welcome to {hostname}")),
+        "Please wait..."
+    );
+    draw::refresh_screen(&framebuffer.device, &framebuffer.var_screen_info);
+    startup.lap("fb");
+
+    // Loading fonts can take a while on a cold boot. It happens off the
+    // main thread, alongside greetd and the session scan, while the splash
+    // stays up; nothing here uses pango until it's done.
+    let fonts = [settings.fonts.heading.clone(), settings.fonts.main.clone()];
+    let loading_fonts = thread::spawn(move || {
+        font::ensure_fonts(&paths::font_dir());
+        for desc in &fonts {
+            font::warm_up_font(desc);
+        }
+    });
 
-    let greetd = match greetd::GreetD::new() {
+    let joined = connecting.join();
+    let greetd = match joined.unwrap_or_else(|e| panic::resume_unwind(e)) {
         Ok(g) => g,
         Err(e) => {
             error!("Unable to connect to greetd: {e}");
-            let _ = Framebuffer::set_kd_mode(KdMode::Text);
-            drop(raw);
-            return;
+            show_fatal(
+                &mut framebuffer.frame,
+                (w, h),
+                &framebuffer.device,
+                &framebuffer.var_screen_info,
+                &colors,
+                &format!("mflm: unable to connect to greetd: {e}"),
+                Exit::Startup
+            );
+            return Exit::Startup;
         }
     };
 
-    info!("Scanning session targets");
-    let mut targets = Vec::new();
-    for dir in ["/usr/share/wayland-sessions", "/usr/share/xsessions"] {
-        match fs::read_dir(dir) {
-            Ok(rd) => {
-                for entry in rd.flatten() {
-                    if let Some(target) = Target::load(entry.path()) {
-                        targets.push(target);
-                    }
-                }
-            }
-            Err(e) => {
-                warn!("Unable to read sessions dir {dir}: {e}");
-            }
-        }
-    }
+    startup.lap("greetd");
 
-    if targets.is_empty() {
-        error!("No session targets found; cannot continue");
-        let _ = Framebuffer::set_kd_mode(KdMode::Text);
-        drop(raw);
-        return;
-    }
+    let joined = scanning.join();
+    let (discovery, (targets, skipped_targets)) =
+        joined.unwrap_or_else(|e| panic::resume_unwind(e));
+
+    let targets = match Targets::new(targets) {
+        Some(targets) => targets,
+        None => {
+            error!("No session targets found; cannot continue");
+            show_fatal(
+                &mut framebuffer.frame,
+                (w, h),
+                &framebuffer.device,
+                &framebuffer.var_screen_info,
+                &colors,
+                "mflm: no sessions found",
+                Exit::Startup
+            );
+            return Exit::Startup;
+        }
+    };
 
     info!("Loaded {} session targets", targets.len());
+    startup.lap("sessions");
+
+    if let Err(e) = loading_fonts.join() {
+        panic::resume_unwind(e);
+    }
+    startup.lap("fonts");
 
     let mut lm = LoginManager::new(
         &mut framebuffer,
@@ -405,38 +1399,78 @@ fn main() {
         (settings.ui.form_width, settings.ui.form_height),
         greetd,
         targets,
+        discovery,
         &settings.fonts,
         colors,
+        hostname,
+        keys,
         &settings.login,
         &settings.ui,
+        &settings.power,
+        &settings.input
     );
 
+    lm.skipped_targets = skipped_targets;
+    lm.set_info_panel(&settings.panel);
+    lm.load_session_icons();
+    lm.status = status;
+    if settings.logging.stats {
+        lm.log_stats_every = Some(std::time::Duration::from_secs(
+            settings.logging.stats_interval_secs.max(1)
+        ));
+    }
+    signals::install();
+    if settings.config.watch {
+        config_watch::start(&paths::config_files());
+    }
+    if settings.watchdog.timeout_secs > 0 {
+        // Its own handle on the log: the logger's may be locked.
+        let log = OpenOptions::new().append(true).open(&log_path).ok();
+        lm.watchdog = watchdog::Watchdog::start(
+            std::time::Duration::from_secs(settings.watchdog.timeout_secs),
+            console.restore(),
+            log
+        );
+    }
+    if !config_warnings.is_empty() {
+        lm.config_warning = Some((
+            config_warnings.join("; "),
+            std::time::Instant::now() + CONFIG_WARNING_TIME
+        ));
+    }
+
     lm.clear();
-    let bg = lm.colors.neutral;
-    if let Err(e) = lm.draw_bg(&bg) {
+    if let Err(e) = lm.draw_bg() {
         error!("Unable to draw background: {e}");
-        let _ = Framebuffer::set_kd_mode(KdMode::Text);
-        drop(raw);
-        return;
+        lm.show_fatal(&format!("mflm: unable to draw: {e}"), Exit::Fatal);
+        return Exit::Fatal;
     }
-    lm.refresh();
+    lm.flush();
+    startup.lap("draw");
+    info!("startup timing: {startup}");
 
-    lm.greeter_loop();
-    if let Err(e) = Framebuffer::set_kd_mode(KdMode::Text) {
-        error!("Unable to leave graphics mode: {e}");
+    let replayed = lm.greeter_loop(replay);
+    lm.watchdog.idle();
+    drop(console);
+
+    match replayed {
+        Ok(exit) => exit,
+        Err(e) => {
+            error!("Replay failed: {e}");
+            eprintln!("mflm: {e}");
+            Exit::Replay
+        }
     }
-    drop(raw);
 }
 
-fn init_logging() -> Result<(), io::Error> {
-    let log_dir = Path::new("/var/log/mflm");
-    let log_path = log_dir.join("mflm.log");
-
-    fs::create_dir_all(log_dir)?;
+fn init_logging(log_path: &Path) -> Result<(), io::Error> {
+    if let Some(log_dir) = log_path.parent() {
+        fs::create_dir_all(log_dir)?;
+    }
     let file = OpenOptions::new()
         .create(true)
         .append(true)
-        .open(&log_path)?;
+        .open(log_path)?;
 
     // Debug = verbose
     let mut log_config_builder = LogConfigBuilder::new();
@@ -446,6 +1480,121 @@ fn init_logging() -> Result<(), io::Error> {
     let log_config = log_config_builder.build();
 
     WriteLogger::init(LevelFilter::Debug, log_config, file)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        .map_err(io::Error::other)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{framebuffer, pixels, targets, with_hostname};
+
+    fn ui(style: settings::HostnameStyle) -> settings::Ui {
+        settings::Ui {
+            hostname_style: style,
+            ..Default::default()
+        }
+    }
+
+    fn found(name: &str) -> io::Result<std::ffi::OsString> {
+        Ok(name.into())
+    }
+
+    #[test]
+    fn session_list_is_never_empty() {
+        assert!(Targets::new(Vec::new()).is_none());
+    }
+
+    #[test]
+    fn session_selection_wraps_both_ways() {
+        for len in [1, 2, 5] {
+            let targets = targets(len);
+            let mut index = 0;
+            let mut seen = Vec::new();
+            for _ in 0..len {
+                index = targets.next(index);
+                seen.push(index);
+            }
+            let mut expected: Vec<usize> = (1..len).collect();
+            expected.push(0);
+            assert_eq!(seen, expected, "next through {} sessions", len);
+
+            seen.clear();
+            for _ in 0..len {
+                index = targets.prev(index);
+                seen.push(index);
+            }
+            let expected: Vec<usize> = (0..len).rev().collect();
+            assert_eq!(seen, expected, "prev through {} sessions", len);
+        }
+    }
+
+    #[test]
+    fn session_selection_copes_with_a_stale_index() {
+        // An index from a longer list, before a re-scan.
+        let targets = targets(2);
+        assert_eq!(targets.next(7), 0);
+        assert_eq!(targets.prev(7), 0);
+        assert_eq!(targets.get(7).name, "session 1");
+    }
+
+    #[test]
+    fn configured_hostname_skips_the_lookup() {
+        let ui = settings::Ui {
+            hostname: Some(" kiosk ".to_string()),
+            ..Default::default()
+        };
+        let name = display_hostname(&ui, || panic!("looked up"));
+        assert_eq!(name, "kiosk");
+    }
+
+    #[test]
+    fn blank_configured_hostname_is_looked_up() {
+        let ui = settings::Ui {
+            hostname: Some("  ".to_string()),
+            ..ui(settings::HostnameStyle::Fqdn)
+        };
+        assert_eq!(display_hostname(&ui, || found("box")), "box");
+    }
+
+    #[test]
+    fn hostname_style_shapes_the_name() {
+        let short = ui(settings::HostnameStyle::Short);
+        let fqdn = ui(settings::HostnameStyle::Fqdn);
+        let lookup = || found("box.example.org");
+        assert_eq!(display_hostname(&short, lookup), "box");
+        assert_eq!(display_hostname(&fqdn, lookup), "box.example.org");
+    }
+
+    #[test]
+    fn failed_or_empty_lookup_falls_back() {
+        let short = ui(settings::HostnameStyle::Short);
+        let failed = || Err(io::Error::from(io::ErrorKind::Other));
+        assert_eq!(display_hostname(&short, failed), FALLBACK_HOSTNAME);
+        assert_eq!(display_hostname(&short, || found("")), FALLBACK_HOSTNAME);
+        assert_eq!(
+            display_hostname(&short, || found(".example.org")),
+            FALLBACK_HOSTNAME
+        );
+    }
+
+    #[test]
+    fn greeter_draws_when_the_hostname_lookup_fails() {
+        let ui = settings::Ui::default();
+        let failed = || Err(io::Error::from(io::ErrorKind::PermissionDenied));
+        let mut fb = framebuffer(1024, 768);
+        let mut lm =
+            with_hostname(&mut fb, "", display_hostname(&ui, failed));
+        assert_eq!(lm.hostname, FALLBACK_HOSTNAME);
+        lm.clear();
+        lm.draw_bg().unwrap();
+
+        // The greeting is still drawn, with the fallback name.
+        let layout = lm.form_layout();
+        let (_, heading_y, _, _) = layout.heading;
+        let bg = lm.colors.background.as_argb8888();
+        let screen = pixels(&lm);
+        let greeting = &screen[(heading_y * 1024) as usize..][..1024 * 40];
+        assert!(greeting.iter().any(|&px| px != bg));
+    }
+}
@@ -0,0 +1,39 @@
+use std::{
+    fmt,
+    time::{Duration, Instant}
+};
+
+/// Times consecutive phases of something. Displays as
+/// `a=12ms b=3ms total=15ms`, for logging.
+pub struct Stopwatch {
+    started: Instant,
+    last: Instant,
+    laps: Vec<(&'static str, Duration)>
+}
+
+impl Stopwatch {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            started: now,
+            last: now,
+            laps: Vec::new()
+        }
+    }
+
+    /// Ends the phase `name`, which started at the previous lap.
+    pub fn lap(&mut self, name: &'static str) {
+        let now = Instant::now();
+        self.laps.push((name, now - self.last));
+        self.last = now;
+    }
+}
+
+impl fmt::Display for Stopwatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, duration) in &self.laps {
+            write!(f, "{name}={}ms ", duration.as_millis())?;
+        }
+        write!(f, "total={}ms", (self.last - self.started).as_millis())
+    }
+}
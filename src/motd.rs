@@ -0,0 +1,47 @@
+use std::{fs, path::Path, time::SystemTime};
+
+use log::info;
+
+/// The first `max_lines` lines of `path`, with trailing blank lines
+/// dropped. Anything unreadable is just no message.
+pub fn read(path: &Path, max_lines: usize) -> Vec<String> {
+    let text = fs::read_to_string(path).unwrap_or_default();
+    let mut lines: Vec<String> = text
+        .lines()
+        .take(max_lines)
+        .map(|line| line.trim_end().to_string())
+        .collect();
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    lines
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl crate::LoginManager<'_> {
+    /// Re-reads the motd file if it changed since last time. Returns true
+    /// if the text changed and the screen needs drawing again.
+    pub(crate) fn update_motd(&mut self) -> bool {
+        let path = match &self.motd_file {
+            Some(path) => path,
+            None => return false
+        };
+        // A missing file stays at None, and so stays empty.
+        let mtime = modified(path);
+        if mtime == self.motd_mtime {
+            return false;
+        }
+        self.motd_mtime = mtime;
+
+        let lines = read(path, self.motd_max_lines);
+        if lines == self.motd {
+            return false;
+        }
+        info!("motd changed ({} lines)", lines.len());
+        self.motd = lines;
+        true
+    }
+}
@@ -0,0 +1,68 @@
+use std::{fs, time::Instant};
+
+const ROUTE_PATH: &str = "/proc/net/route";
+const NET_CLASS_DIR: &str = "/sys/class/net";
+
+// from linux/route.h
+const RTF_UP: u32 = 0x0001;
+
+/// How often connectivity is re-checked.
+const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Whether the machine looks online: an IPv4 default route, or failing
+/// that any non-loopback interface that is up. Only reads procfs/sysfs.
+pub fn is_online() -> bool {
+    has_default_route() || has_interface_up()
+}
+
+fn has_default_route() -> bool {
+    let routes = match fs::read_to_string(ROUTE_PATH) {
+        Ok(routes) => routes,
+        Err(_) => return false
+    };
+    routes.lines().skip(1).any(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 4 {
+            return false;
+        }
+        let flags = u32::from_str_radix(fields[3], 16).unwrap_or(0);
+        fields[1] == "00000000" && flags & RTF_UP != 0
+    })
+}
+
+fn has_interface_up() -> bool {
+    let entries = match fs::read_dir(NET_CLASS_DIR) {
+        Ok(entries) => entries,
+        Err(_) => return false
+    };
+    entries.filter_map(Result::ok).any(|e| {
+        e.file_name() != "lo"
+            && fs::read_to_string(e.path().join("operstate"))
+                .map(|state| state.trim() == "up")
+                .unwrap_or(false)
+    })
+}
+
+impl crate::LoginManager<'_> {
+    /// Re-checks connectivity if it is due and redraws the badge if it
+    /// changed.
+    pub(crate) fn update_network(&mut self) -> Result<(), crate::Error> {
+        if !self.show_network && !self.require_network {
+            return Ok(());
+        }
+        if let Some(checked) = self.network_checked {
+            if checked.elapsed() < CHECK_INTERVAL {
+                return Ok(());
+            }
+        }
+        self.network_checked = Some(Instant::now());
+
+        let online = is_online();
+        if self.online == Some(online) {
+            return Ok(());
+        }
+        log::info!("Network is {}", if online { "up" } else { "down" });
+        self.online = Some(online);
+        self.draw_network()
+    }
+}
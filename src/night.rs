@@ -0,0 +1,192 @@
+use chrono::{Local, Timelike};
+use log::{info, warn};
+use thiserror::Error;
+
+use crate::settings::NightMode;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum NightModeError {
+    #[error("invalid time {0:?}; expected HH:MM")]
+    InvalidTime(String)
+}
+
+/// Parses "HH:MM" into minutes after midnight.
+pub fn parse_time(s: &str) -> Result<u32, NightModeError> {
+    let invalid = || NightModeError::InvalidTime(s.to_string());
+    let (h, m) = s.trim().split_once(':').ok_or_else(invalid)?;
+    let h: u32 = h.parse().map_err(|_| invalid())?;
+    let m: u32 = m.parse().map_err(|_| invalid())?;
+    if h > 23 || m > 59 {
+        return Err(invalid());
+    }
+    Ok(h * 60 + m)
+}
+
+/// A daily window in minutes after midnight, `start` inclusive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NightWindow {
+    start: u32,
+    end: u32,
+    pub strength: f32
+}
+
+impl NightWindow {
+    /// `None` if night mode is off.
+    pub fn from_settings(
+        night: &NightMode
+    ) -> Result<Option<Self>, NightModeError> {
+        if !night.enabled {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            start: parse_time(&night.start)?,
+            end: parse_time(&night.end)?,
+            strength: night.strength.clamp(0.0, 1.0)
+        }))
+    }
+
+    pub fn contains(&self, minute: u32) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            // Crosses midnight.
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+pub fn current_minute() -> u32 {
+    let now = Local::now();
+    now.hour() * 60 + now.minute()
+}
+
+/// Builds the window from settings, logging and ignoring bad times.
+pub fn window(night: &NightMode) -> Option<NightWindow> {
+    NightWindow::from_settings(night).unwrap_or_else(|e| {
+        warn!("Night mode disabled: {e}");
+        None
+    })
+}
+
+impl crate::LoginManager<'_> {
+    /// Switches between the normal and warm palettes as the window opens
+    /// and closes. Returns true if the colors changed and everything needs
+    /// drawing again.
+    pub(crate) fn update_night_mode(&mut self) -> bool {
+        let window = match self.night_window {
+            Some(window) => window,
+            None => return false
+        };
+        let active = window.contains(current_minute());
+        if active == self.night_active {
+            return false;
+        }
+        info!("Night mode {}", if active { "on" } else { "off" });
+        self.night_active = active;
+        self.colors = if active {
+            self.base_colors.warmed(window.strength)
+        } else {
+            self.base_colors
+        };
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{color::Color, settings::ResolvedColors};
+
+    fn palette(c: Color) -> ResolvedColors {
+        ResolvedColors {
+            foreground: c,
+            background: c,
+            neutral: c,
+            selected: c,
+            error: c,
+            form_idle: c,
+            form_busy: c,
+            form_error: c
+        }
+    }
+
+    fn night(start: &str, end: &str) -> NightWindow {
+        let night = NightMode {
+            enabled: true,
+            start: start.to_string(),
+            end: end.to_string(),
+            strength: 0.5
+        };
+        NightWindow::from_settings(&night).unwrap().unwrap()
+    }
+
+    #[test]
+    fn warming_scales_each_channel_by_the_light() {
+        let white = palette(Color::from_hex("#ffffffff").unwrap());
+        assert_eq!(white.warmed(0.0), white);
+        assert_eq!(white.warmed(1.0).foreground.as_hex(), "#ffffc489");
+        assert_eq!(white.warmed(0.5).background.as_hex(), "#ffffe2c4");
+        // Past 1 is as warm as it gets.
+        assert_eq!(white.warmed(3.0), white.warmed(1.0));
+
+        let grey = palette(Color::from_hex("#80808080").unwrap());
+        assert_eq!(grey.warmed(1.0).error.as_hex(), "#80806245");
+        let black = palette(Color::from_hex("#ff000000").unwrap());
+        assert_eq!(black.warmed(1.0), black);
+    }
+
+    #[test]
+    fn times_parse_as_minutes_after_midnight() {
+        assert_eq!(parse_time("00:00").unwrap(), 0);
+        assert_eq!(parse_time(" 06:30 ").unwrap(), 390);
+        assert_eq!(parse_time("23:59").unwrap(), 1439);
+        for bad in ["24:00", "12:60", "1230", "12:", ":30", "ab:cd", ""] {
+            assert!(parse_time(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn window_within_a_day() {
+        let window = night("09:00", "17:00");
+        assert!(!window.contains(8 * 60 + 59));
+        assert!(window.contains(9 * 60));
+        assert!(window.contains(16 * 60 + 59));
+        assert!(!window.contains(17 * 60));
+    }
+
+    #[test]
+    fn window_crossing_midnight() {
+        let window = night("20:00", "06:00");
+        assert!(!window.contains(19 * 60 + 59));
+        assert!(window.contains(20 * 60));
+        assert!(window.contains(23 * 60 + 59));
+        assert!(window.contains(0));
+        assert!(window.contains(5 * 60 + 59));
+        assert!(!window.contains(6 * 60));
+        assert!(!window.contains(12 * 60));
+    }
+
+    #[test]
+    fn empty_window_is_never_open() {
+        let window = night("20:00", "20:00");
+        assert!((0..24 * 60).all(|minute| !window.contains(minute)));
+    }
+
+    #[test]
+    fn disabled_or_bad_settings_mean_no_window() {
+        assert_eq!(window(&NightMode::default()), None);
+        let bad = NightMode {
+            enabled: true,
+            start: "25:00".to_string(),
+            ..NightMode::default()
+        };
+        assert_eq!(window(&bad), None);
+        let strong = NightMode {
+            enabled: true,
+            strength: 7.0,
+            ..NightMode::default()
+        };
+        assert_eq!(window(&strong).unwrap().strength, 1.0);
+    }
+}
@@ -0,0 +1,97 @@
+use std::{
+    env,
+    path::{Path, PathBuf}
+};
+
+use nix::unistd::{self, AccessFlags};
+
+pub const SYSTEM_CONFIG: &str = "/etc/mflm/config.toml";
+pub const SYSTEM_LOG_DIR: &str = "/var/log/mflm";
+pub const SYSTEM_STATE_DIR: &str = "/var/cache/mflm";
+
+/// An XDG base directory: `$var` if set to an absolute path, otherwise
+/// `home_fallback` under $HOME.
+fn xdg_dir(var: &str, home_fallback: &str) -> Option<PathBuf> {
+    if let Some(dir) = env::var_os(var) {
+        let dir = PathBuf::from(dir);
+        if dir.is_absolute() {
+            return Some(dir);
+        }
+    }
+    let home = env::var_os("HOME").filter(|home| !home.is_empty())?;
+    Some(Path::new(&home).join(home_fallback))
+}
+
+/// Config files in the order they're merged, later ones on top. When not
+/// running as root the user's own file overrides the system one.
+pub fn config_files() -> Vec<PathBuf> {
+    let mut files = vec![PathBuf::from(SYSTEM_CONFIG)];
+    if !unistd::geteuid().is_root() {
+        if let Some(dir) = xdg_dir("XDG_CONFIG_HOME", ".config") {
+            files.push(dir.join("mflm").join("config.toml"));
+        }
+    }
+    files
+}
+
+/// Whether `dir` could be written to, or created and then written to,
+/// judged by the nearest part of it that exists. Creates nothing: that's
+/// left to whatever writes there.
+fn writable(dir: &Path) -> bool {
+    let existing = match dir.ancestors().find(|d| d.exists()) {
+        Some(existing) => existing,
+        None => return false
+    };
+    existing.is_dir()
+        && unistd::access(existing, AccessFlags::W_OK | AccessFlags::X_OK)
+            .is_ok()
+}
+
+/// `system` if we can write there, otherwise `$XDG_STATE_HOME/mflm`.
+fn state_dir_or(system: &str) -> PathBuf {
+    let system = PathBuf::from(system);
+    if writable(&system) {
+        return system;
+    }
+    match xdg_dir("XDG_STATE_HOME", ".local/state") {
+        Some(dir) => dir.join("mflm"),
+        None => system
+    }
+}
+
+pub fn log_dir() -> PathBuf {
+    state_dir_or(SYSTEM_LOG_DIR)
+}
+
+pub fn state_file() -> PathBuf {
+    state_dir_or(SYSTEM_STATE_DIR).join("state")
+}
+
+/// Where the embedded fallback font is written for fontconfig to load.
+pub fn font_dir() -> PathBuf {
+    state_dir_or(SYSTEM_STATE_DIR).join("fonts")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, process};
+
+    use super::*;
+
+    #[test]
+    fn writable_checks_the_nearest_existing_dir() {
+        let tmp = env::temp_dir()
+            .join(format!("mflm-paths-{}", process::id()));
+        fs::create_dir_all(&tmp).unwrap();
+        assert!(writable(&tmp));
+
+        let missing = tmp.join("a").join("b");
+        assert!(writable(&missing));
+        assert!(!tmp.join("a").exists(), "nothing is created");
+
+        let file = tmp.join("file");
+        fs::write(&file, "").unwrap();
+        assert!(!writable(&file.join("dir")));
+        fs::remove_dir_all(&tmp).unwrap();
+    }
+}
@@ -0,0 +1,260 @@
+//! `ui.qr`: a QR code in a corner of the screen, such as "scan for help"
+//! on a reception kiosk. Encoded once at startup and drawn with the
+//! background.
+
+use log::warn;
+use qrcode::{Color as Module, QrCode};
+
+use crate::{
+    buffer::Buffer,
+    color::Color,
+    settings::{self, Anchor},
+    WithContext
+};
+
+/// Light modules around the code, as scanners expect.
+const QUIET_ZONE: u32 = 4;
+/// Space between the code and the edges of the screen.
+const MARGIN_PX: u32 = 16;
+
+pub(crate) struct QrImage {
+    code: QrCode,
+    size_px: u32,
+    position: Anchor
+}
+
+impl QrImage {
+    /// Encodes `ui.qr`. `None`, after a warning, if the data doesn't fit
+    /// in a QR code.
+    pub fn new(qr: &settings::Qr) -> Option<Self> {
+        match QrCode::new(&qr.data) {
+            Ok(code) => Some(Self {
+                code,
+                size_px: qr.size_px,
+                position: qr.position
+            }),
+            Err(e) => {
+                warn!("Unable to encode ui.qr: {e}");
+                None
+            }
+        }
+    }
+
+    /// The largest whole number of pixels per module that fits the code
+    /// and its quiet zone in `size_px`; at least 1.
+    fn module_px(&self) -> u32 {
+        let modules = self.code.width() as u32 + QUIET_ZONE * 2;
+        (self.size_px / modules).max(1)
+    }
+}
+
+/// Width and height of `code` drawn with `module_px`, quiet zone included.
+pub fn qr_size(code: &QrCode, module_px: u32) -> u32 {
+    (code.width() as u32 + QUIET_ZONE * 2) * module_px
+}
+
+/// Draws `code` at the buffer's top left, quiet zone included, each
+/// module `module_px` square: dark modules in `dark` and the rest in
+/// `light`. Clipped to the buffer.
+pub fn draw_qr(
+    buf: &mut Buffer<'_>,
+    code: &QrCode,
+    module_px: u32,
+    dark: &Color,
+    light: &Color
+) {
+    let size = qr_size(code, module_px);
+    buf.fill_rect((0, 0, size, size), light);
+
+    let width = code.width();
+    for (i, module) in code.to_colors().into_iter().enumerate() {
+        if module == Module::Light {
+            continue;
+        }
+        let x = (i % width) as u32 + QUIET_ZONE;
+        let y = (i / width) as u32 + QUIET_ZONE;
+        buf.fill_rect(
+            (x * module_px, y * module_px, module_px, module_px),
+            dark
+        );
+    }
+}
+
+impl crate::LoginManager<'_> {
+    /// Draws `ui.qr` in its corner, if there is one.
+    pub(crate) fn draw_qr_code(&mut self) -> Result<(), crate::Error> {
+        let qr = match &self.qr {
+            Some(qr) => qr,
+            None => return Ok(())
+        };
+        let module_px = qr.module_px();
+        let size = qr_size(&qr.code, module_px);
+        let (screen_w, screen_h) = self.screen_size;
+        let right = screen_w.saturating_sub(size + MARGIN_PX);
+        let bottom = screen_h.saturating_sub(size + MARGIN_PX);
+        let (x, y) = match qr.position {
+            Anchor::Center => (
+                screen_w.saturating_sub(size) / 2,
+                screen_h.saturating_sub(size) / 2
+            ),
+            Anchor::TopLeft => (MARGIN_PX, MARGIN_PX),
+            Anchor::TopRight => (right, MARGIN_PX),
+            Anchor::BottomLeft => (MARGIN_PX, bottom),
+            Anchor::BottomRight => (right, bottom)
+        };
+
+        let mut buf = Buffer::new(self.buf, self.screen_size);
+        let mut buf = buf.offset((x, y)).with_context("qr code")?;
+        draw_qr(
+            &mut buf,
+            &qr.code,
+            module_px,
+            &self.colors.foreground,
+            &self.colors.background
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DARK: [u8; 3] = [0x10, 0x20, 0x30];
+
+    fn code() -> QrCode {
+        QrCode::new(b"https://example.com/help").unwrap()
+    }
+
+    /// Draws `code` and reads it back as rows of modules, true for dark,
+    /// quiet zone included. Every pixel of a module must agree.
+    fn drawn(code: &QrCode, module_px: u32) -> Vec<Vec<bool>> {
+        let size = qr_size(code, module_px);
+        let mut frame = vec![0; (size * size * 4) as usize];
+        let dark = Color::from_rgba_u8(DARK[0], DARK[1], DARK[2], 255);
+        let light = Color::from_rgba_u8(255, 255, 255, 255);
+        draw_qr(
+            &mut Buffer::new(&mut frame, (size, size)),
+            code,
+            module_px,
+            &dark,
+            &light
+        );
+
+        let pixel = |x: u32, y: u32| {
+            let i = ((y * size + x) * 4) as usize;
+            let px = &frame[i..i + 4];
+            if px == dark.as_argb8888().to_ne_bytes() {
+                true
+            } else {
+                let light = light.as_argb8888().to_ne_bytes();
+                assert_eq!(px, light, "pixel {},{}", x, y);
+                false
+            }
+        };
+        let modules = size / module_px;
+        (0..modules)
+            .map(|my| {
+                (0..modules)
+                    .map(|mx| {
+                        let dark = pixel(mx * module_px, my * module_px);
+                        for y in my * module_px..(my + 1) * module_px {
+                            for x in mx * module_px..(mx + 1) * module_px {
+                                assert_eq!(pixel(x, y), dark);
+                            }
+                        }
+                        dark
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Whether the 7x7 modules at `x`, `y` are a finder pattern: a dark
+    /// ring, a light ring and a dark 3x3 middle.
+    fn is_finder(modules: &[Vec<bool>], x: usize, y: usize) -> bool {
+        (0..7).all(|dy| {
+            (0..7).all(|dx| {
+                let ring = dx.min(dy).min(6 - dx).min(6 - dy);
+                modules[y + dy][x + dx] == (ring != 1)
+            })
+        })
+    }
+
+    #[test]
+    fn size_includes_the_quiet_zone() {
+        let code = code();
+        let width = code.width() as u32;
+        assert_eq!(qr_size(&code, 1), width + 8);
+        assert_eq!(qr_size(&code, 3), (width + 8) * 3);
+        assert_eq!(drawn(&code, 3).len(), code.width() + 8);
+    }
+
+    #[test]
+    fn modules_are_drawn_where_the_code_has_them() {
+        let code = code();
+        let width = code.width();
+        let expected = code.to_colors();
+        for module_px in [1, 2, 5] {
+            let modules = drawn(&code, module_px);
+            for (y, row) in modules.iter().enumerate() {
+                for (x, &dark) in row.iter().enumerate() {
+                    let inside = (4..width + 4).contains(&x)
+                        && (4..width + 4).contains(&y);
+                    let module = inside
+                        && expected[(y - 4) * width + x - 4] == Module::Dark;
+                    assert_eq!(dark, module, "module {},{}", x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn finders_sit_in_three_corners() {
+        let code = code();
+        let modules = drawn(&code, 2);
+        let far = code.width() + 4 - 7;
+        assert!(is_finder(&modules, 4, 4));
+        assert!(is_finder(&modules, far, 4));
+        assert!(is_finder(&modules, 4, far));
+        assert!(!is_finder(&modules, far, far));
+        // With light separators between them and the rest of the code.
+        for i in 0..8 {
+            assert!(!modules[11][4 + i] && !modules[4 + i][11]);
+            assert!(!modules[11][far - 1 + i] && !modules[4 + i][far - 1]);
+            assert!(!modules[far - 1][4 + i] && !modules[far - 1 + i][11]);
+        }
+        // And a timing pattern from one to the next.
+        for (i, row) in modules.iter().enumerate().take(far - 1).skip(12) {
+            assert_eq!(modules[10][i], i % 2 == 0, "timing {}", i);
+            assert_eq!(row[10], i % 2 == 0, "timing {}", i);
+        }
+    }
+
+    #[test]
+    fn modules_are_as_big_as_fit() {
+        let image = |size_px| QrImage {
+            code: code(),
+            size_px,
+            position: Anchor::TopRight
+        };
+        let modules = code().width() as u32 + 8;
+        assert_eq!(image(modules * 4).module_px(), 4);
+        assert_eq!(image(modules * 4 - 1).module_px(), 3);
+        assert_eq!(image(10).module_px(), 1);
+    }
+
+    #[test]
+    fn drawing_is_clipped_to_the_buffer() {
+        let code = code();
+        let mut frame = vec![0; 20 * 10 * 4];
+        let dark = Color::from_rgba_u8(0, 0, 0, 255);
+        draw_qr(
+            &mut Buffer::new(&mut frame, (20, 10)),
+            &code,
+            4,
+            &dark,
+            &Color::from_rgba_u8(255, 255, 255, 255)
+        );
+    }
+}
@@ -0,0 +1,395 @@
+//! `--replay`: drives the greeter from a script instead of the keyboard,
+//! for automated tests. One step per line:
+//!
+//! ```text
+//! # comments and blank lines are ignored
+//! key tab
+//! type alice
+//! key enter
+//! sleep 500ms
+//! expect-mode password
+//! expect-message Login failed
+//! ```
+//!
+//! `key` takes the same descriptors as `[keys]`, `type` sends each
+//! character of the rest of the line, and `expect-*` lines check the
+//! greeter's state at that point. The first failed expectation stops the
+//! script.
+
+use std::{
+    collections::VecDeque,
+    fs, io,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant}
+};
+
+use log::info;
+use thiserror::Error;
+
+use crate::{
+    input::Event,
+    keys::{self, Key},
+    Mode
+};
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum ReplayError {
+    #[error("unable to read replay script {path}: {source}")]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: io::Error
+    },
+
+    #[error("replay script line {line}: {message}")]
+    Parse { line: usize, message: String },
+
+    #[error("replay script line {line}: expected {expected}, found {found}")]
+    Mismatch {
+        line: usize,
+        expected: String,
+        found: String
+    },
+
+    #[error("greeter exited before replay script line {line}")]
+    Unfinished { line: usize }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    Key(Key),
+    Sleep(Duration),
+    ExpectMode(Mode),
+    /// An empty string expects no message.
+    ExpectMessage(String)
+}
+
+/// The greeter state `expect-*` lines are checked against, as of the last
+/// event handed out.
+#[derive(Debug, Clone, Default)]
+struct Observed {
+    mode: Option<Mode>,
+    message: Option<String>
+}
+
+pub struct Replay {
+    steps: VecDeque<(usize, Step)>,
+    sleep_until: Option<Instant>,
+    observed: Observed,
+    failure: Option<ReplayError>
+}
+
+/// The name `expect-mode` and `--status-fd` use for `mode`.
+pub(crate) fn mode_name(mode: Mode) -> &'static str {
+    match mode {
+        Mode::SelectingSession => "session",
+        Mode::EditingUsername => "username",
+        Mode::EditingPassword => "password"
+    }
+}
+
+pub(crate) fn parse_mode(s: &str) -> Option<Mode> {
+    [
+        Mode::SelectingSession,
+        Mode::EditingUsername,
+        Mode::EditingPassword
+    ]
+    .iter()
+    .copied()
+    .find(|&mode| mode_name(mode) == s)
+}
+
+/// Parses "500ms" or "2s".
+fn parse_duration(s: &str) -> Option<Duration> {
+    if let Some(ms) = s.strip_suffix("ms") {
+        return ms.trim().parse().ok().map(Duration::from_millis);
+    }
+    let secs = s.strip_suffix('s')?;
+    secs.trim().parse().ok().map(Duration::from_secs)
+}
+
+/// Parses a whole script into steps, each with its line number.
+fn parse(text: &str) -> Result<Vec<(usize, Step)>, ReplayError> {
+    let mut steps = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line_no = i + 1;
+        let error = |message: String| ReplayError::Parse {
+            line: line_no,
+            message
+        };
+
+        let line = line.trim_start();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (command, arg) = line.split_once(' ').unwrap_or((line, ""));
+        match command {
+            "key" => {
+                let key = keys::parse_descriptor(arg)
+                    .map_err(|e| error(e.to_string()))?;
+                steps.push((line_no, Step::Key(key)));
+            }
+            // Unlike `key`, keeps case and spaces.
+            "type" => {
+                for c in arg.chars() {
+                    steps.push((line_no, Step::Key(Key::Char(c))));
+                }
+            }
+            "sleep" => {
+                let duration = parse_duration(arg.trim()).ok_or_else(|| {
+                    error(format!("invalid duration {:?}", arg.trim()))
+                })?;
+                steps.push((line_no, Step::Sleep(duration)));
+            }
+            "expect-mode" => {
+                let mode = parse_mode(arg.trim()).ok_or_else(|| {
+                    error(format!(
+                        "unknown mode {:?}; expected session, username or password",
+                        arg.trim()
+                    ))
+                })?;
+                steps.push((line_no, Step::ExpectMode(mode)));
+            }
+            "expect-message" => {
+                steps.push((
+                    line_no,
+                    Step::ExpectMessage(arg.trim().to_string())
+                ));
+            }
+            _ => return Err(error(format!("unknown command {command:?}")))
+        }
+    }
+    Ok(steps)
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> Result<Self, ReplayError> {
+        let text =
+            fs::read_to_string(path).map_err(|source| ReplayError::Read {
+                path: path.to_path_buf(),
+                source
+            })?;
+        let replay = Self::from_script(&text)?;
+        info!(
+            "Replaying {} steps from {}",
+            replay.steps.len(),
+            path.display()
+        );
+        Ok(replay)
+    }
+
+    /// A replay of the script `text`.
+    pub fn from_script(text: &str) -> Result<Self, ReplayError> {
+        Ok(Self {
+            steps: parse(text)?.into(),
+            sleep_until: None,
+            observed: Observed::default(),
+            failure: None
+        })
+    }
+
+    /// Records the state that following `expect-*` lines check.
+    pub(crate) fn observe(&mut self, mode: Mode, message: Option<&str>) {
+        self.observed = Observed {
+            mode: Some(mode),
+            message: message.map(str::to_string)
+        };
+    }
+
+    /// Like `Input::wait`: sleeps through a `sleep` step for up to
+    /// `timeout`, and returns true once the next step is ready.
+    pub fn wait(&mut self, timeout: Duration) -> bool {
+        let deadline = Instant::now().checked_add(timeout);
+        while let Some((_, Step::Sleep(duration))) = self.steps.front() {
+            let until = *self
+                .sleep_until
+                .get_or_insert_with(|| Instant::now() + *duration);
+            let now = Instant::now();
+            if until > now {
+                let wake = match deadline {
+                    Some(deadline) if deadline <= now => return false,
+                    Some(deadline) => until.min(deadline),
+                    None => until
+                };
+                thread::sleep(wake - now);
+                continue;
+            }
+            self.steps.pop_front();
+            self.sleep_until = None;
+        }
+        true
+    }
+
+    /// The next key, after sleeping and checking expectations up to it.
+    /// `None` at the end of the script or after a failed expectation.
+    pub fn next_event(&mut self) -> Option<Event> {
+        while self.failure.is_none() {
+            self.wait(Duration::MAX);
+            let (line, step) = self.steps.pop_front()?;
+            let mismatch = |expected: &str, found: &str| {
+                ReplayError::Mismatch {
+                    line,
+                    expected: expected.to_string(),
+                    found: found.to_string()
+                }
+            };
+            match step {
+                Step::Key(key) => return Some(Event::Key(key)),
+                Step::Sleep(_) => (),
+                Step::ExpectMode(mode) => {
+                    let found = self.observed.mode.map_or("nothing", mode_name);
+                    if self.observed.mode != Some(mode) {
+                        let expected = format!("mode {}", mode_name(mode));
+                        let found = format!("mode {found}");
+                        self.failure = Some(mismatch(&expected, &found));
+                    }
+                }
+                Step::ExpectMessage(message) => {
+                    let found = self.observed.message.clone().unwrap_or_default();
+                    if found != message {
+                        let expected = format!("message {message:?}");
+                        let found = format!("message {found:?}");
+                        self.failure = Some(mismatch(&expected, &found));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// How the script went: the first failed expectation, or an error if
+    /// the greeter exited before the script ended.
+    pub fn finish(self) -> Result<(), ReplayError> {
+        if let Some(failure) = self.failure {
+            return Err(failure);
+        }
+        match self.steps.front() {
+            Some(&(line, _)) => Err(ReplayError::Unfinished { line }),
+            None => Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replay(text: &str) -> Replay {
+        Replay::from_script(text).unwrap()
+    }
+
+    fn parse_error(text: &str) -> (usize, String) {
+        match parse(text) {
+            Err(ReplayError::Parse { line, message }) => (line, message),
+            other => panic!("{:?} parsed as {:?}", text, other)
+        }
+    }
+
+    #[test]
+    fn steps_keep_their_line_numbers() {
+        let script = "# log in\n\
+                      \n\
+                      key tab\n\
+                      type aB c\n\
+                      \x20 sleep 500ms\n\
+                      sleep 2s\n\
+                      expect-mode password\n\
+                      expect-message  Login failed \n\
+                      expect-message\n";
+        let expected = vec![
+            (3, Step::Key(Key::Tab)),
+            (4, Step::Key(Key::Char('a'))),
+            (4, Step::Key(Key::Char('B'))),
+            (4, Step::Key(Key::Char(' '))),
+            (4, Step::Key(Key::Char('c'))),
+            (5, Step::Sleep(Duration::from_millis(500))),
+            (6, Step::Sleep(Duration::from_secs(2))),
+            (7, Step::ExpectMode(Mode::EditingPassword)),
+            (8, Step::ExpectMessage("Login failed".to_string())),
+            (9, Step::ExpectMessage(String::new()))
+        ];
+        assert_eq!(parse(script).unwrap(), expected);
+    }
+
+    #[test]
+    fn comments_and_blank_lines_only() {
+        assert_eq!(parse("").unwrap(), vec![]);
+        assert_eq!(parse("\n  \n# key nonsense\n  # sleep\n").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("0ms"), Some(Duration::ZERO));
+        assert_eq!(parse_duration("250ms"), Some(Duration::from_millis(250)));
+        assert_eq!(parse_duration("3s"), Some(Duration::from_secs(3)));
+        assert_eq!(parse_duration("10 ms"), Some(Duration::from_millis(10)));
+        for bad in ["", "5", "ms", "s", "-1s", "1.5s", "2m", "1h"] {
+            assert_eq!(parse_duration(bad), None, "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        assert_eq!(parse_error("key tab\nsleep soon").0, 2);
+        assert!(parse_error("sleep").1.contains("invalid duration"));
+        assert_eq!(parse_error("\n\nkey nosuchkey").0, 3);
+        assert_eq!(parse_error("expect-mode typing").0, 1);
+        let (line, message) = parse_error("key tab\n# ok\npress enter");
+        assert_eq!(line, 3);
+        assert_eq!(message, "unknown command \"press\"");
+    }
+
+    #[test]
+    fn every_mode_has_a_name() {
+        for mode in [
+            Mode::SelectingSession,
+            Mode::EditingUsername,
+            Mode::EditingPassword
+        ] {
+            assert_eq!(parse_mode(mode_name(mode)), Some(mode));
+        }
+        assert_eq!(parse_mode("Password"), None);
+    }
+
+    #[test]
+    fn expectations_are_checked_in_order() {
+        let mut replay =
+            replay("expect-mode username\ntype x\nexpect-message Oops");
+        replay.observe(Mode::EditingUsername, None);
+        assert_eq!(replay.next_event(), Some(Event::Key(Key::Char('x'))));
+        replay.observe(Mode::EditingUsername, Some("Oops"));
+        assert!(replay.next_event().is_none());
+        assert!(replay.finish().is_ok());
+    }
+
+    #[test]
+    fn first_failed_expectation_stops_the_script() {
+        let mut replay = replay("expect-mode password\nkey enter");
+        replay.observe(Mode::EditingUsername, None);
+        assert!(replay.next_event().is_none());
+        match replay.finish() {
+            Err(ReplayError::Mismatch {
+                line,
+                expected,
+                found
+            }) => {
+                assert_eq!(line, 1);
+                assert_eq!(expected, "mode password");
+                assert_eq!(found, "mode username");
+            }
+            other => panic!("{:?}", other)
+        }
+    }
+
+    #[test]
+    fn exiting_early_is_unfinished() {
+        let mut replay = replay("key tab\n\nkey enter");
+        assert!(replay.next_event().is_some());
+        assert!(matches!(
+            replay.finish(),
+            Err(ReplayError::Unfinished { line: 3 })
+        ));
+    }
+}
@@ -0,0 +1,173 @@
+use std::{fs, path::PathBuf};
+
+use log::{info, warn};
+
+pub use crate::entry::{SessionKind, SkipReason};
+use crate::{settings, Target, Targets};
+
+const SESSION_DIRS: [(&str, SessionKind); 2] = [
+    ("/usr/share/wayland-sessions", SessionKind::Wayland),
+    ("/usr/share/xsessions", SessionKind::X11)
+];
+
+/// Entries left out by discovery, and why.
+pub type Skipped = Vec<(PathBuf, SkipReason)>;
+
+/// "foo.desktop (no Exec), bar.desktop (Hidden)"
+pub fn describe_skipped(skipped: &Skipped) -> String {
+    skipped
+        .iter()
+        .map(|(path, reason)| {
+            let name = path.file_name().unwrap_or(path.as_os_str());
+            format!("{} ({reason})", name.to_string_lossy())
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// How desktop entries are turned into targets; kept around so sessions can
+/// be re-scanned later with the same settings.
+pub struct Discovery {
+    command_prefix: Vec<String>,
+    wrap_in_shell: bool,
+    tag_type: settings::TagType
+}
+
+impl Discovery {
+    pub fn from_settings(sessions: &settings::Sessions) -> Self {
+        let command_prefix = match shell_words::split(&sessions.command_prefix)
+        {
+            Ok(prefix) => prefix,
+            Err(e) => {
+                warn!(
+                    "Ignoring sessions.command_prefix {:?}: {e}",
+                    sessions.command_prefix
+                );
+                Vec::new()
+            }
+        };
+        Self {
+            command_prefix,
+            wrap_in_shell: sessions.wrap_in_shell,
+            tag_type: sessions.tag_type
+        }
+    }
+
+    /// Loads every session desktop entry, returning the targets and the
+    /// entries that were skipped.
+    pub fn discover(&self) -> (Vec<Target>, Skipped) {
+        let mut targets = Vec::new();
+        let mut skipped = Vec::new();
+        for (dir, kind) in SESSION_DIRS {
+            match fs::read_dir(dir) {
+                Ok(rd) => {
+                    for entry in rd.flatten() {
+                        let path = entry.path();
+                        match Target::load(
+                            &path,
+                            kind,
+                            &self.command_prefix,
+                            self.wrap_in_shell
+                        ) {
+                            Ok(target) => targets.push(target),
+                            Err(reason) => skipped.push((path, reason))
+                        }
+                    }
+                }
+                Err(e) => {
+                    warn!("Unable to read sessions dir {dir}: {e}");
+                }
+            }
+        }
+        self.tag(&mut targets);
+        if !skipped.is_empty() {
+            info!(
+                "Skipped {} entries: {}",
+                skipped.len(),
+                describe_skipped(&skipped)
+            );
+        }
+        (targets, skipped)
+    }
+}
+
+impl Discovery {
+    /// Decides which targets show their kind, which with
+    /// `sessions.tag_type = "auto"` is those sharing a name.
+    fn tag(&self, targets: &mut [Target]) {
+        let shared = |name: &str| {
+            targets.iter().filter(|t| t.name == name).count() > 1
+        };
+        let tagged: Vec<bool> = targets
+            .iter()
+            .map(|t| match self.tag_type {
+                settings::TagType::Auto => shared(&t.name),
+                settings::TagType::Always => true,
+                settings::TagType::Never => false
+            })
+            .collect();
+        for (target, tagged) in targets.iter_mut().zip(tagged) {
+            target.tagged = tagged;
+        }
+    }
+}
+
+impl crate::LoginManager<'_> {
+    /// Re-runs session discovery, keeping the selected session if it still
+    /// exists. Returns whether the list changed.
+    pub(crate) fn rescan_targets(&mut self) -> bool {
+        info!("Re-scanning session targets");
+        let (targets, skipped) = self.discovery.discover();
+        self.skipped_targets = skipped;
+        let targets = match Targets::new(targets) {
+            Some(targets) => targets,
+            None => {
+                warn!("Re-scan found no session targets; keeping the old list");
+                return false;
+            }
+        };
+
+        // An entry can change without its name: a new Exec=, comment or
+        // icon.
+        if targets == self.targets {
+            info!("Session targets unchanged");
+            return false;
+        }
+        let old: Vec<&str> = self.targets.names().collect();
+        let new: Vec<&str> = targets.names().collect();
+        let added: Vec<&str> =
+            new.iter().filter(|n| !old.contains(n)).copied().collect();
+        let removed: Vec<&str> =
+            old.iter().filter(|n| !new.contains(n)).copied().collect();
+        let changed: Vec<&str> = targets
+            .0
+            .iter()
+            .filter(|t| self.targets.0.iter().all(|old| old != *t))
+            .map(|t| t.name.as_str())
+            .filter(|n| !added.contains(n))
+            .collect();
+        info!(
+            "Session targets added: {added:?}, removed: {removed:?}, \
+             changed: {changed:?}"
+        );
+
+        let selected = self.current_target().name.clone();
+        let forced = self
+            .forced_target
+            .as_deref()
+            .and_then(|t| targets.position(t));
+        self.target_index =
+            targets.position(&selected).or(forced).unwrap_or(0);
+        let show_target = !(forced.is_some() && self.hide_target);
+        let show_username = self.form.shows(crate::Mode::EditingUsername);
+        self.form.set_rows(crate::form_rows(
+            &self.row_order,
+            show_target,
+            show_username
+        ));
+        self.targets = targets;
+        self.relayout();
+        self.load_session_icons();
+        true
+    }
+}
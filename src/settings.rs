@@ -1,8 +1,15 @@
-use serde::Deserialize;
+use log::info;
+use serde::{Deserialize, Serialize};
 
-use crate::color::{Color, ParseColorError};
+pub use crate::font::TextValign;
+pub use crate::layout::Anchor;
+use crate::{
+    color::{Color, ParseColorError},
+    keys::Action,
+    layout::Margin
+};
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Fonts {
     /// Pango font description string for the main UI font (used for session/user/pass rows).
     /// Example: "DejaVu Sans Mono" or "Sans".
@@ -21,7 +28,7 @@ pub struct Fonts {
     pub heading_size_px: f32
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Colors {
     /// Hex: "#RRGGBB" or "#AARRGGBB".
     pub foreground: String,
@@ -32,7 +39,13 @@ pub struct Colors {
     /// Used for selections / active fields / in-progress actions.
     pub selected: String,
     /// Used for errors (e.g. auth failure).
-    pub error: String
+    pub error: String,
+    /// Form fill while waiting for input. Defaults to the background.
+    pub form_idle: Option<String>,
+    /// Form fill while logging in. Defaults to `selected`.
+    pub form_busy: Option<String>,
+    /// Form fill after a failed login. Defaults to `error`.
+    pub form_error: Option<String>
 }
 
 impl Default for Colors {
@@ -42,18 +55,43 @@ impl Default for Colors {
             background: "#000000".to_string(),
             neutral: "#cccccc".to_string(),
             selected: "#aaccee".to_string(),
-            error: "#ecbcad".to_string()
+            error: "#ecbcad".to_string(),
+            form_idle: None,
+            form_busy: None,
+            form_error: None
         }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct ResolvedColors {
     pub foreground: Color,
     pub background: Color,
     pub neutral: Color,
     pub selected: Color,
-    pub error: Color
+    pub error: Color,
+    pub form_idle: Color,
+    pub form_busy: Color,
+    pub form_error: Color
+}
+
+impl ResolvedColors {
+    /// The palette as seen under warm (about 3400K) light; `strength` 0
+    /// leaves it alone, 1 is fully warm.
+    pub fn warmed(&self, strength: f32) -> ResolvedColors {
+        let light = Color::from_rgba_u8(0xFF, 0xC4, 0x89, 0xFF);
+        let warm = |c: &Color| c.blend(&c.multiply(&light), strength);
+        ResolvedColors {
+            foreground: warm(&self.foreground),
+            background: warm(&self.background),
+            neutral: warm(&self.neutral),
+            selected: warm(&self.selected),
+            error: warm(&self.error),
+            form_idle: warm(&self.form_idle),
+            form_busy: warm(&self.form_busy),
+            form_error: warm(&self.form_error)
+        }
+    }
 }
 
 impl Default for Fonts {
@@ -75,13 +113,129 @@ fn default_heading_font_size_px() -> f32 {
     72.0
 }
 
-#[derive(Default, Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Login {
     /// Optional session target name to force.
     pub target: Option<String>,
 
     /// Optional username to force.
-    pub username: Option<String>
+    pub username: Option<String>,
+
+    /// When login.username is shown, show it selected: dimmed, and
+    /// replaced rather than added to once typing starts.
+    #[serde(default)]
+    pub select_username: bool,
+
+    /// Strip leading/trailing whitespace from typed usernames.
+    #[serde(default = "default_trim_username")]
+    pub trim_username: bool,
+
+    /// Complete usernames from local accounts with the complete_next /
+    /// complete_prev keys.
+    #[serde(default)]
+    pub complete_usernames: bool,
+
+    /// Clear the typed username after a failed login instead of keeping
+    /// it (dimmed) for the next attempt.
+    #[serde(default)]
+    pub clear_username_on_failure: bool,
+
+    /// After a failed login as a name with capitals, suggest the lowercase
+    /// name if that's a local account.
+    #[serde(default)]
+    pub username_case_hint: bool,
+
+    /// Refuse to submit until the network is up.
+    #[serde(default)]
+    pub require_network: bool,
+
+    /// Submit as soon as the password reaches this many characters, for
+    /// badge readers that don't send Enter. 0 disables it.
+    #[serde(default)]
+    pub auto_submit_length: usize,
+
+    /// Submit once password input has been idle this long. 0 disables it.
+    #[serde(default)]
+    pub auto_submit_idle_ms: u64,
+
+    /// What it takes to log in from the password field.
+    #[serde(default = "default_submit_key")]
+    pub submit_key: SubmitKey,
+
+    /// Longest password that can be typed, up to 64. 0 allows the most.
+    #[serde(default)]
+    pub max_password_len: usize,
+
+    /// Only accept digits in the password, for PINs.
+    #[serde(default)]
+    pub numeric_only: bool,
+
+    /// Show a "Starting ..." screen this long after a successful login,
+    /// while the session takes over the display. 0 just clears the screen.
+    #[serde(default)]
+    pub handoff_delay_ms: u64,
+
+    /// Count greetd hanging up right after StartSession as a failed login.
+    /// By default it's taken to mean the session started.
+    #[serde(default)]
+    pub strict_start_ack: bool,
+
+    /// Submit with nothing in the password field, for PAM stacks that
+    /// need no secret, such as a security key touch.
+    #[serde(default)]
+    pub allow_empty_password: bool,
+
+    /// How long after a failed login the next attempt is held off, so key
+    /// bounce can't send two in a row to PAM's own rate limiting.
+    #[serde(default = "default_retry_delay_ms")]
+    pub retry_delay_ms: u64
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SubmitKey {
+    /// The submit keys, as bound in [keys].
+    Enter,
+    /// Ctrl+Enter, which terminals that tell it apart send as Ctrl+J.
+    CtrlEnter,
+    /// Enter twice within two seconds.
+    DoubleEnter
+}
+
+fn default_submit_key() -> SubmitKey {
+    SubmitKey::Enter
+}
+
+impl Default for Login {
+    fn default() -> Self {
+        Self {
+            target: None,
+            username: None,
+            select_username: false,
+            trim_username: default_trim_username(),
+            complete_usernames: false,
+            clear_username_on_failure: false,
+            username_case_hint: false,
+            require_network: false,
+            auto_submit_length: 0,
+            auto_submit_idle_ms: 0,
+            submit_key: default_submit_key(),
+            max_password_len: 0,
+            numeric_only: false,
+            handoff_delay_ms: 0,
+            strict_start_ack: false,
+            allow_empty_password: false,
+            retry_delay_ms: default_retry_delay_ms()
+        }
+    }
+}
+
+fn default_trim_username() -> bool {
+    true
+}
+
+fn default_retry_delay_ms() -> u64 {
+    300
 }
 
 fn default_gap_below_session_px() -> u32 {
@@ -140,7 +294,47 @@ fn default_session_right_arrow() -> String {
     "❯".to_string()
 }
 
-#[derive(Debug, Clone, Copy, Deserialize)]
+fn default_show_session_comment() -> bool {
+    false
+}
+
+fn default_show_version() -> bool {
+    false
+}
+
+fn default_show_battery() -> bool {
+    false
+}
+
+fn default_battery_low_percent() -> u8 {
+    15
+}
+
+fn default_show_network() -> bool {
+    false
+}
+
+fn default_show_failed_attempts() -> bool {
+    false
+}
+
+fn default_bell() -> bool {
+    false
+}
+
+fn default_bell_frequency_hz() -> u32 {
+    0
+}
+
+fn default_bell_duration_ms() -> u32 {
+    100
+}
+
+fn default_bell_on_wrap() -> bool {
+    false
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum TextAlign {
     Left,
@@ -148,7 +342,154 @@ pub enum TextAlign {
     Right
 }
 
-#[derive(Debug, Clone, Deserialize)]
+fn default_text_valign() -> TextValign {
+    TextValign::Center
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LayoutProfile {
+    /// Fields only, in a centered box of ui.form_width.
+    Wide,
+    /// Labels above the fields in a full-width column.
+    Narrow,
+    /// Narrow on screens narrower than 640px, wide otherwise.
+    Auto
+}
+
+fn default_layout() -> LayoutProfile {
+    LayoutProfile::Wide
+}
+
+/// Warm-tinted palette during a daily time window.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct NightMode {
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Local time, "HH:MM".
+    #[serde(default = "default_night_start")]
+    pub start: String,
+
+    /// Local time, "HH:MM". May be earlier than start to cross midnight.
+    #[serde(default = "default_night_end")]
+    pub end: String,
+
+    /// 0 to 1.
+    #[serde(default = "default_night_strength")]
+    pub strength: f32
+}
+
+fn default_night_start() -> String {
+    "20:00".to_string()
+}
+
+fn default_night_end() -> String {
+    "06:00".to_string()
+}
+
+fn default_night_strength() -> f32 {
+    0.3
+}
+
+impl Default for NightMode {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            start: default_night_start(),
+            end: default_night_end(),
+            strength: default_night_strength()
+        }
+    }
+}
+
+/// `ui.qr`: what a QR code holds, such as a help desk URL, and where it
+/// goes.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Qr {
+    pub data: String,
+
+    /// Width and height, including the light border around it.
+    #[serde(default = "default_qr_size_px")]
+    pub size_px: u32,
+
+    #[serde(default = "default_qr_position")]
+    pub position: Anchor
+}
+
+fn default_qr_size_px() -> u32 {
+    160
+}
+
+fn default_qr_position() -> Anchor {
+    Anchor::BottomRight
+}
+
+/// Text above each row of the form.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Labels {
+    #[serde(default = "default_label_session")]
+    pub session: String,
+
+    #[serde(default = "default_label_username")]
+    pub username: String,
+
+    #[serde(default = "default_label_password")]
+    pub password: String
+}
+
+fn default_label_session() -> String {
+    "Session".to_string()
+}
+
+fn default_label_username() -> String {
+    "Username".to_string()
+}
+
+fn default_label_password() -> String {
+    "Password".to_string()
+}
+
+impl Default for Labels {
+    fn default() -> Self {
+        Self {
+            session: default_label_session(),
+            username: default_label_username(),
+            password: default_label_password()
+        }
+    }
+}
+
+fn default_anchor() -> Anchor {
+    Anchor::Center
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HostnameStyle {
+    /// Everything before the first dot.
+    Short,
+    Fqdn
+}
+
+fn default_hostname_style() -> HostnameStyle {
+    HostnameStyle::Fqdn
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PasswordStyle {
+    /// A `password_char` per character.
+    Text,
+    /// A large dot per character, for PINs.
+    Dots
+}
+
+fn default_password_style() -> PasswordStyle {
+    PasswordStyle::Text
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Ui {
     #[serde(default = "default_hide_target")]
     pub hide_target: bool,
@@ -168,15 +509,35 @@ pub struct Ui {
     #[serde(default = "default_password_char")]
     pub password_char: String,
 
+    #[serde(default = "default_password_style")]
+    pub password_style: PasswordStyle,
+
+    /// Show the last typed password character this long before masking
+    /// it. 0 disables it.
+    #[serde(default)]
+    pub password_peek_ms: u64,
+
     #[serde(default = "default_text_align")]
     pub text_align: TextAlign,
 
+    /// Where text sits in the height of a form row, the same for every
+    /// font and size.
+    #[serde(default = "default_text_valign")]
+    pub text_valign: TextValign,
+
     #[serde(default = "default_input_margin_px")]
     pub input_margin_px: u32,
 
+    /// Overrides `input_margin_px`: pixels, or a share of the form width
+    /// such as "8%".
+    #[serde(default)]
+    pub input_margin: Option<InputMargin>,
+
     #[serde(default = "default_heading_offset_y_px")]
     pub heading_offset_y_px: u32,
 
+    /// Wipe the screen to black rather than the background color before
+    /// starting a session or power action.
     #[serde(default = "default_blackout_on_success")]
     pub blackout_on_success: bool,
 
@@ -190,7 +551,224 @@ pub struct Ui {
     pub session_left_arrow: String,
 
     #[serde(default = "default_session_right_arrow")]
-    pub session_right_arrow: String
+    pub session_right_arrow: String,
+
+    /// Beep on auth failure and when an input field is full.
+    #[serde(default = "default_bell")]
+    pub bell: bool,
+
+    /// Tone frequency for the console speaker (KIOCSOUND). 0 writes BEL
+    /// instead.
+    #[serde(default = "default_bell_frequency_hz")]
+    pub bell_frequency_hz: u32,
+
+    #[serde(default = "default_bell_duration_ms")]
+    pub bell_duration_ms: u32,
+
+    /// Also beep when field navigation wraps around.
+    #[serde(default = "default_bell_on_wrap")]
+    pub bell_on_wrap: bool,
+
+    /// Show the selected session's Comment= under the session row while it
+    /// is focused.
+    #[serde(default = "default_show_session_comment")]
+    pub show_session_comment: bool,
+
+    /// Show the session's Icon= to the left of its name, if it's a PNG
+    /// we can find.
+    #[serde(default)]
+    pub show_session_icons: bool,
+
+    /// Hint line at the bottom of the screen, instead of hints for the
+    /// focused field. Empty hides it.
+    #[serde(default)]
+    pub footer: Option<String>,
+
+    /// Show the mflm version in the bottom right corner.
+    #[serde(default = "default_show_version")]
+    pub show_version: bool,
+
+    /// Name shown in the greeting instead of the kernel hostname.
+    #[serde(default)]
+    pub hostname: Option<String>,
+
+    #[serde(default = "default_hostname_style")]
+    pub hostname_style: HostnameStyle,
+
+    /// Show the battery level in the top right corner on machines that
+    /// have one.
+    #[serde(default = "default_show_battery")]
+    pub show_battery: bool,
+
+    /// Below this percentage the battery level is drawn in colors.error.
+    #[serde(default = "default_battery_low_percent")]
+    pub battery_low_percent: u8,
+
+    /// Show an "offline" badge above the form while the network is down.
+    #[serde(default = "default_show_network")]
+    pub show_network: bool,
+
+    /// Show how many logins have failed since boot below the form.
+    #[serde(default = "default_show_failed_attempts")]
+    pub show_failed_attempts: bool,
+
+    #[serde(default = "default_layout")]
+    pub layout: LayoutProfile,
+
+    /// Screen corner (or the center) the form is placed against.
+    #[serde(default = "default_anchor")]
+    pub anchor: Anchor,
+
+    /// Distance of the form from its anchored edges. With the center
+    /// anchor, shifts it right/down.
+    #[serde(default)]
+    pub offset_x_px: i32,
+
+    #[serde(default)]
+    pub offset_y_px: i32,
+
+    /// Where the greeting goes. "center" keeps it centered at the top;
+    /// the corners use ui.heading_offset_y_px from the top or bottom edge.
+    #[serde(default = "default_anchor")]
+    pub greeting_anchor: Anchor,
+
+    /// Keep the greeting, message of the day and key hints in a column
+    /// this wide, placed by `greeting_anchor`, instead of across the
+    /// whole screen.
+    #[serde(default)]
+    pub content_max_width_px: Option<u32>,
+
+    #[serde(default)]
+    pub night_mode: NightMode,
+
+    /// File whose first lines are shown under the greeting. Re-read when
+    /// it changes.
+    pub motd_file: Option<String>,
+
+    #[serde(default = "default_motd_max_lines")]
+    pub motd_max_lines: usize,
+
+    /// Rows shown by the username list (the `user_list` key). 0 turns the
+    /// list off.
+    #[serde(default = "default_user_list_rows")]
+    pub user_list_rows: usize,
+
+    /// Most screen refreshes per second; faster drawing is coalesced. 0
+    /// refreshes after every change.
+    #[serde(default = "default_max_refresh_hz")]
+    pub max_refresh_hz: u32,
+
+    /// Let the `debug_overlay` key show what a login would start.
+    #[serde(default)]
+    pub debug_overlay: bool,
+
+    #[serde(default)]
+    pub labels: Labels,
+
+    /// Show only the password field, for a single shared account. Needs
+    /// login.username and login.target.
+    #[serde(default)]
+    pub single_field: bool,
+
+    /// Show the "Welcome to ..." greeting. Without it, the form can use
+    /// the whole screen height.
+    #[serde(default = "default_show_greeting")]
+    pub show_greeting: bool,
+
+    /// 0 to 1; below 1 the form panel lets what's under it show through.
+    #[serde(default = "default_form_opacity")]
+    pub form_opacity: f32,
+
+    /// 0 to 1: how far the text of the rows without focus fades toward
+    /// the background. 0 draws it in the plain foreground color.
+    #[serde(default = "default_unfocused_dim")]
+    pub unfocused_dim: f32,
+
+    /// Thickness of the field underlines. 0 hides them.
+    #[serde(default = "default_underline_px")]
+    pub underline_px: u32,
+
+    /// "full", "text" (under what's typed), pixels ("200") or a share of
+    /// the row ("50%").
+    #[serde(default = "default_underline_width")]
+    pub underline_width: String,
+
+    /// Put the underlines this far below the text baseline instead of at
+    /// the bottom of the row.
+    #[serde(default)]
+    pub underline_gap_px: Option<u32>,
+
+    /// After `attract_after_secs` without a key, cycle the underline
+    /// through `attract_palette` and drift the greeting, for showrooms.
+    #[serde(default)]
+    pub attract_mode: bool,
+
+    #[serde(default = "default_attract_after_secs")]
+    pub attract_after_secs: u64,
+
+    /// Hex colors, as in [colors].
+    #[serde(default = "default_attract_palette")]
+    pub attract_palette: Vec<String>,
+
+    /// The form's rows, top to bottom: "session", "username" and
+    /// "password", each at most once. Password is required; a row left
+    /// out is hidden where it could be with `hide_target` or
+    /// `hide_username`, and otherwise goes last.
+    #[serde(default = "default_row_order")]
+    pub row_order: Vec<String>,
+
+    /// A QR code in a corner of the screen.
+    pub qr: Option<Qr>
+}
+
+fn default_row_order() -> Vec<String> {
+    ["session", "username", "password"]
+        .iter()
+        .map(|row| row.to_string())
+        .collect()
+}
+
+fn default_attract_after_secs() -> u64 {
+    300
+}
+
+fn default_attract_palette() -> Vec<String> {
+    ["#aaccee", "#ccaaee", "#ecbcad", "#bbddaa"]
+        .iter()
+        .map(|hex| hex.to_string())
+        .collect()
+}
+
+fn default_underline_px() -> u32 {
+    4
+}
+
+fn default_underline_width() -> String {
+    "full".to_string()
+}
+
+fn default_form_opacity() -> f32 {
+    1.0
+}
+
+fn default_unfocused_dim() -> f32 {
+    0.4
+}
+
+fn default_show_greeting() -> bool {
+    true
+}
+
+fn default_motd_max_lines() -> usize {
+    2
+}
+
+fn default_user_list_rows() -> usize {
+    4
+}
+
+fn default_max_refresh_hz() -> u32 {
+    60
 }
 
 impl Default for Ui {
@@ -202,19 +780,473 @@ impl Default for Ui {
             gap_below_username_px: default_gap_below_username_px(),
             row_h: default_row_h(),
             password_char: default_password_char(),
+            password_style: default_password_style(),
+            password_peek_ms: 0,
             text_align: default_text_align(),
+            text_valign: default_text_valign(),
             input_margin_px: default_input_margin_px(),
+            input_margin: None,
             heading_offset_y_px: default_heading_offset_y_px(),
             blackout_on_success: default_blackout_on_success(),
             form_width: default_form_width(),
             form_height: default_form_height(),
             session_left_arrow: default_session_left_arrow(),
-            session_right_arrow: default_session_right_arrow()
+            session_right_arrow: default_session_right_arrow(),
+            bell: default_bell(),
+            bell_frequency_hz: default_bell_frequency_hz(),
+            bell_duration_ms: default_bell_duration_ms(),
+            bell_on_wrap: default_bell_on_wrap(),
+            show_session_comment: default_show_session_comment(),
+            show_session_icons: false,
+            footer: None,
+            show_version: default_show_version(),
+            hostname: None,
+            hostname_style: default_hostname_style(),
+            show_battery: default_show_battery(),
+            battery_low_percent: default_battery_low_percent(),
+            show_network: default_show_network(),
+            show_failed_attempts: default_show_failed_attempts(),
+            layout: default_layout(),
+            anchor: default_anchor(),
+            offset_x_px: 0,
+            offset_y_px: 0,
+            greeting_anchor: default_anchor(),
+            content_max_width_px: None,
+            night_mode: NightMode::default(),
+            motd_file: None,
+            motd_max_lines: default_motd_max_lines(),
+            user_list_rows: default_user_list_rows(),
+            max_refresh_hz: default_max_refresh_hz(),
+            debug_overlay: false,
+            labels: Labels::default(),
+            single_field: false,
+            show_greeting: default_show_greeting(),
+            form_opacity: default_form_opacity(),
+            unfocused_dim: default_unfocused_dim(),
+            underline_px: default_underline_px(),
+            underline_width: default_underline_width(),
+            underline_gap_px: None,
+            attract_mode: false,
+            attract_after_secs: default_attract_after_secs(),
+            attract_palette: default_attract_palette(),
+            row_order: default_row_order(),
+            qr: None
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+pub struct Sessions {
+    /// Command line prepended to every session's Exec (e.g.
+    /// "dbus-run-session").
+    #[serde(default)]
+    pub command_prefix: String,
+
+    /// Run sessions through `/bin/sh -l -c "exec ..."` so profile hooks run.
+    #[serde(default)]
+    pub wrap_in_shell: bool,
+
+    /// When to tag sessions in the session row as wayland or x11.
+    #[serde(default)]
+    pub tag_type: TagType
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TagType {
+    /// Only sessions whose name another session has too.
+    #[default]
+    Auto,
+    Always,
+    Never
+}
+
+fn default_max_repeat_per_sec() -> u32 {
+    40
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Input {
+    /// More of the same character than this in a second is dropped, for
+    /// stuck keys. 0 disables it.
+    #[serde(default = "default_max_repeat_per_sec")]
+    pub max_repeat_per_sec: u32
+}
+
+impl Default for Input {
+    fn default() -> Self {
+        Self {
+            max_repeat_per_sec: default_max_repeat_per_sec()
+        }
+    }
+}
+
+fn default_watchdog_timeout_secs() -> u64 {
+    30
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Watchdog {
+    /// Exit, for greetd to start the greeter again, if drawing or
+    /// anything else between keypresses takes longer than this. 0
+    /// disables it.
+    #[serde(default = "default_watchdog_timeout_secs")]
+    pub timeout_secs: u64
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Self {
+            timeout_secs: default_watchdog_timeout_secs()
+        }
+    }
+}
+
+fn default_quiet_console() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Display {
+    /// Keep kernel log messages off the screen while the greeter is up,
+    /// by lowering console_loglevel until it exits.
+    #[serde(default = "default_quiet_console")]
+    pub quiet_console: bool,
+
+    /// Without a framebuffer, prompt for logins line by line on stdin
+    /// and stdout instead of exiting, as on a serial console.
+    #[serde(default)]
+    pub fallback_text: bool
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self {
+            quiet_console: default_quiet_console(),
+            fallback_text: false
+        }
+    }
+}
+
+fn default_stats_interval_secs() -> u64 {
+    60
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Logging {
+    /// Log drawing statistics every `stats_interval_secs`, as the debug
+    /// overlay shows them.
+    #[serde(default)]
+    pub stats: bool,
+
+    #[serde(default = "default_stats_interval_secs")]
+    pub stats_interval_secs: u64
+}
+
+impl Default for Logging {
+    fn default() -> Self {
+        Self {
+            stats: false,
+            stats_interval_secs: default_stats_interval_secs()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ConfigFile {
+    /// Reload the colors whenever a config file changes, as SIGHUP does.
+    #[serde(default)]
+    pub watch: bool
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PanelSide {
+    #[default]
+    None,
+    Left,
+    Right
+}
+
+fn default_panel_width_percent() -> u32 {
+    50
+}
+
+/// `[panel]`: a column of fixed information beside the form, for kiosks.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Panel {
+    /// Which side of the screen it takes; the form is centered in the
+    /// rest.
+    #[serde(default)]
+    pub side: PanelSide,
+
+    /// How much of the screen width it takes, from 10 to 90.
+    #[serde(default = "default_panel_width_percent")]
+    pub width_percent: u32,
+
+    /// A PNG shown at the top of the panel, such as a QR code.
+    #[serde(default)]
+    pub image: Option<String>,
+
+    /// Lines of text shown under the image.
+    #[serde(default)]
+    pub text: Option<String>
+}
+
+impl Default for Panel {
+    fn default() -> Self {
+        Self {
+            side: PanelSide::None,
+            width_percent: default_panel_width_percent(),
+            image: None,
+            text: None
+        }
+    }
+}
+
+/// `ui.input_margin` as written: a number of pixels or a string such as
+/// "8%".
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum InputMargin {
+    Px(u32),
+    Text(String)
+}
+
+impl InputMargin {
+    /// The margin it asks for, or None if it's neither pixels nor a
+    /// percentage.
+    pub fn margin(&self) -> Option<Margin> {
+        match self {
+            InputMargin::Px(px) => Some(Margin::Px(*px)),
+            InputMargin::Text(text) => Margin::parse(text)
         }
     }
 }
 
-#[derive(Default, Debug, Clone, Deserialize)]
+/// One key descriptor or a list of them.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum KeyList {
+    One(String),
+    Many(Vec<String>)
+}
+
+impl KeyList {
+    pub fn as_slice(&self) -> &[String] {
+        match self {
+            KeyList::One(key) => std::slice::from_ref(key),
+            KeyList::Many(keys) => keys
+        }
+    }
+}
+
+fn key_list(keys: &[&str]) -> KeyList {
+    KeyList::Many(keys.iter().map(|k| k.to_string()).collect())
+}
+
+fn default_keys_next_field() -> KeyList {
+    key_list(&["tab", "down"])
+}
+
+fn default_keys_prev_field() -> KeyList {
+    key_list(&["up"])
+}
+
+fn default_keys_next_session() -> KeyList {
+    key_list(&["right"])
+}
+
+fn default_keys_prev_session() -> KeyList {
+    key_list(&["left"])
+}
+
+fn default_keys_clear_field() -> KeyList {
+    key_list(&["ctrl+u", "ctrl+k"])
+}
+
+fn default_keys_delete_word() -> KeyList {
+    key_list(&["ctrl+w"])
+}
+
+fn default_keys_line_start() -> KeyList {
+    key_list(&["ctrl+a", "home"])
+}
+
+fn default_keys_line_end() -> KeyList {
+    key_list(&["ctrl+e", "end"])
+}
+
+fn default_keys_complete_next() -> KeyList {
+    key_list(&["ctrl+n"])
+}
+
+fn default_keys_complete_prev() -> KeyList {
+    key_list(&["ctrl+p"])
+}
+
+fn default_keys_submit() -> KeyList {
+    key_list(&["enter"])
+}
+
+fn default_keys_quit() -> KeyList {
+    key_list(&["ctrl+c", "ctrl+d"])
+}
+
+fn default_keys_rescan() -> KeyList {
+    key_list(&["f5"])
+}
+
+fn default_keys_user_list() -> KeyList {
+    key_list(&["f2"])
+}
+
+fn default_keys_debug_overlay() -> KeyList {
+    key_list(&["f10"])
+}
+
+fn default_keys_unbound() -> KeyList {
+    key_list(&[])
+}
+
+/// Key descriptors ("ctrl+u", "tab", "f12", "up") per action.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Keys {
+    #[serde(default = "default_keys_next_field")]
+    pub next_field: KeyList,
+
+    #[serde(default = "default_keys_prev_field")]
+    pub prev_field: KeyList,
+
+    #[serde(default = "default_keys_next_session")]
+    pub next_session: KeyList,
+
+    #[serde(default = "default_keys_prev_session")]
+    pub prev_session: KeyList,
+
+    #[serde(default = "default_keys_clear_field")]
+    pub clear_field: KeyList,
+
+    #[serde(default = "default_keys_delete_word")]
+    pub delete_word: KeyList,
+
+    #[serde(default = "default_keys_line_start")]
+    pub line_start: KeyList,
+
+    #[serde(default = "default_keys_line_end")]
+    pub line_end: KeyList,
+
+    #[serde(default = "default_keys_complete_next")]
+    pub complete_next: KeyList,
+
+    #[serde(default = "default_keys_complete_prev")]
+    pub complete_prev: KeyList,
+
+    #[serde(default = "default_keys_submit")]
+    pub submit: KeyList,
+
+    #[serde(default = "default_keys_quit")]
+    pub quit: KeyList,
+
+    #[serde(default = "default_keys_unbound")]
+    pub shutdown: KeyList,
+
+    #[serde(default = "default_keys_unbound")]
+    pub reboot: KeyList,
+
+    #[serde(default = "default_keys_unbound")]
+    pub toggle_reveal: KeyList,
+
+    #[serde(default = "default_keys_rescan")]
+    pub rescan: KeyList,
+
+    #[serde(default = "default_keys_user_list")]
+    pub user_list: KeyList,
+
+    /// Only with `ui.debug_overlay = true`.
+    #[serde(default = "default_keys_debug_overlay")]
+    pub debug_overlay: KeyList
+}
+
+impl Default for Keys {
+    fn default() -> Self {
+        Self {
+            next_field: default_keys_next_field(),
+            prev_field: default_keys_prev_field(),
+            next_session: default_keys_next_session(),
+            prev_session: default_keys_prev_session(),
+            clear_field: default_keys_clear_field(),
+            delete_word: default_keys_delete_word(),
+            line_start: default_keys_line_start(),
+            line_end: default_keys_line_end(),
+            complete_next: default_keys_complete_next(),
+            complete_prev: default_keys_complete_prev(),
+            submit: default_keys_submit(),
+            quit: default_keys_quit(),
+            shutdown: default_keys_unbound(),
+            reboot: default_keys_unbound(),
+            toggle_reveal: default_keys_unbound(),
+            rescan: default_keys_rescan(),
+            user_list: default_keys_user_list(),
+            debug_overlay: default_keys_debug_overlay()
+        }
+    }
+}
+
+impl Keys {
+    pub fn entries(&self) -> [(Action, &[String]); 18] {
+        [
+            (Action::NextField, self.next_field.as_slice()),
+            (Action::PrevField, self.prev_field.as_slice()),
+            (Action::NextSession, self.next_session.as_slice()),
+            (Action::PrevSession, self.prev_session.as_slice()),
+            (Action::ClearField, self.clear_field.as_slice()),
+            (Action::DeleteWord, self.delete_word.as_slice()),
+            (Action::LineStart, self.line_start.as_slice()),
+            (Action::LineEnd, self.line_end.as_slice()),
+            (Action::CompleteNext, self.complete_next.as_slice()),
+            (Action::CompletePrev, self.complete_prev.as_slice()),
+            (Action::Submit, self.submit.as_slice()),
+            (Action::Quit, self.quit.as_slice()),
+            (Action::Shutdown, self.shutdown.as_slice()),
+            (Action::Reboot, self.reboot.as_slice()),
+            (Action::ToggleReveal, self.toggle_reveal.as_slice()),
+            (Action::Rescan, self.rescan.as_slice()),
+            (Action::UserList, self.user_list.as_slice()),
+            (Action::DebugOverlay, self.debug_overlay.as_slice())
+        ]
+    }
+}
+
+fn default_shutdown_command() -> String {
+    "systemctl poweroff".to_string()
+}
+
+fn default_reboot_command() -> String {
+    "systemctl reboot".to_string()
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Power {
+    /// Run by the `shutdown` key binding.
+    #[serde(default = "default_shutdown_command")]
+    pub shutdown_command: String,
+
+    /// Run by the `reboot` key binding.
+    #[serde(default = "default_reboot_command")]
+    pub reboot_command: String
+}
+
+impl Default for Power {
+    fn default() -> Self {
+        Self {
+            shutdown_command: default_shutdown_command(),
+            reboot_command: default_reboot_command()
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
 pub struct Settings {
     #[serde(default)]
     pub fonts: Fonts,
@@ -226,12 +1258,64 @@ pub struct Settings {
     pub login: Login,
 
     #[serde(default)]
-    pub ui: Ui
+    pub ui: Ui,
+
+    #[serde(default)]
+    pub sessions: Sessions,
+
+    #[serde(default)]
+    pub keys: Keys,
+
+    #[serde(default)]
+    pub power: Power,
+
+    #[serde(default)]
+    pub input: Input,
+
+    #[serde(default)]
+    pub watchdog: Watchdog,
+
+    #[serde(default)]
+    pub display: Display,
+
+    #[serde(default)]
+    pub logging: Logging,
+
+    #[serde(default)]
+    pub config: ConfigFile,
+
+    #[serde(default)]
+    pub panel: Panel
 }
 
 impl Settings {
-    /// Loads configuration from /etc/mflm/config.toml
+    /// Loads configuration from /etc/mflm/config.toml, with the user's
+    /// $XDG_CONFIG_HOME/mflm/config.toml on top when not running as root.
     pub fn load() -> Result<Self, config::ConfigError> {
+        let mut builder = Self::defaults()?;
+        for path in crate::paths::config_files() {
+            if path.exists() {
+                info!("Reading config from {}", path.display());
+            } else {
+                info!("No config at {}", path.display());
+            }
+            builder = builder.add_source(
+                config::File::from(path)
+                    .format(config::FileFormat::Toml)
+                    .required(false)
+            );
+        }
+
+        let cfg = builder.build()?;
+        cfg.try_deserialize::<Self>()
+    }
+
+    /// A config builder with every default set, for config files to go on
+    /// top of.
+    fn defaults() -> Result<
+        config::ConfigBuilder<config::builder::DefaultState>,
+        config::ConfigError
+    > {
         let builder = config::Config::builder()
             .set_default("fonts.main", Fonts::default().main)?
             .set_default("fonts.heading", Fonts::default().heading)?
@@ -242,13 +1326,75 @@ impl Settings {
             .set_default("colors.neutral", Colors::default().neutral)?
             .set_default("colors.selected", Colors::default().selected)?
             .set_default("colors.error", Colors::default().error)?
+            .set_default("login.trim_username", default_trim_username())?
+            .set_default("login.select_username", false)?
+            .set_default("login.complete_usernames", false)?
+            .set_default("login.clear_username_on_failure", false)?
+            .set_default("login.username_case_hint", false)?
+            .set_default("login.require_network", false)?
+            .set_default("login.auto_submit_length", 0)?
+            .set_default("login.auto_submit_idle_ms", 0)?
+            .set_default("login.submit_key", "enter")?
+            .set_default("login.max_password_len", 0)?
+            .set_default("login.numeric_only", false)?
+            .set_default("login.handoff_delay_ms", 0)?
+            .set_default("login.strict_start_ack", false)?
+            .set_default("login.allow_empty_password", false)?
+            .set_default("login.retry_delay_ms", default_retry_delay_ms())?
+            .set_default("sessions.command_prefix", "")?
+            .set_default("sessions.wrap_in_shell", false)?
+            .set_default("sessions.tag_type", "auto")?
+            .set_default(
+                "input.max_repeat_per_sec",
+                default_max_repeat_per_sec() as u64
+            )?
+            .set_default(
+                "watchdog.timeout_secs",
+                default_watchdog_timeout_secs()
+            )?
+            .set_default("display.quiet_console", default_quiet_console())?
+            .set_default("display.fallback_text", false)?
+            .set_default("logging.stats", false)?
+            .set_default(
+                "logging.stats_interval_secs",
+                default_stats_interval_secs()
+            )?
+            .set_default("config.watch", false)?
+            .set_default("panel.side", "none")?
+            .set_default(
+                "panel.width_percent",
+                default_panel_width_percent() as u64
+            )?
+            .set_default("keys.next_field", vec!["tab", "down"])?
+            .set_default("keys.prev_field", vec!["up"])?
+            .set_default("keys.next_session", vec!["right"])?
+            .set_default("keys.prev_session", vec!["left"])?
+            .set_default("keys.clear_field", vec!["ctrl+u", "ctrl+k"])?
+            .set_default("keys.delete_word", vec!["ctrl+w"])?
+            .set_default("keys.line_start", vec!["ctrl+a", "home"])?
+            .set_default("keys.line_end", vec!["ctrl+e", "end"])?
+            .set_default("keys.complete_next", vec!["ctrl+n"])?
+            .set_default("keys.complete_prev", vec!["ctrl+p"])?
+            .set_default("keys.submit", vec!["enter"])?
+            .set_default("keys.quit", vec!["ctrl+c", "ctrl+d"])?
+            .set_default("keys.shutdown", Vec::<String>::new())?
+            .set_default("keys.reboot", Vec::<String>::new())?
+            .set_default("keys.toggle_reveal", Vec::<String>::new())?
+            .set_default("keys.rescan", vec!["f5"])?
+            .set_default("keys.user_list", vec!["f2"])?
+            .set_default("keys.debug_overlay", vec!["f10"])?
+            .set_default("power.shutdown_command", default_shutdown_command())?
+            .set_default("power.reboot_command", default_reboot_command())?
             .set_default("ui.hide_target", default_hide_target())?
             .set_default("ui.hide_username", default_hide_username())?
             .set_default("ui.gap_below_session_px", default_gap_below_session_px())?
             .set_default("ui.gap_below_username_px", default_gap_below_username_px())?
             .set_default("ui.row_h", default_row_h())?
             .set_default("ui.password_char", default_password_char())?
+            .set_default("ui.password_style", "text")?
+            .set_default("ui.password_peek_ms", 0)?
             .set_default("ui.text_align", "center")?
+            .set_default("ui.text_valign", "center")?
             .set_default("ui.input_margin_px", default_input_margin_px())?
             .set_default("ui.heading_offset_y_px", default_heading_offset_y_px())?
             .set_default("ui.form_width", default_form_width())?
@@ -256,25 +1402,229 @@ impl Settings {
             .set_default("ui.session_left_arrow", default_session_left_arrow())?
             .set_default("ui.session_right_arrow", default_session_right_arrow())?
             .set_default("ui.blackout_on_success", default_blackout_on_success())?
-            .add_source(
-                config::File::from(std::path::Path::new(
-                    "/etc/mflm/config.toml"
-                ))
-                .format(config::FileFormat::Toml)
-                .required(false)
-            );
+            .set_default("ui.bell", default_bell())?
+            .set_default("ui.bell_frequency_hz", default_bell_frequency_hz())?
+            .set_default("ui.bell_duration_ms", default_bell_duration_ms())?
+            .set_default("ui.bell_on_wrap", default_bell_on_wrap())?
+            .set_default("ui.show_session_comment", default_show_session_comment())?
+            .set_default("ui.show_session_icons", false)?
+            .set_default("ui.show_version", default_show_version())?
+            .set_default("ui.hostname_style", "fqdn")?
+            .set_default("ui.show_battery", default_show_battery())?
+            .set_default("ui.battery_low_percent", default_battery_low_percent())?
+            .set_default("ui.show_network", default_show_network())?
+            .set_default(
+                "ui.show_failed_attempts",
+                default_show_failed_attempts()
+            )?
+            .set_default("ui.layout", "wide")?
+            .set_default("ui.anchor", "center")?
+            .set_default("ui.offset_x_px", 0)?
+            .set_default("ui.offset_y_px", 0)?
+            .set_default("ui.greeting_anchor", "center")?
+            .set_default("ui.night_mode.enabled", false)?
+            .set_default("ui.night_mode.start", default_night_start())?
+            .set_default("ui.night_mode.end", default_night_end())?
+            .set_default(
+                "ui.night_mode.strength",
+                default_night_strength() as f64
+            )?
+            .set_default("ui.motd_max_lines", default_motd_max_lines() as u64)?
+            .set_default(
+                "ui.user_list_rows",
+                default_user_list_rows() as u64
+            )?
+            .set_default("ui.max_refresh_hz", default_max_refresh_hz() as u64)?
+            .set_default("ui.debug_overlay", false)?
+            .set_default("ui.labels.session", default_label_session())?
+            .set_default("ui.labels.username", default_label_username())?
+            .set_default("ui.labels.password", default_label_password())?
+            .set_default("ui.single_field", false)?
+            .set_default("ui.show_greeting", default_show_greeting())?
+            .set_default("ui.form_opacity", default_form_opacity() as f64)?
+            .set_default("ui.unfocused_dim", default_unfocused_dim() as f64)?
+            .set_default("ui.underline_px", default_underline_px() as u64)?
+            .set_default("ui.underline_width", default_underline_width())?
+            .set_default("ui.attract_mode", false)?
+            .set_default("ui.attract_after_secs", default_attract_after_secs())?
+            .set_default("ui.attract_palette", default_attract_palette())?
+            .set_default("ui.row_order", default_row_order())?;
+        Ok(builder)
+    }
 
-        let cfg = builder.build()?;
-        cfg.try_deserialize::<Self>()
+    /// The settings as a TOML config file, with colors written out as
+    /// "#AARRGGBB". For `--dump-config`.
+    pub fn to_toml(&self) -> Result<String, toml::ser::Error> {
+        let mut settings = self.clone();
+        // An invalid color is left as it is, to show what's wrong.
+        let normalize = |hex: &mut String| {
+            if let Ok(color) = Color::from_hex(hex) {
+                *hex = color.as_hex();
+            }
+        };
+        let colors = &mut settings.colors;
+        normalize(&mut colors.foreground);
+        normalize(&mut colors.background);
+        normalize(&mut colors.neutral);
+        normalize(&mut colors.selected);
+        normalize(&mut colors.error);
+        colors.form_idle.iter_mut().for_each(normalize);
+        colors.form_busy.iter_mut().for_each(normalize);
+        colors.form_error.iter_mut().for_each(normalize);
+        // Nothing here is secret yet; anything that is gets blanked out
+        // here before it can be printed.
+        toml::to_string(&settings)
     }
 
     pub fn resolve_colors(&self) -> Result<ResolvedColors, ParseColorError> {
+        let background = Color::from_hex(&self.colors.background)?;
+        let selected = Color::from_hex(&self.colors.selected)?;
+        let error = Color::from_hex(&self.colors.error)?;
+        let or = |hex: &Option<String>, default: Color| match hex {
+            Some(hex) => Color::from_hex(hex),
+            None => Ok(default)
+        };
         Ok(ResolvedColors {
             foreground: Color::from_hex(&self.colors.foreground)?,
-            background: Color::from_hex(&self.colors.background)?,
+            background,
             neutral: Color::from_hex(&self.colors.neutral)?,
-            selected: Color::from_hex(&self.colors.selected)?,
-            error: Color::from_hex(&self.colors.error)?
+            selected,
+            error,
+            form_idle: or(&self.colors.form_idle, background)?,
+            form_busy: or(&self.colors.form_busy, selected)?,
+            form_error: or(&self.colors.form_error, error)?
         })
     }
+
+    /// Turns `ui.single_field` off unless `login.username` and
+    /// `login.target` are both set, since the one row left can't ask for
+    /// either. Errs with what was wrong when it had to.
+    pub fn check_single_field(&mut self) -> Result<(), &'static str> {
+        let configured = |s: &Option<String>| {
+            s.as_deref().is_some_and(|s| !s.trim().is_empty())
+        };
+        if !self.ui.single_field
+            || (configured(&self.login.username)
+                && configured(&self.login.target))
+        {
+            return Ok(());
+        }
+        self.ui.single_field = false;
+        Err("ui.single_field needs login.username and login.target")
+    }
+}
+
+#[cfg(test)]
+impl Settings {
+    /// The defaults with `text` as the only config file.
+    pub(crate) fn from_toml(text: &str) -> Self {
+        Self::defaults()
+            .unwrap()
+            .add_source(config::File::from_str(text, config::FileFormat::Toml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_toml(text: &str) -> Settings {
+        Settings::from_toml(text)
+    }
+
+    /// Dumps `settings`, loads the dump back and checks it dumps the same.
+    fn round_trip(settings: &Settings) -> Settings {
+        let dump = settings.to_toml().unwrap();
+        let reloaded = from_toml(&dump);
+        assert_eq!(reloaded.to_toml().unwrap(), dump);
+        reloaded
+    }
+
+    #[test]
+    fn default_dump_loads_back_the_same() {
+        round_trip(&from_toml(""));
+    }
+
+    #[test]
+    fn changed_settings_dump_and_load_back_the_same() {
+        let settings = from_toml(
+            r##"
+            [colors]
+            foreground = "FFFFFF"
+            background = "#80112233"
+            form_idle = "#123456"
+
+            [ui]
+            input_margin = "8%"
+            underline_width = "50%"
+            anchor = "bottom-right"
+            offset_x_px = -20
+            row_order = ["password", "username"]
+            password_char = "•"
+
+            [ui.night_mode]
+            enabled = true
+            start = "22:30"
+
+            [keys]
+            quit = "ctrl+q"
+            shutdown = ["f12", "ctrl+alt+delete"]
+            "##
+        );
+        let reloaded = round_trip(&settings);
+        assert_eq!(
+            reloaded.resolve_colors().unwrap(),
+            settings.resolve_colors().unwrap()
+        );
+        assert_eq!(reloaded.ui.input_margin_px, settings.ui.input_margin_px);
+        assert_eq!(
+            reloaded.ui.input_margin.and_then(|m| m.margin()),
+            Some(Margin::Fraction(0.08))
+        );
+        assert_eq!(reloaded.ui.anchor, Anchor::BottomRight);
+        assert_eq!(reloaded.ui.offset_x_px, -20);
+        assert_eq!(reloaded.ui.password_char, "•");
+        assert_eq!(reloaded.keys.quit.as_slice(), ["ctrl+q"]);
+    }
+
+    #[test]
+    fn colors_are_dumped_in_full() {
+        let dump = from_toml("[colors]\nforeground = \"FFF0E0\"")
+            .to_toml()
+            .unwrap();
+        assert!(dump.contains("foreground = \"#fffff0e0\""), "{}", dump);
+    }
+
+    #[test]
+    fn single_field_needs_a_username_and_a_target() {
+        let on = "[ui]\nsingle_field = true\n[login]\n";
+        for login in [
+            "",
+            "username = \"alice\"",
+            "target = \"sway\"",
+            "username = \" \"\ntarget = \"sway\"",
+            "username = \"alice\"\ntarget = \"\""
+        ] {
+            let mut settings = from_toml(&format!("{on}{login}"));
+            assert_eq!(
+                settings.check_single_field(),
+                Err("ui.single_field needs login.username and login.target")
+            );
+            assert!(!settings.ui.single_field, "{}", login);
+        }
+
+        let mut settings =
+            from_toml(&format!("{on}username = \"alice\"\ntarget = \"sway\""));
+        assert_eq!(settings.check_single_field(), Ok(()));
+        assert!(settings.ui.single_field);
+
+        // Without a single field there's nothing to check.
+        let mut settings = from_toml("");
+        assert_eq!(settings.check_single_field(), Ok(()));
+        assert!(!settings.ui.single_field);
+    }
 }
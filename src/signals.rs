@@ -0,0 +1,108 @@
+//! SIGUSR1 asks for a full redraw, for when something else has drawn over
+//! the framebuffer: `pkill -USR1 mflm`. SIGHUP reloads the colors from the
+//! config files. The handlers only set a flag, which the greeter loop
+//! checks on each tick. SIGTERM and SIGINT still end the greeter, after
+//! putting the console back.
+
+use std::{
+    convert::TryFrom,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock
+    }
+};
+
+use log::warn;
+use nix::{
+    libc::c_int,
+    sys::signal::{
+        self, sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal
+    }
+};
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+static REDRAW: AtomicBool = AtomicBool::new(false);
+static RELOAD: AtomicBool = AtomicBool::new(false);
+/// Run on SIGTERM and SIGINT before dying of them.
+static ON_TERMINATE: OnceLock<fn()> = OnceLock::new();
+
+extern "C" fn on_sigusr1(_: c_int) {
+    REDRAW.store(true, Ordering::Relaxed);
+}
+
+extern "C" fn on_sighup(_: c_int) {
+    RELOAD.store(true, Ordering::Relaxed);
+}
+
+extern "C" fn on_terminating(signum: c_int) {
+    if let Some(cleanup) = ON_TERMINATE.get() {
+        cleanup();
+    }
+    // Then die of it as we would have without the handler, so greetd
+    // sees why.
+    if let Ok(sig) = Signal::try_from(signum) {
+        let _ = unsafe { signal::signal(sig, SigHandler::SigDfl) };
+        let _ = signal::raise(sig);
+    }
+}
+
+fn handle(signal: Signal, handler: extern "C" fn(c_int)) -> nix::Result<()> {
+    let action = SigAction::new(
+        SigHandler::Handler(handler),
+        SaFlags::SA_RESTART,
+        SigSet::empty()
+    );
+    unsafe { sigaction(signal, &action) }.map(drop)
+}
+
+/// Starts listening for SIGUSR1 and SIGHUP. Without it, either signal
+/// would kill us.
+pub fn install() {
+    match handle(Signal::SIGUSR1, on_sigusr1) {
+        Ok(()) => INSTALLED.store(true, Ordering::Relaxed),
+        Err(e) => warn!("Unable to handle SIGUSR1, so it can't redraw: {e}")
+    }
+    match handle(Signal::SIGHUP, on_sighup) {
+        Ok(()) => INSTALLED.store(true, Ordering::Relaxed),
+        Err(e) => warn!("Unable to handle SIGHUP, so it can't reload: {e}")
+    }
+}
+
+/// Runs `cleanup` when SIGTERM or SIGINT arrives, then lets the signal
+/// end the greeter. `cleanup` runs in the signal handler, so it may only
+/// make raw syscalls.
+pub fn on_terminate(cleanup: fn()) {
+    if ON_TERMINATE.set(cleanup).is_err() {
+        return;
+    }
+    for sig in [Signal::SIGTERM, Signal::SIGINT] {
+        if let Err(e) = handle(sig, on_terminating) {
+            warn!("Unable to handle {sig}; the console may stay blank: {e}");
+        }
+    }
+}
+
+/// Whether a signal is handled, so the loop needs to tick to notice it.
+pub fn installed() -> bool {
+    INSTALLED.load(Ordering::Relaxed)
+}
+
+/// Asks for a full redraw at the next tick, as SIGUSR1 does.
+pub fn request_redraw() {
+    REDRAW.store(true, Ordering::Relaxed);
+}
+
+/// Whether a redraw was asked for since the last call.
+pub fn take_redraw() -> bool {
+    REDRAW.swap(false, Ordering::Relaxed)
+}
+
+/// Asks for the config to be reloaded at the next tick, as SIGHUP does.
+pub fn request_reload() {
+    RELOAD.store(true, Ordering::Relaxed);
+}
+
+/// Whether a reload was asked for since the last call.
+pub fn take_reload() -> bool {
+    RELOAD.swap(false, Ordering::Relaxed)
+}
@@ -0,0 +1,349 @@
+use std::{
+    env,
+    fs::{self, File},
+    io,
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+    process
+};
+
+use chrono::{Local, TimeZone};
+use log::warn;
+use nix::fcntl::{flock, FlockArg};
+use serde::{Deserialize, Serialize};
+
+const BOOT_ID_FILE: &str = "/proc/sys/kernel/random/boot_id";
+
+/// The seat without `XDG_SEAT`.
+const DEFAULT_SEAT: &str = "seat0";
+
+/// The state file: a table for each seat, keyed by its name.
+type Tables = toml::Table;
+
+/// One seat's table in the state file.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SeatState {
+    #[serde(default)]
+    boot_id: String,
+    #[serde(default)]
+    failed_attempts: u32,
+    first_failure: Option<i64>
+}
+
+/// Failed login attempts since boot. Kept on disk so restarting the
+/// greeter doesn't reset it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FailedAttempts {
+    pub count: u32,
+    /// Unix time of the first failure.
+    pub since: Option<i64>
+}
+
+impl FailedAttempts {
+    /// "3 failed attempts since 09:12"
+    pub fn describe(&self) -> String {
+        let noun = if self.count == 1 { "attempt" } else { "attempts" };
+        let since = self
+            .since
+            .and_then(|t| Local.timestamp_opt(t, 0).single())
+            .map(|t| format!(" since {}", t.format("%H:%M")))
+            .unwrap_or_default();
+        format!("{} failed {noun}{since}", self.count)
+    }
+}
+
+/// State that outlives a greeter process, but not a reboot. Each seat
+/// has its own, in one file shared by all of them.
+pub struct State {
+    path: PathBuf,
+    seat: String,
+    boot_id: String,
+    pub failed_attempts: FailedAttempts
+}
+
+/// The seat this greeter is on, from `XDG_SEAT`.
+pub fn seat() -> String {
+    env::var("XDG_SEAT")
+        .ok()
+        .map(|seat| seat.trim().to_string())
+        .filter(|seat| !seat.is_empty())
+        .unwrap_or_else(|| DEFAULT_SEAT.to_string())
+}
+
+/// Reads the state file's tables. A file that doesn't parse is moved
+/// aside, so the next save starts it afresh without losing it.
+fn read_tables(path: &Path) -> Tables {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => Some(text),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Tables::new(),
+        // Not UTF-8, so not something we wrote.
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => None,
+        Err(e) => {
+            warn!("Unable to read state file {}: {e}", path.display());
+            return Tables::new();
+        }
+    };
+    if let Some(tables) = text.and_then(|text| text.parse().ok()) {
+        return tables;
+    }
+
+    let aside = path.with_extension("bad");
+    warn!(
+        "State file {} is corrupt; moving it to {} and starting afresh",
+        path.display(),
+        aside.display()
+    );
+    if let Err(e) = fs::rename(path, &aside) {
+        warn!("Unable to move the state file aside: {e}");
+    }
+    Tables::new()
+}
+
+/// Takes the lock that greeters on other seats wait on before reading
+/// and rewriting the state file. Held until the file is dropped.
+fn lock(path: &Path) -> io::Result<File> {
+    let file = File::create(path.with_extension("lock"))?;
+    flock(file.as_raw_fd(), FlockArg::LockExclusive)?;
+    Ok(file)
+}
+
+pub fn boot_id() -> String {
+    fs::read_to_string(BOOT_ID_FILE)
+        .map(|id| id.trim().to_string())
+        .unwrap_or_default()
+}
+
+impl State {
+    /// Loads `seat`'s state from `path`, starting afresh if it's missing,
+    /// unreadable or from a different boot.
+    pub fn for_seat(path: &Path, seat: &str, boot_id: &str) -> Self {
+        let mut state = State {
+            path: path.to_path_buf(),
+            seat: seat.to_string(),
+            boot_id: boot_id.to_string(),
+            failed_attempts: FailedAttempts::default()
+        };
+        // Without the lock, a corrupt file could be moved aside just as
+        // another seat rewrites it.
+        let _lock = lock(path).ok();
+        let saved = match read_tables(path).remove(seat) {
+            Some(table) => table,
+            None => return state
+        };
+        let saved: SeatState = match saved.try_into() {
+            Ok(saved) => saved,
+            Err(e) => {
+                warn!("Ignoring seat {seat}'s state: {e}");
+                return state;
+            }
+        };
+
+        // Without a boot id to compare against, assume it's stale.
+        if !boot_id.is_empty() && saved.boot_id == boot_id {
+            state.failed_attempts = FailedAttempts {
+                count: saved.failed_attempts,
+                since: saved.first_failure
+            };
+        }
+        state
+    }
+
+    /// Writes this seat's table, keeping the other seats' as they are.
+    /// Greeters on other seats wait for it to finish.
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let _lock = lock(&self.path)?;
+        let table = SeatState {
+            boot_id: self.boot_id.clone(),
+            failed_attempts: self.failed_attempts.count,
+            first_failure: self.failed_attempts.since
+        };
+        let table = toml::Value::try_from(table).map_err(io::Error::other)?;
+
+        let mut tables = read_tables(&self.path);
+        tables.insert(self.seat.clone(), table);
+        let text = toml::to_string(&tables).map_err(io::Error::other)?;
+
+        // Write and rename so a crash can't leave half a file behind.
+        let tmp = self.path.with_extension(format!("tmp.{}", process::id()));
+        fs::write(&tmp, text)?;
+        fs::rename(&tmp, &self.path)
+    }
+
+    pub fn record_failure(&mut self) {
+        let attempts = &mut self.failed_attempts;
+        attempts.count = attempts.count.saturating_add(1);
+        if attempts.since.is_none() {
+            attempts.since = Some(Local::now().timestamp());
+        }
+        self.save_or_warn();
+    }
+
+    pub fn clear_failures(&mut self) {
+        if self.failed_attempts == FailedAttempts::default() {
+            return;
+        }
+        self.failed_attempts = FailedAttempts::default();
+        self.save_or_warn();
+    }
+
+    fn save_or_warn(&self) {
+        if let Err(e) = self.save() {
+            warn!("Unable to write state file {}: {e}", self.path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory for one test's state file.
+    fn state_path(name: &str) -> PathBuf {
+        let dir = env::temp_dir()
+            .join(format!("mflm-state-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join("state")
+    }
+
+    fn failed(count: u32, since: i64) -> FailedAttempts {
+        FailedAttempts {
+            count,
+            since: Some(since)
+        }
+    }
+
+    fn written(path: &Path) -> Tables {
+        fs::read_to_string(path).unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn failures_survive_a_restart_within_a_boot() {
+        let path = state_path("restart");
+        let mut state = State::for_seat(&path, "seat0", "boot-a");
+        state.record_failure();
+        state.record_failure();
+        let since = state.failed_attempts.since;
+        assert!(since.is_some());
+
+        let state = State::for_seat(&path, "seat0", "boot-a");
+        assert_eq!(state.failed_attempts.count, 2);
+        assert_eq!(state.failed_attempts.since, since);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn failures_reset_on_a_new_boot() {
+        let path = state_path("reboot");
+        let mut state = State::for_seat(&path, "seat0", "boot-a");
+        state.failed_attempts = failed(3, 100);
+        state.save().unwrap();
+
+        let mut state = State::for_seat(&path, "seat0", "boot-b");
+        assert_eq!(state.failed_attempts, FailedAttempts::default());
+        // The first failure this boot starts the count again and claims
+        // the file for the new boot.
+        state.record_failure();
+        assert_eq!(written(&path)["seat0"]["boot_id"].as_str(), Some("boot-b"));
+        let state = State::for_seat(&path, "seat0", "boot-b");
+        assert_eq!(state.failed_attempts.count, 1);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn failures_without_a_boot_id_are_stale() {
+        let path = state_path("no-boot-id");
+        let mut state = State::for_seat(&path, "seat0", "");
+        state.failed_attempts = failed(3, 100);
+        state.save().unwrap();
+        let state = State::for_seat(&path, "seat0", "");
+        assert_eq!(state.failed_attempts, FailedAttempts::default());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn login_clears_failures() {
+        let path = state_path("clear");
+        let mut state = State::for_seat(&path, "seat0", "boot-a");
+        state.record_failure();
+        state.clear_failures();
+        let state = State::for_seat(&path, "seat0", "boot-a");
+        assert_eq!(state.failed_attempts, FailedAttempts::default());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn seats_share_the_file() {
+        let path = state_path("seats");
+        let mut seat0 = State::for_seat(&path, "seat0", "boot");
+        seat0.failed_attempts = failed(2, 100);
+        seat0.save().unwrap();
+        let mut seat1 = State::for_seat(&path, "seat1", "boot");
+        assert_eq!(seat1.failed_attempts, FailedAttempts::default());
+        seat1.failed_attempts = failed(5, 200);
+        seat1.save().unwrap();
+
+        let seat0 = State::for_seat(&path, "seat0", "boot");
+        let seat1 = State::for_seat(&path, "seat1", "boot");
+        assert_eq!(seat0.failed_attempts, failed(2, 100));
+        assert_eq!(seat1.failed_attempts, failed(5, 200));
+
+        // It's TOML, with a table for each seat.
+        let tables = written(&path);
+        assert_eq!(tables["seat1"]["failed_attempts"].as_integer(), Some(5));
+        // No temp file is left behind.
+        let dir = path.parent().unwrap();
+        let files = fs::read_dir(dir).unwrap().count();
+        assert_eq!(files, 2, "state and state.lock");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn saving_keeps_what_other_seats_wrote() {
+        let path = state_path("others");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let other = "[seat1]\nboot_id = \"boot\"\nfailed_attempts = 1\n\
+                     newer_key = true\n";
+        fs::write(&path, other).unwrap();
+        let mut state = State::for_seat(&path, "seat0", "boot");
+        state.failed_attempts = failed(1, 100);
+        state.save().unwrap();
+
+        let tables = written(&path);
+        assert_eq!(tables["seat1"]["newer_key"].as_bool(), Some(true));
+        assert_eq!(tables["seat0"]["failed_attempts"].as_integer(), Some(1));
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn corrupt_file_is_moved_aside() {
+        let path = state_path("corrupt");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        for corrupt in [&b"[seat0\nfailed_attempts = 3\n"[..], b"\xff\xfe"] {
+            fs::write(&path, corrupt).unwrap();
+            let mut state = State::for_seat(&path, "seat0", "boot");
+            assert_eq!(state.failed_attempts, FailedAttempts::default());
+            assert_eq!(fs::read(path.with_extension("bad")).unwrap(), corrupt);
+            assert!(!path.exists());
+
+            // And the next save starts afresh.
+            state.failed_attempts = failed(1, 100);
+            state.save().unwrap();
+            let state = State::for_seat(&path, "seat0", "boot");
+            assert_eq!(state.failed_attempts, failed(1, 100));
+        }
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn seat_table_of_the_wrong_shape_is_ignored() {
+        let path = state_path("shape");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "[seat0]\nfailed_attempts = \"many\"\n").unwrap();
+        let state = State::for_seat(&path, "seat0", "boot");
+        assert_eq!(state.failed_attempts, FailedAttempts::default());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
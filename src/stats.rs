@@ -0,0 +1,96 @@
+//! Drawing counters for the debug overlay and `logging.stats`. Plain adds
+//! on the main thread; opening the overlay starts them from zero.
+
+use std::{
+    fmt,
+    time::{Duration, Instant}
+};
+
+use crate::buffer;
+
+/// Frame timings and pixel counts since the last `reset`.
+#[derive(Default)]
+pub struct DrawStats {
+    /// Screen refreshes, each showing a frame.
+    frames: u64,
+    /// Time spent drawing towards the next frame so far.
+    pending: Duration,
+    draw_time: Duration,
+    last_frame: Duration,
+    slowest_frame: Duration,
+    pixels: u64,
+    /// Text rows left as they were, or drawn again.
+    row_hits: u64,
+    row_misses: u64
+}
+
+impl DrawStats {
+    /// Starts from zero.
+    pub fn reset(&mut self) {
+        buffer::take_pixels_written();
+        *self = Self::default();
+    }
+
+    /// A draw that started at `started` just finished.
+    pub fn drew(&mut self, started: Instant) {
+        self.pending += started.elapsed();
+    }
+
+    /// The screen was refreshed, showing what's been drawn since the
+    /// last time.
+    pub fn refreshed(&mut self) {
+        let frame = std::mem::take(&mut self.pending);
+        self.frames += 1;
+        self.draw_time += frame;
+        self.last_frame = frame;
+        self.slowest_frame = self.slowest_frame.max(frame);
+        self.pixels += buffer::take_pixels_written();
+    }
+
+    /// A text row was left as it was (`hit`) or drawn again.
+    pub fn row(&mut self, hit: bool) {
+        if hit {
+            self.row_hits += 1;
+        } else {
+            self.row_misses += 1;
+        }
+    }
+
+    /// Lines for the debug overlay.
+    pub fn lines(&self) -> [String; 2] {
+        let frames = self.frames.max(1);
+        [
+            format!(
+                "Draw: {} frames, last {:.1}ms, mean {:.1}ms, slowest {:.1}ms",
+                self.frames,
+                ms(self.last_frame),
+                ms(self.draw_time) / frames as f64,
+                ms(self.slowest_frame)
+            ),
+            format!(
+                "Pixels: {} ({} per frame), rows unchanged: {}",
+                self.pixels,
+                self.pixels / frames,
+                self.hit_rate()
+            )
+        ]
+    }
+
+    fn hit_rate(&self) -> String {
+        match self.row_hits + self.row_misses {
+            0 => "n/a".to_string(),
+            rows => format!("{}%", self.row_hits * 100 / rows)
+        }
+    }
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+impl fmt::Display for DrawStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [draw, pixels] = self.lines();
+        write!(f, "{draw}; {pixels}")
+    }
+}
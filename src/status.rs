@@ -0,0 +1,89 @@
+//! `--status-fd`: a plain-text line per greeter state change, for screen
+//! reader bridges and integration tests:
+//!
+//! ```text
+//! focus=username
+//! session=Sway
+//! auth=failed: Login failed
+//! auth=success
+//! ```
+//!
+//! Never blocks the UI: if the reader falls behind, lines are dropped.
+//! Nothing written here ever contains password material.
+
+use std::{
+    fs::File,
+    io::{self, Write},
+    os::unix::io::{FromRawFd, RawFd}
+};
+
+use log::{debug, warn};
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+
+use crate::{replay::mode_name, Mode};
+
+#[derive(Default)]
+pub struct Status {
+    file: Option<File>,
+    /// Last reported, so only changes are written.
+    focus: Option<Mode>,
+    session: Option<String>
+}
+
+impl Status {
+    /// Writes to `fd`, which is made non-blocking.
+    pub fn open(fd: RawFd) -> io::Result<Self> {
+        let flags = OFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFL)?);
+        fcntl(fd, FcntlArg::F_SETFL(flags | OFlag::O_NONBLOCK))?;
+        Ok(Self {
+            file: Some(unsafe { File::from_raw_fd(fd) }),
+            ..Self::default()
+        })
+    }
+
+    /// Writes `line`, or drops it if the reader isn't keeping up. Lines
+    /// are far shorter than PIPE_BUF, so a pipe never gets half of one.
+    pub fn send(&mut self, line: &str) {
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => return
+        };
+        // One line per change, even for multi-line greetd messages.
+        let line = line.replace('\n', " ");
+        match file.write(format!("{line}\n").as_bytes()) {
+            Ok(_) => (),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                debug!("Status reader is behind; dropped {line:?}");
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                debug!("Interrupted; dropped status {line:?}");
+            }
+            Err(e) => {
+                warn!("Unable to write status: {e}; no more will be sent");
+                self.file = None;
+            }
+        }
+    }
+
+    pub fn focus(&mut self, mode: Mode) {
+        if self.focus != Some(mode) {
+            self.focus = Some(mode);
+            self.send(&format!("focus={}", mode_name(mode)));
+        }
+    }
+
+    pub fn session(&mut self, name: &str) {
+        if self.session.as_deref() != Some(name) {
+            self.session = Some(name.to_string());
+            self.send(&format!("session={name}"));
+        }
+    }
+}
+
+impl crate::LoginManager<'_> {
+    /// Reports focus and session changes since the last call.
+    pub(crate) fn report_status(&mut self) {
+        self.status.focus(self.form.mode);
+        self.status.session(&self.targets.get(self.target_index).name);
+    }
+}
@@ -0,0 +1,101 @@
+//! A `LoginManager` drawing into memory, for tests of what ends up on
+//! screen.
+
+use std::{fs::File, mem, path::PathBuf};
+
+use framebuffer::Framebuffer;
+use memmap::MmapMut;
+
+use crate::{
+    exit::Exit, greetd, keys, replay::Replay, sessions, settings::Settings,
+    LoginManager, Target, Targets
+};
+
+/// A `w`x`h` framebuffer in memory, 32 bits a pixel. Refreshes go to
+/// /dev/null, which refuses them.
+pub(crate) fn framebuffer(w: u32, h: u32) -> Framebuffer {
+    // Every field is a plain integer, for which zero is fine.
+    let mut var_screen_info: framebuffer::VarScreeninfo =
+        unsafe { mem::zeroed() };
+    var_screen_info.xres = w;
+    var_screen_info.yres = h;
+    var_screen_info.xres_virtual = w;
+    var_screen_info.yres_virtual = h;
+    var_screen_info.bits_per_pixel = 32;
+    Framebuffer {
+        device: File::open("/dev/null").unwrap(),
+        frame: MmapMut::map_anon((w * h * 4) as usize).unwrap(),
+        var_screen_info,
+        fix_screen_info: unsafe { mem::zeroed() }
+    }
+}
+
+/// `len` Wayland sessions, "session 0" onwards.
+pub(crate) fn targets(len: usize) -> Targets {
+    let target = |i: usize| Target {
+        name: format!("session {i}"),
+        path: PathBuf::from(format!("/s/{i}.desktop")),
+        comment: None,
+        icon: None,
+        exec: vec![format!("s{i}")].into(),
+        effective_exec: vec![format!("s{i}")].into(),
+        kind: sessions::SessionKind::Wayland,
+        tagged: false
+    };
+    Targets::new((0..len).map(target).collect()).unwrap()
+}
+
+/// A greeter on `fb` with `config` over the defaults and two sessions,
+/// logging in with `--no-greetd` on a machine called "host".
+pub(crate) fn login_manager<'a>(
+    fb: &'a mut Framebuffer,
+    config: &str
+) -> LoginManager<'a> {
+    with_hostname(fb, config, "host".to_string())
+}
+
+/// Like `login_manager`, greeting `hostname`.
+pub(crate) fn with_hostname<'a>(
+    fb: &'a mut Framebuffer,
+    config: &str,
+    hostname: String
+) -> LoginManager<'a> {
+    let settings = Settings::from_toml(config);
+    let screen_size = (fb.var_screen_info.xres, fb.var_screen_info.yres);
+    let mut lm = LoginManager::new(
+        fb,
+        screen_size,
+        (settings.ui.form_width, settings.ui.form_height),
+        greetd::Backend::DryRun,
+        targets(2),
+        sessions::Discovery::from_settings(&settings.sessions),
+        &settings.fonts,
+        settings.resolve_colors().unwrap(),
+        hostname,
+        keys::KeyBindings::from_settings(&settings.keys).unwrap(),
+        &settings.login,
+        &settings.ui,
+        &settings.power,
+        &settings.input
+    );
+    lm.set_info_panel(&settings.panel);
+    lm
+}
+
+/// Draws the greeter as it starts up and runs it through the replay
+/// `script`. Returns how it ended: `Exit::Quit` once the script runs
+/// out.
+pub(crate) fn run(lm: &mut LoginManager<'_>, script: &str) -> Exit {
+    lm.clear();
+    lm.draw_bg().unwrap();
+    let replay = Replay::from_script(script).unwrap();
+    lm.greeter_loop(Some(replay)).unwrap()
+}
+
+/// The pixels of `lm`'s screen, as the framebuffer takes them.
+pub(crate) fn pixels(lm: &LoginManager<'_>) -> Vec<u32> {
+    lm.buf
+        .chunks_exact(4)
+        .map(|px| u32::from_ne_bytes([px[0], px[1], px[2], px[3]]))
+        .collect()
+}
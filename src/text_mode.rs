@@ -0,0 +1,224 @@
+//! `display.fallback_text`: line-based prompts on stdin and stdout for
+//! when there's no framebuffer, as on a server with only a serial
+//! console. Logs in through the same greetd backend and session list as
+//! the graphical greeter, without any of its drawing.
+
+use std::{
+    io::{self, BufRead, Read, Write},
+    thread,
+    time::Instant
+};
+
+use log::{info, warn};
+use nix::sys::termios::{self, LocalFlags, SetArg};
+
+use crate::{
+    exit::Exit,
+    form::{Form, Rules, Submit, Typed},
+    greetd::{Backend, GreetDError, Progress},
+    keys::Key,
+    metrics::Stopwatch,
+    settings,
+    Mode,
+    Targets
+};
+
+/// Longest line read at a prompt; anything after it is dropped.
+const LINE_CAP: usize = 1024;
+
+/// Prompts for logins until one succeeds or input ends. The prompts fill
+/// in the same form the graphical greeter does, one line per field.
+pub fn run(
+    mut greetd: Backend,
+    targets: Targets,
+    login: &settings::Login,
+    hostname: &str
+) -> Exit {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let default_target = login
+        .target
+        .as_deref()
+        .and_then(|name| targets.position(name))
+        .unwrap_or(0);
+    let rows = vec![Mode::EditingUsername, Mode::EditingPassword];
+    let mut form = Form::new(Rules::new(login, rows, false), true);
+    let mut target = default_target;
+
+    println!("\nWelcome to {hostname}");
+    if greetd.is_dry_run() {
+        println!("DRY RUN — no real login");
+    }
+    loop {
+        if form.mode == Mode::EditingUsername {
+            if targets.len() > 1 {
+                println!();
+                for (i, name) in targets.names().enumerate() {
+                    println!("  {}) {name}", i + 1);
+                }
+                let prompt = format!("session [{}]: ", default_target + 1);
+                let answer = match prompt_line(&mut input, &prompt, true) {
+                    Some(answer) => answer,
+                    None => return Exit::Quit
+                };
+                target = match answer.trim() {
+                    "" => default_target,
+                    n => match n.parse::<usize>() {
+                        Ok(n) if (1..=targets.len()).contains(&n) => n - 1,
+                        _ => {
+                            println!("No such session");
+                            continue;
+                        }
+                    }
+                };
+            }
+
+            let prompt = if form.username.is_empty() {
+                "username: ".to_string()
+            } else {
+                format!("username [{}]: ", form.username.as_str())
+            };
+            let line = match prompt_line(&mut input, &prompt, true) {
+                Some(line) => line,
+                None => return Exit::Quit
+            };
+            if type_line(&mut form, &line) {
+                form.submit(Instant::now());
+            }
+            continue;
+        }
+
+        let line = match prompt_line(&mut input, "password: ", false) {
+            Some(line) => line,
+            None => return Exit::Quit
+        };
+        if !type_line(&mut form, &line) {
+            continue;
+        }
+        match form.submit(Instant::now()) {
+            Submit::Moved | Submit::TooSoon => continue,
+            Submit::ControlChars => {
+                println!("Username contains control characters");
+                continue;
+            }
+            Submit::Ready => ()
+        }
+
+        let target = targets.get(target);
+        let (username, password) = form.credentials();
+        info!(
+            "Attempting login via greetd in text mode ({:?}, {})",
+            target.name,
+            crate::log_safe::username(&username)
+        );
+        let mut timing = Stopwatch::new();
+        let res = greetd.login(
+            username,
+            password,
+            &target.effective_exec,
+            &mut timing,
+            &mut |progress| {
+                if let Progress::Message { text, .. } = progress {
+                    println!("{text}");
+                }
+            }
+        );
+        info!("login timing: {timing}");
+        form.finish_attempt(res.is_err(), Instant::now());
+        match res {
+            Ok(()) => {
+                info!("Login succeeded; exiting text mode");
+                println!("Starting {}...", target.name);
+                return Exit::LoggedIn;
+            }
+            Err(e) => {
+                warn!("Login failed: {e}");
+                match e {
+                    GreetDError::Daemon { description } => {
+                        println!("greetd error: {description}")
+                    }
+                    GreetDError::AuthFailed { description }
+                        if !description.trim().is_empty() =>
+                    {
+                        println!("{description}")
+                    }
+                    _ => println!("Login failed")
+                }
+                thread::sleep(form.retry_in(Instant::now()));
+            }
+        }
+    }
+}
+
+/// Puts a line read at a prompt in the field with focus, the way the
+/// greeter takes a paste. An empty line leaves the field as it is. False,
+/// with why printed, if the field refused it.
+fn type_line(form: &mut Form, line: &str) -> bool {
+    if line.is_empty() {
+        return true;
+    }
+    form.before_key(Key::Unknown, None, true);
+    if let Some(field) = form.field_mut() {
+        field.clear();
+    }
+    let refused = match form.paste(line) {
+        Typed::Inserted | Typed::Ignored => return true,
+        Typed::Full => "Too long",
+        Typed::NotDigit => "Digits only"
+    };
+    if let Some(field) = form.field_mut() {
+        field.clear();
+    }
+    println!("{refused}");
+    false
+}
+
+/// Writes `prompt` and reads a line, without the line ending. With `echo`
+/// false the terminal doesn't show what's typed, if stdin is a terminal.
+/// `None` once input ends.
+fn prompt_line(
+    input: &mut impl BufRead,
+    prompt: &str,
+    echo: bool
+) -> Option<String> {
+    print!("{prompt}");
+    let _ = io::stdout().flush();
+
+    let fd = nix::libc::STDIN_FILENO;
+    let saved = if echo {
+        None
+    } else {
+        termios::tcgetattr(fd).ok()
+    };
+    if let Some(saved) = &saved {
+        let mut quiet = saved.clone();
+        quiet.local_flags.remove(LocalFlags::ECHO);
+        quiet.local_flags.insert(LocalFlags::ECHONL);
+        if let Err(e) = termios::tcsetattr(fd, SetArg::TCSANOW, &quiet) {
+            warn!("Unable to turn off echo: {e}");
+        }
+    }
+    let mut line = Vec::new();
+    let read = (&mut *input)
+        .take(LINE_CAP as u64)
+        .read_until(b'\n', &mut line);
+    if let Some(saved) = &saved {
+        let _ = termios::tcsetattr(fd, SetArg::TCSANOW, saved);
+    }
+
+    match read {
+        Ok(0) | Err(_) => None,
+        Ok(_) => {
+            if line.last() == Some(&b'\n') {
+                line.pop();
+            } else if line.len() == LINE_CAP {
+                // Drop the rest of an overlong line.
+                let _ = input.skip_until(b'\n');
+            }
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            Some(String::from_utf8_lossy(&line).into_owned())
+        }
+    }
+}
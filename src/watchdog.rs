@@ -0,0 +1,102 @@
+//! Exits the greeter if the main loop stops making progress, e.g. a
+//! fontconfig deadlock mid-draw, so greetd starts a fresh one instead of
+//! leaving a half-painted form up.
+
+use std::{
+    fs::File,
+    os::unix::io::AsRawFd,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc
+    },
+    thread,
+    time::{Duration, Instant}
+};
+
+use log::error;
+use nix::{libc::STDERR_FILENO, unistd};
+
+use crate::{console::Restore, exit::Exit};
+
+/// How often the monitor thread looks at the heartbeat.
+const CHECK_EVERY: Duration = Duration::from_secs(1);
+
+/// The main loop's side: marks when it's busy and when it's waiting for
+/// input, which can take as long as it likes. Does nothing unless
+/// started.
+#[derive(Default)]
+pub struct Watchdog {
+    /// Milliseconds after the instant that the current busy stretch
+    /// began, plus one; 0 while waiting.
+    heartbeat: Option<(Arc<AtomicU64>, Instant)>
+}
+
+impl Watchdog {
+    /// Starts the monitor thread, busy from now. Once a busy stretch goes
+    /// over `timeout`, it applies `restore`, says why in `log` (stderr
+    /// without one) and exits the process.
+    pub fn start(
+        timeout: Duration,
+        restore: Restore,
+        log: Option<File>
+    ) -> Self {
+        let started = Instant::now();
+        let busy_since = Arc::new(AtomicU64::new(1));
+        let heartbeat = Arc::clone(&busy_since);
+        // Written as it is when the time comes, without allocating.
+        let message = format!(
+            "mflm watchdog: the main loop has been busy for over {}s, \
+             probably stuck in a draw; exiting so greetd starts again\n",
+            timeout.as_secs()
+        );
+        let spawned = thread::Builder::new()
+            .name("watchdog".to_string())
+            .spawn(move || {
+                monitor(started, &heartbeat, timeout);
+                // The main thread may be holding any lock, the logger's
+                // included, so only raw syscalls on fds opened beforehand
+                // from here on.
+                restore.apply();
+                let fd = log.as_ref().map_or(STDERR_FILENO, File::as_raw_fd);
+                let _ = unistd::write(fd, message.as_bytes());
+                Exit::Fatal.exit_now()
+            });
+        if let Err(e) = spawned {
+            error!("Unable to start the watchdog: {e}");
+            return Self::default();
+        }
+        Self {
+            heartbeat: Some((busy_since, started))
+        }
+    }
+
+    /// The loop is working from now on.
+    pub fn beat(&self) {
+        if let Some((busy_since, started)) = &self.heartbeat {
+            let now = started.elapsed().as_millis() as u64 + 1;
+            busy_since.store(now, Ordering::Relaxed);
+        }
+    }
+
+    /// The loop is waiting for input or for greetd, which isn't stuck.
+    pub fn idle(&self) {
+        if let Some((busy_since, _)) = &self.heartbeat {
+            busy_since.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Returns once a busy stretch goes over `timeout`.
+fn monitor(started: Instant, busy_since: &AtomicU64, timeout: Duration) {
+    loop {
+        thread::sleep(CHECK_EVERY);
+        let since = match busy_since.load(Ordering::Relaxed) {
+            0 => continue,
+            ms => Duration::from_millis(ms - 1)
+        };
+        let busy = started.elapsed().saturating_sub(since);
+        if busy > timeout {
+            return;
+        }
+    }
+}